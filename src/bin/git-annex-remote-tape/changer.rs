@@ -0,0 +1,256 @@
+use anyhow::{anyhow, bail, Context, Result};
+use git_annex_remote_tape::catalog::{self, Catalog, Placement};
+use git_annex_remote_tape::changer::{self, Address, Changer, Element, ElementType};
+use git_annex_remote_tape::inventory;
+use git_annex_remote_tape::scheduler::{self, DriveSlot, Lease, Lock, Scheduler};
+use git_annex_remote_tape::tape::Drive;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::cli::ChangerCommand;
+use crate::git;
+
+pub fn run(changer: &Path, drive: Option<&Path>, command: ChangerCommand) -> Result<()> {
+    let changer = changer::open(changer)?;
+    let changer = changer.as_ref();
+
+    match command {
+        ChangerCommand::Status {} => status(changer),
+        ChangerCommand::Load {
+            source,
+            drive: unit,
+        } => {
+            let source = resolve(changer, &source)?;
+            let barcode = changer.element(source)?.barcode;
+            let _reservation = reserve(barcode.as_deref(), &[unit])?;
+            changer.load(source, unit)?;
+            println!("Loaded {source} into drive {unit}");
+
+            Ok(())
+        }
+        ChangerCommand::Unload { drive: unit, slot } => {
+            let barcode = changer.element(Address::drive(unit))?.barcode;
+            let _reservation = reserve(barcode.as_deref(), &[unit])?;
+
+            if let Some(path) = drive {
+                Drive::new(path)?.eject()?;
+            }
+
+            let slot = slot.map(|s| resolve(changer, &s)).transpose()?;
+            let slot = changer.unload(unit, slot)?;
+            println!("Unloaded drive {unit} into {slot}");
+
+            Ok(())
+        }
+        ChangerCommand::Inventory { scan, drive: unit } => {
+            let drive = match drive {
+                Some(path) if scan => Some(Rc::new(Drive::new(path)?)),
+                None if scan => bail!("Scanning requires the tape drive (-f)"),
+                _ => None,
+            };
+
+            let _reservation = if scan {
+                Some(reserve(None, &[unit])?)
+            } else {
+                None
+            };
+
+            inventory(changer, drive.as_ref().map(|d| (d, unit)))
+        }
+        ChangerCommand::Export {
+            barcodes,
+            drive: unit,
+        } => export(changer, drive.map(|path| (path, unit)), &barcodes),
+        ChangerCommand::Import {} => import(changer),
+        ChangerCommand::Transfer { from, to } => {
+            let from = resolve(changer, &from)?;
+            let to = Address::from_str(&to).map_err(|_| anyhow!("Invalid element: {to}"))?;
+            let barcode = changer.element(from)?.barcode;
+            let units: Vec<u16> = [from, to]
+                .iter()
+                .filter(|a| a.kind == ElementType::DataTransfer)
+                .map(|a| a.unit)
+                .collect();
+            let _reservation = reserve(barcode.as_deref(), &units)?;
+            changer.move_medium(from, to)?;
+            println!("Moved {from} to {to}");
+
+            Ok(())
+        }
+    }
+}
+
+/// Locks which keep remote processes from using a cartridge and drives
+/// while they are moved.
+struct Reservation {
+    _media: Option<Lock>,
+    _drives: Vec<Lease>,
+}
+
+/// Reserve the cartridge with `barcode` and the drives `units` through the
+/// scheduler of the remotes, failing if a remote process is using them.
+fn reserve(barcode: Option<&str>, units: &[u16]) -> Result<Reservation> {
+    let catalog = git::catalog().context("Moving cartridges requires a git-annex repository")?;
+    let dir = catalog.lock_dir();
+
+    // Like the scheduler, lock the cartridge before the drives.
+    let media = match barcode {
+        Some(barcode) => match Scheduler::new(&dir, Vec::new())?.reserve(barcode) {
+            Ok(lock) => Some(lock),
+            Err(scheduler::Error::Busy) => {
+                bail!("Cartridge {barcode} is in use by another process")
+            }
+            Err(e) => return Err(e.into()),
+        },
+        None => None,
+    };
+
+    let mut drives = Vec::new();
+    for &unit in units {
+        let slot = DriveSlot {
+            unit,
+            path: PathBuf::new(),
+        };
+
+        match Scheduler::new(&dir, vec![slot])?.acquire(None, None, None, Duration::ZERO) {
+            Ok(lease) => drives.push(lease),
+            Err(scheduler::Error::Busy) => bail!("Drive {unit} is in use by another process"),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(Reservation {
+        _media: media,
+        _drives: drives,
+    })
+}
+
+/// Resolve an element address or a barcode to the address of an element.
+fn resolve(changer: &dyn Changer, s: &str) -> Result<Address> {
+    match Address::from_str(s) {
+        Ok(address) => Ok(address),
+        Err(_) => Ok(changer.find_barcode(s)?.address),
+    }
+}
+
+fn status(changer: &dyn Changer) -> Result<()> {
+    let status = changer.status()?;
+
+    for element in status.elements() {
+        print_element(element);
+    }
+
+    Ok(())
+}
+
+fn inventory(changer: &dyn Changer, drive: Option<(&Rc<Drive>, u16)>) -> Result<()> {
+    let catalog = git::catalog().context("Inventory requires a git-annex repository")?;
+    let inventory = inventory::run(&catalog, changer, drive)?;
+
+    for finding in &inventory.findings {
+        println!("{finding}");
+    }
+
+    Ok(())
+}
+
+fn export(changer: &dyn Changer, drive: Option<(&Path, u16)>, barcodes: &[String]) -> Result<()> {
+    let catalog = git::catalog()?;
+
+    for barcode in barcodes {
+        let element = changer.find_barcode(barcode)?;
+        let in_drive = element.address.kind == ElementType::DataTransfer;
+        let units = if in_drive {
+            vec![element.address.unit]
+        } else {
+            Vec::new()
+        };
+        let _reservation = reserve(Some(barcode), &units)?;
+
+        if in_drive {
+            let Some((path, unit)) = drive else {
+                bail!(
+                    "Cartridge {barcode} is in {}, the tape drive (-f) is needed to eject it",
+                    element.address
+                );
+            };
+
+            if element.address.unit != unit {
+                bail!(
+                    "Cartridge {barcode} is in {}, but the tape drive (-f) is drive {unit} (--drive)",
+                    element.address
+                );
+            }
+
+            Drive::new(path)?.eject()?;
+        }
+
+        let mailslot = changer.export(element.address)?;
+        println!("Exported {barcode} to {mailslot}");
+
+        set_placement(&catalog, barcode, Placement::Offsite)?;
+    }
+
+    Ok(())
+}
+
+fn import(changer: &dyn Changer) -> Result<()> {
+    let catalog = git::catalog()?;
+    let status = changer.status()?;
+
+    for element in status.mailslots.iter().filter(|e| e.full) {
+        let slot = changer.import(element.address)?;
+        let barcode = element.barcode.as_deref().unwrap_or("(no barcode)");
+        println!("Imported {barcode} into {slot}");
+
+        if let Some(barcode) = &element.barcode {
+            set_placement(&catalog, barcode, Placement::Library(slot))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Record where a cartridge went, if it is known to the catalog.
+fn set_placement(catalog: &Catalog, barcode: &str, placement: Placement) -> Result<()> {
+    let Some(mut record) = catalog.media_by_barcode(barcode)? else {
+        println!("  {barcode} is not a known cartridge");
+        return Ok(());
+    };
+
+    if let Placement::Library(_) = placement {
+        record.last_seen = Some(catalog::now());
+    }
+
+    record.placement = placement;
+    catalog.save_media(&record)?;
+
+    println!("  media {} is now {placement}", record.id);
+
+    Ok(())
+}
+
+fn print_element(element: &Element) {
+    let mut line = format!("{:<12}", element.address.to_string());
+
+    if element.full {
+        line += &format!(
+            " Full  {}",
+            element.barcode.as_deref().unwrap_or("(no barcode)")
+        );
+
+        if let Some(source) = element.source {
+            line += &format!(" (from {source})");
+        }
+    } else {
+        line += " Empty";
+    }
+
+    if element.exception {
+        line += " [exception]";
+    }
+
+    println!("{line}");
+}
@@ -1,4 +1,6 @@
 use clap::{Parser, Subcommand};
+use git_annex_remote_tape::format::ArchiveFormat;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -14,6 +16,10 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Command {
     Tape {
+        /// Path of the SCSI tape drive.
+        #[arg(short = 'f', long, default_value = "/dev/nst0")]
+        drive: PathBuf,
+
         #[command(subcommand)]
         command: Option<TapeCommand>,
     },
@@ -22,12 +28,71 @@ pub enum Command {
         #[command(subcommand)]
         command: Option<JobCommand>,
     },
+
+    Drives {
+        /// Path of the SCSI tape drive.
+        #[arg(short = 'f', long, default_value = "/dev/nst0")]
+        drive: PathBuf,
+
+        #[command(subcommand)]
+        command: DriveCommand,
+    },
+
+    Changer {
+        /// Path of the SCSI medium changer.
+        #[arg(short, long, default_value = "/dev/sch0")]
+        changer: PathBuf,
+
+        /// Path of the SCSI tape drive which is ejected before unloading.
+        #[arg(short = 'f', long)]
+        drive: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: ChangerCommand,
+    },
+
+    /// Read LTFS volumes without mounting them.
+    Ltfs {
+        /// Path of the SCSI tape drive.
+        #[arg(short = 'f', long, default_value = "/dev/nst0")]
+        drive: PathBuf,
+
+        #[command(subcommand)]
+        command: LtfsCommand,
+    },
+
+    /// Manage a virtual tape library backed by a directory of cartridge images.
+    Vtl {
+        /// Directory of the virtual library.
+        dir: PathBuf,
+
+        #[command(subcommand)]
+        command: VtlCommand,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum TapeCommand {
     /// Intitialize a tape cartridge for use with git-annex-remote-tape.
-    Init {},
+    Init {
+        /// Human readable label, usually the barcode of the cartridge.
+        #[arg(short, long, default_value = "")]
+        label: String,
+
+        /// Pool the cartridge is added to.
+        #[arg(short, long, default_value = "")]
+        pool: String,
+
+        /// UUID of the special remote which owns the cartridge. Other
+        /// remotes refuse to write to it.
+        #[arg(short, long, default_value = "")]
+        remote: String,
+
+        /// Format of the archives written to the cartridge, `native` or
+        /// `tar`. Archives in tar format can be extracted with tar alone.
+        #[arg(short, long, default_value = "native", value_parser = parse_format)]
+        format: ArchiveFormat,
+    },
 
     /// Erase all data from a tape cartridge.
     Erase {
@@ -36,8 +101,50 @@ pub enum TapeCommand {
         secure: bool,
     },
 
-    /// Get information about the tape cartridge.
+    /// Get information and health about the tape drive and cartridge.
     Info {},
+
+    /// Scan all archives on the cartridge and rebuild its catalog of objects.
+    Scan {
+        /// File holding the key the objects are encrypted with.
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+    },
+
+    /// Recover all readable objects from a damaged cartridge.
+    Salvage {
+        /// Directory to which the recovered objects are written, named by key.
+        dir: PathBuf,
+
+        /// File holding the key the objects are encrypted with.
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+    },
+
+    /// Append an archive holding a git bundle of the repository in the
+    /// current directory.
+    Bundle {
+        /// File holding the key the objects are encrypted with.
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+    },
+
+    /// Recreate the repository from the newest git bundle on the cartridge
+    /// and mark the objects on the cartridge as present.
+    Restore {
+        /// Directory into which the repository is cloned.
+        dir: PathBuf,
+
+        /// File holding the key the objects are encrypted with.
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+    },
+
+    /// Create a new key for encrypting objects, for use as `tapekeyfile`.
+    Keygen {
+        /// File to which the key is written.
+        file: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -54,3 +161,161 @@ pub enum JobCommand {
     /// Drop single or all pendings retrieval jobs.
     Drop { job_id: Option<u32> },
 }
+
+#[derive(Subcommand)]
+pub enum DriveCommand {
+    /// List known drives with their cleaning and diagnostic history.
+    List {},
+
+    /// Run the drive self-test and optionally a write/read/compare test.
+    Test {
+        /// Overwrite the loaded scratch cartridge with test data.
+        #[arg(long)]
+        scratch: bool,
+
+        /// Block sizes to use for the write/read/compare test.
+        #[arg(short, long, value_delimiter = ',', default_values_t = [65536, 262144, 1048576])]
+        block_sizes: Vec<usize>,
+
+        /// Number of blocks to write per block size.
+        #[arg(short = 'n', long, default_value_t = 256)]
+        blocks: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ChangerCommand {
+    /// Show the drives, slots and import/export elements with their cartridges.
+    Status {},
+
+    /// Load a cartridge into a drive.
+    Load {
+        /// Slot (e.g. `3` or `mailslot:0`) or barcode of the cartridge.
+        source: String,
+
+        /// Number of the drive within the changer.
+        #[arg(default_value_t = 0)]
+        drive: u16,
+    },
+
+    /// Return the cartridge in a drive to a slot.
+    Unload {
+        /// Number of the drive within the changer.
+        #[arg(default_value_t = 0)]
+        drive: u16,
+
+        /// Destination slot (defaults to the slot the cartridge came from).
+        slot: Option<String>,
+    },
+
+    /// Reconcile the cartridges in the library with the catalog.
+    Inventory {
+        /// Load cartridges without barcode into the drive to read their media header.
+        #[arg(long)]
+        scan: bool,
+
+        /// Number of the drive within the changer used for scanning.
+        #[arg(default_value_t = 0)]
+        drive: u16,
+    },
+
+    /// Move cartridges to the import/export elements and mark them offsite.
+    Export {
+        /// Barcodes of the cartridges.
+        #[arg(required = true)]
+        barcodes: Vec<String>,
+
+        /// Number of the drive within the changer which the tape drive (-f) is.
+        #[arg(long, default_value_t = 0)]
+        drive: u16,
+    },
+
+    /// Move cartridges from the import/export elements to free slots and mark them onsite.
+    Import {},
+
+    /// Move a cartridge between two elements.
+    Transfer {
+        /// Source element (e.g. `3` or `mailslot:0`) or barcode.
+        from: String,
+
+        /// Destination element (e.g. `4` or `mailslot:0`).
+        to: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum LtfsCommand {
+    /// Show the labels and the latest index of the volume.
+    Info {},
+
+    /// List the files of the volume with their sizes.
+    List {},
+
+    /// Copy files of the volume into the work tree of the repository in the
+    /// current directory and add them to git-annex.
+    Import {
+        /// Directory of the work tree the files are copied into.
+        #[arg(short, long, default_value = ".")]
+        dir: PathBuf,
+
+        /// Files or directories of the volume to import, all by default.
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VtlCommand {
+    /// Create a new virtual library with blank cartridges.
+    Create {
+        /// Number of storage slots.
+        #[arg(long, default_value_t = 8)]
+        slots: u16,
+
+        /// Number of drives.
+        #[arg(long, default_value_t = 1)]
+        drives: u16,
+
+        /// Number of import/export elements.
+        #[arg(long, default_value_t = 1)]
+        mailslots: u16,
+
+        /// Number of blank data cartridges to put into the slots.
+        #[arg(long, default_value_t = 4)]
+        cartridges: u16,
+
+        /// Also put a cleaning cartridge into the slot after the data cartridges.
+        #[arg(long)]
+        cleaning: bool,
+    },
+
+    /// Put a cartridge into an import/export element, as an operator would.
+    Insert {
+        /// Barcode of the cartridge.
+        barcode: String,
+
+        /// Number of the import/export element.
+        #[arg(default_value_t = 0)]
+        mailslot: u16,
+    },
+
+    /// Take a cartridge out of an import/export element, as an operator would.
+    Remove {
+        /// Number of the import/export element.
+        #[arg(default_value_t = 0)]
+        mailslot: u16,
+    },
+
+    /// Make a record of a cartridge unreadable to rehearse disaster recovery.
+    Damage {
+        /// Barcode of the cartridge.
+        barcode: String,
+
+        /// Logical block number of the record.
+        block: u64,
+    },
+}
+
+fn parse_format(s: &str) -> Result<ArchiveFormat, String> {
+    s.parse()
+        .map_err(|_| format!("unknown format {s}, expected native or tar"))
+}
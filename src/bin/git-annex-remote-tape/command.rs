@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 
 use crate::error::Error;
@@ -11,6 +12,12 @@ pub enum Command {
     Remove,
     ListConfigs,
     ExportSupported,
+    Export,
+    TransferExport,
+    CheckPresentExport,
+    RemoveExport,
+    RemoveExportDirectory,
+    RenameExport,
     GetCost,
     GetOrdered,
     GetAvailability,
@@ -30,6 +37,12 @@ impl FromStr for Command {
             "REMOVE" => Ok(Self::Remove),
             "LISTCONFIGS" => Ok(Self::ListConfigs),
             "EXPORTSUPPORTED" => Ok(Self::ExportSupported),
+            "EXPORT" => Ok(Self::Export),
+            "TRANSFEREXPORT" => Ok(Self::TransferExport),
+            "CHECKPRESENTEXPORT" => Ok(Self::CheckPresentExport),
+            "REMOVEEXPORT" => Ok(Self::RemoveExport),
+            "REMOVEEXPORTDIRECTORY" => Ok(Self::RemoveExportDirectory),
+            "RENAMEEXPORT" => Ok(Self::RenameExport),
             "GETCOST" => Ok(Self::GetCost),
             "GETORDERED" => Ok(Self::GetOrdered),
             "GETAVAILABILITY" => Ok(Self::GetAvailability),
@@ -39,9 +52,9 @@ impl FromStr for Command {
     }
 }
 
-impl ToString for Command {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::InitRemote => "INITREMOTE",
             Self::Extensions => "EXTENSIONS",
             Self::Prepare => "PREPARE",
@@ -50,11 +63,16 @@ impl ToString for Command {
             Self::Remove => "REMOVE",
             Self::ListConfigs => "LISTCONFIGS",
             Self::ExportSupported => "EXPORTSUPPORTED",
+            Self::Export => "EXPORT",
+            Self::TransferExport => "TRANSFEREXPORT",
+            Self::CheckPresentExport => "CHECKPRESENTEXPORT",
+            Self::RemoveExport => "REMOVEEXPORT",
+            Self::RemoveExportDirectory => "REMOVEEXPORTDIRECTORY",
+            Self::RenameExport => "RENAMEEXPORT",
             Self::GetCost => "GETCOST",
             Self::GetOrdered => "GETORDERED",
             Self::GetAvailability => "GETAVAILABILITY",
             Self::GetInfo => "GETINFO",
-        }
-        .to_string()
+        })
     }
 }
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use git_annex_remote_tape::catalog::{self, DriveRecord};
+use git_annex_remote_tape::diagnostic::{self, Report};
+use git_annex_remote_tape::tape::Drive;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::cli::DriveCommand;
+use crate::git;
+
+pub fn run(drive: &Path, command: DriveCommand) -> Result<()> {
+    match command {
+        DriveCommand::List {} => list(),
+        DriveCommand::Test {
+            scratch,
+            block_sizes,
+            blocks,
+        } => test(&Drive::new(drive)?, scratch, &block_sizes, blocks),
+    }
+}
+
+fn list() -> Result<()> {
+    for record in git::catalog()?.drives()? {
+        print_drive(&record);
+    }
+
+    Ok(())
+}
+
+fn print_drive(record: &DriveRecord) {
+    println!(
+        "{} {} {}",
+        record.serial,
+        record.vendor.as_deref().unwrap_or(""),
+        record.product.as_deref().unwrap_or("")
+    );
+
+    if record.cleaning_pending() {
+        println!("  Cleaning: requested");
+    }
+
+    for report in &record.diagnostics {
+        println!(
+            "  Test at {}: {} (self-test {:?}, cartridge {})",
+            report.time,
+            if report.passed() { "passed" } else { "failed" },
+            report.self_test,
+            report.media.as_deref().unwrap_or("none")
+        );
+    }
+
+    // Failures with several different cartridges point to the drive.
+    let failed_media: HashSet<_> = record
+        .diagnostics
+        .iter()
+        .filter(|r| !r.passed())
+        .filter_map(|r| r.media.as_ref())
+        .collect();
+
+    if failed_media.len() > 1 {
+        println!(
+            "  Tests failed with {} different cartridges, the drive is likely at fault",
+            failed_media.len()
+        );
+    }
+}
+
+fn test(drive: &Drive, scratch: bool, block_sizes: &[usize], blocks: usize) -> Result<()> {
+    let serial = drive.serial_number()?;
+    let inquiry = drive.inquiry()?;
+
+    println!(
+        "Testing drive {serial} ({} {} {})",
+        inquiry.vendor, inquiry.product, inquiry.revision
+    );
+
+    let self_test = drive.self_test();
+    match &self_test {
+        diagnostic::SelfTest::Passed => println!("Self-test: passed"),
+        diagnostic::SelfTest::Failed(e) => println!("Self-test: failed ({e})"),
+        diagnostic::SelfTest::Unsupported => println!("Self-test: not supported by drive"),
+    }
+
+    let media = drive
+        .media_health()
+        .ok()
+        .and_then(|h| h.barcode.or(h.serial_number));
+
+    let mut write_read = Vec::new();
+    if scratch {
+        for block_size in block_sizes {
+            let result = drive.write_read_test(*block_size, blocks);
+
+            print!(
+                "Write/read/compare with {} x {block_size} bytes: ",
+                result.blocks
+            );
+
+            if let Some(e) = &result.error {
+                println!("error ({e})");
+            } else if result.mismatches > 0 {
+                println!("{} mismatching blocks", result.mismatches);
+            } else {
+                println!(
+                    "passed (write {:.1} MB/s, read {:.1} MB/s)",
+                    result.write_throughput(),
+                    result.read_throughput()
+                );
+            }
+
+            write_read.push(result);
+        }
+    }
+
+    let report = Report {
+        time: catalog::now(),
+        self_test,
+        media,
+        write_read,
+    };
+
+    println!(
+        "Result: {}",
+        if report.passed() { "passed" } else { "failed" }
+    );
+
+    let catalog = git::catalog()?;
+    let mut record = catalog.drive(&serial)?;
+    record.vendor = Some(inquiry.vendor);
+    record.product = Some(inquiry.product);
+    record.diagnostics.push(report);
+    catalog.save_drive(&record)?;
+
+    Ok(())
+}
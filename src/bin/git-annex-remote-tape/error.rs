@@ -1,16 +1,90 @@
+use git_annex_remote_tape::{catalog, changer, cleaning, ltfs, mt, scheduler, tape};
+use std::fmt;
 use std::io;
 
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
+    Tape(mt::Error),
+    Media(tape::Error),
+    Catalog(catalog::Error),
+    Changer(changer::Error),
+    Cleaning(cleaning::Error),
+    Scheduler(scheduler::Error),
+    Ltfs(ltfs::Error),
     InvalidCommand,
     InvalidArguments,
     InvalidDirection,
+    NotStored,
+    Unavailable(String),
     EndOfFile,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "{e}"),
+            Self::Tape(e) => write!(f, "Drive error: {e}"),
+            Self::Media(e) => write!(f, "Cartridge error: {e}"),
+            Self::Catalog(e) => write!(f, "Catalog error: {e}"),
+            Self::Changer(e) => write!(f, "Changer error: {e}"),
+            Self::Cleaning(e) => write!(f, "Cleaning error: {e}"),
+            Self::Scheduler(e) => write!(f, "Scheduling error: {e}"),
+            Self::Ltfs(e) => write!(f, "LTFS error: {e}"),
+            Self::InvalidCommand => write!(f, "Invalid command"),
+            Self::InvalidArguments => write!(f, "Invalid arguments"),
+            Self::InvalidDirection => write!(f, "Invalid transfer direction"),
+            Self::NotStored => write!(f, "Key is not stored on tape"),
+            Self::Unavailable(reason) => write!(f, "{reason}"),
+            Self::EndOfFile => write!(f, "End of file"),
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Self::IO(error)
     }
 }
+
+impl From<mt::Error> for Error {
+    fn from(error: mt::Error) -> Self {
+        Self::Tape(error)
+    }
+}
+
+impl From<tape::Error> for Error {
+    fn from(error: tape::Error) -> Self {
+        Self::Media(error)
+    }
+}
+
+impl From<catalog::Error> for Error {
+    fn from(error: catalog::Error) -> Self {
+        Self::Catalog(error)
+    }
+}
+
+impl From<changer::Error> for Error {
+    fn from(error: changer::Error) -> Self {
+        Self::Changer(error)
+    }
+}
+
+impl From<cleaning::Error> for Error {
+    fn from(error: cleaning::Error) -> Self {
+        Self::Cleaning(error)
+    }
+}
+
+impl From<scheduler::Error> for Error {
+    fn from(error: scheduler::Error) -> Self {
+        Self::Scheduler(error)
+    }
+}
+
+impl From<ltfs::Error> for Error {
+    fn from(error: ltfs::Error) -> Self {
+        Self::Ltfs(error)
+    }
+}
@@ -22,7 +22,7 @@ impl Extension {
         }
     }
 
-    fn from_strs(s: &str) -> Result<FlagSet<Self>, Error> {
+    pub fn from_strs(s: &str) -> Result<FlagSet<Self>, Error> {
         let mut es = FlagSet::<Self>::new_truncated(0);
 
         for p in s.split_ascii_whitespace() {
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use git_annex_remote_tape::catalog::Catalog;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+/// Find the git directory of the repository in the current working directory.
+pub fn git_dir() -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--absolute-git-dir"])
+        .output()?;
+
+    if !output.status.success() {
+        bail!("Not inside a git repository");
+    }
+
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+/// Open the catalog of the repository in the current working directory.
+pub fn catalog() -> Result<Catalog> {
+    Ok(Catalog::open(&git_dir()?)?)
+}
+
+/// Run git on the repository of `git_dir` and return its output, `None` if
+/// it fails.
+fn output(git_dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(git_dir)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// UUID of the git-annex repository.
+pub fn annex_uuid(git_dir: &Path) -> Option<String> {
+    Some(
+        output(git_dir, &["config", "--get", "annex.uuid"])?
+            .trim()
+            .to_string(),
+    )
+}
+
+/// The commit checked out in the work tree.
+pub fn head(git_dir: &Path) -> Option<String> {
+    Some(
+        output(git_dir, &["rev-parse", "--verify", "--quiet", "HEAD"])?
+            .trim()
+            .to_string(),
+    )
+}
+
+/// Paths of the files in the work tree which use `key`.
+pub fn annex_paths(git_dir: &Path, key: &str) -> Vec<String> {
+    let Some(output) = output(git_dir, &["annex", "whereused", &format!("--key={key}")]) else {
+        return Vec::new();
+    };
+
+    // Each line holds the key and a path.
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Run a git command, failing with its error output.
+fn run(command: &mut Command) -> Result<()> {
+    let output = command.stdin(Stdio::null()).output()?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+
+    Ok(())
+}
+
+/// Path for a temporary bundle of this process.
+pub fn bundle_path() -> PathBuf {
+    std::env::temp_dir().join(format!("git-annex-remote-tape-{}.bundle", process::id()))
+}
+
+/// Create a bundle of the branches and tags of the repository, including the
+/// `git-annex` branch, in a temporary file which is removed once closed.
+pub fn bundle(git_dir: &Path) -> Result<File> {
+    let path = bundle_path();
+    run(Command::new("git")
+        .arg("--git-dir")
+        .arg(git_dir)
+        .args(["bundle", "create", "--quiet"])
+        .arg(&path)
+        .args(["HEAD", "--branches", "--tags"]))?;
+
+    let file = File::open(&path)?;
+    fs::remove_file(&path)?;
+
+    Ok(file)
+}
+
+/// Clone the repository in `bundle` into `dir`, with all of its branches.
+pub fn clone_bundle(bundle: &Path, dir: &Path) -> Result<()> {
+    run(Command::new("git")
+        .args(["clone", "--quiet"])
+        .arg(bundle)
+        .arg(dir))?;
+
+    // Only the branch of HEAD is checked out, the others would be left as
+    // branches of a remote pointing to the bundle.
+    run(Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["fetch", "--quiet", "--update-head-ok"])
+        .arg(bundle)
+        .args(["refs/heads/*:refs/heads/*", "refs/tags/*:refs/tags/*"]))?;
+
+    run(Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["remote", "remove", "origin"]))
+}
+
+/// Initialize git-annex in the repository at `dir`.
+pub fn annex_init(dir: &Path) -> Result<()> {
+    run(Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["annex", "init"]))
+}
+
+/// Add files of the work tree of the repository in the current directory to
+/// git-annex.
+pub fn annex_add(paths: &[PathBuf]) -> Result<()> {
+    let mut child = Command::new("git")
+        .args(["annex", "add", "--batch", "-z"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    for path in paths {
+        stdin.write_all(path.as_os_str().as_bytes())?;
+        stdin.write_all(b"\0")?;
+    }
+    drop(stdin);
+
+    if !child.wait()?.success() {
+        bail!("git annex add failed");
+    }
+
+    Ok(())
+}
+
+/// Record that the remotes with the given UUIDs have the given keys.
+pub fn set_present_keys(dir: &Path, keys: &[(&str, &str)]) -> Result<()> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["annex", "setpresentkey", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    for (key, uuid) in keys {
+        writeln!(stdin, "{key} {uuid} 1")?;
+    }
+    drop(stdin);
+
+    if !child.wait()?.success() {
+        bail!("git annex setpresentkey failed");
+    }
+
+    Ok(())
+}
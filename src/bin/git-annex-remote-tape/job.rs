@@ -1,10 +1,13 @@
+use anyhow::Result;
+
 use crate::cli::JobCommand;
 
-pub fn run(command: JobCommand) {
+/// Run a job subcommand, listing the pending jobs without one.
+pub fn run(command: Option<JobCommand>) -> Result<()> {
     match command {
-        JobCommand::Info { job_id } => unimplemented!(),
-        JobCommand::List {} => unimplemented!(),
-        JobCommand::Start { job_id } => unimplemented!(),
-        JobCommand::Drop { job_id } => unimplemented!(),
+        Some(JobCommand::Info { job_id }) => unimplemented!(),
+        Some(JobCommand::List {}) | None => unimplemented!(),
+        Some(JobCommand::Start { job_id }) => unimplemented!(),
+        Some(JobCommand::Drop { job_id }) => unimplemented!(),
     }
 }
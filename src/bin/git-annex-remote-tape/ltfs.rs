@@ -0,0 +1,124 @@
+use anyhow::{bail, Result};
+use git_annex_remote_tape::ltfs::{File, Volume};
+use git_annex_remote_tape::tape::Drive;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::cli::LtfsCommand;
+use crate::git;
+
+pub fn run(drive: &Path, command: LtfsCommand) -> Result<()> {
+    let drive = Drive::new(drive)?;
+    let volume = drive.ltfs(&[])?;
+
+    match command {
+        LtfsCommand::Info {} => info(&volume),
+        LtfsCommand::List {} => list(&volume),
+        LtfsCommand::Import { dir, paths } => import(&volume, &dir, &paths),
+    }
+}
+
+fn info(volume: &Volume) -> Result<()> {
+    let label = volume.label();
+    let index = volume.index();
+
+    println!("Volume: {} ({})", label.volume_uuid, volume.vol1().volume);
+    println!("Created by: {}", label.creator);
+    println!("Block size: {}", label.block_size);
+    println!(
+        "Index: generation {} at block {} of partition {}, written by {}",
+        index.generation, index.location.block, index.location.partition, index.creator
+    );
+
+    let files = index.files();
+    let bytes: u64 = files.iter().map(|(_, file)| file.length).sum();
+    println!("Files: {} ({bytes} bytes)", files.len());
+
+    Ok(())
+}
+
+fn list(volume: &Volume) -> Result<()> {
+    for (path, file) in volume.index().files() {
+        match &file.symlink {
+            Some(target) => println!("{path} -> {target}"),
+            None => println!("{path} ({} bytes)", file.length),
+        }
+    }
+
+    Ok(())
+}
+
+fn import(volume: &Volume, dir: &Path, paths: &[String]) -> Result<()> {
+    git::git_dir()?;
+
+    let files = volume.index().files();
+    let mut selected: Vec<(String, &File)> = match paths.is_empty() {
+        true => files.clone(),
+        false => Vec::new(),
+    };
+    for path in paths {
+        let path = path.trim_matches('/');
+        let matches = files.iter().filter(|(name, _)| {
+            name == path || name.strip_prefix(path).is_some_and(|r| r.starts_with('/'))
+        });
+
+        let count = selected.len();
+        selected.extend(matches.cloned());
+        if selected.len() == count {
+            bail!("{path} not found on the volume");
+        }
+    }
+    selected.sort_by(|a, b| a.0.cmp(&b.0));
+    selected.dedup_by(|a, b| a.0 == b.0);
+
+    selected.retain(|(path, file)| {
+        if file.symlink.is_some() {
+            println!("Skipping symbolic link {path}");
+        }
+        file.symlink.is_none()
+    });
+
+    // Nothing is copied if any file is in the way.
+    for (path, _) in &selected {
+        if fs::symlink_metadata(dir.join(path)).is_ok() {
+            bail!("{} already exists", dir.join(path).display());
+        }
+    }
+
+    // Read the files in the order they were written to avoid seeking back
+    // and forth.
+    selected.sort_by_key(|(_, file)| {
+        file.extents
+            .iter()
+            .map(|extent| (extent.partition, extent.start_block))
+            .min()
+    });
+
+    let mut imported: Vec<PathBuf> = Vec::new();
+    let mut bytes = 0;
+    for (path, file) in selected {
+        let dest = dir.join(&path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&dest)?;
+        if let Err(e) = volume.read_file(file, &mut out) {
+            fs::remove_file(&dest)?;
+            bail!("cannot read {path}: {e}");
+        }
+        out.set_modified(UNIX_EPOCH + Duration::from_secs(file.modify_time))?;
+
+        bytes += file.length;
+        imported.push(dest);
+    }
+
+    git::annex_add(&imported)?;
+    println!("Imported {} files ({bytes} bytes)", imported.len());
+
+    Ok(())
+}
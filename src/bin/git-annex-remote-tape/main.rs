@@ -1,13 +1,20 @@
 #![allow(dead_code, unused_variables)]
 
 use clap::Parser;
+use std::process;
+
+mod changer;
 mod cli;
 mod command;
+mod drives;
 mod error;
 mod extension;
+mod git;
 mod job;
+mod ltfs;
 mod remote;
 mod tape;
+mod vtl;
 
 use crate::cli::Command;
 use crate::remote::Remote;
@@ -15,9 +22,25 @@ use crate::remote::Remote;
 fn main() {
     let args = cli::Cli::parse();
 
-    match args.command {
-        Some(Command::Tape { command }) => tape::run(command),
+    let result = match args.command {
+        Some(Command::Tape { drive, command }) => tape::run(&drive, command),
         Some(Command::Jobs { command }) => job::run(command),
-        None => Remote::new().run(),
+        Some(Command::Drives { drive, command }) => drives::run(&drive, command),
+        Some(Command::Changer {
+            changer,
+            drive,
+            command,
+        }) => changer::run(&changer, drive.as_deref(), command),
+        Some(Command::Ltfs { drive, command }) => ltfs::run(&drive, command),
+        Some(Command::Vtl { dir, command }) => vtl::run(&dir, command),
+        None => {
+            Remote::new().run();
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {e:#}");
+        process::exit(1);
     }
 }
@@ -1,21 +1,54 @@
 use flagset::FlagSet;
-use git_annex_remote_tape::tape::{Archive, Drive, Media};
+use git_annex_remote_tape::catalog::{self, Catalog, MediaRecord, Placement};
+use git_annex_remote_tape::changer::{self, Address, Changer, ElementType};
+use git_annex_remote_tape::compression::Compression;
+use git_annex_remote_tape::crypto::Cipher;
+use git_annex_remote_tape::format::{ArchiveFormat, MediaHeader, MediaIdentity, Metadata, Origin};
+use git_annex_remote_tape::parity::Parity;
+use git_annex_remote_tape::scheduler::{self, DriveSlot, Lease, Scheduler};
+use git_annex_remote_tape::spool::Spool;
+use git_annex_remote_tape::tape::{self, Archive, Drive, Location, Media, TombstoneEntry};
+use git_annex_remote_tape::{cleaning, health, ltfs};
 use std::collections::HashMap;
-use std::io::{self, stdin};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, stdin, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
 use std::string::ToString;
+use std::time::UNIX_EPOCH;
 use std::{io::Write, result::Result};
 
 use crate::command::Command;
 use crate::error::Error;
 use crate::extension::Extension;
+use crate::git;
 
 static TAPE_COST: i64 = 1100;
 
+/// Extended attribute recording the key of an exported file.
+const KEY_ATTRIBUTE: &str = "git-annex.key";
+
+/// Seconds after which the index of the exported tree is written again,
+/// changes made in between are kept in the journal of the catalog.
+const INDEX_INTERVAL: u64 = 5 * 60;
+
 #[derive(Default)]
-pub struct Remote<'a> {
+pub struct Remote {
     // Options
     drive_path: Option<PathBuf>,
+    cleaning_policy: cleaning::Policy,
+    cleaning_sense: Option<cleaning::SenseBits>,
+    cleaning_slot: Option<u16>,
+    changer_path: Option<PathBuf>,
+    changer_drive: u16,
+    drives: Vec<PathBuf>,
+    parity: Option<Parity>,
+    compression: Option<Compression>,
+    cipher: Option<Cipher>,
+    git_bundle: bool,
+    pool: String,
+    tape_format: ArchiveFormat,
 
     // Properties
     uuid: Option<uuid::Uuid>,
@@ -24,25 +57,64 @@ pub struct Remote<'a> {
     supported_extensions: Option<FlagSet<Extension>>,
 
     // State
-    drive: Option<Drive>,
-    media: Option<Media<'a>>,
-    archive: Option<Archive<'a>>,
+    catalog: Option<Catalog>,
+    drive: Option<Rc<Drive>>,
+    changer: Option<Rc<dyn Changer>>,
+    scheduler: Option<Scheduler>,
+    lease: Option<Lease>,
+    media: Option<Media>,
+    archive: Option<Archive>,
+    /// Copies of the stored objects which are waiting in the pack of the archive.
+    spool: Option<Spool>,
+
+    /// Path of the file the next export request refers to.
+    export_name: Option<String>,
+    /// LTFS volume on the loaded cartridge, which the tree is exported to.
+    volume: Option<ltfs::Volume>,
+    /// Time the index of the volume was last written.
+    volume_synced: u64,
 
     prepared: bool,
 }
 
-impl<'a> Remote<'a> {
-    pub fn new() -> Remote<'a> {
+impl Remote {
+    pub fn new() -> Remote {
         Remote::default()
     }
 
     /// Fetch retrieves the necessary information about the remote from git-annex.
     fn fetch(&mut self, initialize: bool) -> Result<(), Error> {
         let drive = self.get_option("drive")?;
+        let cleaning = self.get_option("cleaning")?;
+        let cleaning_sense = self.get_option("cleaningsense")?;
 
         self.drive_path = Some(drive.into());
+        self.cleaning_policy =
+            cleaning::Policy::from_str(&cleaning).map_err(|_| Error::InvalidArguments)?;
+        self.cleaning_sense = match cleaning_sense.as_str() {
+            "" => None,
+            bits => Some(cleaning::SenseBits::from_str(bits).map_err(|_| Error::InvalidArguments)?),
+        };
+        self.cleaning_slot = self.get_parsed_option("cleaningslot")?;
+        self.parity = self.get_parsed_option("parity")?;
+        self.compression = self.get_parsed_option("compression")?;
+        self.cipher = self.load_cipher(initialize)?;
+        self.git_bundle = self.get_flag_option("gitbundle")?;
+        self.pool = self.get_option("pool")?;
+        self.tape_format = self.get_parsed_option("tapeformat")?.unwrap_or_default();
+        self.changer_path = self.get_parsed_option("changer")?;
+        self.changer_drive = self.get_parsed_option("changerdrive")?.unwrap_or(0);
+        self.drives = self
+            .get_option("drives")?
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect();
         self.uuid = Some(self.get_uuid()?);
-        self.git_dir = Some(self.get_git_dir()?);
+
+        let git_dir = self.get_git_dir()?;
+        self.catalog = Some(Catalog::open(Path::new(&git_dir))?);
+        self.git_dir = Some(git_dir);
 
         if let Some(exts) = self.supported_extensions {
             if exts.contains(Extension::GetGitRemoteName) && !initialize {
@@ -53,6 +125,573 @@ impl<'a> Remote<'a> {
         Ok(())
     }
 
+    /// Load the key objects are encrypted with, from `tapekeyfile` or from
+    /// the creds git-annex stores for the remote.
+    ///
+    /// Initializing a remote with `tapeencryption=yes` creates a key unless
+    /// one has been stored before.
+    fn load_cipher(&self, initialize: bool) -> Result<Option<Cipher>, Error> {
+        let key_file = self.get_option("tapekeyfile")?;
+        if !key_file.is_empty() {
+            return Ok(Some(Cipher::read(Path::new(&key_file))?));
+        }
+
+        if !self.get_flag_option("tapeencryption")? {
+            return Ok(None);
+        }
+
+        if let Some((_, key)) = self.get_creds("tapekey")? {
+            let cipher = key.parse().map_err(|_| Error::InvalidArguments)?;
+            return Ok(Some(cipher));
+        }
+
+        if !initialize {
+            return Err(Error::Unavailable(
+                "The key objects are encrypted with is missing".to_string(),
+            ));
+        }
+
+        let cipher = Cipher::generate();
+        writeln!(io::stdout(), "SETCREDS tapekey {} {cipher}", cipher.id())?;
+
+        Ok(Some(cipher))
+    }
+
+    /// Open the tape drive on first use.
+    fn drive(&mut self) -> Result<Rc<Drive>, Error> {
+        if self.drive.is_none() {
+            let Some(path) = &self.drive_path else {
+                return Err(Error::InvalidArguments);
+            };
+
+            let drive = Drive::new(path)?;
+
+            if let Some(bits) = self.cleaning_sense {
+                drive.set_cleaning_request(bits.byte, bits.mask, bits.pattern)?;
+            }
+
+            self.drive = Some(Rc::new(drive));
+        }
+
+        Ok(Rc::clone(self.drive.as_ref().unwrap()))
+    }
+
+    /// Check whether the loaded cartridge is healthy enough to receive new data.
+    ///
+    /// Returns a reason if writes should be steered away from the cartridge.
+    fn check_media_health(&mut self) -> Result<Option<String>, Error> {
+        let health = match self.drive()?.media_health() {
+            Ok(health) => health,
+            Err(e) => {
+                self.debug(format!("Failed to get cartridge health: {e}").as_str())?;
+                return Ok(None);
+            }
+        };
+
+        let score = health.score();
+        if health.status() < health::Status::Good {
+            return Ok(Some(format!(
+                "Cartridge health is {} (score {score})",
+                health.status()
+            )));
+        }
+
+        Ok(None)
+    }
+
+    /// Move on to the next cartridge of the pool while the loaded one is
+    /// degrading, marking it in the catalog so that it is not filled again.
+    ///
+    /// Returns a reason if no healthy cartridge can be loaded.
+    fn healthy_media(&mut self) -> Result<Option<String>, Error> {
+        while let Some(reason) = self.check_media_health()? {
+            // Blank cartridges have no record to mark.
+            let Ok(id) = self.media().map(|media| media.id()) else {
+                return Ok(Some(format!("{reason}, please load another cartridge")));
+            };
+
+            let mut record = self.media_record(&id)?;
+            record.degraded = Some(reason.clone());
+            self.catalog()?.save_media(&record)?;
+
+            let (remote, pool) = (self.origin().remote, self.pool.clone());
+            let spare = self.catalog()?.spare_media(&remote, &pool)?;
+            let Some(barcode) = spare.and_then(|record| record.barcode) else {
+                return Ok(Some(format!("{reason}, please load another cartridge")));
+            };
+
+            self.info(&format!("{reason}, switching to cartridge {barcode}"))?;
+            self.release()?;
+            self.acquire(Some(barcode.clone()), None)?;
+            self.load_cartridge(&barcode)?;
+        }
+
+        Ok(None)
+    }
+
+    /// Open the medium changer on first use, if one is configured.
+    fn changer(&mut self) -> Result<Option<Rc<dyn Changer>>, Error> {
+        if self.changer.is_none() {
+            let Some(path) = &self.changer_path else {
+                return Ok(None);
+            };
+
+            self.changer = Some(Rc::from(changer::open(path)?));
+        }
+
+        Ok(self.changer.clone())
+    }
+
+    /// Read the media header of the loaded cartridge on first use.
+    fn media(&mut self) -> Result<&Media, Error> {
+        if self.media.is_none() {
+            let (cipher, origin) = (self.cipher.clone(), self.origin());
+            let media = self.drive()?.load_media()?;
+            self.media = Some(media.with_cipher(cipher).with_origin(origin));
+        }
+
+        Ok(self.media.as_ref().unwrap())
+    }
+
+    /// The repository and remote recorded in the headers of new archives.
+    fn origin(&self) -> Origin {
+        Origin {
+            repository: self
+                .git_dir
+                .as_ref()
+                .and_then(|git_dir| git::annex_uuid(Path::new(git_dir)))
+                .unwrap_or_default(),
+            remote: self.uuid.map(|uuid| uuid.to_string()).unwrap_or_default(),
+            remote_name: self.git_remote_name.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Describe an object by the files which use it, the modification time
+    /// of its contents and the commit checked out.
+    fn metadata(&self, key: &str, data: &File) -> Result<Metadata, Error> {
+        let mtime = data
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs());
+
+        let Some(git_dir) = &self.git_dir else {
+            return Ok(Metadata {
+                mtime,
+                ..Metadata::default()
+            });
+        };
+
+        Ok(Metadata {
+            paths: git::annex_paths(Path::new(git_dir), key),
+            mtime,
+            commit: git::head(Path::new(git_dir)).unwrap_or_default(),
+        })
+    }
+
+    /// Terminate the archive which is currently being written, if any.
+    fn close_archive(&mut self) -> Result<(), Error> {
+        if let Some(mut archive) = self.archive.take() {
+            if self.git_bundle {
+                self.write_bundle(&mut archive)?;
+            }
+
+            archive.close()?;
+
+            if let Some(spool) = &mut self.spool {
+                spool.settle(0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The spool of this process, which is started on first use.
+    fn spool(&mut self) -> Result<&mut Spool, Error> {
+        if self.spool.is_none() {
+            let remote = self.origin().remote;
+            let dir = self.catalog()?.spool_dir(&remote);
+            self.spool = Some(Spool::create(&dir)?);
+        }
+
+        Ok(self.spool.as_mut().unwrap())
+    }
+
+    /// Remove the spooled objects whose pack has been written, once they
+    /// have left the buffer of the drive.
+    fn settle_spool(&mut self) -> Result<(), Error> {
+        let (Some(archive), Some(spool)) = (&mut self.archive, &mut self.spool) else {
+            return Ok(());
+        };
+
+        let waiting = archive.pending();
+        if spool.keys().len() > waiting {
+            archive.flush_buffer()?;
+            spool.settle(waiting)?;
+        }
+
+        Ok(())
+    }
+
+    /// Store the objects again which a process left in its spool, e.g.
+    /// because it crashed before their pack was written.
+    fn replay_spool(&mut self) -> Result<(), Error> {
+        let remote = self.origin().remote;
+        let dir = self.catalog()?.spool_dir(&remote);
+
+        for mut spool in Spool::abandoned(&dir)? {
+            for key in spool.keys().to_vec() {
+                if let Some(reason) = self.ready_to_store()? {
+                    return Err(Error::Unavailable(reason));
+                }
+
+                self.debug(&format!("Storing {key} again from the spool"))?;
+                let location = self.store(&key, &spool.path(&key).to_string_lossy())?;
+                self.set_state(&key, &location.to_string())?;
+            }
+
+            self.close_archive()?;
+            spool.settle(0)?;
+            spool.finish()?;
+        }
+
+        self.release_idle()
+    }
+
+    /// Write a bundle of the repository into `archive`, from which the
+    /// repository can be restored. Failing to create the bundle does not keep
+    /// the archive from being closed.
+    fn write_bundle(&self, archive: &mut Archive) -> Result<(), Error> {
+        let Some(git_dir) = &self.git_dir else {
+            return Ok(());
+        };
+
+        let mut bundle = match git::bundle(Path::new(git_dir)) {
+            Ok(bundle) => bundle,
+            Err(e) => return self.debug(&format!("Failed to create git bundle: {e}")),
+        };
+
+        let length = bundle.metadata()?.len();
+        archive.write_object(tape::BUNDLE_KEY, length, &mut bundle)?;
+
+        Ok(())
+    }
+
+    /// Barcode of the loaded cartridge, if the library or cartridge memory knows it.
+    fn media_barcode(&mut self) -> Result<Option<String>, Error> {
+        if let Some(changer) = self.changer()? {
+            if let Ok(element) = changer.element(Address::drive(self.changer_drive)) {
+                if element.barcode.is_some() {
+                    return Ok(element.barcode);
+                }
+            }
+        }
+
+        Ok(self
+            .drive()?
+            .media_health()
+            .ok()
+            .and_then(|health| health.barcode)
+            .filter(|barcode| !barcode.is_empty()))
+    }
+
+    /// Open the catalog, also if the remote has not been prepared yet.
+    fn catalog(&mut self) -> Result<&Catalog, Error> {
+        if self.catalog.is_none() {
+            let git_dir = self.get_git_dir()?;
+            self.catalog = Some(Catalog::open(Path::new(&git_dir))?);
+        }
+
+        Ok(self.catalog.as_ref().unwrap())
+    }
+
+    /// Look up a cartridge in the catalog.
+    fn media_record(&mut self, id: &str) -> Result<MediaRecord, Error> {
+        Ok(self.catalog()?.media(id)?)
+    }
+
+    fn placement(&mut self, id: &str) -> Result<Placement, Error> {
+        Ok(self.media_record(id)?.placement)
+    }
+
+    /// Barcode of the cartridge holding `location`, refusing offsite cartridges.
+    fn location_barcode(&mut self, location: &Location) -> Result<Option<String>, Error> {
+        let record = self.media_record(&location.media)?;
+
+        if record.placement == Placement::Offsite {
+            return Err(Error::Unavailable(format!(
+                "Cartridge {} is offsite, import it into the library first",
+                location.media
+            )));
+        }
+
+        Ok(location.barcode.clone().or(record.barcode))
+    }
+
+    /// Barcode of the cartridge which new archives are appended to.
+    fn fill_barcode(&mut self) -> Result<Option<String>, Error> {
+        let remote = self.origin().remote;
+
+        Ok(self.catalog()?.fill_media(&remote)?.and_then(|m| m.barcode))
+    }
+
+    /// Open the scheduler which assigns the drives of the library.
+    fn scheduler(&mut self) -> Result<&Scheduler, Error> {
+        if self.scheduler.is_none() {
+            let drives = if self.drives.is_empty() {
+                let path = self.drive_path.clone().ok_or(Error::InvalidArguments)?;
+
+                vec![DriveSlot {
+                    unit: self.changer_drive,
+                    path,
+                }]
+            } else {
+                self.drives
+                    .iter()
+                    .enumerate()
+                    .map(|(unit, path)| DriveSlot {
+                        unit: unit as u16,
+                        path: path.clone(),
+                    })
+                    .collect()
+            };
+
+            let dir = self.catalog()?.lock_dir();
+            self.scheduler = Some(Scheduler::new(&dir, drives)?);
+        }
+
+        Ok(self.scheduler.as_ref().unwrap())
+    }
+
+    /// Reserve a drive and the cartridge with the `wanted` barcode so that
+    /// concurrent remote processes never request it in another drive.
+    ///
+    /// Drives holding the `avoid` cartridge are only used if no other drive is free.
+    fn acquire(&mut self, wanted: Option<String>, avoid: Option<String>) -> Result<(), Error> {
+        if let Some(lease) = &self.lease {
+            if wanted.is_none() || lease.media == wanted {
+                return Ok(());
+            }
+        }
+
+        self.release()?;
+
+        let changer = self.changer()?;
+        let avoid = avoid.filter(|a| Some(a) != wanted.as_ref());
+        let lease = self.scheduler()?.acquire(
+            changer.as_deref(),
+            wanted.as_deref(),
+            avoid.as_deref(),
+            scheduler::DEFAULT_TIMEOUT,
+        )?;
+
+        self.drive_path = Some(lease.drive.path.clone());
+        self.changer_drive = lease.drive.unit;
+        self.lease = Some(lease);
+
+        Ok(())
+    }
+
+    /// Give up the drive unless an archive is still being written to it.
+    fn release_idle(&mut self) -> Result<(), Error> {
+        if self.archive.is_none() {
+            self.release()?;
+        }
+
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<(), Error> {
+        self.close_archive()?;
+        self.close_volume()?;
+        self.media = None;
+        self.drive = None;
+        self.lease = None;
+
+        Ok(())
+    }
+
+    /// Make sure the cartridge holding `location` is loaded, moving it from
+    /// its library slot into the drive if necessary.
+    fn mount(&mut self, location: &Location) -> Result<(), Error> {
+        match self.media() {
+            Ok(media) if media.contains(location) => return Ok(()),
+            Ok(_) | Err(Error::Media(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let Some(barcode) = self.location_barcode(location)? else {
+            return Err(Error::Unavailable(
+                "Cartridge is not loaded and its barcode is unknown".to_string(),
+            ));
+        };
+
+        self.load_cartridge(&barcode)?;
+
+        if !self.media()?.contains(location) {
+            self.media = None;
+
+            return Err(Error::Unavailable(format!(
+                "Cartridge {barcode} does not hold the expected media header"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Move the cartridge with `barcode` into the drive.
+    fn load_cartridge(&mut self, barcode: &str) -> Result<(), Error> {
+        let Some(changer) = self.changer()? else {
+            return Err(Error::Unavailable(format!(
+                "Cartridge {barcode} is not loaded and no changer is configured"
+            )));
+        };
+
+        let element = match changer.find_barcode(barcode) {
+            Ok(element) => element,
+            Err(changer::Error::BarcodeNotFound(_)) => {
+                return Err(Error::Unavailable(format!(
+                    "Cartridge {barcode} is not in the library"
+                )))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let drive_address = Address::drive(self.changer_drive);
+
+        if element.address.kind == ElementType::DataTransfer && element.address != drive_address {
+            return Err(Error::Unavailable(format!(
+                "Cartridge {barcode} is in use in {}",
+                element.address
+            )));
+        }
+
+        self.close_archive()?;
+        self.media = None;
+
+        let drive = self.drive()?;
+
+        if element.address != drive_address {
+            self.info(format!("Loading cartridge {barcode} from {}", element.address).as_str())?;
+
+            if changer.element(drive_address)?.full {
+                drive.eject()?;
+                changer.unload(self.changer_drive, None)?;
+            }
+
+            changer.load(element.address, self.changer_drive)?;
+        }
+
+        drive.wait_ready(tape::LOAD_TIMEOUT)?;
+
+        Ok(())
+    }
+
+    /// Load the cartridge which is being filled before starting a new archive.
+    fn load_fill(&mut self) -> Result<(), Error> {
+        let Some(barcode) = self.lease.as_ref().and_then(|l| l.media.clone()) else {
+            return Ok(());
+        };
+
+        if self.media_barcode()?.as_deref() == Some(barcode.as_str()) {
+            return Ok(());
+        }
+
+        match self.load_cartridge(&barcode) {
+            Err(Error::Unavailable(reason)) => self.debug(&reason),
+            result => result,
+        }
+    }
+
+    /// Identify the drive in the catalog by its serial number.
+    fn drive_serial(&mut self) -> Result<String, Error> {
+        if let Ok(serial) = self.drive()?.serial_number() {
+            if !serial.is_empty() {
+                return Ok(serial);
+            }
+        }
+
+        // Fall back to the device name if the drive does not report a serial.
+        let path = self.drive_path.as_ref().ok_or(Error::InvalidArguments)?;
+
+        Ok(path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default())
+    }
+
+    /// Check whether the drive requests cleaning and record the request.
+    ///
+    /// Returns a reason if writes should be refused until the drive is cleaned.
+    fn check_cleaning(&mut self) -> Result<Option<String>, Error> {
+        let reason = match self.drive()?.cleaning_required() {
+            Ok(Some(reason)) => reason,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.debug(format!("Failed to check for cleaning request: {e}").as_str())?;
+                return Ok(None);
+            }
+        };
+
+        let serial = self.drive_serial()?;
+        let catalog = self.catalog()?;
+        let mut record = catalog.drive(&serial)?;
+        if record.request_cleaning(reason.clone()) {
+            catalog.save_drive(&record)?;
+        }
+
+        let msg = format!("Drive {serial} requests cleaning ({reason})");
+
+        if let Some(slot) = self.cleaning_slot {
+            self.info(format!("{msg}, loading cleaning cartridge from slot {slot}").as_str())?;
+
+            let kind = match self.clean(slot) {
+                Ok(()) => cleaning::EventKind::Performed { slot: Some(slot) },
+                Err(e) => cleaning::EventKind::Failed(e.to_string()),
+            };
+
+            let cleaned = matches!(kind, cleaning::EventKind::Performed { .. });
+
+            let catalog = self.catalog()?;
+            let mut record = catalog.drive(&serial)?;
+            record.record_cleaning(kind);
+            catalog.save_drive(&record)?;
+
+            if cleaned {
+                return Ok(None);
+            }
+        }
+
+        match self.cleaning_policy {
+            cleaning::Policy::Refuse => Ok(Some(msg)),
+            cleaning::Policy::Warn => {
+                self.info(&msg)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Clean the drive with the cleaning cartridge in the given changer slot.
+    fn clean(&mut self, slot: u16) -> Result<(), Error> {
+        self.drive()?;
+        self.changer()?;
+
+        self.close_archive()?;
+        self.media = None;
+
+        let (Some(drive), Some(changer)) = (&self.drive, &self.changer) else {
+            return Err(Error::InvalidArguments);
+        };
+
+        Ok(cleaning::clean(
+            changer.as_ref(),
+            drive,
+            self.changer_drive,
+            slot,
+        )?)
+    }
+
     fn init(&mut self) -> Result<(), Error> {
         self.fetch(true)?;
 
@@ -64,6 +703,13 @@ impl<'a> Remote<'a> {
     fn prepare(&mut self) -> Result<(), Error> {
         self.fetch(false)?;
 
+        // git-annex counts on the objects acknowledged before a crash.
+        if let Err(e) = self.replay_spool() {
+            writeln!(io::stdout(), "PREPARE-FAILURE {e}")?;
+
+            return Ok(());
+        }
+
         self.prepared = true;
 
         writeln!(io::stdout(), "PREPARE-SUCCESS")?;
@@ -72,34 +718,266 @@ impl<'a> Remote<'a> {
     }
 
     fn extensions(&mut self, arg: Option<&str>) -> Result<(), Error> {
-        match arg {
-            Some(extensions) => {
-                self.supported_extensions = Some(Extension::from_strs(extensions)?);
-            }
-            None => {}
+        if let Some(extensions) = arg {
+            self.supported_extensions = Some(Extension::from_strs(extensions)?);
         }
 
         writeln!(io::stdout(), "EXTENSIONS INFO")?;
         Ok(())
     }
 
-    fn transfer_store(&self, key: &str, file: &str) -> Result<(), Error> {
-        self.set_state(key, format!("block={} offset={}", 1234, 999).as_str())?;
+    /// Load the cartridge being filled into a drive and check that both are
+    /// fit for writing.
+    ///
+    /// Returns a reason if the store has to be refused.
+    fn ready_to_store(&mut self) -> Result<Option<String>, Error> {
+        if self.archive.is_none() {
+            let fill = self.fill_barcode()?;
+            self.acquire(fill, None)?;
+            self.load_fill()?;
+        }
+
+        if let Some(reason) = self.check_cleaning()? {
+            return Ok(Some(reason));
+        }
+
+        self.healthy_media()
+    }
+
+    fn transfer_store(&mut self, key: &str, file: &str) -> Result<(), Error> {
+        match self.ready_to_store() {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                writeln!(io::stdout(), "TRANSFER-FAILURE STORE {key} {reason}")?;
+
+                return Ok(());
+            }
+            Err(e) => {
+                writeln!(io::stdout(), "TRANSFER-FAILURE STORE {key} {e}")?;
+
+                return Ok(());
+            }
+        }
+
+        match self.store(key, file) {
+            Ok(location) => {
+                self.set_state(key, location.to_string().as_str())?;
+
+                writeln!(io::stdout(), "TRANSFER-SUCCESS STORE {key}")?;
+            }
+            Err(e) => {
+                // Do not append to an archive which might end in a partial object,
+                // but keep the small objects which are waiting to be packed.
+                let flushed = self.archive.take().map_or(Ok(()), |mut a| a.flush());
+
+                writeln!(io::stdout(), "TRANSFER-FAILURE STORE {key} {e}")?;
+                flushed?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store(&mut self, key: &str, file: &str) -> Result<Location, Error> {
+        let mut data = File::open(file)?;
+        let length = data.metadata()?.len();
+
+        let barcode = self.media_barcode()?;
+        self.open_archive(barcode.clone(), true)?;
+        let media = self.media()?.id();
+
+        let metadata = self.metadata(key, &data)?;
+        let archive = self.archive.as_mut().unwrap();
+        let block = archive.write_object_with_metadata(key, length, &mut data, &metadata)?;
+
+        // git-annex may drop its copy once the store is acknowledged. Small
+        // objects are kept in the spool until their pack has been written,
+        // nothing else may be left in the buffer of the drive.
+        if archive.pending() > 0 {
+            data.seek(SeekFrom::Start(0))?;
+            self.spool()?.add(key, &mut data)?;
+        } else {
+            archive.sync()?;
+        }
+        self.settle_spool()?;
+
+        Ok(Location {
+            media,
+            block,
+            barcode,
+        })
+    }
+
+    /// Start a new archive on the loaded cartridge, unless one is open. The
+    /// cartridge becomes the one being filled if `filling`.
+    fn open_archive(&mut self, barcode: Option<String>, filling: bool) -> Result<(), Error> {
+        if self.archive.is_some() {
+            return Ok(());
+        }
+
+        self.init_blank(barcode.clone())?;
+        self.record_media(barcode, filling)?;
+
+        let (parity, compression) = (self.parity, self.compression);
+        let archive = self
+            .media()?
+            .append_archive(catalog::now(), &tape::hostname(), parity)?
+            .with_compression(compression);
+        self.archive = Some(archive);
+
+        Ok(())
+    }
+
+    /// Initialize the loaded cartridge for this remote if it is blank, labelled
+    /// with its barcode, added to the configured pool and in the configured
+    /// format.
+    fn init_blank(&mut self, barcode: Option<String>) -> Result<(), Error> {
+        match self.media() {
+            Err(Error::Media(tape::Error::Blank)) => {}
+            Err(Error::Media(tape::Error::InvalidHeader(_))) => {
+                return Err(Error::Unavailable(
+                    "The loaded cartridge has not been initialized for git-annex".to_string(),
+                ))
+            }
+            result => return result.map(|_| ()),
+        }
+
+        let identity = MediaIdentity {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            label: barcode.unwrap_or_default(),
+            pool: self.pool.clone(),
+            remote: self.origin().remote,
+        };
+
+        self.info(&format!("Initializing blank cartridge {}", identity.uuid))?;
+        let header = MediaHeader::new(catalog::now(), &tape::hostname())
+            .with_identity(identity)
+            .with_format(self.tape_format);
+        self.drive()?.write_media_header(&header)?;
+
+        Ok(())
+    }
+
+    /// Remember the loaded cartridge and its barcode in the catalog and, if
+    /// `filling`, mark it as the cartridge being filled.
+    fn record_media(&mut self, barcode: Option<String>, filling: bool) -> Result<(), Error> {
+        let (id, created, host, identity) = {
+            let media = self.media()?;
+            (
+                media.id(),
+                media.creation_time(),
+                media.host().to_string(),
+                media.identity().clone(),
+            )
+        };
+
+        let placement = match self.changer_path {
+            Some(_) => Placement::Library(Address::drive(self.changer_drive)),
+            None => Placement::Unknown,
+        };
+
+        let Some(catalog) = &self.catalog else {
+            return Ok(());
+        };
+
+        let mut record = catalog.media(&id)?;
 
-        writeln!(io::stdout(), "TRANSFER-SUCCESS STORE {key}")?;
+        record.created = created;
+        record.host = host;
+        record.identity = identity;
+        record.placement = placement;
+        if filling {
+            record.last_written = Some(catalog::now());
+        }
+        if barcode.is_some() {
+            record.barcode = barcode;
+        }
+
+        catalog.save_media(&record)?;
 
         Ok(())
     }
 
-    fn transfer_retrieve(&self, key: &str, file: &str) -> Result<(), Error> {
-        let error = "Data not available yet";
+    fn transfer_retrieve(&mut self, key: &str, file: &str) -> Result<(), Error> {
+        let result = self.retrieve(key, file);
+        self.release_idle()?;
+
+        match result {
+            Ok(()) => writeln!(io::stdout(), "TRANSFER-SUCCESS RETRIEVE {key}")?,
+            Err(e) => writeln!(io::stdout(), "TRANSFER-FAILURE RETRIEVE {key} {e}")?,
+        }
+
+        Ok(())
+    }
+
+    fn retrieve(&mut self, key: &str, file: &str) -> Result<(), Error> {
+        let location = self.location(key)?.ok_or(Error::NotStored)?;
+
+        // Serve retrievals from a drive other than the one being filled.
+        let barcode = self.location_barcode(&location)?;
+        let fill = self.fill_barcode()?;
+        self.acquire(barcode, fill)?;
+
+        // Reading moves the tape away from the end of the open archive.
+        self.close_archive()?;
+        self.mount(&location)?;
+
+        let mut out = File::create(file)?;
+        if let Err(e) = self.media()?.read_object(location.block, key, &mut out) {
+            // Never leave unverified data behind for git-annex.
+            drop(out);
+            let _ = fs::remove_file(file);
+
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Look up where a key has been stored.
+    ///
+    /// Keys without state, e.g. in a repository restored from a bundle, are
+    /// looked up among the objects found by scanning cartridges.
+    fn location(&mut self, key: &str) -> Result<Option<Location>, Error> {
+        let state = self.get_state(key)?;
+        if !state.is_empty() {
+            let mut location = Location::from_str(&state).map_err(|_| Error::InvalidArguments)?;
+            self.migrate_location(key, &mut location)?;
+
+            return Ok(Some(location));
+        }
+
+        let found = self.catalog()?.find_object(key)?;
+
+        Ok(found.map(|(media, object)| Location {
+            media,
+            block: object.block,
+            barcode: None,
+        }))
+    }
+
+    /// Rewrite a location which refers to a cartridge with a UUID by its
+    /// creation time, as recorded before cartridges were identified by their
+    /// UUID.
+    fn migrate_location(&mut self, key: &str, location: &mut Location) -> Result<(), Error> {
+        let catalog = self.catalog()?;
+        if catalog.has_media(&location.media) {
+            return Ok(());
+        }
+
+        let Ok(created) = location.media.parse() else {
+            return Ok(());
+        };
 
-        writeln!(io::stdout(), "TRANSFER-FAILURE RETRIEVE {} {}", key, error)?;
+        if let Some(record) = catalog.media_by_creation_time(created)? {
+            location.media = record.id;
+            self.set_state(key, &location.to_string())?;
+        }
 
         Ok(())
     }
 
-    fn transfer(&self, rest: &str) -> Result<(), Error> {
+    fn transfer(&mut self, rest: &str) -> Result<(), Error> {
         let parts: Vec<&str> = rest.splitn(3, " ").collect();
         let [direction, key, file] = parts[..] else {
             return Err(Error::InvalidArguments);
@@ -112,27 +990,140 @@ impl<'a> Remote<'a> {
         }
     }
 
-    fn check_present(&self, key: &str) -> Result<(), Error> {
-        let state = self.get_state(key)?;
+    fn check_present(&mut self, key: &str) -> Result<(), Error> {
+        let Some(location) = self.location(key)? else {
+            writeln!(io::stdout(), "CHECKPRESENT-FAILURE {key}")?;
 
-        writeln!(
-            io::stdout(),
-            "CHECKPRESENT-{} {key}",
-            match state.as_str() {
-                "" => "FAILURE",
-                _ => "SUCCESS",
+            return Ok(());
+        };
+
+        // Without a changer we cannot reach other cartridges, so trust the state.
+        if self.changer_path.is_none() {
+            writeln!(io::stdout(), "CHECKPRESENT-SUCCESS {key}")?;
+
+            return Ok(());
+        }
+
+        // Keys on offsite cartridges are still present, they just cannot be
+        // retrieved until the cartridge has been imported again.
+        if self.placement(&location.media)? == Placement::Offsite {
+            self.debug(format!("{key} is on offsite cartridge {}", location.media).as_str())?;
+            writeln!(io::stdout(), "CHECKPRESENT-SUCCESS {key}")?;
+
+            return Ok(());
+        }
+
+        let result = self
+            .location_barcode(&location)
+            .and_then(|barcode| {
+                let fill = self.fill_barcode()?;
+                self.acquire(barcode, fill)
+            })
+            .and_then(|_| self.close_archive())
+            .and_then(|_| self.mount(&location))
+            .and_then(|_| Ok(self.media()?.check_object(location.block, key)?));
+
+        self.release_idle()?;
+
+        match result {
+            Ok(true) => writeln!(io::stdout(), "CHECKPRESENT-SUCCESS {key}")?,
+            Ok(false) => writeln!(io::stdout(), "CHECKPRESENT-FAILURE {key}")?,
+            Err(e) => writeln!(io::stdout(), "CHECKPRESENT-UNKNOWN {key} {e}")?,
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Error> {
+        // Keys which have never been stored are removed already.
+        if self.location(key)?.is_none() {
+            writeln!(io::stdout(), "REMOVE-SUCCESS {key}")?;
+
+            return Ok(());
+        }
+
+        match self.remove_copies(key) {
+            Ok(()) => {
+                self.set_state(key, "")?;
+
+                writeln!(io::stdout(), "REMOVE-SUCCESS {key}")?;
             }
-        )?;
+            Err(e) => {
+                let flushed = self.archive.take().map_or(Ok(()), |mut a| a.flush());
+
+                writeln!(io::stdout(), "REMOVE-FAILURE {key} {e}")?;
+                flushed?;
+            }
+        }
+
+        self.release_idle()
+    }
+
+    /// Write a tombstone for `key` to every cartridge holding a copy of it:
+    /// the one recorded in its state and those found by scans.
+    fn remove_copies(&mut self, key: &str) -> Result<(), Error> {
+        let mut removed = Vec::new();
+        let mut next = self.location(key)?;
+
+        while let Some(location) = next {
+            // A tombstone which did not remove the copy would be written again
+            // and again.
+            if removed.contains(&location.media) {
+                return Err(Error::Unavailable(format!(
+                    "{key} is still found on cartridge {}",
+                    location.media
+                )));
+            }
+
+            self.write_tombstone(key, &location)?;
+            removed.push(location.media);
+
+            next = self
+                .catalog()?
+                .find_object(key)?
+                .map(|(media, object)| Location {
+                    media,
+                    block: object.block,
+                    barcode: None,
+                });
+        }
 
         Ok(())
     }
 
-    fn remove(&self, key: &str) -> Result<(), Error> {
-        writeln!(
-            io::stdout(),
-            "REMOVE-FAILURE {} Droping keys from tapes is currently not supported",
-            key
-        )?;
+    /// Append a tombstone for `key` to the cartridge holding `location` and
+    /// record it in the catalog, so that the copy is no longer considered
+    /// present. Its space is only reclaimed by compaction.
+    ///
+    /// Tombstones only remove copies on their own cartridge, whose blocks
+    /// tell which was written first.
+    fn write_tombstone(&mut self, key: &str, location: &Location) -> Result<(), Error> {
+        let open = self.archive.is_some() && self.media()?.contains(location);
+
+        if !open {
+            let barcode = self.location_barcode(location)?;
+            self.acquire(barcode, None)?;
+            self.close_archive()?;
+            self.mount(location)?;
+
+            let barcode = self.media_barcode()?;
+            self.open_archive(barcode, false)?;
+        }
+
+        let archive = self.archive.as_mut().unwrap();
+        let block = archive.write_tombstone(key)?;
+        let tombstone = TombstoneEntry {
+            key: key.to_string(),
+            archive: archive.creation_time(),
+            block,
+        };
+
+        self.catalog()?.add_tombstone(&location.media, tombstone)?;
+
+        // Stores go on to the cartridge being filled.
+        if !open {
+            self.close_archive()?;
+        }
 
         Ok(())
     }
@@ -143,13 +1134,298 @@ impl<'a> Remote<'a> {
             "CONFIG drive Path of the SCSI tape drive (e.g. /dev/st0)"
         )?;
 
+        writeln!(
+            io::stdout(),
+            "CONFIG cleaning Refuse writes while the drive requests cleaning (refuse or warn)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG cleaningsense Sense data bits signalling a cleaning request (byte:mask[:pattern])"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG changer Path of the SCSI medium changer (e.g. /dev/sch0)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG changerdrive Number of the drive within the changer (default 0)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG drives Comma separated paths of all drives of the changer, in changer order"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG cleaningslot Changer slot holding the cleaning cartridge"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG parity Data and parity records per stripe of new archives (e.g. 10+2)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG compression Compress objects with zstd, optionally at a given level (e.g. zstd:9)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG tapeencryption Encrypt objects with a key stored in the creds of the remote (yes or no)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG tapekeyfile File holding the key objects are encrypted with, instead of the creds"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG gitbundle Write a git bundle of the repository into each archive (yes or no)"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG pool Pool recorded on blank cartridges initialized by the remote"
+        )?;
+
+        writeln!(
+            io::stdout(),
+            "CONFIG tapeformat Format of archives on blank cartridges initialized by the remote (native or tar)"
+        )?;
+
         writeln!(io::stdout(), "CONFIGEND")?;
 
         Ok(())
     }
 
     fn export_supported(&self) -> Result<(), Error> {
-        writeln!(io::stdout(), "EXPORTSUPPORTED-FAILURE")?;
+        writeln!(io::stdout(), "EXPORTSUPPORTED-SUCCESS")?;
+
+        Ok(())
+    }
+
+    /// Open the LTFS volume on the loaded cartridge on first use, formatting
+    /// the cartridge if it is blank.
+    fn volume(&mut self) -> Result<&mut ltfs::Volume, Error> {
+        if self.volume.is_none() {
+            self.acquire(None, None)?;
+
+            let remote = self.origin().remote;
+            let journal = self.catalog()?.journal(&remote)?;
+            let volume = match self.drive()?.ltfs(&journal) {
+                Err(ltfs::Error::Blank) => {
+                    let barcode = self.media_barcode()?.unwrap_or_default();
+                    let label = ltfs::Label::new(&uuid::Uuid::new_v4().to_string(), catalog::now());
+
+                    self.info(&format!("Formatting blank cartridge {}", label.volume_uuid))?;
+                    self.drive()?.format_ltfs(ltfs::Vol1::new(&barcode), label)
+                }
+                Err(ltfs::Error::NotLtfs) => {
+                    return Err(Error::Unavailable(
+                        "The loaded cartridge is not an LTFS volume".to_string(),
+                    ))
+                }
+                result => result,
+            };
+            self.volume = Some(volume?);
+            self.volume_synced = catalog::now();
+        }
+
+        Ok(self.volume.as_mut().unwrap())
+    }
+
+    /// Keep the changes of the exported tree in the journal of the catalog,
+    /// so that they can be acknowledged, and write the index once the last
+    /// one is `INDEX_INTERVAL` old.
+    fn journal_volume(&mut self) -> Result<(), Error> {
+        let entries = self.volume()?.checkpoint()?;
+        let remote = self.origin().remote;
+        self.catalog()?.append_journal(&remote, &entries)?;
+
+        if catalog::now().saturating_sub(self.volume_synced) >= INDEX_INTERVAL {
+            self.sync_volume()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the index of the exported tree to the cartridge, and drop the
+    /// changes it holds from the journal.
+    fn sync_volume(&mut self) -> Result<(), Error> {
+        let Some(volume) = self.volume.as_mut() else {
+            return Ok(());
+        };
+
+        volume.sync(catalog::now())?;
+        let volume_uuid = volume.label().volume_uuid.clone();
+        let remote = self.origin().remote;
+        self.catalog()?.clear_journal(&remote, &volume_uuid)?;
+        self.volume_synced = catalog::now();
+
+        Ok(())
+    }
+
+    fn close_volume(&mut self) -> Result<(), Error> {
+        self.sync_volume()?;
+        self.volume = None;
+
+        Ok(())
+    }
+
+    fn export(&mut self, name: &str) -> Result<(), Error> {
+        self.export_name = Some(name.to_string());
+
+        Ok(())
+    }
+
+    fn transfer_export(&mut self, rest: &str) -> Result<(), Error> {
+        let parts: Vec<&str> = rest.splitn(3, " ").collect();
+        let [direction, key, file] = parts[..] else {
+            return Err(Error::InvalidArguments);
+        };
+        let name = self.export_name.take().ok_or(Error::InvalidArguments)?;
+
+        let result = match direction {
+            "STORE" => self.store_export(key, file, &name),
+            "RETRIEVE" => self.retrieve_export(key, file, &name),
+            _ => return Err(Error::InvalidDirection),
+        };
+
+        match result {
+            Ok(()) => writeln!(io::stdout(), "TRANSFER-SUCCESS {direction} {key}")?,
+            Err(e) => writeln!(io::stdout(), "TRANSFER-FAILURE {direction} {key} {e}")?,
+        }
+
+        Ok(())
+    }
+
+    /// Append a file to the volume under its path in the exported tree.
+    ///
+    /// Like every change of the exported tree, it is acknowledged only once
+    /// it is in the journal, as git-annex forgets about it afterwards.
+    fn store_export(&mut self, key: &str, file: &str, name: &str) -> Result<(), Error> {
+        self.volume()?;
+
+        if let Some(reason) = self.check_cleaning()? {
+            return Err(Error::Unavailable(reason));
+        }
+
+        if let Some(reason) = self.check_media_health()? {
+            return Err(Error::Unavailable(reason));
+        }
+
+        let mut data = File::open(file)?;
+        let metadata = data.metadata()?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+
+        let attributes = vec![(KEY_ATTRIBUTE.to_string(), key.to_string())];
+        let volume = self.volume()?;
+        volume.write_file(name, metadata.len(), &mut data, mtime, attributes)?;
+
+        self.journal_volume()
+    }
+
+    fn retrieve_export(&mut self, key: &str, file: &str, name: &str) -> Result<(), Error> {
+        let volume = self.volume()?;
+        let Some(exported) = volume.find(name) else {
+            return Err(Error::NotStored);
+        };
+
+        // Files written by another LTFS implementation have no key, and are
+        // not present as far as CHECKPRESENTEXPORT is concerned either.
+        if exported.attribute(KEY_ATTRIBUTE) != Some(key) {
+            return Err(Error::NotStored);
+        }
+
+        let mut out = File::create(file)?;
+        if let Err(e) = volume.read_file(exported, &mut out) {
+            drop(out);
+            let _ = fs::remove_file(file);
+
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    fn check_present_export(&mut self, key: &str) -> Result<(), Error> {
+        let name = self.export_name.take().ok_or(Error::InvalidArguments)?;
+
+        let present = self.volume().map(|volume| {
+            volume
+                .find(&name)
+                .and_then(|file| file.attribute(KEY_ATTRIBUTE))
+                == Some(key)
+        });
+
+        match present {
+            Ok(true) => writeln!(io::stdout(), "CHECKPRESENT-SUCCESS {key}")?,
+            Ok(false) => writeln!(io::stdout(), "CHECKPRESENT-FAILURE {key}")?,
+            Err(e) => writeln!(io::stdout(), "CHECKPRESENT-UNKNOWN {key} {e}")?,
+        }
+
+        Ok(())
+    }
+
+    /// Drop a file from the exported tree. Its contents stay on tape.
+    fn remove_export(&mut self, key: &str) -> Result<(), Error> {
+        let name = self.export_name.take().ok_or(Error::InvalidArguments)?;
+
+        let removed = self
+            .volume()
+            .map(|volume| volume.remove_file(&name))
+            .and_then(|_| self.journal_volume());
+
+        match removed {
+            Ok(()) => writeln!(io::stdout(), "REMOVE-SUCCESS {key}")?,
+            Err(e) => writeln!(io::stdout(), "REMOVE-FAILURE {key} {e}")?,
+        }
+
+        Ok(())
+    }
+
+    fn remove_export_directory(&mut self, directory: &str) -> Result<(), Error> {
+        let removed = self
+            .volume()
+            .map(|volume| volume.remove_directory(directory))
+            .and_then(|_| self.journal_volume());
+
+        match removed {
+            Ok(()) => writeln!(io::stdout(), "REMOVEEXPORTDIRECTORY-SUCCESS")?,
+            Err(_) => writeln!(io::stdout(), "REMOVEEXPORTDIRECTORY-FAILURE")?,
+        }
+
+        Ok(())
+    }
+
+    fn rename_export(&mut self, rest: &str) -> Result<(), Error> {
+        let Some((key, new_name)) = rest.split_once(" ") else {
+            return Err(Error::InvalidArguments);
+        };
+        let name = self.export_name.take().ok_or(Error::InvalidArguments)?;
+
+        let renamed = self
+            .volume()
+            .and_then(|volume| Ok(volume.rename_file(&name, new_name)?))
+            .and_then(|renamed| {
+                self.journal_volume()?;
+                Ok(renamed)
+            });
+
+        match renamed {
+            Ok(true) => writeln!(io::stdout(), "RENAMEEXPORT-SUCCESS {key}")?,
+            Ok(false) | Err(_) => writeln!(io::stdout(), "RENAMEEXPORT-FAILURE {key}")?,
+        }
 
         Ok(())
     }
@@ -166,20 +1442,42 @@ impl<'a> Remote<'a> {
         Ok(())
     }
 
-    fn get_availability(&self) -> Result<(), Error> {
-        writeln!(io::stdout(), "AVAILABILITY UNAVAILABLE")?;
+    fn get_availability(&mut self) -> Result<(), Error> {
+        // The remote is unreachable once all of its cartridges have been
+        // rotated offsite.
+        let media = self.catalog()?.all_media()?;
+        let offsite = !media.is_empty() && media.iter().all(|m| m.placement == Placement::Offsite);
+
+        writeln!(
+            io::stdout(),
+            "AVAILABILITY {}",
+            if offsite { "UNAVAILABLE" } else { "LOCAL" }
+        )?;
 
         Ok(())
     }
 
-    fn get_info(&self) -> Result<(), Error> {
+    fn get_info(&mut self) -> Result<(), Error> {
         let mut infos = HashMap::<&'static str, String>::new();
 
-        match &self.drive_path {
-            Some(path) => {
-                infos.insert("drive", path.display().to_string());
+        if let Some(path) = &self.drive_path {
+            infos.insert("drive", path.display().to_string());
+        }
+
+        if let Ok(drive) = self.drive() {
+            if let Ok(health) = drive.health() {
+                infos.insert(
+                    "drive health",
+                    format!("{} ({})", health.score(), health.status()),
+                );
+            }
+
+            if let Ok(health) = drive.media_health() {
+                infos.insert(
+                    "cartridge health",
+                    format!("{} ({})", health.score(), health.status()),
+                );
             }
-            None => (),
         }
 
         for (key, value) in infos {
@@ -239,6 +1537,42 @@ impl<'a> Remote<'a> {
         self.read_value()
     }
 
+    /// Get an optional config option and parse it.
+    fn get_parsed_option<T: FromStr>(&self, option: &str) -> Result<Option<T>, Error> {
+        match self.get_option(option)?.as_str() {
+            "" => Ok(None),
+            value => Ok(Some(
+                T::from_str(value).map_err(|_| Error::InvalidArguments)?,
+            )),
+        }
+    }
+
+    /// Get an option which is either `yes` or `no`, the default.
+    fn get_flag_option(&self, option: &str) -> Result<bool, Error> {
+        match self.get_option(option)?.as_str() {
+            "" | "no" => Ok(false),
+            "yes" => Ok(true),
+            _ => Err(Error::InvalidArguments),
+        }
+    }
+
+    /// Get the user and password stored for `setting`, if any.
+    fn get_creds(&self, setting: &str) -> Result<Option<(String, String)>, Error> {
+        writeln!(io::stdout(), "GETCREDS {setting}")?;
+
+        let line = self.read_line()?;
+        let Some(("CREDS", creds)) = line.split_once(" ") else {
+            return Err(Error::InvalidCommand);
+        };
+
+        match creds.split_once(" ") {
+            Some((user, password)) if !password.is_empty() => {
+                Ok(Some((user.to_string(), password.to_string())))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn get_uuid(&self) -> Result<uuid::Uuid, Error> {
         let uuid_str = self.get_value("GETUUID")?;
 
@@ -291,6 +1625,12 @@ impl<'a> Remote<'a> {
                 Remove => self.remove(args.unwrap()),
                 ListConfigs => self.list_configs(),
                 ExportSupported => self.export_supported(),
+                Export => self.export(args.unwrap()),
+                TransferExport => self.transfer_export(args.unwrap()),
+                CheckPresentExport => self.check_present_export(args.unwrap()),
+                RemoveExport => self.remove_export(args.unwrap()),
+                RemoveExportDirectory => self.remove_export_directory(args.unwrap()),
+                RenameExport => self.rename_export(args.unwrap()),
                 GetCost => self.get_cost(),
                 GetOrdered => self.get_ordered(),
                 GetAvailability => self.get_availability(),
@@ -314,18 +1654,34 @@ impl<'a> Remote<'a> {
     }
 
     pub fn run(&mut self) {
-        writeln!(io::stdout(), "VERSION 2").unwrap();
+        println!("VERSION 2");
 
         loop {
             match self.read_line() {
-                Ok(line) => match self.process_line(line.as_str()) {
-                    Err(e) => {
+                Ok(line) => {
+                    if let Err(e) = self.process_line(line.as_str()) {
                         self.error(format!("Failed to process line: {e:?}").as_str())
                             .unwrap();
                     }
-                    Ok(_) => {}
-                },
-                Err(Error::EndOfFile) => break,
+                }
+                Err(Error::EndOfFile) => {
+                    if let Err(e) = self.close_archive() {
+                        self.error(format!("Failed to close archive: {e}").as_str())
+                            .unwrap();
+                    }
+
+                    if let Some(Err(e)) = self.spool.take().map(Spool::finish) {
+                        self.error(format!("Failed to end spool: {e}").as_str())
+                            .unwrap();
+                    }
+
+                    if let Err(e) = self.close_volume() {
+                        self.error(format!("Failed to write LTFS index: {e}").as_str())
+                            .unwrap();
+                    }
+
+                    break;
+                }
                 Err(e) => {
                     self.error(format!("Failed to read line: {e:?}").as_str())
                         .unwrap();
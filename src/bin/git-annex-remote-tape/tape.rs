@@ -1,9 +1,405 @@
+use anyhow::{bail, Result};
+use git_annex_remote_tape::catalog::{self, Catalog};
+use git_annex_remote_tape::crypto::Cipher;
+use git_annex_remote_tape::format::{ArchiveFormat, MediaHeader, MediaIdentity, Metadata, Origin};
+use git_annex_remote_tape::health::{DriveHealth, MediaHealth};
+use git_annex_remote_tape::tape::{self, ArchiveEntry, Drive, SalvageSink};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
 use crate::cli::TapeCommand;
+use crate::git;
 
-pub fn run(command: TapeCommand) {
+/// Run a tape subcommand, showing information about the cartridge without one.
+pub fn run(drive: &Path, command: Option<TapeCommand>) -> Result<()> {
     match command {
-        TapeCommand::Init {} => unimplemented!(),
-        TapeCommand::Erase { secure } => unimplemented!(),
-        TapeCommand::Info {} => unimplemented!(),
+        Some(TapeCommand::Init {
+            label,
+            pool,
+            remote,
+            format,
+        }) => init(&Drive::new(drive)?, label, pool, remote, format),
+        Some(TapeCommand::Erase { secure }) => Ok(Drive::new(drive)?.erase(secure)?),
+        Some(TapeCommand::Info {}) | None => info(&Drive::new(drive)?),
+        Some(TapeCommand::Scan { key_file }) => scan(Drive::new(drive)?, read_key(key_file)?),
+        Some(TapeCommand::Salvage { dir, key_file }) => {
+            salvage(Drive::new(drive)?, &dir, read_key(key_file)?)
+        }
+        Some(TapeCommand::Bundle { key_file }) => bundle(Drive::new(drive)?, read_key(key_file)?),
+        Some(TapeCommand::Restore { dir, key_file }) => {
+            restore(Drive::new(drive)?, &dir, read_key(key_file)?)
+        }
+        Some(TapeCommand::Keygen { file }) => keygen(&file),
+    }
+}
+
+fn init(
+    drive: &Drive,
+    label: String,
+    pool: String,
+    remote: String,
+    format: ArchiveFormat,
+) -> Result<()> {
+    let identity = MediaIdentity {
+        uuid: uuid::Uuid::new_v4().to_string(),
+        label,
+        pool,
+        remote,
+    };
+
+    println!("Cartridge: {}", identity.uuid);
+    let header = MediaHeader::new(catalog::now(), &tape::hostname())
+        .with_identity(identity)
+        .with_format(format);
+    drive.write_media_header(&header)?;
+
+    Ok(())
+}
+
+fn read_key(path: Option<PathBuf>) -> Result<Option<Cipher>> {
+    Ok(path.map(|path| Cipher::read(&path)).transpose()?)
+}
+
+fn keygen(path: &Path) -> Result<()> {
+    let cipher = Cipher::generate();
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    writeln!(file, "{cipher}")?;
+    file.sync_all()?;
+
+    println!("Key id: {}", cipher.id());
+
+    Ok(())
+}
+
+fn scan(drive: Drive, cipher: Option<Cipher>) -> Result<()> {
+    let catalog = git::catalog()?;
+    let media = Rc::new(drive).load_media()?.with_cipher(cipher);
+    let scan = media.scan()?;
+
+    let mut record = catalog.media(&media.id())?;
+    record.created = media.creation_time();
+    record.host = media.host().to_string();
+    record.identity = media.identity().clone();
+    catalog.save_media(&record)?;
+
+    let identity = media.identity();
+    if !identity.uuid.is_empty() {
+        println!("Cartridge: {} ({})", identity.uuid, identity.label);
+        println!("Pool: {}", identity.pool);
+        println!("Owner: {}", identity.remote);
+    }
+    println!("Format: {}", media.format());
+
+    catalog.save_objects(&record.id, &scan.objects)?;
+    catalog.save_tombstones(&record.id, &scan.tombstones)?;
+
+    let indexed = scan.archives.iter().filter(|a| a.indexed).count();
+    println!(
+        "Archives: {} ({indexed} from their table of contents)",
+        scan.archives.len()
+    );
+    println!("Objects: {}", scan.objects.len());
+    println!("Bundles: {}", scan.bundles.len());
+    println!("Tombstones: {}", scan.tombstones.len());
+    print_sealed(&scan.archives);
+
+    // Archives written by the same remote are listed once.
+    let mut origins = Vec::new();
+    for archive in &scan.archives {
+        let origin = (&archive.origin, &archive.writer);
+        if !archive.origin.remote.is_empty() && !origins.contains(&origin) {
+            origins.push(origin);
+        }
+    }
+
+    for (origin, writer) in origins {
+        println!(
+            "Remote {} ({}) of repository {}, written by {writer}",
+            origin.remote_name, origin.remote, origin.repository
+        );
+    }
+
+    if !scan.torn.is_empty() {
+        println!("Torn objects, which need to be stored again:");
+        for entry in &scan.torn {
+            println!("  {} (block {})", entry.key, entry.block);
+        }
+    }
+
+    Ok(())
+}
+
+fn bundle(drive: Drive, cipher: Option<Cipher>) -> Result<()> {
+    let git_dir = git::git_dir()?;
+    let mut bundle = git::bundle(&git_dir)?;
+    let length = bundle.metadata()?.len();
+
+    let origin = Origin {
+        repository: git::annex_uuid(&git_dir).unwrap_or_default(),
+        ..Origin::default()
+    };
+    let media = Rc::new(drive)
+        .load_media()?
+        .with_cipher(cipher)
+        .with_origin(origin);
+
+    let mut archive = media.append_archive(catalog::now(), &tape::hostname(), None)?;
+    archive.write_object(tape::BUNDLE_KEY, length, &mut bundle)?;
+    archive.close()?;
+
+    println!("Bundle: {length} bytes");
+
+    Ok(())
+}
+
+fn restore(drive: Drive, dir: &Path, cipher: Option<Cipher>) -> Result<()> {
+    let media = Rc::new(drive).load_media()?.with_cipher(cipher);
+    let scan = media.scan()?;
+
+    let Some(bundle) = scan.newest_bundle() else {
+        bail!("The cartridge holds no git bundle");
+    };
+
+    let path = git::bundle_path();
+    media.read_object(bundle.block, tape::BUNDLE_KEY, &mut File::create(&path)?)?;
+    let cloned = git::clone_bundle(&path, dir);
+    fs::remove_file(&path)?;
+    cloned?;
+
+    // Let the remote find the objects, which have no state if they have
+    // been stored after the bundle.
+    let catalog = Catalog::open(&dir.join(".git"))?;
+    let mut record = catalog.media(&media.id())?;
+    record.created = media.creation_time();
+    record.host = media.host().to_string();
+    record.identity = media.identity().clone();
+    catalog.save_media(&record)?;
+    catalog.save_objects(&record.id, &scan.objects)?;
+    catalog.save_tombstones(&record.id, &scan.tombstones)?;
+
+    // Objects are present in the remote which wrote their archive.
+    let mut present = Vec::new();
+    let mut remotes = Vec::new();
+    for object in &scan.objects {
+        let Some(archive) = scan
+            .archives
+            .iter()
+            .find(|a| a.creation_time == object.archive && !a.origin.remote.is_empty())
+        else {
+            continue;
+        };
+
+        present.push((object.key.as_str(), archive.origin.remote.as_str()));
+        if !remotes.contains(&&archive.origin.remote_name) {
+            remotes.push(&archive.origin.remote_name);
+        }
+    }
+
+    git::annex_init(dir)?;
+    git::set_present_keys(dir, &present)?;
+
+    println!("Repository: {}", dir.display());
+    println!("Objects marked present: {}", present.len());
+    for name in remotes.iter().filter(|name| !name.is_empty()) {
+        println!("Enable the remote with: git annex enableremote {name}");
+    }
+
+    Ok(())
+}
+
+/// Writes salvaged objects into a directory, named by their key.
+///
+/// Contents are written to a temporary file first, so that only verified
+/// objects end up under their key. The metadata of recovered objects is
+/// appended to `metadata.jsonl`, one JSON object per line.
+struct DirectorySink {
+    dir: PathBuf,
+
+    /// Temporary file and key of the object being received.
+    current: Option<(File, String)>,
+    metadata: Option<Metadata>,
+}
+
+/// A line of `metadata.jsonl`.
+#[derive(Serialize)]
+struct MetadataLine<'a> {
+    key: &'a str,
+
+    #[serde(flatten)]
+    metadata: &'a Metadata,
+}
+
+impl DirectorySink {
+    fn temp_path(&self) -> PathBuf {
+        self.dir.join(".salvage.tmp")
+    }
+
+    fn save_metadata(&self, key: &str, metadata: &Metadata) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("metadata.jsonl"))?;
+        let line = serde_json::to_string(&MetadataLine { key, metadata })?;
+
+        writeln!(file, "{line}")
+    }
+}
+
+impl SalvageSink for DirectorySink {
+    fn begin(&mut self, key: &str) -> io::Result<()> {
+        let file = File::create(self.temp_path())?;
+        self.current = Some((file, key.to_string()));
+        self.metadata = None;
+
+        Ok(())
+    }
+
+    fn describe(&mut self, metadata: &Metadata) -> io::Result<()> {
+        self.metadata = Some(metadata.clone());
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.current {
+            Some((file, _)) => file.write_all(data),
+            None => Ok(()),
+        }
     }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if let Some((file, key)) = self.current.take() {
+            file.sync_all()?;
+            fs::rename(self.temp_path(), self.dir.join(&key))?;
+
+            if let Some(metadata) = self.metadata.take() {
+                self.save_metadata(&key, &metadata)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self) -> io::Result<()> {
+        if self.current.take().is_some() {
+            fs::remove_file(self.temp_path())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn salvage(drive: Drive, dir: &Path, cipher: Option<Cipher>) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let media = Rc::new(drive).load_media()?.with_cipher(cipher);
+    let mut sink = DirectorySink {
+        dir: dir.to_path_buf(),
+        current: None,
+        metadata: None,
+    };
+
+    let salvage = media.salvage(&mut sink)?;
+
+    println!("Unreadable records: {}", salvage.unreadable);
+    println!(
+        "Recovered objects: {} (in {})",
+        salvage.recovered.len(),
+        dir.display()
+    );
+    print_sealed(&salvage.sealed);
+
+    if !salvage.lost.is_empty() {
+        println!("Lost objects:");
+        for lost in &salvage.lost {
+            println!("  {} (block {}, {})", lost.key, lost.block, lost.loss);
+        }
+    }
+
+    Ok(())
+}
+
+/// List the archives which could not be read without their key.
+fn print_sealed(archives: &[ArchiveEntry]) {
+    for archive in archives {
+        if let Some(id) = &archive.unknown_key {
+            println!(
+                "Archive at block {} is encrypted with unknown key {id}: {} objects, {} bytes",
+                archive.block, archive.sealed_objects, archive.sealed_length
+            );
+        }
+    }
+}
+
+fn info(drive: &Drive) -> Result<()> {
+    let status = drive.status()?;
+
+    println!("File number: {}", status.mt_fileno);
+    println!("Block number: {}", status.mt_blkno);
+    println!("Status: {:?}", status.mt_gstat);
+
+    match drive.cleaning_required() {
+        Ok(Some(reason)) => println!("Cleaning: requested ({reason})"),
+        Ok(None) => println!("Cleaning: not required"),
+        Err(e) => println!("Cleaning: unknown ({e})"),
+    }
+
+    match drive.health() {
+        Ok(health) => print_drive_health(&health),
+        Err(e) => println!("Drive health: unavailable ({e})"),
+    }
+
+    match drive.media_health() {
+        Ok(health) => print_media_health(&health),
+        Err(e) => println!("Cartridge health: unavailable ({e})"),
+    }
+
+    Ok(())
+}
+
+fn print_drive_health(health: &DriveHealth) {
+    println!("Drive health: {} ({})", health.score(), health.status());
+    println!(
+        "  Corrected errors per GB: {:.3}",
+        health.corrected_per_gb()
+    );
+    println!(
+        "  Uncorrected errors per GB: {:.3}",
+        health.uncorrected_per_gb()
+    );
+    println!("  Bytes written: {}", health.bytes_written);
+    println!("  Bytes read: {}", health.bytes_read);
+}
+
+fn print_media_health(health: &MediaHealth) {
+    println!("Cartridge health: {} ({})", health.score(), health.status());
+
+    if let Some(barcode) = &health.barcode {
+        println!("  Barcode: {barcode}");
+    }
+
+    if let Some(serial) = &health.serial_number {
+        println!("  Serial number: {serial}");
+    }
+
+    println!(
+        "  Corrected errors per GB: {:.3}",
+        health.corrected_per_gb()
+    );
+    println!(
+        "  Uncorrected errors per GB: {:.3}",
+        health.uncorrected_per_gb()
+    );
+    println!("  Load count: {}", health.load_count);
+    println!("  Lifetime MB written: {}", health.lifetime_mb_written);
+    println!("  Lifetime MB read: {}", health.lifetime_mb_read);
 }
@@ -0,0 +1,57 @@
+use anyhow::{anyhow, Result};
+use git_annex_remote_tape::vtl::{Library, CLEANING_PREFIX};
+use std::path::Path;
+
+use crate::cli::VtlCommand;
+
+pub fn run(dir: &Path, command: VtlCommand) -> Result<()> {
+    match command {
+        VtlCommand::Create {
+            slots,
+            drives,
+            mailslots,
+            cartridges,
+            cleaning,
+        } => {
+            let mut barcodes: Vec<String> =
+                (0..cartridges).map(|i| format!("VTL{i:03}L8")).collect();
+            if cleaning {
+                barcodes.push(format!("{CLEANING_PREFIX}000L1"));
+            }
+
+            let library = Library::create(dir, slots, drives, mailslots, &barcodes)?;
+
+            for unit in 0..drives {
+                println!("Drive {unit}: {}", library.drive_path(unit).display());
+            }
+
+            println!("Changer: {}", dir.display());
+
+            Ok(())
+        }
+        VtlCommand::Insert { barcode, mailslot } => {
+            library(dir)?.insert(mailslot, &barcode)?;
+            println!("Inserted {barcode} into mailslot {mailslot}");
+
+            Ok(())
+        }
+        VtlCommand::Remove { mailslot } => {
+            match library(dir)?.remove(mailslot)? {
+                Some(barcode) => println!("Removed {barcode} from mailslot {mailslot}"),
+                None => println!("Mailslot {mailslot} is empty"),
+            }
+
+            Ok(())
+        }
+        VtlCommand::Damage { barcode, block } => {
+            library(dir)?.damage(&barcode, block)?;
+            println!("Damaged block {block} of {barcode}");
+
+            Ok(())
+        }
+    }
+}
+
+fn library(dir: &Path) -> Result<Library> {
+    Library::open(dir).ok_or_else(|| anyhow!("No virtual library in {}", dir.display()))
+}
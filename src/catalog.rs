@@ -0,0 +1,533 @@
+//! Persistent records about drives and cartridges
+//!
+//! The catalog lives inside the git-annex repository:
+//!
+//! ```text
+//! .git/annex/drives/<serial>/details.json
+//! .git/annex/tapes/<id>/details.json
+//! .git/annex/tapes/<id>/objects.json
+//! .git/annex/tapes/<id>/tombstones.json
+//! .git/annex/inventory.json
+//! .git/annex/exports/<remote>/journal.jsonl
+//! ```
+//!
+//! Next to it are the lock files of the remote processes and the spool of
+//! small objects waiting to be packed, see [`crate::spool`].
+//!
+//! Cartridges are identified by the UUID in their media header, which
+//! locations stored in git-annex refer to. Older media headers have no UUID,
+//! those cartridges are identified by their creation time instead. Records of
+//! cartridges with a UUID which are still named after their creation time are
+//! moved when the catalog is opened.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::changer::Address;
+use crate::format::MediaIdentity;
+use crate::health::DriveHealth;
+use crate::inventory::Inventory;
+use crate::ltfs::JournalEntry;
+use crate::tape::{ObjectEntry, TombstoneEntry};
+use crate::{cleaning, diagnostic};
+
+const DETAILS_FILE: &str = "details.json";
+const INVENTORY_FILE: &str = "inventory.json";
+const JOURNAL_FILE: &str = "journal.jsonl";
+const OBJECTS_FILE: &str = "objects.json";
+const TOMBSTONES_FILE: &str = "tombstones.json";
+
+#[derive(Debug)]
+pub enum Error {
+    IO(io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Everything we know about a single tape drive.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DriveRecord {
+    pub serial: String,
+    pub vendor: Option<String>,
+    pub product: Option<String>,
+    pub health: Option<DriveHealth>,
+
+    #[serde(default)]
+    pub cleaning: Vec<cleaning::Event>,
+
+    #[serde(default)]
+    pub diagnostics: Vec<diagnostic::Report>,
+}
+
+impl DriveRecord {
+    /// Whether the last cleaning request has not been followed by a cleaning.
+    pub fn cleaning_pending(&self) -> bool {
+        matches!(
+            self.cleaning.last(),
+            Some(cleaning::Event {
+                kind: cleaning::EventKind::Requested(_),
+                ..
+            })
+        )
+    }
+
+    /// Record a cleaning request unless one is already pending.
+    ///
+    /// Returns true if a new event has been recorded.
+    pub fn request_cleaning(&mut self, reason: cleaning::Reason) -> bool {
+        if self.cleaning_pending() {
+            return false;
+        }
+
+        self.record_cleaning(cleaning::EventKind::Requested(reason));
+
+        true
+    }
+
+    pub fn record_cleaning(&mut self, kind: cleaning::EventKind) {
+        self.cleaning.push(cleaning::Event { time: now(), kind });
+    }
+}
+
+/// Where a cartridge has last been seen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    #[default]
+    Unknown,
+
+    /// In an element of the library.
+    Library(Address),
+
+    /// Not found in the library during the last inventory.
+    Offsite,
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown"),
+            Self::Library(address) => write!(f, "in library ({address})"),
+            Self::Offsite => write!(f, "offsite"),
+        }
+    }
+}
+
+/// Everything we know about a single cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaRecord {
+    /// Id of the cartridge, see [`crate::tape::Media::id`].
+    #[serde(skip)]
+    pub id: String,
+
+    /// Creation time from the media header, which records used to be
+    /// identified by.
+    #[serde(default, alias = "id")]
+    pub created: u64,
+
+    pub host: String,
+    pub barcode: Option<String>,
+
+    #[serde(default)]
+    pub placement: Placement,
+
+    /// Time of the last inventory which found the cartridge.
+    pub last_seen: Option<u64>,
+
+    /// Time at which the last archive was started on the cartridge.
+    #[serde(default)]
+    pub last_written: Option<u64>,
+
+    /// Identity from the media header.
+    #[serde(default)]
+    pub identity: MediaIdentity,
+
+    /// Why new archives are no longer appended to the cartridge, after its
+    /// health has been found degrading.
+    #[serde(default)]
+    pub degraded: Option<String>,
+}
+
+pub struct Catalog {
+    root: PathBuf,
+}
+
+impl Catalog {
+    /// Open the catalog of the git-annex repository at `git_dir`.
+    pub fn open(git_dir: &Path) -> Result<Self> {
+        let root = git_dir.join("annex");
+
+        fs::create_dir_all(&root)?;
+
+        let catalog = Self { root };
+        catalog.migrate()?;
+
+        Ok(catalog)
+    }
+
+    /// Move the records of cartridges with a UUID which are named after their
+    /// creation time.
+    fn migrate(&self) -> Result<()> {
+        let tapes = self.root.join("tapes");
+
+        for name in self.entries("tapes")? {
+            // UUIDs never parse as a creation time.
+            if name.parse::<u64>().is_err() {
+                continue;
+            }
+
+            let uuid = self.media(&name)?.identity.uuid;
+            if uuid.is_empty() {
+                continue;
+            }
+
+            match fs::rename(tapes.join(&name), tapes.join(&uuid)) {
+                // Another process may have moved it already.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read<T: DeserializeOwned + Default>(&self, path: &Path) -> Result<T> {
+        match fs::read(path) {
+            Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write<T: Serialize + ?Sized>(&self, path: &Path, value: &T) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first so that a crash never leaves a
+        // truncated record behind.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    fn drive_path(&self, serial: &str) -> PathBuf {
+        self.root.join("drives").join(serial).join(DETAILS_FILE)
+    }
+
+    /// Load the record of a drive, or an empty one if the drive is unknown.
+    pub fn drive(&self, serial: &str) -> Result<DriveRecord> {
+        let mut record: DriveRecord = self.read(&self.drive_path(serial))?;
+        record.serial = serial.to_string();
+
+        Ok(record)
+    }
+
+    pub fn save_drive(&self, record: &DriveRecord) -> Result<()> {
+        self.write(&self.drive_path(&record.serial), record)
+    }
+
+    fn media_path(&self, id: &str) -> PathBuf {
+        self.root.join("tapes").join(id).join(DETAILS_FILE)
+    }
+
+    /// Load the record of a cartridge, or an empty one if it is unknown.
+    pub fn media(&self, id: &str) -> Result<MediaRecord> {
+        let mut record: MediaRecord = self.read(&self.media_path(id))?;
+        record.id = id.to_string();
+
+        Ok(record)
+    }
+
+    /// Check whether a cartridge has a record.
+    pub fn has_media(&self, id: &str) -> bool {
+        self.media_path(id).exists()
+    }
+
+    pub fn save_media(&self, record: &MediaRecord) -> Result<()> {
+        self.write(&self.media_path(&record.id), record)
+    }
+
+    fn objects_path(&self, id: &str) -> PathBuf {
+        self.root.join("tapes").join(id).join(OBJECTS_FILE)
+    }
+
+    /// Load the objects found on a cartridge by its last scan.
+    pub fn objects(&self, id: &str) -> Result<Vec<ObjectEntry>> {
+        self.read(&self.objects_path(id))
+    }
+
+    pub fn save_objects(&self, id: &str, objects: &[ObjectEntry]) -> Result<()> {
+        self.write(&self.objects_path(id), objects)
+    }
+
+    fn tombstones_path(&self, id: &str) -> PathBuf {
+        self.root.join("tapes").join(id).join(TOMBSTONES_FILE)
+    }
+
+    /// Load the tombstones found on a cartridge by its last scan, and those
+    /// written since.
+    pub fn tombstones(&self, id: &str) -> Result<Vec<TombstoneEntry>> {
+        self.read(&self.tombstones_path(id))
+    }
+
+    pub fn save_tombstones(&self, id: &str, tombstones: &[TombstoneEntry]) -> Result<()> {
+        self.write(&self.tombstones_path(id), tombstones)
+    }
+
+    /// Remember a tombstone which has just been written to a cartridge.
+    pub fn add_tombstone(&self, id: &str, tombstone: TombstoneEntry) -> Result<()> {
+        let mut tombstones = self.tombstones(id)?;
+        tombstones.push(tombstone);
+
+        self.save_tombstones(id, &tombstones)
+    }
+
+    /// Find the most recently written copy of an object among the objects
+    /// found by scanning cartridges, with the id of its cartridge.
+    ///
+    /// Objects which have been removed by a later tombstone on their
+    /// cartridge are not found. Blocks of different cartridges cannot be
+    /// compared, so tombstones never remove copies on other cartridges.
+    pub fn find_object(&self, key: &str) -> Result<Option<(String, ObjectEntry)>> {
+        let mut found: Option<(String, ObjectEntry)> = None;
+        for media in self.all_media()? {
+            let tombstones = self.tombstones(&media.id)?;
+
+            for object in self.objects(&media.id)? {
+                let newer = found
+                    .as_ref()
+                    .is_none_or(|(_, o)| o.archive < object.archive);
+                let removed = tombstones.iter().any(|t| t.removes(&object));
+                if object.key == key && newer && !removed {
+                    found = Some((media.id.clone(), object));
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Load the records of all known cartridges.
+    pub fn all_media(&self) -> Result<Vec<MediaRecord>> {
+        let mut media = Vec::new();
+        for name in self.entries("tapes")? {
+            media.push(self.media(&name)?);
+        }
+
+        media.sort_by_key(|m| m.created);
+
+        Ok(media)
+    }
+
+    /// Find a cartridge by its barcode.
+    pub fn media_by_barcode(&self, barcode: &str) -> Result<Option<MediaRecord>> {
+        Ok(self
+            .all_media()?
+            .into_iter()
+            .find(|m| m.barcode.as_deref() == Some(barcode)))
+    }
+
+    /// Find a cartridge with a UUID by its creation time, which locations
+    /// recorded before cartridges were identified by their UUID refer to.
+    ///
+    /// Such locations are rewritten once found, so they are rare enough to
+    /// look at every record.
+    pub fn media_by_creation_time(&self, created: u64) -> Result<Option<MediaRecord>> {
+        Ok(self
+            .all_media()?
+            .into_iter()
+            .find(|m| m.created == created && !m.identity.uuid.is_empty()))
+    }
+
+    /// The onsite cartridge which the remote with UUID `remote` has written
+    /// to most recently. Cartridges owned by other remotes are skipped.
+    pub fn fill_media(&self, remote: &str) -> Result<Option<MediaRecord>> {
+        Ok(self
+            .all_media()?
+            .into_iter()
+            .filter(|m| m.last_written.is_some() && m.placement != Placement::Offsite)
+            .filter(|m| m.identity.accepts(remote) && m.degraded.is_none())
+            .max_by_key(|m| m.last_written))
+    }
+
+    /// The cartridge of `pool` in the library which the remote with UUID
+    /// `remote` fills next, once the one being filled is degrading. The one
+    /// written to most recently is preferred.
+    pub fn spare_media(&self, remote: &str, pool: &str) -> Result<Option<MediaRecord>> {
+        Ok(self
+            .all_media()?
+            .into_iter()
+            .filter(|m| matches!(m.placement, Placement::Library(_)) && m.barcode.is_some())
+            .filter(|m| m.identity.accepts(remote) && m.identity.pool == pool)
+            .filter(|m| m.degraded.is_none())
+            .max_by_key(|m| m.last_written))
+    }
+
+    /// Directory for the lock files which coordinate concurrent remote processes.
+    pub fn lock_dir(&self) -> PathBuf {
+        self.root.join("locks")
+    }
+
+    /// Directory in which the remote with the UUID `remote` keeps copies of
+    /// the small objects which are waiting to be packed.
+    pub fn spool_dir(&self, remote: &str) -> PathBuf {
+        self.root.join("spool").join(remote)
+    }
+
+    fn journal_path(&self, remote: &str) -> PathBuf {
+        self.root.join("exports").join(remote).join(JOURNAL_FILE)
+    }
+
+    /// Load the changes which the remote with the UUID `remote` has made to
+    /// exported LTFS volumes without writing their index yet.
+    pub fn journal(&self, remote: &str) -> Result<Vec<JournalEntry>> {
+        let file = match File::open(self.journal_path(remote)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        let mut lines = BufReader::new(file).lines().peekable();
+        while let Some(line) = lines.next() {
+            match serde_json::from_str(&line?) {
+                Ok(entry) => entries.push(entry),
+                // A crash may have cut the last entry short, it was never
+                // acknowledged.
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Add entries to the journal of a remote. They are on disk when this
+    /// returns.
+    pub fn append_journal(&self, remote: &str, entries: &[JournalEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.journal_path(remote);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(&journal_lines(entries)?)?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+
+    /// Drop the entries of the journal of a remote which were made to the
+    /// volume `volume_uuid`, once its index holds them.
+    pub fn clear_journal(&self, remote: &str, volume_uuid: &str) -> Result<()> {
+        let mut entries = self.journal(remote)?;
+        let count = entries.len();
+        entries.retain(|entry| entry.volume_uuid != volume_uuid);
+        if entries.len() == count {
+            return Ok(());
+        }
+
+        let path = self.journal_path(remote);
+        if entries.is_empty() {
+            fs::remove_file(&path)?;
+            return Ok(());
+        }
+
+        let tmp = path.with_extension("jsonl.tmp");
+        fs::write(&tmp, journal_lines(&entries)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Load the result of the last library inventory.
+    pub fn inventory(&self) -> Result<Inventory> {
+        self.read(&self.root.join(INVENTORY_FILE))
+    }
+
+    pub fn save_inventory(&self, inventory: &Inventory) -> Result<()> {
+        self.write(&self.root.join(INVENTORY_FILE), inventory)
+    }
+
+    /// Names of the entries of a catalog directory.
+    fn entries(&self, dir: &str) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            names.push(entry?.file_name().to_string_lossy().to_string());
+        }
+
+        Ok(names)
+    }
+
+    /// Load the records of all known drives.
+    pub fn drives(&self) -> Result<Vec<DriveRecord>> {
+        let mut drives = Vec::new();
+        for serial in self.entries("drives")? {
+            drives.push(self.drive(&serial)?);
+        }
+
+        drives.sort_by(|a, b| a.serial.cmp(&b.serial));
+
+        Ok(drives)
+    }
+}
+
+/// Journal entries as JSON, one per line.
+fn journal_lines(entries: &[JournalEntry]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut buf, entry)?;
+        buf.push(b'\n');
+    }
+
+    Ok(buf)
+}
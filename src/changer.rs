@@ -0,0 +1,521 @@
+//! SCSI medium changers (autoloaders and tape libraries)
+//!
+//! Uses the Linux ch driver via /dev/sch* and its CHIO* ioctls.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{chio, vtl};
+
+#[derive(Debug)]
+pub enum Error {
+    Errno(nix::Error),
+    IO(io::Error),
+    NoSuchElement(Address),
+    Empty(Address),
+    Full(Address),
+    BarcodeNotFound(String),
+    NoEmptyElement(ElementType),
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(value: nix::Error) -> Self {
+        Self::Errno(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Errno(e) => write!(f, "{e}"),
+            Self::IO(e) => write!(f, "{e}"),
+            Self::NoSuchElement(addr) => write!(f, "no such element: {addr}"),
+            Self::Empty(addr) => write!(f, "element is empty: {addr}"),
+            Self::Full(addr) => write!(f, "element is full: {addr}"),
+            Self::BarcodeNotFound(barcode) => write!(f, "no cartridge with barcode {barcode}"),
+            Self::NoEmptyElement(kind) => write!(f, "no empty {kind} available"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementType {
+    /// Media transport element (robot).
+    Transport,
+
+    /// Storage element (slot).
+    Storage,
+
+    /// Import/export element (mail slot).
+    ImportExport,
+
+    /// Data transfer element (drive).
+    DataTransfer,
+}
+
+impl ElementType {
+    fn to_chet(self) -> libc::c_int {
+        match self {
+            Self::Transport => chio::CHET_MT,
+            Self::Storage => chio::CHET_ST,
+            Self::ImportExport => chio::CHET_IE,
+            Self::DataTransfer => chio::CHET_DT,
+        }
+    }
+
+    fn from_chet(chet: libc::c_int) -> Option<Self> {
+        match chet {
+            chio::CHET_MT => Some(Self::Transport),
+            chio::CHET_ST => Some(Self::Storage),
+            chio::CHET_IE => Some(Self::ImportExport),
+            chio::CHET_DT => Some(Self::DataTransfer),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ElementType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Transport => "picker",
+            Self::Storage => "slot",
+            Self::ImportExport => "mailslot",
+            Self::DataTransfer => "drive",
+        })
+    }
+}
+
+/// Address of an element within the changer, e.g. `slot 3` or `drive 0`.
+///
+/// Units are numbered from zero for each element type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub kind: ElementType,
+    pub unit: u16,
+}
+
+impl Address {
+    pub fn slot(unit: u16) -> Self {
+        Self {
+            kind: ElementType::Storage,
+            unit,
+        }
+    }
+
+    pub fn drive(unit: u16) -> Self {
+        Self {
+            kind: ElementType::DataTransfer,
+            unit,
+        }
+    }
+
+    pub fn mailslot(unit: u16) -> Self {
+        Self {
+            kind: ElementType::ImportExport,
+            unit,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.unit)
+    }
+}
+
+impl FromStr for Address {
+    type Err = ();
+
+    /// Parse `slot:3`, `drive:0`, `mailslot:1` or a plain slot number.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, unit) = match s.split_once(':') {
+            Some((kind, unit)) => (kind, unit),
+            None => ("slot", s),
+        };
+
+        let unit = unit.parse::<u16>().map_err(|_| ())?;
+        let kind = match kind {
+            "picker" => ElementType::Transport,
+            "slot" => ElementType::Storage,
+            "mailslot" => ElementType::ImportExport,
+            "drive" => ElementType::DataTransfer,
+            _ => return Err(()),
+        };
+
+        Ok(Self { kind, unit })
+    }
+}
+
+/// Status of a single changer element.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub address: Address,
+    pub full: bool,
+    pub exception: bool,
+    pub accessible: bool,
+
+    /// Media was placed in the element by an operator.
+    pub imported: bool,
+
+    /// Primary volume tag (barcode) of the cartridge in the element.
+    pub barcode: Option<String>,
+
+    /// Element from which the cartridge was moved here.
+    pub source: Option<Address>,
+}
+
+/// Number of elements of each type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Params {
+    pub pickers: u16,
+    pub slots: u16,
+    pub mailslots: u16,
+    pub drives: u16,
+}
+
+impl Params {
+    pub fn count(&self, kind: ElementType) -> u16 {
+        match kind {
+            ElementType::Transport => self.pickers,
+            ElementType::Storage => self.slots,
+            ElementType::ImportExport => self.mailslots,
+            ElementType::DataTransfer => self.drives,
+        }
+    }
+}
+
+/// Status of all elements of a changer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    pub drives: Vec<Element>,
+    pub slots: Vec<Element>,
+    pub mailslots: Vec<Element>,
+}
+
+impl Status {
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.drives
+            .iter()
+            .chain(self.slots.iter())
+            .chain(self.mailslots.iter())
+    }
+
+    /// Find the element holding the cartridge with the given barcode.
+    pub fn find_barcode(&self, barcode: &str) -> Option<&Element> {
+        self.elements()
+            .find(|e| e.full && e.barcode.as_deref() == Some(barcode))
+    }
+
+    /// Find the first empty element of the given type.
+    pub fn find_empty(&self, kind: ElementType) -> Option<&Element> {
+        let elements = match kind {
+            ElementType::DataTransfer => &self.drives,
+            ElementType::Storage => &self.slots,
+            ElementType::ImportExport => &self.mailslots,
+            ElementType::Transport => return None,
+        };
+
+        elements.iter().find(|e| !e.full && e.accessible)
+    }
+}
+
+fn voltag(tag: &[libc::c_char; 36]) -> Option<String> {
+    let bytes: Vec<u8> = tag
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+
+    let s = String::from_utf8_lossy(&bytes).trim().to_string();
+
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+/// A medium changer as used by the remote and the command line interface.
+///
+/// Implemented by `MediumChanger` for the Linux ch driver and by
+/// `vtl::VirtualChanger` for a directory of cartridge images.
+pub trait Changer {
+    /// Get the number of elements of each type.
+    fn params(&self) -> Result<Params>;
+
+    /// Get the status of a single element including its barcode.
+    fn element(&self, address: Address) -> Result<Element>;
+
+    /// Let the changer re-scan its inventory (INITIALIZE ELEMENT STATUS).
+    fn init_elements(&self) -> Result<()>;
+
+    /// Move a cartridge from one element to another.
+    fn move_medium(&self, from: Address, to: Address) -> Result<()>;
+
+    /// Move the cartridge in `source` to `first` and the cartridge in `first`
+    /// to `second`.
+    ///
+    /// Changers which cannot do this in a single operation use two moves.
+    fn exchange(&self, source: Address, first: Address, second: Address) -> Result<()> {
+        self.move_medium(first, second)?;
+        self.move_medium(source, first)
+    }
+
+    /// Get the status of all elements of a given type.
+    fn elements(&self, kind: ElementType) -> Result<Vec<Element>> {
+        let count = self.params()?.count(kind);
+
+        (0..count)
+            .map(|unit| self.element(Address { kind, unit }))
+            .collect()
+    }
+
+    /// Get the status of all drives, slots and import/export elements.
+    fn status(&self) -> Result<Status> {
+        Ok(Status {
+            drives: self.elements(ElementType::DataTransfer)?,
+            slots: self.elements(ElementType::Storage)?,
+            mailslots: self.elements(ElementType::ImportExport)?,
+        })
+    }
+
+    /// Load the cartridge from a slot into a drive.
+    fn load(&self, slot: Address, drive: u16) -> Result<()> {
+        self.move_medium(slot, Address::drive(drive))
+    }
+
+    /// Return the cartridge in a drive to a slot.
+    ///
+    /// Without an explicit slot the cartridge is returned to the slot it
+    /// was loaded from, or to the first empty slot.
+    /// The drive must have ejected the cartridge before.
+    fn unload(&self, drive: u16, slot: Option<Address>) -> Result<Address> {
+        let drive = Address::drive(drive);
+
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                let elem = self.element(drive)?;
+                match elem.source.filter(|s| s.kind == ElementType::Storage) {
+                    Some(source) if !self.element(source)?.full => source,
+                    _ => {
+                        let status = self.status()?;
+                        status
+                            .find_empty(ElementType::Storage)
+                            .map(|e| e.address)
+                            .ok_or(Error::NoEmptyElement(ElementType::Storage))?
+                    }
+                }
+            }
+        };
+
+        self.move_medium(drive, slot)?;
+
+        Ok(slot)
+    }
+
+    /// Move a cartridge to the first empty import/export element so that an
+    /// operator can take it out of the library.
+    fn export(&self, from: Address) -> Result<Address> {
+        let mailslot = self
+            .status()?
+            .find_empty(ElementType::ImportExport)
+            .map(|e| e.address)
+            .ok_or(Error::NoEmptyElement(ElementType::ImportExport))?;
+
+        self.move_medium(from, mailslot)?;
+
+        Ok(mailslot)
+    }
+
+    /// Move a cartridge from an import/export element to the first empty slot.
+    fn import(&self, mailslot: Address) -> Result<Address> {
+        let slot = self
+            .status()?
+            .find_empty(ElementType::Storage)
+            .map(|e| e.address)
+            .ok_or(Error::NoEmptyElement(ElementType::Storage))?;
+
+        self.move_medium(mailslot, slot)?;
+
+        Ok(slot)
+    }
+
+    /// Find the element which holds the cartridge with the given barcode.
+    fn find_barcode(&self, barcode: &str) -> Result<Element> {
+        self.status()?
+            .find_barcode(barcode)
+            .cloned()
+            .ok_or_else(|| Error::BarcodeNotFound(barcode.to_string()))
+    }
+}
+
+/// Open a changer device, or a virtual library if `path` is a directory.
+pub fn open(path: &Path) -> Result<Box<dyn Changer>> {
+    if let Some(changer) = vtl::VirtualChanger::open(path)? {
+        return Ok(Box::new(changer));
+    }
+
+    Ok(Box::new(MediumChanger::new(path)?))
+}
+
+pub struct MediumChanger {
+    file: File,
+}
+
+impl MediumChanger {
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Ok(Self { file })
+    }
+
+    /// Get the full/empty status of all elements of a given type.
+    ///
+    /// This is cheaper than querying each element individually.
+    pub fn occupancy(&self, kind: ElementType) -> Result<Vec<bool>> {
+        let count = self.params()?.count(kind);
+        let mut data = vec![0u8; count as usize];
+
+        let status = chio::changer_element_status {
+            ces_type: kind.to_chet(),
+            ces_data: data.as_mut_ptr(),
+        };
+
+        unsafe {
+            chio::chiogstatus(self.file.as_raw_fd(), &status)?;
+        }
+
+        Ok(data.iter().map(|s| s & chio::CESTATUS_FULL != 0).collect())
+    }
+}
+
+impl Changer for MediumChanger {
+    fn params(&self) -> Result<Params> {
+        let mut params = chio::changer_params::default();
+
+        unsafe {
+            chio::chiogparams(self.file.as_raw_fd(), &mut params)?;
+        }
+
+        Ok(Params {
+            pickers: params.cp_npickers as u16,
+            slots: params.cp_nslots as u16,
+            mailslots: params.cp_nportals as u16,
+            drives: params.cp_ndrives as u16,
+        })
+    }
+
+    fn element(&self, address: Address) -> Result<Element> {
+        let mut elem = chio::changer_get_element {
+            cge_type: address.kind.to_chet(),
+            cge_unit: address.unit as libc::c_int,
+            ..Default::default()
+        };
+
+        unsafe {
+            chio::chiogelem(
+                self.file.as_raw_fd(),
+                &mut elem as *mut chio::changer_get_element,
+            )
+            .map_err(|e| match e {
+                nix::errno::Errno::EINVAL => Error::NoSuchElement(address),
+                e => Error::Errno(e),
+            })?;
+        }
+
+        let status = elem.cge_status as libc::c_uchar;
+
+        let source = if elem.cge_flags & chio::CGE_SRC != 0 {
+            ElementType::from_chet(elem.cge_srctype).map(|kind| Address {
+                kind,
+                unit: elem.cge_srcunit as u16,
+            })
+        } else {
+            None
+        };
+
+        let barcode = if elem.cge_flags & chio::CGE_PVOLTAG != 0 {
+            voltag(&elem.cge_pvoltag)
+        } else {
+            None
+        };
+
+        Ok(Element {
+            address,
+            full: status & chio::CESTATUS_FULL != 0,
+            exception: status & chio::CESTATUS_EXCEPT != 0,
+            accessible: status & chio::CESTATUS_ACCESS != 0,
+            imported: status & chio::CESTATUS_IMPEXP != 0,
+            barcode,
+            source,
+        })
+    }
+
+    fn init_elements(&self) -> Result<()> {
+        unsafe {
+            chio::chioinitelem(self.file.as_raw_fd())?;
+        }
+
+        Ok(())
+    }
+
+    fn move_medium(&self, from: Address, to: Address) -> Result<()> {
+        let from_elem = self.element(from)?;
+        if !from_elem.full {
+            return Err(Error::Empty(from));
+        }
+
+        let to_elem = self.element(to)?;
+        if to_elem.full {
+            return Err(Error::Full(to));
+        }
+
+        let mv = chio::changer_move {
+            cm_fromtype: from.kind.to_chet(),
+            cm_fromunit: from.unit as libc::c_int,
+            cm_totype: to.kind.to_chet(),
+            cm_tounit: to.unit as libc::c_int,
+            cm_flags: 0,
+        };
+
+        unsafe {
+            chio::chiomove(self.file.as_raw_fd(), &mv)?;
+        }
+
+        Ok(())
+    }
+
+    fn exchange(&self, source: Address, first: Address, second: Address) -> Result<()> {
+        let ex = chio::changer_exchange {
+            ce_srctype: source.kind.to_chet(),
+            ce_srcunit: source.unit as libc::c_int,
+            ce_fdsttype: first.kind.to_chet(),
+            ce_fdstunit: first.unit as libc::c_int,
+            ce_sdsttype: second.kind.to_chet(),
+            ce_sdstunit: second.unit as libc::c_int,
+            ce_flags: 0,
+        };
+
+        unsafe {
+            chio::chioexchange(self.file.as_raw_fd(), &ex)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Content checksums of objects and verification of hash based git-annex keys
+//!
+//! Every object is checksummed while it is streamed to tape and verified while
+//! it is read back. Keys of hash based git-annex backends (`SHA256E`,
+//! `BLAKE2B256`, ...) contain the hash of their content, which is verified in
+//! addition.
+
+use blake2::{Blake2b, Blake2s};
+use serde::{Deserialize, Serialize};
+use sha2::digest::consts::{U20, U28, U32, U48, U64};
+use sha2::digest::DynDigest;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Algorithm of the content checksum of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+}
+
+impl Algorithm {
+    /// Tag identifying the algorithm on tape.
+    pub fn tag(self) -> u8 {
+        match self {
+            Self::Sha256 => 1,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        let digest: Box<dyn DynDigest> = match self {
+            Self::Sha256 => Box::new(sha2::Sha256::default()),
+        };
+
+        Hasher {
+            algorithm: self,
+            digest,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Checksum of the contents of an object.
+///
+/// Serialized as `<algorithm>:<hex digest>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub digest: Vec<u8>,
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, to_hex(&self.digest))
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, digest) = s.split_once(':').ok_or(())?;
+
+        Ok(Self {
+            algorithm: algorithm.parse()?,
+            digest: from_hex(digest).ok_or(())?,
+        })
+    }
+}
+
+impl From<Checksum> for String {
+    fn from(value: Checksum) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for Checksum {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .parse()
+            .map_err(|_| format!("invalid checksum {value}"))
+    }
+}
+
+/// Computes a checksum over data which is fed to it piecewise.
+pub struct Hasher {
+    algorithm: Algorithm,
+    digest: Box<dyn DynDigest>,
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    pub fn finish(self) -> Checksum {
+        Checksum {
+            algorithm: self.algorithm,
+            digest: self.digest.finalize().into_vec(),
+        }
+    }
+}
+
+/// Verifies content against the hash contained in its git-annex key.
+pub struct KeyHash {
+    expected: Vec<u8>,
+    digest: Box<dyn DynDigest>,
+}
+
+impl KeyHash {
+    /// Parse the hash out of a key.
+    ///
+    /// Returns `None` for keys of backends which are not hash based or not
+    /// supported, and for chunks, whose key hash covers the whole file.
+    pub fn from_key(key: &str) -> Option<Self> {
+        let (fields, name) = key.split_once("--")?;
+        let mut fields = fields.split('-');
+
+        let backend = fields.next()?;
+        if fields.any(|field| field.starts_with('C')) {
+            return None;
+        }
+
+        // Backends ending in E keep the file extension after the hash.
+        let (backend, hash) = match backend.strip_suffix('E') {
+            Some(backend) => (backend, name.split('.').next()?),
+            None => (backend, name),
+        };
+
+        let digest = digest(backend)?;
+        let expected = from_hex(hash)?;
+        if expected.len() != digest.output_size() {
+            return None;
+        }
+
+        Some(Self { expected, digest })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    /// Check whether the content matches the key.
+    pub fn verify(self) -> bool {
+        *self.digest.finalize() == self.expected[..]
+    }
+}
+
+fn digest(backend: &str) -> Option<Box<dyn DynDigest>> {
+    let digest: Box<dyn DynDigest> = match backend {
+        "SHA224" => Box::new(sha2::Sha224::default()),
+        "SHA256" => Box::new(sha2::Sha256::default()),
+        "SHA384" => Box::new(sha2::Sha384::default()),
+        "SHA512" => Box::new(sha2::Sha512::default()),
+        "SHA3_224" => Box::new(sha3::Sha3_224::default()),
+        "SHA3_256" => Box::new(sha3::Sha3_256::default()),
+        "SHA3_384" => Box::new(sha3::Sha3_384::default()),
+        "SHA3_512" => Box::new(sha3::Sha3_512::default()),
+        "BLAKE2B160" => Box::new(Blake2b::<U20>::default()),
+        "BLAKE2B224" => Box::new(Blake2b::<U28>::default()),
+        "BLAKE2B256" => Box::new(Blake2b::<U32>::default()),
+        "BLAKE2B384" => Box::new(Blake2b::<U48>::default()),
+        "BLAKE2B512" => Box::new(Blake2b::<U64>::default()),
+        "BLAKE2S160" => Box::new(Blake2s::<U20>::default()),
+        "BLAKE2S224" => Box::new(Blake2s::<U28>::default()),
+        "BLAKE2S256" => Box::new(Blake2s::<U32>::default()),
+        _ => return None,
+    };
+
+    Some(digest)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,134 @@
+//! Linux SCSI Medium Changer ioctl definitions
+//!
+//! from: /usr/include/linux/chio.h
+//!
+//! also see: Documentation/scsi/scsi-changer.rst
+
+use nix;
+
+// Element types
+pub const CHET_MT: libc::c_int = 0; // Media transport element (robot).
+pub const CHET_ST: libc::c_int = 1; // Storage element (media slots).
+pub const CHET_IE: libc::c_int = 2; // Import/export element.
+pub const CHET_DT: libc::c_int = 3; // Data transfer element (tape/cdrom/whatever).
+pub const CHET_V1: libc::c_int = 4; // Vendor specific #1.
+pub const CHET_V2: libc::c_int = 5; // Vendor specific #2.
+pub const CHET_V3: libc::c_int = 6; // Vendor specific #3.
+pub const CHET_V4: libc::c_int = 7; // Vendor specific #4.
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct changer_params {
+    pub cp_curpicker: libc::c_int, // Current transport element.
+    pub cp_npickers: libc::c_int,  // Number of transport elements (CHET_MT).
+    pub cp_nslots: libc::c_int,    // Number of storage elements (CHET_ST).
+    pub cp_nportals: libc::c_int,  // Number of import/export elements (CHET_IE).
+    pub cp_ndrives: libc::c_int,   // Number of data transfer elements (CHET_DT).
+}
+
+//#define CHIOGPARAMS _IOR('c', 6,struct changer_params)
+nix::ioctl_read!(chiogparams, b'c', 6, changer_params);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct changer_move {
+    pub cm_fromtype: libc::c_int, // Type/unit of source element.
+    pub cm_fromunit: libc::c_int,
+    pub cm_totype: libc::c_int, // Type/unit of destination element.
+    pub cm_tounit: libc::c_int,
+    pub cm_flags: libc::c_int,
+}
+
+pub const CM_INVERT: libc::c_int = 1; // Flag: rotate media (for double-sided like MOD).
+
+//#define CHIOMOVE _IOW('c', 1,struct changer_move)
+nix::ioctl_write_ptr!(chiomove, b'c', 1, changer_move);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct changer_exchange {
+    pub ce_srctype: libc::c_int, // Type/unit of source element.
+    pub ce_srcunit: libc::c_int,
+    pub ce_fdsttype: libc::c_int, // Type/unit of first destination element.
+    pub ce_fdstunit: libc::c_int,
+    pub ce_sdsttype: libc::c_int, // Type/unit of second destination element.
+    pub ce_sdstunit: libc::c_int,
+    pub ce_flags: libc::c_int,
+}
+
+pub const CE_INVERT1: libc::c_int = 1;
+pub const CE_INVERT2: libc::c_int = 2;
+
+//#define CHIOEXCHANGE _IOW('c', 2,struct changer_exchange)
+nix::ioctl_write_ptr!(chioexchange, b'c', 2, changer_exchange);
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct changer_element_status {
+    pub ces_type: libc::c_int,
+    pub ces_data: *mut libc::c_uchar, // One status byte per element.
+}
+
+pub const CESTATUS_FULL: libc::c_uchar = 0x01; // Full.
+pub const CESTATUS_IMPEXP: libc::c_uchar = 0x02; // Media was imported (inserted by sysop).
+pub const CESTATUS_EXCEPT: libc::c_uchar = 0x04; // Error condition.
+pub const CESTATUS_ACCESS: libc::c_uchar = 0x08; // Access allowed.
+pub const CESTATUS_EXENAB: libc::c_uchar = 0x10; // Element can export media.
+pub const CESTATUS_INENAB: libc::c_uchar = 0x20; // Element can import media.
+
+//#define CHIOGSTATUS _IOW('c', 8,struct changer_element_status)
+nix::ioctl_write_ptr!(chiogstatus, b'c', 8, changer_element_status);
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct changer_get_element {
+    pub cge_type: libc::c_int, // Type/unit.
+    pub cge_unit: libc::c_int,
+    pub cge_status: libc::c_int,  // Status.
+    pub cge_errno: libc::c_int,   // Errno.
+    pub cge_srctype: libc::c_int, // Source element of the last move/exchange.
+    pub cge_srcunit: libc::c_int,
+    pub cge_id: libc::c_int,  // SCSI id (for data transfer elements).
+    pub cge_lun: libc::c_int, // SCSI lun (for data transfer elements).
+    pub cge_pvoltag: [libc::c_char; 36], // Primary volume tag.
+    pub cge_avoltag: [libc::c_char; 36], // Alternate volume tag.
+    pub cge_flags: libc::c_int,
+}
+
+impl Default for changer_get_element {
+    fn default() -> Self {
+        Self {
+            cge_type: 0,
+            cge_unit: 0,
+            cge_status: 0,
+            cge_errno: 0,
+            cge_srctype: 0,
+            cge_srcunit: 0,
+            cge_id: 0,
+            cge_lun: 0,
+            cge_pvoltag: [0; 36],
+            cge_avoltag: [0; 36],
+            cge_flags: 0,
+        }
+    }
+}
+
+pub const CGE_ERRNO: libc::c_int = 0x01; // Errno available.
+pub const CGE_INVERT: libc::c_int = 0x02; // Media inverted.
+pub const CGE_SRC: libc::c_int = 0x04; // Media src available.
+pub const CGE_IDLUN: libc::c_int = 0x08; // ID+LUN available.
+pub const CGE_PVOLTAG: libc::c_int = 0x10; // Primary volume tag available.
+pub const CGE_AVOLTAG: libc::c_int = 0x20; // Alternate volume tag available.
+
+// The kernel writes the element status back into the passed structure,
+// although the request is encoded as a write. It is declared with the
+// encoding of the header and a mutable pointer.
+//#define CHIOGELEM _IOW('c',16,struct changer_get_element)
+nix::ioctl_readwrite_bad!(
+    chiogelem,
+    nix::request_code_write!(b'c', 16, std::mem::size_of::<changer_get_element>()),
+    changer_get_element
+);
+
+//#define CHIOINITELEM _IO('c',17)
+nix::ioctl_none!(chioinitelem, b'c', 17);
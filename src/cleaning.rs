@@ -0,0 +1,208 @@
+//! Detection and bookkeeping of drive cleaning
+//!
+//! Drives signal that they need cleaning in several ways: the st driver sets
+//! `GMT_CLN` in the generic status (if configured with `MT_ST_SET_CLN`), the
+//! sequential-access device log page has a cleaning required parameter,
+//! TapeAlert raises the "Clean now" or "Clean periodic" flags and the sense
+//! data may carry ASC/ASCQ 0x00/0x17 "Cleaning requested".
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::changer::{self, Address, Changer};
+use crate::tape::{self, Drive};
+use crate::{mt, tapealert};
+
+/// Maximum time a cleaning cycle may take.
+pub const CLEANING_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug)]
+pub enum Error {
+    Changer(changer::Error),
+    Tape(mt::Error),
+}
+
+impl From<changer::Error> for Error {
+    fn from(value: changer::Error) -> Self {
+        Self::Changer(value)
+    }
+}
+
+impl From<mt::Error> for Error {
+    fn from(value: mt::Error) -> Self {
+        Self::Tape(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Changer(e) => write!(f, "changer: {e}"),
+            Self::Tape(e) => write!(f, "drive: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Why a drive requested cleaning.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The st driver reported `GMT_CLN` in the generic status.
+    StatusFlag,
+
+    /// The sequential-access device log page reports that cleaning is required.
+    LogPage,
+
+    /// A TapeAlert flag requested cleaning.
+    TapeAlert(tapealert::Flag),
+
+    /// The sense data contained a cleaning request.
+    Sense { asc: u8, ascq: u8 },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StatusFlag => write!(f, "drive status"),
+            Self::LogPage => write!(f, "sequential-access device log page"),
+            Self::TapeAlert(flag) => write!(f, "TapeAlert {flag}"),
+            Self::Sense { asc, ascq } => {
+                write!(f, "sense data (ASC 0x{asc:02x}, ASCQ 0x{ascq:02x})")
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// The drive requested cleaning.
+    Requested(Reason),
+
+    /// A cleaning cartridge has been run through the drive.
+    Performed {
+        /// Changer slot of the cleaning cartridge.
+        slot: Option<u16>,
+    },
+
+    /// Cleaning was attempted but failed.
+    Failed(String),
+}
+
+/// A cleaning related event in the history of a drive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+/// What to do with writes while a drive requests cleaning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Refuse to write until the drive has been cleaned.
+    #[default]
+    Refuse,
+
+    /// Only warn about the cleaning request.
+    Warn,
+}
+
+impl FromStr for Policy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "refuse" => Ok(Self::Refuse),
+            "warn" => Ok(Self::Warn),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Location of a drive specific cleaning request bit in the sense data.
+///
+/// Parsed from `<byte>:<mask>[:<pattern>]`, e.g. `70:0x08` for drives which
+/// set bit 3 of sense byte 70 when they need cleaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenseBits {
+    pub byte: u8,
+    pub mask: u8,
+    pub pattern: u8,
+}
+
+impl FromStr for SenseBits {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| match v.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| ()),
+            None => v.parse::<u8>().map_err(|_| ()),
+        };
+
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            [byte, mask] => Ok(Self {
+                byte: parse(byte)?,
+                mask: parse(mask)?,
+                pattern: 0,
+            }),
+            [byte, mask, pattern] => Ok(Self {
+                byte: parse(byte)?,
+                mask: parse(mask)?,
+                pattern: parse(pattern)?,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Check whether the TapeAlert flags request cleaning.
+pub fn from_tape_alerts(flags: &[tapealert::Flag]) -> Option<Reason> {
+    flags
+        .iter()
+        .find(|f| matches!(f.0, tapealert::CLEAN_NOW | tapealert::CLEAN_PERIODIC))
+        .map(|f| Reason::TapeAlert(*f))
+}
+
+/// Check whether the sense data requests cleaning.
+pub fn from_sense(asc: u8, ascq: u8) -> Option<Reason> {
+    match (asc, ascq) {
+        (0x00, 0x17) => Some(Reason::Sense { asc, ascq }),
+        _ => None,
+    }
+}
+
+/// Clean a drive with the cleaning cartridge from a changer slot.
+///
+/// A data cartridge in the drive is returned to its slot first and loaded
+/// again after the cleaning cartridge has been put back.
+pub fn clean(
+    changer: &dyn Changer,
+    drive: &Drive,
+    drive_unit: u16,
+    cleaning_slot: u16,
+) -> Result<(), Error> {
+    let cleaning_slot = Address::slot(cleaning_slot);
+
+    let data_slot = if changer.element(Address::drive(drive_unit))?.full {
+        drive.eject()?;
+        Some(changer.unload(drive_unit, None)?)
+    } else {
+        None
+    };
+
+    changer.load(cleaning_slot, drive_unit)?;
+
+    // The drive ejects the cleaning cartridge by itself once it is done.
+    drive.wait_empty(CLEANING_TIMEOUT)?;
+    changer.unload(drive_unit, Some(cleaning_slot))?;
+
+    if let Some(slot) = data_slot {
+        changer.load(slot, drive_unit)?;
+        drive.wait_ready(tape::LOAD_TIMEOUT)?;
+    }
+
+    Ok(())
+}
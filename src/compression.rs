@@ -0,0 +1,191 @@
+//! Software compression of object contents with zstd
+//!
+//! The first chunk of each object is compressed on trial. Objects whose first
+//! chunk does not shrink noticeably, e.g. media files or archives, are stored
+//! as they are. The others are compressed into a temporary file, as the
+//! length of the compressed contents has to be known before they are written.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Compression level used if none is configured.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Length of the chunk which is compressed on trial.
+const TRIAL_SIZE: u64 = 256 * 1024;
+
+/// Number of temporary files created by this process, to name them.
+static SPOOLED: AtomicU64 = AtomicU64::new(0);
+
+/// Compression with zstd at a given level, written as `zstd[:<level>]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub level: i32,
+}
+
+impl Compression {
+    pub fn new(level: i32) -> Option<Self> {
+        zstd::compression_level_range()
+            .contains(&level)
+            .then_some(Self { level })
+    }
+
+    /// Compress the contents of an object, unless the first chunk shows that
+    /// compression does not pay off.
+    pub fn compress<'a>(&self, data: &'a mut dyn Read) -> io::Result<Compressed<'a>> {
+        let mut trial = Vec::new();
+        (&mut *data).take(TRIAL_SIZE).read_to_end(&mut trial)?;
+
+        // Less than 1/32 saved is not worth decompressing for.
+        let compressed = zstd::bulk::compress(&trial, self.level)?;
+        let gain = compressed.len() + trial.len() / 32 < trial.len();
+
+        let mut data = Cursor::new(trial).chain(data);
+        if !gain {
+            return Ok(Compressed::Skipped(data));
+        }
+
+        let mut file = spool_file()?;
+
+        let mut encoder = zstd::Encoder::new(&mut file, self.level)?;
+        encoder.include_checksum(true)?;
+        let uncompressed = io::copy(&mut data, &mut encoder)?;
+        encoder.finish()?;
+
+        let length = file.stream_position()?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(Compressed::Spooled {
+            file,
+            length,
+            uncompressed,
+        })
+    }
+
+    /// Compress the contents of a small object in memory as a whole, `None`
+    /// if that does not pay off.
+    pub fn compress_bytes(&self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let compressed = zstd::bulk::compress(data, self.level)?;
+
+        Ok((compressed.len() + data.len() / 32 < data.len()).then_some(compressed))
+    }
+}
+
+/// Decompress the contents of a small object compressed by
+/// [`Compression::compress_bytes`], which are `length` bytes uncompressed.
+pub fn decompress_bytes(data: &[u8], length: u64) -> io::Result<Vec<u8>> {
+    let contents = zstd::bulk::decompress(data, length as usize)?;
+
+    if contents.len() as u64 != length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompressed contents are too short",
+        ));
+    }
+
+    Ok(contents)
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "zstd:{}", self.level)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("zstd", level)) => Self::new(level.parse().map_err(|_| ())?).ok_or(()),
+            None if s == "zstd" => Ok(Self {
+                level: DEFAULT_LEVEL,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Contents of an object, ready to be written.
+pub enum Compressed<'a> {
+    /// Compression does not pay off, the contents are stored as they are.
+    Skipped(io::Chain<Cursor<Vec<u8>>, &'a mut dyn Read>),
+    /// The compressed contents, in a temporary file.
+    Spooled {
+        file: File,
+        length: u64,
+        uncompressed: u64,
+    },
+}
+
+/// Create a temporary file which is removed once it is closed.
+fn spool_file() -> io::Result<File> {
+    let n = SPOOLED.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!(
+        "git-annex-remote-tape-{}-{n}.zst",
+        std::process::id()
+    ));
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+
+    Ok(file)
+}
+
+/// Passes the stored contents of an object on to `inner`, decompressing
+/// them if necessary.
+pub enum Decoder<W: Write> {
+    Plain(W),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+}
+
+impl<W: Write> Decoder<W> {
+    pub fn new(compressed: bool, inner: W) -> io::Result<Self> {
+        if compressed {
+            Ok(Self::Zstd(zstd::stream::write::Decoder::new(inner)?))
+        } else {
+            Ok(Self::Plain(inner))
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self {
+            Self::Plain(inner) => inner,
+            Self::Zstd(decoder) => decoder.get_ref(),
+        }
+    }
+
+    /// Write out the remaining contents and return the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Plain(inner) => Ok(inner),
+            Self::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(inner) => inner.write(buf),
+            Self::Zstd(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(inner) => inner.flush(),
+            Self::Zstd(decoder) => decoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Encryption of objects with XChaCha20-Poly1305
+//!
+//! The contents of an object are sealed record by record. Each record has a
+//! nonce made of a random prefix, which is stored in the object header, the
+//! number of the record within the object and a flag marking the last record,
+//! so that records can neither be reordered nor dropped from the end.
+//!
+//! Keys and checksums of objects are sealed one by one with a random nonce
+//! which precedes them.
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fs, io};
+
+use crate::checksum::{self, Checksum};
+
+/// Length of the authentication tag added to every sealed record and field.
+pub const TAG_LENGTH: usize = 16;
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+
+/// Random part of the nonces of the records of an object.
+pub type Nonce = [u8; NONCE_PREFIX_LENGTH];
+pub const NONCE_PREFIX_LENGTH: usize = NONCE_LENGTH - 5;
+
+/// Identifies a key without revealing it, from the SHA-256 of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyId(pub [u8; 8]);
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", checksum::to_hex(&self.0))
+    }
+}
+
+/// A key for encrypting objects, written as 64 hexadecimal digits.
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u8; KEY_LENGTH],
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(key: [u8; KEY_LENGTH]) -> Self {
+        Self {
+            key,
+            aead: XChaCha20Poly1305::new(&key.into()),
+        }
+    }
+
+    /// Create a new random key.
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+
+        Self::new(key)
+    }
+
+    /// Read a key from a file holding its hexadecimal digits.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not hold a key", path.display()),
+            )
+        })
+    }
+
+    pub fn id(&self) -> KeyId {
+        let digest = Sha256::new()
+            .chain_update(b"git-annex-remote-tape key id")
+            .chain_update(self.key)
+            .finalize();
+
+        KeyId(digest[..8].try_into().unwrap())
+    }
+
+    /// Create the nonce prefix for the records of a new object.
+    pub fn nonce() -> Nonce {
+        let mut nonce = Nonce::default();
+        OsRng.fill_bytes(&mut nonce);
+
+        nonce
+    }
+
+    /// Seal record `index` of an object.
+    pub fn seal_record(&self, nonce: &Nonce, index: u32, last: bool, data: &[u8]) -> Vec<u8> {
+        // Encryption only fails for data far beyond the size of a record.
+        self.aead
+            .encrypt(&record_nonce(nonce, index, last), data)
+            .unwrap()
+    }
+
+    /// Open record `index` of an object, `None` if it has been tampered with.
+    pub fn open_record(
+        &self,
+        nonce: &Nonce,
+        index: u32,
+        last: bool,
+        record: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.aead
+            .decrypt(&record_nonce(nonce, index, last), record)
+            .ok()
+    }
+
+    /// Seal a key, the result is written as hexadecimal digits.
+    pub fn seal_key(&self, key: &str) -> String {
+        checksum::to_hex(&self.seal(key.as_bytes()))
+    }
+
+    pub fn open_key(&self, sealed: &str) -> Option<String> {
+        let key = self.open(&checksum::from_hex(sealed)?)?;
+
+        String::from_utf8(key).ok()
+    }
+
+    /// Seal the digest of a checksum, which would otherwise reveal the
+    /// hash in the key.
+    pub fn seal_checksum(&self, checksum: &Checksum) -> Checksum {
+        Checksum {
+            algorithm: checksum.algorithm,
+            digest: self.seal(&checksum.digest),
+        }
+    }
+
+    pub fn open_checksum(&self, sealed: &Checksum) -> Option<Checksum> {
+        Some(Checksum {
+            algorithm: sealed.algorithm,
+            digest: self.open(&sealed.digest)?,
+        })
+    }
+
+    /// Seal data with a random nonce, which precedes the result.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let mut nonce = XNonce::default();
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(self.aead.encrypt(&nonce, data).unwrap());
+
+        sealed
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LENGTH {
+            return None;
+        }
+
+        let (nonce, data) = sealed.split_at(NONCE_LENGTH);
+        self.aead.decrypt(XNonce::from_slice(nonce), data).ok()
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", checksum::to_hex(&self.key))
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher").field("id", &self.id()).finish()
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = checksum::from_hex(s).ok_or(())?;
+
+        Ok(Self::new(key.try_into().map_err(|_| ())?))
+    }
+}
+
+/// Length of the sealed records of `length` bytes of contents split into
+/// records of `size` bytes. Even empty contents are sealed into one record.
+pub fn sealed_length(length: u64, size: usize) -> u64 {
+    let records = length.div_ceil(size as u64).max(1);
+
+    length + records * TAG_LENGTH as u64
+}
+
+fn record_nonce(nonce: &Nonce, index: u32, last: bool) -> XNonce {
+    let mut full = XNonce::default();
+    full[..NONCE_PREFIX_LENGTH].copy_from_slice(nonce);
+    full[NONCE_PREFIX_LENGTH..NONCE_LENGTH - 1].copy_from_slice(&index.to_be_bytes());
+    full[NONCE_LENGTH - 1] = last as u8;
+
+    full
+}
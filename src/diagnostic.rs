@@ -0,0 +1,169 @@
+//! Drive self-tests and write/read/compare tests
+//!
+//! The results are stored in the catalog together with the cartridge which
+//! was used, so that repeated failures can be attributed to either the drive
+//! or the tape.
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::{mt, scsi};
+
+/// Outcome of the drive self-test.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SelfTest {
+    Passed,
+    Failed(String),
+    Unsupported,
+}
+
+/// Outcome of the write/read/compare test for a single block size.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BlockSizeResult {
+    pub block_size: usize,
+    pub blocks: usize,
+    pub write_seconds: f64,
+    pub read_seconds: f64,
+    pub mismatches: usize,
+    pub error: Option<String>,
+}
+
+impl BlockSizeResult {
+    pub fn passed(&self) -> bool {
+        self.mismatches == 0 && self.error.is_none()
+    }
+
+    fn throughput(&self, seconds: f64) -> f64 {
+        if seconds > 0.0 {
+            (self.block_size * self.blocks) as f64 / seconds / 1_000_000.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Write throughput in MB/s.
+    pub fn write_throughput(&self) -> f64 {
+        self.throughput(self.write_seconds)
+    }
+
+    /// Read throughput in MB/s.
+    pub fn read_throughput(&self) -> f64 {
+        self.throughput(self.read_seconds)
+    }
+}
+
+/// A complete diagnostic run as stored in the catalog.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub time: u64,
+    pub self_test: SelfTest,
+
+    /// Serial number or barcode of the cartridge used for the test.
+    pub media: Option<String>,
+    pub write_read: Vec<BlockSizeResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        !matches!(self.self_test, SelfTest::Failed(_))
+            && self.write_read.iter().all(BlockSizeResult::passed)
+    }
+}
+
+/// Run the non-destructive self-test of the drive.
+pub fn self_test(mt: &dyn mt::TapeDevice) -> SelfTest {
+    match mt.send_diagnostic() {
+        Ok(()) => SelfTest::Passed,
+        Err(mt::Error::Scsi(scsi::Error::CheckCondition(sense)))
+            if sense.key == scsi::SenseKey::IllegalRequest =>
+        {
+            // Fall back to the st driver in case the drive rejects SEND DIAGNOSTIC.
+            match mt.self_test() {
+                Ok(_) => SelfTest::Passed,
+                Err(_) => SelfTest::Unsupported,
+            }
+        }
+        Err(e) => SelfTest::Failed(e.to_string()),
+    }
+}
+
+/// Fill a block with a pattern which is unique for each block.
+fn fill_pattern(block: &mut [u8], seed: u64) {
+    // xorshift64
+    let mut state = seed.wrapping_mul(0x9e3779b97f4a7c15) | 1;
+
+    for chunk in block.chunks_mut(8) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        let bytes = state.to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+/// Write `blocks` blocks of `block_size` bytes from the beginning of the
+/// tape, rewind and read them back while comparing them to the written data.
+///
+/// This overwrites the loaded cartridge!
+pub fn write_read_compare(
+    mt: &dyn mt::TapeDevice,
+    block_size: usize,
+    blocks: usize,
+) -> BlockSizeResult {
+    let mut result = BlockSizeResult {
+        block_size,
+        blocks,
+        ..Default::default()
+    };
+
+    if let Err(e) = write_read_compare_inner(mt, &mut result) {
+        result.error = Some(e.to_string());
+    }
+
+    result
+}
+
+fn write_read_compare_inner(
+    mt: &dyn mt::TapeDevice,
+    result: &mut BlockSizeResult,
+) -> mt::Result<()> {
+    let mut expected = vec![0u8; result.block_size];
+    let mut actual = vec![0u8; result.block_size];
+
+    // Use variable block mode so that each write results in one record.
+    mt.set_block_length(0)?;
+    mt.rewind()?;
+
+    let start = Instant::now();
+    for i in 0..result.blocks {
+        fill_pattern(&mut expected, i as u64);
+
+        let n = mt.write_block(&expected)?;
+        if n != expected.len() {
+            result.error = Some(format!("short write of block {i}: {n} bytes"));
+            return Ok(());
+        }
+    }
+
+    mt.weof(1)?;
+    result.write_seconds = start.elapsed().as_secs_f64();
+
+    mt.rewind()?;
+
+    let start = Instant::now();
+    for i in 0..result.blocks {
+        fill_pattern(&mut expected, i as u64);
+
+        let n = mt.read_block(&mut actual)?;
+        if n != expected.len() || actual != expected {
+            result.mismatches += 1;
+        }
+    }
+
+    result.read_seconds = start.elapsed().as_secs_f64();
+
+    mt.rewind()?;
+
+    Ok(())
+}
@@ -1,29 +1,1160 @@
+//! On-tape encoding of the media, archive and object headers
+//!
+//! Every header is written as a single tape record with the following layout.
+//! All integers are big endian.
+//!
+//! ```text
+//! offset  size  field
+//!      0     8  magic, identifies the header type
+//!      8     1  version
+//!      9     3  reserved, zero
+//!     12     4  header length in bytes, including magic and checksum
+//!     16     n  fields of the header type
+//!  len-4     4  CRC32C over all preceding bytes of the header
+//! ```
+//!
+//! Strings are encoded as a `u16` length followed by UTF-8 bytes.
+//!
+//! New fields are only ever appended to a header type, together with a new
+//! version. Decoders read the fields they know and skip the rest, so that
+//! headers written by newer versions stay readable. Incompatible changes
+//! require a new magic.
+
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::checksum::{Algorithm, Checksum};
+use crate::crypto::{KeyId, Nonce};
+use crate::parity::Parity;
+
+/// Magic of the media header ("MEDIATHD").
+pub const MEDIA_HEADER_MAGIC: [u8; 8] = *b"MEDIATHD";
+
+/// Magic of the archive header ("ARCHIVHD").
+pub const ARCHIVE_HEADER_MAGIC: [u8; 8] = *b"ARCHIVHD";
+
+/// Magic of the object header ("OBJECTHD").
+pub const OBJECT_HEADER_MAGIC: [u8; 8] = *b"OBJECTHD";
+
+/// Magic of the object trailer ("OBJECTTR").
+pub const OBJECT_TRAILER_MAGIC: [u8; 8] = *b"OBJECTTR";
+
+/// Magic of the table of contents of an archive ("ARCHVTOC").
+pub const ARCHIVE_TOC_MAGIC: [u8; 8] = *b"ARCHVTOC";
+
+/// Magic of the tombstone of a removed object ("TOMBSTON").
+pub const TOMBSTONE_MAGIC: [u8; 8] = *b"TOMBSTON";
+
+const ARCHIVE_HEADER_VERSION: u8 = 4;
+const MEDIA_HEADER_VERSION: u8 = 3;
+const OBJECT_HEADER_VERSION: u8 = 8;
+const OBJECT_TRAILER_VERSION: u8 = 2;
+const ARCHIVE_TOC_VERSION: u8 = 2;
+const TOMBSTONE_VERSION: u8 = 1;
+
+/// Writer recorded in new archive headers.
+pub const WRITER: &str = concat!("git-annex-remote-tape ", env!("CARGO_PKG_VERSION"));
+
+/// Encoded metadata is limited to 16 KiB, further paths are left out.
+const METADATA_LIMIT: usize = 16 * 1024;
+
+/// Length of magic, version, reserved bytes and header length.
+const PREFIX_LENGTH: usize = 16;
+const CHECKSUM_LENGTH: usize = 4;
+
+/// Length of the fields of a table of contents besides its entries.
+pub const ARCHIVE_TOC_OVERHEAD: usize = PREFIX_LENGTH + 8 + 4 + 4 + 4 + CHECKSUM_LENGTH;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The record is shorter than the header.
+    Truncated,
+    /// The record does not start with the magic of the expected header type.
+    Magic([u8; 8]),
+    Version(u8),
+    Length(u32),
+    Checksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// A string field is not valid UTF-8.
+    Encoding,
+    /// Unknown checksum algorithm.
+    Algorithm(u8),
+    /// Invalid ratio of data to parity records.
+    Parity(u8, u8),
+    /// Unknown compression.
+    Compression(u8),
+    /// Unknown layout of archives.
+    ArchiveFormat(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "header is truncated"),
+            Self::Magic(magic) => write!(f, "unexpected magic {}", magic.escape_ascii()),
+            Self::Version(version) => write!(f, "unsupported version {version}"),
+            Self::Length(length) => write!(f, "invalid header length {length}"),
+            Self::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch (expected 0x{expected:08x}, found 0x{actual:08x})"
+            ),
+            Self::Encoding => write!(f, "string is not valid UTF-8"),
+            Self::Algorithm(tag) => write!(f, "unknown checksum algorithm {tag}"),
+            Self::Parity(data, parity) => write!(f, "invalid parity ratio {data}+{parity}"),
+            Self::Compression(codec) => write!(f, "unknown compression {codec}"),
+            Self::ArchiveFormat(tag) => write!(f, "unknown archive format {tag}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A header type with a fixed magic and versioned fields.
+pub trait Header: Sized {
+    const MAGIC: [u8; 8];
+    const VERSION: u8;
+
+    fn encode_fields(&self, fields: &mut Encoder);
+
+    /// Decode the fields of a header written with `version`.
+    fn decode_fields(version: u8, fields: &mut Decoder) -> Result<Self, Error>;
+}
+
+/// Writes the fields of a header.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Write a string, truncated to 64 KiB at a character boundary.
+    pub fn str(&mut self, value: &str) {
+        let mut end = value.len().min(u16::MAX as usize);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.buf.extend_from_slice(&(end as u16).to_be_bytes());
+        self.buf.extend_from_slice(&value.as_bytes()[..end]);
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Write bytes prefixed with their length as a `u16`.
+    fn str_bytes(&mut self, value: &[u8]) {
+        self.buf
+            .extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(value);
+    }
+}
+
+/// Reads the fields of a header.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < n {
+            return Err(Error::Truncated);
+        }
+
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn str(&mut self) -> Result<String, Error> {
+        let length = self.u16()?;
+        let bytes = self.take(length as usize)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Encoding)
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        self.take(n)
+    }
+
+    /// Read bytes prefixed with their length as a `u16`.
+    fn str_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.u16()?;
+
+        self.take(length as usize)
+    }
+}
+
+/// Encode a header into a single tape record.
+pub fn encode<T: Header>(header: &T) -> Vec<u8> {
+    let mut fields = Encoder::default();
+    header.encode_fields(&mut fields);
+
+    let length = PREFIX_LENGTH + fields.buf.len() + CHECKSUM_LENGTH;
+
+    let mut record = Vec::with_capacity(length);
+    record.extend_from_slice(&T::MAGIC);
+    record.push(T::VERSION);
+    record.extend_from_slice(&[0; 3]);
+    record.extend_from_slice(&(length as u32).to_be_bytes());
+    record.extend_from_slice(&fields.buf);
+
+    let checksum = crc32c::crc32c(&record);
+    record.extend_from_slice(&checksum.to_be_bytes());
+
+    record
+}
+
+/// Decode a header from the beginning of a tape record.
+pub fn decode<T: Header>(record: &[u8]) -> Result<T, Error> {
+    if record.len() < PREFIX_LENGTH {
+        return Err(Error::Truncated);
+    }
+
+    let magic: [u8; 8] = record[..8].try_into().unwrap();
+    if magic != T::MAGIC {
+        return Err(Error::Magic(magic));
+    }
+
+    let version = record[8];
+    if version == 0 {
+        return Err(Error::Version(version));
+    }
+
+    let length = u32::from_be_bytes(record[12..16].try_into().unwrap());
+    if (length as usize) < PREFIX_LENGTH + CHECKSUM_LENGTH {
+        return Err(Error::Length(length));
+    }
 
-static MEDIA_HEADER_MAGIC: i64 = 0x4d45444941544844;
+    let header = record.get(..length as usize).ok_or(Error::Truncated)?;
+    let (content, checksum) = header.split_at(header.len() - CHECKSUM_LENGTH);
 
-static ARCHIVE_HEADER_VERSION: u8 = 1;
-static MEDIA_HEADER_VERSION: u8 = 1;
-static OBJECT_HEADER_VERSION: u8 = 1;
+    let expected = u32::from_be_bytes(checksum.try_into().unwrap());
+    let actual = crc32c::crc32c(content);
+    if expected != actual {
+        return Err(Error::Checksum { expected, actual });
+    }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct MediaHeader<'a> {
-    version: u8,
-    magic: i64,
-    creation_time: u64,
-    host: &'a str,
+    T::decode_fields(
+        version,
+        &mut Decoder {
+            buf: &content[PREFIX_LENGTH..],
+        },
+    )
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ArchiveHeader<'a> {
-    version: i8,
-    creation_time: u64,
-    host: &'a str,
+/// First record of a cartridge, followed by a filemark.
+///
+/// Version 2 added the identity of the cartridge. Version 3 added the layout
+/// of its archives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaHeader {
+    pub creation_time: u64,
+    pub host: String,
+    pub identity: MediaIdentity,
+    pub format: ArchiveFormat,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ObjectHeader<'a> {
-    version: u8,
-    object_length: u64,
-    key: &'a str,
+/// Layout of the archives on a cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Records made of the headers in this module.
+    #[default]
+    Native,
+
+    /// POSIX pax tar streams, see [`crate::tar`].
+    Tar,
+}
+
+impl ArchiveFormat {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Native => 0,
+            Self::Tar => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Native),
+            1 => Some(Self::Tar),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Tar => write!(f, "tar"),
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Self::Native),
+            "tar" => Ok(Self::Tar),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Identifies a cartridge and the remote it belongs to.
+///
+/// Fields are empty if unknown, e.g. in media headers of version 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaIdentity {
+    /// UUID of the cartridge.
+    pub uuid: String,
+
+    /// Human readable label, usually the barcode.
+    pub label: String,
+
+    /// Pool of cartridges the cartridge has been added to.
+    pub pool: String,
+
+    /// UUID of the special remote which owns the cartridge. Cartridges
+    /// without an owner can be written by any remote.
+    pub remote: String,
+}
+
+impl MediaIdentity {
+    /// Check whether the remote with UUID `remote` may append archives.
+    pub fn accepts(&self, remote: &str) -> bool {
+        self.remote.is_empty() || remote.is_empty() || self.remote == remote
+    }
+}
+
+impl MediaHeader {
+    pub fn new(creation_time: u64, host: &str) -> Self {
+        Self {
+            creation_time,
+            host: host.to_string(),
+            identity: MediaIdentity::default(),
+            format: ArchiveFormat::Native,
+        }
+    }
+
+    pub fn with_identity(self, identity: MediaIdentity) -> Self {
+        Self { identity, ..self }
+    }
+
+    pub fn with_format(self, format: ArchiveFormat) -> Self {
+        Self { format, ..self }
+    }
+}
+
+impl Header for MediaHeader {
+    const MAGIC: [u8; 8] = MEDIA_HEADER_MAGIC;
+    const VERSION: u8 = MEDIA_HEADER_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u64(self.creation_time);
+        fields.str(&self.host);
+        fields.str(&self.identity.uuid);
+        fields.str(&self.identity.label);
+        fields.str(&self.identity.pool);
+        fields.str(&self.identity.remote);
+        fields.u8(self.format.tag());
+    }
+
+    fn decode_fields(version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        let creation_time = fields.u64()?;
+        let host = fields.str()?;
+
+        let identity = match version {
+            1 => MediaIdentity::default(),
+            _ => MediaIdentity {
+                uuid: fields.str()?,
+                label: fields.str()?,
+                pool: fields.str()?,
+                remote: fields.str()?,
+            },
+        };
+
+        let format = match version {
+            1 | 2 => ArchiveFormat::Native,
+            _ => {
+                let tag = fields.u8()?;
+                ArchiveFormat::from_tag(tag).ok_or(Error::ArchiveFormat(tag))?
+            }
+        };
+
+        Ok(Self {
+            creation_time,
+            host,
+            identity,
+            format,
+        })
+    }
+}
+
+/// First record of an archive.
+///
+/// Version 2 added the ratio of data to parity records of the objects in the
+/// archive. Version 3 added the id of the key the objects are encrypted with.
+/// Version 4 added the origin of the archive and the version of its writer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveHeader {
+    pub creation_time: u64,
+    pub host: String,
+    pub parity: Option<Parity>,
+    pub key_id: Option<KeyId>,
+    pub origin: Origin,
+
+    /// Name and version of the program which wrote the archive.
+    pub writer: String,
+}
+
+/// The git-annex repository and remote an archive has been written for.
+///
+/// Fields are empty if unknown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Origin {
+    /// UUID of the git-annex repository.
+    pub repository: String,
+
+    /// UUID of the special remote.
+    pub remote: String,
+
+    /// Name of the git remote of the special remote.
+    pub remote_name: String,
+}
+
+impl ArchiveHeader {
+    pub fn new(creation_time: u64, host: &str) -> Self {
+        Self {
+            creation_time,
+            host: host.to_string(),
+            parity: None,
+            key_id: None,
+            origin: Origin::default(),
+            writer: WRITER.to_string(),
+        }
+    }
+
+    /// Protect the contents of the objects in the archive with parity records.
+    pub fn with_parity(self, parity: Option<Parity>) -> Self {
+        Self { parity, ..self }
+    }
+
+    /// Mark the objects in the archive as encrypted with the key `key_id`.
+    pub fn with_key_id(self, key_id: Option<KeyId>) -> Self {
+        Self { key_id, ..self }
+    }
+
+    pub fn with_origin(self, origin: Origin) -> Self {
+        Self { origin, ..self }
+    }
+}
+
+impl Header for ArchiveHeader {
+    const MAGIC: [u8; 8] = ARCHIVE_HEADER_MAGIC;
+    const VERSION: u8 = ARCHIVE_HEADER_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u64(self.creation_time);
+        fields.str(&self.host);
+        encode_parity(fields, self.parity);
+        encode_optional(fields, self.key_id.as_ref().map(|id| &id.0[..]));
+
+        fields.str(&self.origin.repository);
+        fields.str(&self.origin.remote);
+        fields.str(&self.origin.remote_name);
+        fields.str(&self.writer);
+    }
+
+    fn decode_fields(version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        let creation_time = fields.u64()?;
+        let host = fields.str()?;
+
+        let parity = match version {
+            1 => None,
+            _ => decode_parity(fields)?,
+        };
+
+        let key_id = match version {
+            1 | 2 => None,
+            _ => decode_optional(fields)?.map(KeyId),
+        };
+
+        let (origin, writer) = match version {
+            1..=3 => (Origin::default(), String::new()),
+            _ => (
+                Origin {
+                    repository: fields.str()?,
+                    remote: fields.str()?,
+                    remote_name: fields.str()?,
+                },
+                fields.str()?,
+            ),
+        };
+
+        Ok(Self {
+            creation_time,
+            host,
+            parity,
+            key_id,
+            origin,
+            writer,
+        })
+    }
+}
+
+/// Record preceding the contents of an object.
+///
+/// Version 2 added the checksum algorithm. Objects with a checksum are
+/// followed by an [`ObjectTrailer`]. Version 3 added the parity ratio of the
+/// archive, so that objects can be read without their archive header.
+/// Version 4 added the members of packs. Version 5 added compression, the
+/// object length is that of the compressed contents. Version 6 added the
+/// nonce of encrypted objects, whose keys, member checksums and contents are
+/// sealed. Version 7 added the [`Metadata`] of the object and of each member.
+/// Version 8 added the compression of each member.
+///
+/// A pack holds the contents of many small objects back to back. It has an
+/// empty key and lists its members instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHeader {
+    pub object_length: u64,
+    pub key: String,
+    pub checksum: Option<Algorithm>,
+    pub parity: Option<Parity>,
+    pub members: Vec<PackMember>,
+
+    /// The contents are compressed with zstd.
+    pub compressed: bool,
+    pub uncompressed_length: u64,
+
+    pub nonce: Option<Nonce>,
+
+    /// Encoded [`Metadata`], sealed in encrypted objects.
+    pub metadata: Vec<u8>,
+}
+
+/// An object stored in a pack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackMember {
+    pub key: String,
+
+    /// Offset of the contents in bytes from the start of the pack.
+    pub offset: u64,
+
+    /// Length of the contents in the pack.
+    pub length: u64,
+
+    /// Checksum of the uncompressed contents.
+    pub checksum: Checksum,
+
+    /// Encoded [`Metadata`], sealed in encrypted packs.
+    pub metadata: Vec<u8>,
+
+    /// The contents are compressed with zstd, on their own.
+    pub compressed: bool,
+    pub uncompressed_length: u64,
+}
+
+impl PackMember {
+    /// Encoded length of the member, including its length prefixes.
+    pub fn encoded_len(&self) -> usize {
+        self.to_entry().encoded_len() + 2 + self.metadata.len() + 1 + 8
+    }
+
+    // Members are encoded like entries of a table of contents.
+    fn to_entry(&self) -> TocEntry {
+        TocEntry {
+            key: self.key.clone(),
+            offset: self.offset,
+            length: self.length,
+            checksum: Some(self.checksum.clone()),
+            tombstone: false,
+        }
+    }
+
+    fn decode(fields: &mut Decoder) -> Result<Self, Error> {
+        let entry = TocEntry::decode(fields)?;
+
+        Ok(Self {
+            key: entry.key,
+            offset: entry.offset,
+            length: entry.length,
+            checksum: entry.checksum.ok_or(Error::Algorithm(0))?,
+            metadata: Vec::new(),
+            compressed: false,
+            uncompressed_length: entry.length,
+        })
+    }
+}
+
+/// Describes where an object came from, so that it can be restored without
+/// the repository.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Paths of the files in the work tree which use the object.
+    pub paths: Vec<String>,
+
+    /// Modification time of the contents in seconds since the epoch.
+    pub mtime: Option<u64>,
+
+    /// The commit checked out when the object was stored.
+    pub commit: String,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Encode the metadata as stored in object headers, empty without any.
+    pub fn encode(&self) -> Vec<u8> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let mut paths = Encoder::default();
+        let mut count = 0;
+        for path in &self.paths {
+            if paths.buf.len() + 2 + path.len() > METADATA_LIMIT {
+                break;
+            }
+
+            paths.str(path);
+            count += 1;
+        }
+
+        let mut fields = Encoder::default();
+        fields.u32(count);
+        fields.bytes(&paths.buf);
+
+        let mtime = self.mtime.map(u64::to_be_bytes);
+        encode_optional(&mut fields, mtime.as_ref().map(|mtime| &mtime[..]));
+        fields.str(&self.commit);
+
+        fields.buf
+    }
+
+    pub fn decode(encoded: &[u8]) -> Result<Self, Error> {
+        if encoded.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut fields = Decoder { buf: encoded };
+        let paths = (0..fields.u32()?)
+            .map(|_| fields.str())
+            .collect::<Result<_, _>>()?;
+        let mtime = decode_optional(&mut fields)?.map(u64::from_be_bytes);
+        let commit = fields.str()?;
+
+        Ok(Self {
+            paths,
+            mtime,
+            commit,
+        })
+    }
+}
+
+impl ObjectHeader {
+    pub fn new(object_length: u64, key: &str, checksum: Algorithm) -> Self {
+        Self {
+            object_length,
+            key: key.to_string(),
+            checksum: Some(checksum),
+            parity: None,
+            members: Vec::new(),
+            compressed: false,
+            uncompressed_length: object_length,
+            nonce: None,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Header of a pack of small objects.
+    pub fn pack(object_length: u64, checksum: Algorithm, members: Vec<PackMember>) -> Self {
+        Self {
+            members,
+            ..Self::new(object_length, "", checksum)
+        }
+    }
+
+    /// Protect the contents of the object with parity records.
+    pub fn with_parity(self, parity: Option<Parity>) -> Self {
+        Self { parity, ..self }
+    }
+
+    /// Mark the contents as compressed, `object_length` bytes long on tape.
+    pub fn with_compression(self, object_length: u64) -> Self {
+        Self {
+            object_length,
+            compressed: true,
+            uncompressed_length: self.object_length,
+            ..self
+        }
+    }
+
+    /// Mark the contents as encrypted, `object_length` bytes long on tape.
+    pub fn with_encryption(self, nonce: Nonce, object_length: u64) -> Self {
+        Self {
+            object_length,
+            nonce: Some(nonce),
+            ..self
+        }
+    }
+
+    /// Attach encoded metadata to the object.
+    pub fn with_metadata(self, metadata: Vec<u8>) -> Self {
+        Self { metadata, ..self }
+    }
+
+    pub fn is_pack(&self) -> bool {
+        !self.members.is_empty()
+    }
+}
+
+impl Header for ObjectHeader {
+    const MAGIC: [u8; 8] = OBJECT_HEADER_MAGIC;
+    const VERSION: u8 = OBJECT_HEADER_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u64(self.object_length);
+        fields.str(&self.key);
+        fields.u8(self.checksum.map_or(0, Algorithm::tag));
+        encode_parity(fields, self.parity);
+
+        fields.u32(self.members.len() as u32);
+        for member in &self.members {
+            member.to_entry().encode(fields);
+        }
+
+        fields.u8(self.compressed as u8);
+        fields.u64(self.uncompressed_length);
+        encode_optional(fields, self.nonce.as_ref().map(|nonce| &nonce[..]));
+
+        fields.str_bytes(&self.metadata);
+        for member in &self.members {
+            fields.str_bytes(&member.metadata);
+        }
+
+        for member in &self.members {
+            fields.u8(member.compressed as u8);
+            fields.u64(member.uncompressed_length);
+        }
+    }
+
+    fn decode_fields(version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        let object_length = fields.u64()?;
+        let key = fields.str()?;
+
+        let checksum = match version {
+            1 => None,
+            _ => match fields.u8()? {
+                0 => None,
+                tag => Some(Algorithm::from_tag(tag).ok_or(Error::Algorithm(tag))?),
+            },
+        };
+
+        let parity = match version {
+            1 | 2 => None,
+            _ => decode_parity(fields)?,
+        };
+
+        let mut members = Vec::new();
+        if version >= 4 {
+            for _ in 0..fields.u32()? {
+                members.push(PackMember::decode(fields)?);
+            }
+        }
+
+        let (compressed, uncompressed_length) = match version {
+            1..=4 => (false, object_length),
+            _ => match fields.u8()? {
+                0 => (false, fields.u64()?),
+                1 => (true, fields.u64()?),
+                codec => return Err(Error::Compression(codec)),
+            },
+        };
+
+        let nonce = match version {
+            1..=5 => None,
+            _ => decode_optional(fields)?,
+        };
+
+        let mut metadata = Vec::new();
+        if version >= 7 {
+            metadata = fields.str_bytes()?.to_vec();
+            for member in &mut members {
+                member.metadata = fields.str_bytes()?.to_vec();
+            }
+        }
+
+        if version >= 8 {
+            for member in &mut members {
+                member.compressed = match fields.u8()? {
+                    0 => false,
+                    1 => true,
+                    codec => return Err(Error::Compression(codec)),
+                };
+                member.uncompressed_length = fields.u64()?;
+            }
+        }
+
+        Ok(Self {
+            object_length,
+            key,
+            checksum,
+            parity,
+            members,
+            compressed,
+            uncompressed_length,
+            nonce,
+            metadata,
+        })
+    }
+}
+
+/// Parity ratios are encoded as the number of data and parity records, both
+/// zero without parity.
+fn encode_parity(fields: &mut Encoder, parity: Option<Parity>) {
+    let (data, parity) = parity.map_or((0, 0), |p| (p.data, p.parity));
+
+    fields.u8(data);
+    fields.u8(parity);
+}
+
+fn decode_parity(fields: &mut Decoder) -> Result<Option<Parity>, Error> {
+    match (fields.u8()?, fields.u8()?) {
+        (0, 0) => Ok(None),
+        (data, parity) => Parity::new(data, parity)
+            .map(Some)
+            .ok_or(Error::Parity(data, parity)),
+    }
+}
+
+/// Optional fields of a fixed length are preceded by a flag.
+fn encode_optional(fields: &mut Encoder, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            fields.u8(1);
+            fields.bytes(value);
+        }
+        None => fields.u8(0),
+    }
+}
+
+fn decode_optional<const N: usize>(fields: &mut Decoder) -> Result<Option<[u8; N]>, Error> {
+    match fields.u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(fields.bytes(N)?.try_into().unwrap())),
+    }
+}
+
+/// Record following the contents of an object.
+///
+/// The trailer marks the object as completely written. Version 2 added the
+/// key, length and sequence number, which tie the trailer to its object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectTrailer {
+    pub checksum: Checksum,
+    pub object: Option<TrailerObject>,
+}
+
+/// The object a trailer belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrailerObject {
+    pub key: String,
+    pub length: u64,
+
+    /// Number of objects written to the archive before this one.
+    pub sequence: u64,
+}
+
+impl ObjectTrailer {
+    pub fn new(key: &str, length: u64, sequence: u64, checksum: Checksum) -> Self {
+        Self {
+            checksum,
+            object: Some(TrailerObject {
+                key: key.to_string(),
+                length,
+                sequence,
+            }),
+        }
+    }
+
+    /// Check whether the trailer belongs to an object header and follows
+    /// `sequence` other objects in its archive.
+    ///
+    /// Version 1 trailers carry no identity and match any object.
+    pub fn matches(&self, header: &ObjectHeader, sequence: u64) -> bool {
+        self.belongs_to(header)
+            && self
+                .object
+                .as_ref()
+                .is_none_or(|object| object.sequence == sequence)
+    }
+
+    /// Check whether the trailer belongs to an object header, regardless of
+    /// the position of the object in its archive.
+    pub fn belongs_to(&self, header: &ObjectHeader) -> bool {
+        match &self.object {
+            Some(object) => object.key == header.key && object.length == header.object_length,
+            None => true,
+        }
+    }
+}
+
+impl Header for ObjectTrailer {
+    const MAGIC: [u8; 8] = OBJECT_TRAILER_MAGIC;
+    const VERSION: u8 = OBJECT_TRAILER_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u8(self.checksum.algorithm.tag());
+        fields.u8(self.checksum.digest.len() as u8);
+        fields.bytes(&self.checksum.digest);
+
+        if let Some(object) = &self.object {
+            fields.str(&object.key);
+            fields.u64(object.length);
+            fields.u64(object.sequence);
+        }
+    }
+
+    fn decode_fields(version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        let tag = fields.u8()?;
+        let algorithm = Algorithm::from_tag(tag).ok_or(Error::Algorithm(tag))?;
+
+        let length = fields.u8()?;
+        let digest = fields.bytes(length as usize)?.to_vec();
+
+        let object = match version {
+            1 => None,
+            _ => Some(TrailerObject {
+                key: fields.str()?,
+                length: fields.u64()?,
+                sequence: fields.u64()?,
+            }),
+        };
+
+        Ok(Self {
+            checksum: Checksum { algorithm, digest },
+            object,
+        })
+    }
+}
+
+/// Part of the table of contents of an archive.
+///
+/// The table of contents is written when an archive is closed, in front of
+/// its filemark. Tables which do not fit into one record are split into
+/// several parts.
+///
+/// Each entry is prefixed with its length as a `u16`, so that fields can be
+/// appended to entries as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveToc {
+    /// Creation time of the archive.
+    pub archive: u64,
+
+    /// Index of this part.
+    pub part: u32,
+    pub parts: u32,
+
+    pub entries: Vec<TocEntry>,
+}
+
+/// An object in the table of contents of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    pub key: String,
+
+    /// Records from the archive header to the object header.
+    pub offset: u64,
+
+    pub length: u64,
+
+    /// Content checksum, missing for torn objects and tombstones.
+    pub checksum: Option<Checksum>,
+
+    /// The entry is the tombstone of a removed object rather than an object.
+    /// Only written for tombstones, entries without the flag are objects.
+    pub tombstone: bool,
+}
+
+impl TocEntry {
+    /// Encoded length of the entry, including its length prefix.
+    pub fn encoded_len(&self) -> usize {
+        let checksum = self.checksum.as_ref().map_or(0, |c| c.digest.len());
+
+        2 + 2
+            + self.key.len().min(u16::MAX as usize)
+            + 8
+            + 8
+            + 1
+            + 1
+            + checksum
+            + self.tombstone as usize
+    }
+
+    fn encode(&self, fields: &mut Encoder) {
+        let mut entry = Encoder::default();
+        entry.str(&self.key);
+        entry.u64(self.offset);
+        entry.u64(self.length);
+
+        match &self.checksum {
+            Some(checksum) => {
+                entry.u8(checksum.algorithm.tag());
+                entry.u8(checksum.digest.len() as u8);
+                entry.bytes(&checksum.digest);
+            }
+            None => {
+                entry.u8(0);
+                entry.u8(0);
+            }
+        }
+
+        if self.tombstone {
+            entry.u8(1);
+        }
+
+        fields.str_bytes(&entry.buf);
+    }
+
+    fn decode(fields: &mut Decoder) -> Result<Self, Error> {
+        let length = fields.u16()?;
+        let mut entry = Decoder {
+            buf: fields.bytes(length as usize)?,
+        };
+
+        let key = entry.str()?;
+        let offset = entry.u64()?;
+        let length = entry.u64()?;
+
+        let tag = entry.u8()?;
+        let digest = entry.u8()?;
+        let digest = entry.bytes(digest as usize)?.to_vec();
+
+        let checksum = match tag {
+            0 => None,
+            tag => Some(Checksum {
+                algorithm: Algorithm::from_tag(tag).ok_or(Error::Algorithm(tag))?,
+                digest,
+            }),
+        };
+
+        // Entries of version 1 end here.
+        let tombstone = !entry.buf.is_empty() && entry.u8()? != 0;
+
+        Ok(Self {
+            key,
+            offset,
+            length,
+            checksum,
+            tombstone,
+        })
+    }
+}
+
+impl Header for ArchiveToc {
+    const MAGIC: [u8; 8] = ARCHIVE_TOC_MAGIC;
+    const VERSION: u8 = ARCHIVE_TOC_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u64(self.archive);
+        fields.u32(self.part);
+        fields.u32(self.parts);
+        fields.u32(self.entries.len() as u32);
+
+        for entry in &self.entries {
+            entry.encode(fields);
+        }
+    }
+
+    fn decode_fields(_version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        let archive = fields.u64()?;
+        let part = fields.u32()?;
+        let parts = fields.u32()?;
+
+        let count = fields.u32()?;
+        let entries = (0..count)
+            .map(|_| TocEntry::decode(fields))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            archive,
+            part,
+            parts,
+            entries,
+        })
+    }
+}
+
+/// Marks an object as removed from the remote.
+///
+/// Tape is append-only, so removing an object writes a tombstone into the
+/// current archive. Copies of the object written before the tombstone are
+/// ignored by scans until compaction reclaims their space. Storing the object
+/// again afterwards makes it present again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tombstone {
+    /// Key of the removed object, sealed in encrypted archives.
+    pub key: String,
+}
+
+impl Tombstone {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+        }
+    }
+}
+
+impl Header for Tombstone {
+    const MAGIC: [u8; 8] = TOMBSTONE_MAGIC;
+    const VERSION: u8 = TOMBSTONE_VERSION;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.str(&self.key);
+    }
+
+    fn decode_fields(_version: u8, fields: &mut Decoder) -> Result<Self, Error> {
+        Ok(Self { key: fields.str()? })
+    }
 }
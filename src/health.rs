@@ -0,0 +1,223 @@
+//! Health scoring for tape drives and cartridges
+//!
+//! The scores are derived from the error counter, sequential-access device and
+//! volume statistics log pages. A score of 100 means no errors have been
+//! observed, lower values indicate increasing wear or error rates.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::logpage::{self, error_counter, sequential_access, volume_statistics, LogPage};
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+const MB_PER_GB: f64 = 1_000.0;
+
+/// Number of loads an LTO cartridge is rated for.
+pub const RATED_LOAD_COUNT: u64 = 5_000;
+
+/// Score below which a drive or cartridge is considered degrading.
+pub const DEGRADING_THRESHOLD: u8 = 80;
+
+/// Score below which a drive or cartridge should no longer be used.
+pub const CRITICAL_THRESHOLD: u8 = 50;
+
+// Points deducted per corrected error per GB.
+const CORRECTED_WEIGHT: f64 = 2.0;
+const CORRECTED_MAX_PENALTY: f64 = 40.0;
+
+// Points deducted per uncorrected error per GB.
+const UNCORRECTED_WEIGHT: f64 = 200.0;
+const UNCORRECTED_MAX_PENALTY: f64 = 60.0;
+
+// Points deducted when the rated load count is reached.
+const WEAR_MAX_PENALTY: f64 = 30.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Critical,
+    Degrading,
+    Good,
+}
+
+impl From<u8> for Status {
+    fn from(score: u8) -> Self {
+        if score < CRITICAL_THRESHOLD {
+            Self::Critical
+        } else if score < DEGRADING_THRESHOLD {
+            Self::Degrading
+        } else {
+            Self::Good
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Critical => "critical",
+            Self::Degrading => "degrading",
+            Self::Good => "good",
+        })
+    }
+}
+
+fn per_gb(count: u64, gb: f64) -> f64 {
+    if gb > 0.0 {
+        count as f64 / gb
+    } else {
+        0.0
+    }
+}
+
+fn score(corrected_per_gb: f64, uncorrected_per_gb: f64, wear: f64) -> u8 {
+    let penalty = f64::min(CORRECTED_MAX_PENALTY, corrected_per_gb * CORRECTED_WEIGHT)
+        + f64::min(UNCORRECTED_MAX_PENALTY, uncorrected_per_gb * UNCORRECTED_WEIGHT)
+        + f64::min(WEAR_MAX_PENALTY, wear * WEAR_MAX_PENALTY);
+
+    f64::max(0.0, 100.0 - penalty).round() as u8
+}
+
+/// Counters of the write or read error counter log page.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorCounters {
+    pub corrected: u64,
+    pub uncorrected: u64,
+    pub retries: u64,
+    pub bytes_processed: u64,
+}
+
+impl ErrorCounters {
+    pub fn from_page(page: &LogPage) -> Self {
+        Self {
+            corrected: page.counter(error_counter::TOTAL_CORRECTED).unwrap_or(0),
+            uncorrected: page.counter(error_counter::TOTAL_UNCORRECTED).unwrap_or(0),
+            retries: page
+                .counter(error_counter::TOTAL_REWRITES_OR_REREADS)
+                .unwrap_or(0),
+            bytes_processed: page
+                .counter(error_counter::TOTAL_BYTES_PROCESSED)
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Health of a tape drive.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DriveHealth {
+    pub write: ErrorCounters,
+    pub read: ErrorCounters,
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub cleaning_required: bool,
+}
+
+impl DriveHealth {
+    /// Compute the drive health from the write error counter, read error
+    /// counter and sequential-access device log pages.
+    pub fn from_pages(write: &LogPage, read: &LogPage, sequential: Option<&LogPage>) -> Self {
+        let write = ErrorCounters::from_page(write);
+        let read = ErrorCounters::from_page(read);
+
+        let counter = |code| sequential.and_then(|p| p.counter(code));
+
+        Self {
+            write,
+            read,
+            bytes_written: counter(sequential_access::BYTES_WRITTEN_TO_MEDIA)
+                .unwrap_or(write.bytes_processed),
+            bytes_read: counter(sequential_access::BYTES_READ_FROM_MEDIA)
+                .unwrap_or(read.bytes_processed),
+            cleaning_required: counter(sequential_access::CLEANING_REQUIRED).unwrap_or(0) != 0,
+        }
+    }
+
+    fn gigabytes(&self) -> f64 {
+        (self.bytes_written + self.bytes_read) as f64 / BYTES_PER_GB
+    }
+
+    pub fn corrected_per_gb(&self) -> f64 {
+        per_gb(self.write.corrected + self.read.corrected, self.gigabytes())
+    }
+
+    pub fn uncorrected_per_gb(&self) -> f64 {
+        per_gb(self.write.uncorrected + self.read.uncorrected, self.gigabytes())
+    }
+
+    pub fn score(&self) -> u8 {
+        score(self.corrected_per_gb(), self.uncorrected_per_gb(), 0.0)
+    }
+
+    pub fn status(&self) -> Status {
+        Status::from(self.score())
+    }
+}
+
+/// Health of a tape cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MediaHealth {
+    pub serial_number: Option<String>,
+    pub barcode: Option<String>,
+    pub load_count: u64,
+    pub lifetime_mb_written: u64,
+    pub lifetime_mb_read: u64,
+    pub write_retries: u64,
+    pub read_retries: u64,
+    pub unrecovered_write_errors: u64,
+    pub unrecovered_read_errors: u64,
+}
+
+impl MediaHealth {
+    /// Compute the cartridge health from the volume statistics log page.
+    pub fn from_page(page: &LogPage) -> Self {
+        let counter = |code| page.counter(code).unwrap_or(0);
+        let string = |code| {
+            page.parameter(code)
+                .map(logpage::LogParameter::as_string)
+                .filter(|s| !s.is_empty())
+        };
+
+        Self {
+            serial_number: string(volume_statistics::VOLUME_SERIAL_NUMBER),
+            barcode: string(volume_statistics::VOLUME_BARCODE),
+            load_count: counter(volume_statistics::THREAD_COUNT),
+            lifetime_mb_written: counter(volume_statistics::LIFETIME_MB_WRITTEN),
+            lifetime_mb_read: counter(volume_statistics::LIFETIME_MB_READ),
+            write_retries: counter(volume_statistics::TOTAL_WRITE_RETRIES),
+            read_retries: counter(volume_statistics::TOTAL_READ_RETRIES),
+            unrecovered_write_errors: counter(volume_statistics::TOTAL_UNRECOVERED_WRITE_ERRORS),
+            unrecovered_read_errors: counter(volume_statistics::TOTAL_UNRECOVERED_READ_ERRORS),
+        }
+    }
+
+    fn gigabytes(&self) -> f64 {
+        (self.lifetime_mb_written + self.lifetime_mb_read) as f64 / MB_PER_GB
+    }
+
+    pub fn corrected_per_gb(&self) -> f64 {
+        per_gb(self.write_retries + self.read_retries, self.gigabytes())
+    }
+
+    pub fn uncorrected_per_gb(&self) -> f64 {
+        per_gb(
+            self.unrecovered_write_errors + self.unrecovered_read_errors,
+            self.gigabytes(),
+        )
+    }
+
+    /// Fraction of the rated load count which has been used up.
+    pub fn wear(&self) -> f64 {
+        self.load_count as f64 / RATED_LOAD_COUNT as f64
+    }
+
+    pub fn score(&self) -> u8 {
+        score(
+            self.corrected_per_gb(),
+            self.uncorrected_per_gb(),
+            self.wear(),
+        )
+    }
+
+    pub fn status(&self) -> Status {
+        Status::from(self.score())
+    }
+}
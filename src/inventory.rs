@@ -0,0 +1,382 @@
+//! Library inventory and reconciliation with the catalog
+//!
+//! An inventory reads the element status of the changer including the
+//! barcodes. Cartridges without a barcode can optionally be loaded to read
+//! their media header. The cartridges found are then compared with the
+//! cartridges known from the catalog.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::catalog::{self, MediaRecord, Placement};
+use crate::changer::{self, Address, Changer, Element, ElementType};
+use crate::tape::{self, Drive};
+
+/// What was found on a cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    /// The cartridge has not been loaded.
+    Unscanned,
+
+    /// A media header written by us.
+    Media { id: String, host: String },
+
+    /// The cartridge has no data.
+    Blank,
+
+    /// The cartridge holds data in some other format.
+    Foreign,
+
+    /// The cartridge could not be read.
+    Unreadable(String),
+}
+
+/// A cartridge found in the library.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    pub address: Address,
+    pub barcode: Option<String>,
+    pub identity: Identity,
+}
+
+/// Outcome of the reconciliation for a single cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// A known cartridge is where we expect it.
+    Present { id: String, address: Address },
+
+    /// A known cartridge has been found under a different barcode.
+    Relabelled {
+        id: String,
+        address: Address,
+        old: Option<String>,
+        new: Option<String>,
+    },
+
+    /// A known cartridge is not in the library.
+    Missing { id: String, barcode: Option<String> },
+
+    /// A cartridge with data which does not belong to this repository.
+    Foreign {
+        address: Address,
+        barcode: Option<String>,
+    },
+
+    /// A cartridge without data.
+    Blank {
+        address: Address,
+        barcode: Option<String>,
+    },
+
+    /// A cartridge with an unknown barcode or no barcode which has not been loaded.
+    Unknown {
+        address: Address,
+        barcode: Option<String>,
+    },
+}
+
+fn barcode_or_none(barcode: &Option<String>) -> &str {
+    barcode.as_deref().unwrap_or("(no barcode)")
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Present { id, address } => write!(f, "{address}: media {id} present"),
+            Self::Relabelled {
+                id,
+                address,
+                old,
+                new,
+            } => write!(
+                f,
+                "{address}: media {id} relabelled from {} to {}",
+                barcode_or_none(old),
+                barcode_or_none(new)
+            ),
+            Self::Missing { id, barcode } => {
+                write!(f, "media {id} {} missing", barcode_or_none(barcode))
+            }
+            Self::Foreign { address, barcode } => {
+                write!(f, "{address}: {} foreign", barcode_or_none(barcode))
+            }
+            Self::Blank { address, barcode } => {
+                write!(f, "{address}: {} blank", barcode_or_none(barcode))
+            }
+            Self::Unknown { address, barcode } => {
+                write!(f, "{address}: {} unknown", barcode_or_none(barcode))
+            }
+        }
+    }
+}
+
+/// Result of an inventory as stored in the catalog.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    pub time: u64,
+    pub cartridges: Vec<Cartridge>,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Catalog(catalog::Error),
+    Changer(changer::Error),
+    Tape(tape::Error),
+}
+
+impl From<catalog::Error> for Error {
+    fn from(value: catalog::Error) -> Self {
+        Self::Catalog(value)
+    }
+}
+
+impl From<changer::Error> for Error {
+    fn from(value: changer::Error) -> Self {
+        Self::Changer(value)
+    }
+}
+
+impl From<tape::Error> for Error {
+    fn from(value: tape::Error) -> Self {
+        Self::Tape(value)
+    }
+}
+
+impl From<crate::mt::Error> for Error {
+    fn from(value: crate::mt::Error) -> Self {
+        Self::Tape(value.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Catalog(e) => write!(f, "{e}"),
+            Self::Changer(e) => write!(f, "{e}"),
+            Self::Tape(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Read the media header of the cartridge in the drive.
+pub fn identify(drive: &Rc<Drive>) -> Identity {
+    match drive.load_media() {
+        Ok(media) => Identity::Media {
+            id: media.id(),
+            host: media.host().to_string(),
+        },
+        Err(tape::Error::Blank) => Identity::Blank,
+        Err(tape::Error::InvalidHeader(_)) => Identity::Foreign,
+        Err(e) => Identity::Unreadable(e.to_string()),
+    }
+}
+
+/// Collect the cartridges in the library.
+///
+/// If a drive is given, cartridges without a barcode are loaded into it to
+/// read their media header and returned to their slot afterwards.
+pub fn scan(
+    changer: &dyn Changer,
+    drive: Option<(&Rc<Drive>, u16)>,
+) -> Result<Vec<Cartridge>, Error> {
+    let status = changer.status()?;
+    let mut cartridges = Vec::new();
+
+    for element in status.elements().filter(|e| e.full) {
+        let identity = match drive {
+            Some((drive, unit)) if element.barcode.is_none() => {
+                scan_element(changer, drive, unit, element)?
+            }
+            _ => Identity::Unscanned,
+        };
+
+        cartridges.push(Cartridge {
+            address: element.address,
+            barcode: element.barcode.clone(),
+            identity,
+        });
+    }
+
+    Ok(cartridges)
+}
+
+fn scan_element(
+    changer: &dyn Changer,
+    drive: &Rc<Drive>,
+    unit: u16,
+    element: &Element,
+) -> Result<Identity, Error> {
+    let drive_address = Address::drive(unit);
+
+    if element.address.kind == ElementType::DataTransfer {
+        // Only the drive we have been given can be read.
+        if element.address != drive_address {
+            return Ok(Identity::Unscanned);
+        }
+
+        drive.wait_ready(tape::LOAD_TIMEOUT)?;
+        return Ok(identify(drive));
+    }
+
+    if changer.element(drive_address)?.full {
+        drive.eject()?;
+        changer.unload(unit, None)?;
+    }
+
+    changer.load(element.address, unit)?;
+    drive.wait_ready(tape::LOAD_TIMEOUT)?;
+
+    let identity = identify(drive);
+
+    drive.eject()?;
+    changer.unload(unit, Some(element.address))?;
+
+    Ok(identity)
+}
+
+/// Compare the cartridges found in the library with the known cartridges.
+///
+/// Known cartridges which an unscanned cartridge may be are neither present
+/// nor missing, and keep their placement.
+pub fn reconcile(known: &[MediaRecord], cartridges: &[Cartridge]) -> Vec<Finding> {
+    let by_id: HashMap<&str, &MediaRecord> = known.iter().map(|m| (m.id.as_str(), m)).collect();
+    let by_barcode: HashMap<&str, &MediaRecord> = known
+        .iter()
+        .filter_map(|m| m.barcode.as_deref().map(|b| (b, m)))
+        .collect();
+
+    let mut findings = Vec::new();
+    let mut seen = Vec::new();
+
+    for cartridge in cartridges {
+        let address = cartridge.address;
+        let barcode = cartridge.barcode.clone();
+
+        let finding = match &cartridge.identity {
+            Identity::Media { id, .. } => match by_id.get(id.as_str()) {
+                Some(record) if barcode.is_some() && record.barcode != barcode => {
+                    Finding::Relabelled {
+                        id: id.clone(),
+                        address,
+                        old: record.barcode.clone(),
+                        new: barcode,
+                    }
+                }
+                Some(_) => Finding::Present {
+                    id: id.clone(),
+                    address,
+                },
+                None => Finding::Foreign { address, barcode },
+            },
+            Identity::Blank => Finding::Blank { address, barcode },
+            Identity::Foreign | Identity::Unreadable(_) => Finding::Foreign { address, barcode },
+            Identity::Unscanned => match barcode.as_deref().and_then(|b| by_barcode.get(b)) {
+                Some(record) => Finding::Present {
+                    id: record.id.clone(),
+                    address,
+                },
+                None => Finding::Unknown { address, barcode },
+            },
+        };
+
+        if let Finding::Present { id, .. } | Finding::Relabelled { id, .. } = &finding {
+            seen.push(id.clone());
+        }
+
+        findings.push(finding);
+    }
+
+    // Unscanned cartridges without a known barcode may be any cartridge
+    // without a barcode, or any cartridge at all if they have no barcode
+    // either. Such cartridges are not missing, but may not have been found.
+    let unknown = |record: &MediaRecord| {
+        findings.iter().any(|f| match f {
+            Finding::Unknown { barcode: None, .. } => true,
+            Finding::Unknown { .. } => record.barcode.is_none(),
+            _ => false,
+        })
+    };
+
+    let missing: Vec<_> = known
+        .iter()
+        .filter(|m| !seen.contains(&m.id) && !unknown(m))
+        .collect();
+
+    for record in missing {
+        findings.push(Finding::Missing {
+            id: record.id.clone(),
+            barcode: record.barcode.clone(),
+        });
+    }
+
+    findings
+}
+
+/// Update the placement and barcodes of the known cartridges.
+///
+/// Returns the records which have changed.
+pub fn apply(known: &mut [MediaRecord], findings: &[Finding], time: u64) -> Vec<MediaRecord> {
+    let mut changed = Vec::new();
+
+    for record in known.iter_mut() {
+        let before = record.clone();
+
+        for finding in findings {
+            match finding {
+                Finding::Present { id, address } if *id == record.id => {
+                    record.placement = Placement::Library(*address);
+                    record.last_seen = Some(time);
+                }
+                Finding::Relabelled {
+                    id, address, new, ..
+                } if *id == record.id => {
+                    record.placement = Placement::Library(*address);
+                    record.barcode = new.clone();
+                    record.last_seen = Some(time);
+                }
+                Finding::Missing { id, .. } if *id == record.id => {
+                    record.placement = Placement::Offsite;
+                }
+                _ => {}
+            }
+        }
+
+        if *record != before {
+            changed.push(record.clone());
+        }
+    }
+
+    changed
+}
+
+/// Run an inventory and store its result in the catalog.
+pub fn run(
+    catalog: &catalog::Catalog,
+    changer: &dyn Changer,
+    drive: Option<(&Rc<Drive>, u16)>,
+) -> Result<Inventory, Error> {
+    let cartridges = scan(changer, drive)?;
+
+    let mut known = catalog.all_media()?;
+    let findings = reconcile(&known, &cartridges);
+
+    let time = catalog::now();
+    for record in apply(&mut known, &findings, time) {
+        catalog.save_media(&record)?;
+    }
+
+    let inventory = Inventory {
+        time,
+        cartridges,
+        findings,
+    };
+
+    catalog.save_inventory(&inventory)?;
+
+    Ok(inventory)
+}
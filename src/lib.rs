@@ -1,6 +1,26 @@
 #![allow(dead_code)]
 
+pub mod catalog;
+pub mod changer;
+pub mod checksum;
+pub mod chio;
+pub mod cleaning;
+pub mod compression;
+pub mod crypto;
+pub mod diagnostic;
 pub mod format;
+pub mod health;
+pub mod inventory;
+pub mod logpage;
+pub mod ltfs;
 pub mod mt;
 pub mod mtio;
-pub mod tape;
\ No newline at end of file
+pub mod parity;
+pub mod scheduler;
+pub mod scsi;
+pub mod sgio;
+pub mod spool;
+pub mod tape;
+pub mod tapealert;
+pub mod tar;
+pub mod vtl;
@@ -0,0 +1,143 @@
+//! SCSI log pages
+//!
+//! also see: SPC-4, section 7.3 and SSC-4, section 8.2
+
+/// Write error counter log page.
+pub const WRITE_ERROR_COUNTER: u8 = 0x02;
+
+/// Read error counter log page.
+pub const READ_ERROR_COUNTER: u8 = 0x03;
+
+/// Sequential-access device log page.
+pub const SEQUENTIAL_ACCESS_DEVICE: u8 = 0x0c;
+
+/// Volume statistics log page.
+pub const VOLUME_STATISTICS: u8 = 0x17;
+
+/// TapeAlert log page.
+pub const TAPE_ALERT: u8 = 0x2e;
+
+/// Parameter codes of the write and read error counter log pages.
+pub mod error_counter {
+    pub const CORRECTED_WITHOUT_DELAY: u16 = 0x0000;
+    pub const CORRECTED_WITH_DELAY: u16 = 0x0001;
+    pub const TOTAL_REWRITES_OR_REREADS: u16 = 0x0002;
+    pub const TOTAL_CORRECTED: u16 = 0x0003;
+    pub const TOTAL_CORRECTION_ALGORITHM_PROCESSED: u16 = 0x0004;
+    pub const TOTAL_BYTES_PROCESSED: u16 = 0x0005;
+    pub const TOTAL_UNCORRECTED: u16 = 0x0006;
+}
+
+/// Parameter codes of the sequential-access device log page.
+pub mod sequential_access {
+    pub const BYTES_RECEIVED_FROM_INITIATOR: u16 = 0x0000;
+    pub const BYTES_WRITTEN_TO_MEDIA: u16 = 0x0001;
+    pub const BYTES_READ_FROM_MEDIA: u16 = 0x0002;
+    pub const BYTES_SENT_TO_INITIATOR: u16 = 0x0003;
+    pub const CLEANING_REQUIRED: u16 = 0x0100;
+}
+
+/// Parameter codes of the volume statistics log page.
+pub mod volume_statistics {
+    pub const PAGE_VALID: u16 = 0x0001;
+    pub const THREAD_COUNT: u16 = 0x0002;
+    pub const TOTAL_DATA_SETS_WRITTEN: u16 = 0x0003;
+    pub const TOTAL_WRITE_RETRIES: u16 = 0x0004;
+    pub const TOTAL_UNRECOVERED_WRITE_ERRORS: u16 = 0x0005;
+    pub const TOTAL_SUSPENDED_WRITES: u16 = 0x0006;
+    pub const TOTAL_FATAL_SUSPENDED_WRITES: u16 = 0x0007;
+    pub const TOTAL_DATA_SETS_READ: u16 = 0x0008;
+    pub const TOTAL_READ_RETRIES: u16 = 0x0009;
+    pub const TOTAL_UNRECOVERED_READ_ERRORS: u16 = 0x000a;
+    pub const LIFETIME_MB_WRITTEN: u16 = 0x0010;
+    pub const LIFETIME_MB_READ: u16 = 0x0011;
+    pub const TOTAL_NATIVE_CAPACITY: u16 = 0x0016;
+    pub const TOTAL_USED_NATIVE_CAPACITY: u16 = 0x0017;
+    pub const VOLUME_SERIAL_NUMBER: u16 = 0x0040;
+    pub const VOLUME_BARCODE: u16 = 0x0042;
+}
+
+/// A single parameter of a log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogParameter {
+    pub code: u16,
+    pub control: u8,
+    pub value: Vec<u8>,
+}
+
+impl LogParameter {
+    /// Interpret the parameter value as a big-endian unsigned counter.
+    pub fn as_u64(&self) -> u64 {
+        self.value
+            .iter()
+            .rev()
+            .take(8)
+            .rev()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+    }
+
+    /// Interpret the parameter value as an ASCII string.
+    pub fn as_string(&self) -> String {
+        String::from_utf8_lossy(&self.value)
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string()
+    }
+}
+
+/// A decoded log page as returned by LOG SENSE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogPage {
+    pub code: u8,
+    pub subpage: u8,
+    pub parameters: Vec<LogParameter>,
+}
+
+impl LogPage {
+    /// Parse the raw data returned by LOG SENSE.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 4 {
+            return None;
+        }
+
+        let code = buf[0] & 0x3f;
+        let subpage = buf[1];
+        let length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let end = usize::min(buf.len(), 4 + length);
+
+        let mut parameters = Vec::new();
+        let mut offset = 4;
+
+        while offset + 4 <= end {
+            let param_code = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+            let control = buf[offset + 2];
+            let param_length = buf[offset + 3] as usize;
+
+            let value_start = offset + 4;
+            let value_end = usize::min(end, value_start + param_length);
+
+            parameters.push(LogParameter {
+                code: param_code,
+                control,
+                value: buf[value_start..value_end].to_vec(),
+            });
+
+            offset = value_start + param_length;
+        }
+
+        Some(Self {
+            code,
+            subpage,
+            parameters,
+        })
+    }
+
+    /// Find a parameter by its code.
+    pub fn parameter(&self, code: u16) -> Option<&LogParameter> {
+        self.parameters.iter().find(|p| p.code == code)
+    }
+
+    /// Get a counter parameter by its code.
+    pub fn counter(&self, code: u16) -> Option<u64> {
+        self.parameter(code).map(LogParameter::as_u64)
+    }
+}
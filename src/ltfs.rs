@@ -0,0 +1,1486 @@
+//! Reading and writing LTFS volumes straight off tape
+//!
+//! An LTFS volume spans two partitions. Both begin with an ANSI VOL1 label
+//! and the LTFS label, an XML document, each followed by a filemark. The
+//! index partition `a` holds the latest index, the data partition `b` the
+//! contents of the files and earlier indexes:
+//!
+//! ```text
+//! a: VOL1 | FM | label | FM | index | FM | ...
+//! b: VOL1 | FM | label | FM | data ... | FM | index | FM | data ... | FM | index | FM
+//! ```
+//!
+//! An index is an XML document describing the directory tree, each file with
+//! its extents, runs of bytes starting at a block of either partition. Every
+//! index is preceded and followed by a filemark, and indexes are numbered by
+//! their generation. The index with the highest generation on either
+//! partition describes the volume.
+//!
+//! Files are appended to the data partition, and a new generation of the
+//! index is written to both partitions by [`Volume::sync`]: first at the end
+//! of the data partition, then after the labels of the index partition,
+//! replacing the previous generation there.
+//!
+//! Writing the index takes two passes over the tape, so it is not written
+//! after every change. Each change made since is returned by
+//! [`Volume::checkpoint`] once its contents are on tape, to be kept in a
+//! journal which [`Volume::open`] re-applies to the index read from tape.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::{self, Algorithm};
+use crate::format::WRITER;
+use crate::mt::{self, TapeDevice};
+use crate::mtio;
+
+/// Length of the VOL1 label.
+pub const VOL1_LENGTH: usize = 80;
+
+/// Version of the LTFS format which is written.
+const VERSION: &str = "2.4.0";
+
+/// Block size of new volumes, the default of mkltfs.
+pub const DEFAULT_BLOCK_SIZE: usize = 512 * 1024;
+
+/// Size of the index partition of new volumes in MB. Drives round it up to
+/// whole wraps.
+const INDEX_PARTITION_SIZE: i32 = 38_000;
+
+/// Implementation identifier of the VOL1 label of LTFS volumes.
+const IMPLEMENTATION: &[u8] = b"LTFS";
+
+/// Buffer size for reading the labels, which are shorter than any block.
+const LABEL_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Largest block size of a volume which is accepted.
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maximum number of records of an index, to bound the search for the
+/// filemark preceding it.
+const MAX_INDEX_RECORDS: u64 = 4096;
+
+/// First block after the labels of a partition: VOL1, filemark, LTFS label
+/// and filemark.
+const FIRST_BLOCK: u64 = 4;
+
+/// Extended attribute in which LTFS implementations store the SHA-256 of the
+/// contents of a file.
+pub const SHA256_ATTRIBUTE: &str = "ltfs.hash.sha256sum";
+
+#[derive(Debug)]
+pub enum Error {
+    Tape(mt::Error),
+    IO(io::Error),
+    Blank,
+    /// The cartridge has no VOL1 label of LTFS.
+    NotLtfs,
+    /// The cartridge has a single partition.
+    SinglePartition,
+    /// The LTFS label or an index cannot be parsed.
+    Invalid(String),
+    /// Neither partition ends with an index.
+    NoIndex,
+    ShortFile(String),
+    ChecksumMismatch(String),
+}
+
+impl From<mt::Error> for Error {
+    fn from(value: mt::Error) -> Self {
+        Self::Tape(value)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+impl From<roxmltree::Error> for Error {
+    fn from(value: roxmltree::Error) -> Self {
+        Self::Invalid(value.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tape(e) => write!(f, "{e}"),
+            Self::IO(e) => write!(f, "{e}"),
+            Self::Blank => write!(f, "cartridge is blank"),
+            Self::NotLtfs => write!(f, "cartridge is not an LTFS volume"),
+            Self::SinglePartition => write!(f, "cartridge has a single partition"),
+            Self::Invalid(e) => write!(f, "invalid LTFS metadata: {e}"),
+            Self::NoIndex => write!(f, "no index found on either partition"),
+            Self::ShortFile(name) => write!(f, "extents of {name} end early"),
+            Self::ChecksumMismatch(name) => write!(f, "checksum mismatch of {name}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The ANSI VOL1 label at the beginning of both partitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vol1 {
+    /// Volume serial number, usually the barcode without its media type.
+    pub volume: String,
+    pub owner: String,
+}
+
+impl Vol1 {
+    /// Label of a volume named after the first six characters of its
+    /// barcode.
+    pub fn new(barcode: &str) -> Self {
+        Self {
+            volume: barcode.chars().take(6).collect(),
+            owner: String::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // The label holds ASCII characters only.
+        let field = |value: &str, width: usize| -> String {
+            let value = value.chars().map(|c| match c.is_ascii_graphic() {
+                true => c,
+                false => ' ',
+            });
+            format!("{:width$.width$}", value.collect::<String>())
+        };
+
+        let label = format!(
+            "VOL1{}L{:13}{}{}{:28}4",
+            field(&self.volume, 6),
+            "",
+            field("LTFS", 13),
+            field(&self.owner, 14),
+            ""
+        );
+
+        label.into_bytes()
+    }
+
+    /// Decode a VOL1 label, `None` unless it was written by LTFS.
+    pub fn decode(record: &[u8]) -> Option<Self> {
+        if record.len() != VOL1_LENGTH
+            || !record.starts_with(b"VOL1")
+            || !record[24..].starts_with(IMPLEMENTATION)
+        {
+            return None;
+        }
+
+        let field = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&record[range])
+                .trim_end()
+                .to_string()
+        };
+
+        Some(Self {
+            volume: field(4..10),
+            owner: field(37..51),
+        })
+    }
+}
+
+/// Position of a block, the partition given by its letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub partition: char,
+    pub block: u64,
+}
+
+/// The LTFS label following the VOL1 label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub creator: String,
+    pub format_time: u64,
+    pub volume_uuid: String,
+    /// Partition the label was read from.
+    pub location: char,
+    pub index_partition: char,
+    pub data_partition: char,
+    pub block_size: usize,
+    pub compression: bool,
+}
+
+impl Label {
+    /// Label of a new volume with the default block size and compression.
+    pub fn new(volume_uuid: &str, format_time: u64) -> Self {
+        Self {
+            creator: WRITER.to_string(),
+            format_time,
+            volume_uuid: volume_uuid.to_string(),
+            location: 'a',
+            index_partition: 'a',
+            data_partition: 'b',
+            block_size: DEFAULT_BLOCK_SIZE,
+            compression: true,
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ltfslabel version=\"{VERSION}\">\n\
+             <creator>{}</creator>\n\
+             <formattime>{}</formattime>\n\
+             <volumeuuid>{}</volumeuuid>\n\
+             <location><partition>{}</partition></location>\n\
+             <partitions><index>{}</index><data>{}</data></partitions>\n\
+             <blocksize>{}</blocksize>\n\
+             <compression>{}</compression>\n\
+             </ltfslabel>\n",
+            escape(&self.creator),
+            format_time(self.format_time),
+            escape(&self.volume_uuid),
+            self.location,
+            self.index_partition,
+            self.data_partition,
+            self.block_size,
+            self.compression
+        )
+    }
+
+    pub fn parse(xml: &str) -> Result<Self, Error> {
+        let document = roxmltree::Document::parse(xml)?;
+        let root = document.root_element();
+        if !root.has_tag_name("ltfslabel") {
+            return Err(Error::Invalid("not an LTFS label".to_string()));
+        }
+
+        let partitions = child(root, "partitions")?;
+        let block_size = number(root, "blocksize")? as usize;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(Error::Invalid(format!("block size {block_size}")));
+        }
+
+        Ok(Self {
+            creator: text(root, "creator")?,
+            format_time: time(root, "formattime")?,
+            volume_uuid: text(root, "volumeuuid")?,
+            location: partition(child(child(root, "location")?, "partition")?)?,
+            index_partition: partition(child(partitions, "index")?)?,
+            data_partition: partition(child(partitions, "data")?)?,
+            block_size,
+            compression: boolean(root, "compression")?,
+        })
+    }
+}
+
+/// A generation of the index of a volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub creator: String,
+    pub volume_uuid: String,
+    pub generation: u64,
+    pub update_time: u64,
+    pub location: Position,
+    pub previous: Option<Position>,
+    /// Highest unique identifier of the files and directories so far.
+    pub highest_uid: u64,
+    pub root: Directory,
+}
+
+impl Index {
+    pub fn parse(xml: &str) -> Result<Self, Error> {
+        let document = roxmltree::Document::parse(xml)?;
+        let root = document.root_element();
+        if !root.has_tag_name("ltfsindex") {
+            return Err(Error::Invalid("not an LTFS index".to_string()));
+        }
+
+        Ok(Self {
+            creator: text(root, "creator")?,
+            volume_uuid: text(root, "volumeuuid")?,
+            generation: number(root, "generationnumber")?,
+            update_time: time(root, "updatetime")?,
+            location: position(child(root, "location")?)?,
+            previous: optional(root, "previousgenerationlocation")
+                .map(position)
+                .transpose()?,
+            highest_uid: optional_number(root, "highestfileuid")?,
+            root: Directory::parse(child(root, "directory")?, true)?,
+        })
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ltfsindex version=\"{VERSION}\">\n\
+             <creator>{}</creator>\n\
+             <volumeuuid>{}</volumeuuid>\n\
+             <generationnumber>{}</generationnumber>\n\
+             <updatetime>{}</updatetime>\n\
+             <location>{}</location>\n",
+            escape(&self.creator),
+            escape(&self.volume_uuid),
+            self.generation,
+            format_time(self.update_time),
+            position_xml(self.location)
+        );
+
+        if let Some(previous) = self.previous {
+            xml += &format!(
+                "<previousgenerationlocation>{}</previousgenerationlocation>\n",
+                position_xml(previous)
+            );
+        }
+
+        xml += "<allowpolicyupdate>true</allowpolicyupdate>\n";
+        xml += &format!("<highestfileuid>{}</highestfileuid>\n", self.highest_uid);
+        self.root.write_xml(&mut xml);
+        xml += "</ltfsindex>\n";
+
+        xml
+    }
+
+    /// All files of the index with their paths, in the order of the tree.
+    pub fn files(&self) -> Vec<(String, &File)> {
+        let mut files = Vec::new();
+        self.root.walk("", &mut files);
+        files
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directory {
+    pub uid: u64,
+    pub name: String,
+    pub modify_time: u64,
+    pub directories: Vec<Directory>,
+    pub files: Vec<File>,
+}
+
+impl Directory {
+    /// Parse a directory, the root directory named after the volume.
+    fn parse(node: roxmltree::Node, root: bool) -> Result<Self, Error> {
+        let name = match root {
+            true => optional(node, "name").map(decoded).transpose()?,
+            false => Some(name(node)?),
+        };
+
+        let mut directory = Self {
+            uid: optional_number(node, "fileuid")?,
+            name: name.unwrap_or_default(),
+            modify_time: time(node, "modifytime")?,
+            directories: Vec::new(),
+            files: Vec::new(),
+        };
+
+        for entry in child(node, "contents")?.children() {
+            if entry.has_tag_name("directory") {
+                directory.directories.push(Directory::parse(entry, false)?);
+            } else if entry.has_tag_name("file") {
+                directory.files.push(File::parse(entry)?);
+            }
+        }
+
+        Ok(directory)
+    }
+
+    fn new(uid: u64, name: &str, modify_time: u64) -> Self {
+        Self {
+            uid,
+            name: name.to_string(),
+            modify_time,
+            directories: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn write_xml(&self, xml: &mut String) {
+        *xml += &format!(
+            "<directory><fileuid>{}</fileuid>{}{}<readonly>false</readonly>\n<contents>\n",
+            self.uid,
+            name_xml("name", &self.name),
+            times_xml(self.modify_time)
+        );
+
+        for file in &self.files {
+            file.write_xml(xml);
+        }
+
+        for directory in &self.directories {
+            directory.write_xml(xml);
+        }
+
+        *xml += "</contents></directory>\n";
+    }
+
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.directories.is_empty()
+    }
+
+    fn find(&self, path: &[&str]) -> Option<&File> {
+        match path {
+            [] => None,
+            [name] => self.files.iter().find(|f| f.name == *name),
+            [name, rest @ ..] => self
+                .directories
+                .iter()
+                .find(|d| d.name == *name)?
+                .find(rest),
+        }
+    }
+
+    /// Why a file cannot be added at `path`, if it leads through a file or
+    /// names a directory.
+    fn conflict(&self, path: &[&str], name: &str) -> Option<String> {
+        match path {
+            [] => self
+                .directories
+                .iter()
+                .any(|d| d.name == name)
+                .then(|| format!("{name} is a directory")),
+            [first, rest @ ..] => match self.files.iter().any(|f| f.name == *first) {
+                true => Some(format!("{first} is a file")),
+                false => self
+                    .directories
+                    .iter()
+                    .find(|d| d.name == *first)?
+                    .conflict(rest, name),
+            },
+        }
+    }
+
+    /// Remove the file or directory at `path`, and the directories it leaves
+    /// empty.
+    fn remove(&mut self, path: &[&str], directory: bool) -> bool {
+        match path {
+            [] => false,
+            [name] if directory => {
+                let count = self.directories.len();
+                self.directories.retain(|d| d.name != *name);
+                self.directories.len() != count
+            }
+            [name] => {
+                let count = self.files.len();
+                self.files.retain(|f| f.name != *name);
+                self.files.len() != count
+            }
+            [name, rest @ ..] => {
+                let Some(position) = self.directories.iter().position(|d| d.name == *name) else {
+                    return false;
+                };
+
+                let removed = self.directories[position].remove(rest, directory);
+                if removed && self.directories[position].is_empty() {
+                    self.directories.remove(position);
+                }
+
+                removed
+            }
+        }
+    }
+
+    fn walk<'a>(&'a self, prefix: &str, files: &mut Vec<(String, &'a File)>) {
+        for file in &self.files {
+            files.push((format!("{prefix}{}", file.name), file));
+        }
+
+        for directory in &self.directories {
+            directory.walk(&format!("{prefix}{}/", directory.name), files);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct File {
+    pub uid: u64,
+    pub name: String,
+    pub length: u64,
+    pub modify_time: u64,
+    pub readonly: bool,
+    /// Target of a symbolic link, which has no contents.
+    pub symlink: Option<String>,
+    pub extents: Vec<Extent>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl File {
+    fn parse(node: roxmltree::Node) -> Result<Self, Error> {
+        let mut extents = Vec::new();
+        if let Some(list) = optional(node, "extentinfo") {
+            for extent in list.children().filter(|n| n.has_tag_name("extent")) {
+                extents.push(Extent::parse(extent)?);
+            }
+        }
+
+        let mut attributes = Vec::new();
+        if let Some(list) = optional(node, "extendedattributes") {
+            for xattr in list.children().filter(|n| n.has_tag_name("xattr")) {
+                let value = optional(xattr, "value").and_then(|n| n.text());
+                attributes.push((text(xattr, "key")?, value.unwrap_or("").to_string()));
+            }
+        }
+
+        Ok(Self {
+            uid: optional_number(node, "fileuid")?,
+            name: name(node)?,
+            length: number(node, "length")?,
+            modify_time: time(node, "modifytime")?,
+            readonly: boolean(node, "readonly")?,
+            symlink: optional(node, "symlink").map(decoded).transpose()?,
+            extents,
+            attributes,
+        })
+    }
+
+    fn write_xml(&self, xml: &mut String) {
+        *xml += &format!(
+            "<file><fileuid>{}</fileuid>{}<length>{}</length>{}<readonly>{}</readonly>\n",
+            self.uid,
+            name_xml("name", &self.name),
+            self.length,
+            times_xml(self.modify_time),
+            self.readonly
+        );
+
+        if let Some(target) = &self.symlink {
+            *xml += &name_xml("symlink", target);
+        }
+
+        if !self.attributes.is_empty() {
+            *xml += "<extendedattributes>";
+            for (key, value) in &self.attributes {
+                *xml += &format!(
+                    "<xattr><key>{}</key><value>{}</value></xattr>",
+                    escape(key),
+                    escape(value)
+                );
+            }
+            *xml += "</extendedattributes>\n";
+        }
+
+        if !self.extents.is_empty() {
+            *xml += "<extentinfo>";
+            for extent in &self.extents {
+                *xml += &format!(
+                    "<extent><fileoffset>{}</fileoffset><partition>{}</partition>\
+                     <startblock>{}</startblock><byteoffset>{}</byteoffset>\
+                     <bytecount>{}</bytecount></extent>",
+                    extent.file_offset,
+                    extent.partition,
+                    extent.start_block,
+                    extent.byte_offset,
+                    extent.byte_count
+                );
+            }
+            *xml += "</extentinfo>\n";
+        }
+
+        *xml += "</file>\n";
+    }
+
+    /// Value of an extended attribute.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A run of bytes of a file, starting `byte_offset` bytes into a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extent {
+    pub file_offset: u64,
+    pub partition: char,
+    pub start_block: u64,
+    pub byte_offset: u64,
+    pub byte_count: u64,
+}
+
+impl Extent {
+    fn parse(node: roxmltree::Node) -> Result<Self, Error> {
+        Ok(Self {
+            file_offset: number(node, "fileoffset")?,
+            partition: partition(child(node, "partition")?)?,
+            start_block: number(node, "startblock")?,
+            byte_offset: number(node, "byteoffset")?,
+            byte_count: number(node, "bytecount")?,
+        })
+    }
+}
+
+fn optional<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Result<roxmltree::Node<'a, 'input>, Error> {
+    optional(node, name).ok_or_else(|| Error::Invalid(format!("missing element {name}")))
+}
+
+fn text(node: roxmltree::Node, name: &str) -> Result<String, Error> {
+    Ok(child(node, name)?.text().unwrap_or("").trim().to_string())
+}
+
+fn number(node: roxmltree::Node, name: &str) -> Result<u64, Error> {
+    let value = text(node, name)?;
+    value
+        .parse()
+        .map_err(|_| Error::Invalid(format!("{name} {value:?} is not a number")))
+}
+
+fn optional_number(node: roxmltree::Node, name: &str) -> Result<u64, Error> {
+    match optional(node, name) {
+        Some(_) => number(node, name),
+        None => Ok(0),
+    }
+}
+
+fn boolean(node: roxmltree::Node, name: &str) -> Result<bool, Error> {
+    match optional(node, name).and_then(|n| n.text()).map(str::trim) {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(value) => Err(Error::Invalid(format!("{name} {value:?} is not a boolean"))),
+    }
+}
+
+fn time(node: roxmltree::Node, name: &str) -> Result<u64, Error> {
+    let value = text(node, name)?;
+    parse_time(&value).ok_or_else(|| Error::Invalid(format!("{name} {value:?} is not a time")))
+}
+
+fn partition(node: roxmltree::Node) -> Result<char, Error> {
+    match node.text().map(str::trim) {
+        Some("a") => Ok('a'),
+        Some("b") => Ok('b'),
+        value => Err(Error::Invalid(format!("partition {value:?}"))),
+    }
+}
+
+fn position(node: roxmltree::Node) -> Result<Position, Error> {
+    Ok(Position {
+        partition: partition(child(node, "partition")?)?,
+        block: number(node, "startblock")?,
+    })
+}
+
+/// Text of an element, which is percent-encoded if it would not be valid XML.
+fn decoded(node: roxmltree::Node) -> Result<String, Error> {
+    let value = node.text().unwrap_or("");
+    if node.attribute("percentencoded") != Some("true") {
+        return Ok(value.to_string());
+    }
+
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let digits = tail.get(..2).and_then(|d| std::str::from_utf8(d).ok());
+            let decoded = digits.and_then(|d| u8::from_str_radix(d, 16).ok());
+            bytes.push(decoded.ok_or_else(|| Error::Invalid(format!("name {value:?}")))?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| Error::Invalid(format!("name {value:?}")))
+}
+
+/// Name of a file or directory, which must not escape its directory.
+fn name(node: roxmltree::Node) -> Result<String, Error> {
+    let name = decoded(child(node, "name")?)?;
+    if !valid_name(&name) {
+        return Err(Error::Invalid(format!("name {name:?}")));
+    }
+
+    Ok(name)
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+/// Split a path into the names of its directories and its file name.
+fn split_path(path: &str) -> Result<(Vec<&str>, &str), Error> {
+    let mut names: Vec<&str> = path.split('/').collect();
+    let name = names.pop().unwrap_or_default();
+    if !valid_name(name) || !names.iter().all(|name| valid_name(name)) {
+        return Err(Error::Invalid(format!("path {path:?}")));
+    }
+
+    Ok((names, name))
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Element holding a name, percent-encoded if it contains characters which
+/// XML cannot hold.
+fn name_xml(tag: &str, name: &str) -> String {
+    if !name.chars().any(char::is_control) {
+        return format!("<{tag}>{}</{tag}>", escape(name));
+    }
+
+    let mut encoded = String::new();
+    for c in name.chars() {
+        if c.is_control() || c == '%' {
+            for byte in c.to_string().bytes() {
+                encoded += &format!("%{byte:02X}");
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+
+    format!(
+        "<{tag} percentencoded=\"true\">{}</{tag}>",
+        escape(&encoded)
+    )
+}
+
+/// The times of a file or directory, all of which we set to the modification
+/// time.
+fn times_xml(time: u64) -> String {
+    let time = format_time(time);
+
+    [
+        "creationtime",
+        "changetime",
+        "modifytime",
+        "accesstime",
+        "backuptime",
+    ]
+    .iter()
+    .map(|tag| format!("<{tag}>{time}</{tag}>"))
+    .collect()
+}
+
+fn position_xml(position: Position) -> String {
+    format!(
+        "<partition>{}</partition><startblock>{}</startblock>",
+        position.partition, position.block
+    )
+}
+
+/// Parse a time of the form `2024-01-31T12:00:00.000000000Z` into seconds
+/// since the epoch, dropping the fraction.
+pub fn parse_time(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z')?;
+    let (date, time) = value.split_once('T')?;
+    let time = time.split_once('.').map_or(time, |(time, _)| time);
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    seconds.try_into().ok()
+}
+
+/// Format seconds since the epoch as a time of an index.
+pub fn format_time(time: u64) -> String {
+    let days = (time / 86400) as i64;
+    let seconds = time % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.000000000Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Date of the proleptic Gregorian calendar of a number of days since
+/// 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Physical partition number of a partition letter.
+fn partition_number(partition: char) -> i32 {
+    partition as i32 - 'a' as i32
+}
+
+/// A change of the index of a volume.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    /// A file appended to the data partition.
+    Write {
+        path: String,
+        file: File,
+    },
+    RemoveFile {
+        path: String,
+    },
+    RemoveDirectory {
+        path: String,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+}
+
+/// A change which the index on tape does not hold yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub volume_uuid: String,
+    /// Generation of the index the change was made to.
+    pub generation: u64,
+    pub change: Change,
+}
+
+/// An LTFS volume on the loaded cartridge, described by its latest index.
+pub struct Volume {
+    mt: Rc<dyn TapeDevice>,
+    vol1: Vol1,
+    label: Label,
+    index: Index,
+
+    /// Latest index on the data partition, which the next one points back to.
+    data_index: Option<Position>,
+
+    /// Files have been appended since the last index on the data partition.
+    appended: bool,
+
+    /// The index differs from the one on tape.
+    modified: bool,
+
+    /// Changes made since the last checkpoint.
+    changes: Vec<Change>,
+}
+
+impl Volume {
+    /// Read the labels and the latest index of the volume, and re-apply the
+    /// changes of `journal` which were made to that index.
+    pub fn open(device: Rc<dyn TapeDevice>, journal: &[JournalEntry]) -> Result<Self, Error> {
+        let mt = device.as_ref();
+        mt.set_block_length(0)?;
+        locate(mt, 'a', 0)?;
+
+        let mut record = vec![0u8; LABEL_BUFFER_SIZE];
+        let length = match mt.read_block(&mut record) {
+            Ok(length) => length,
+            // Records longer than a label cannot be one.
+            Err(mt::Error::Errno(Errno::ENOMEM)) => return Err(Error::NotLtfs),
+            Err(e) => {
+                // Reading beyond the end of data fails on blank cartridges.
+                let status = mt.get_status()?;
+                if status.mt_gstat.contains(mtio::GMTStatusFlags::EOD) {
+                    return Err(Error::Blank);
+                }
+
+                return Err(e.into());
+            }
+        };
+        let vol1 = Vol1::decode(&record[..length]).ok_or(Error::NotLtfs)?;
+
+        mt.fsf(1)?;
+        let length = mt.read_block(&mut record)?;
+        let xml = std::str::from_utf8(&record[..length])
+            .map_err(|_| Error::Invalid("label is not UTF-8".to_string()))?;
+        let label = Label::parse(xml)?;
+
+        // The index partition is written last, so its index is the latest
+        // unless the volume was not unmounted cleanly.
+        let mut latest: Option<Index> = None;
+        for partition in [label.data_partition, label.index_partition] {
+            let Some(index) = last_index(mt, partition, label.block_size)? else {
+                continue;
+            };
+
+            if index.volume_uuid != label.volume_uuid {
+                return Err(Error::Invalid(format!(
+                    "index of volume {} on volume {}",
+                    index.volume_uuid, label.volume_uuid
+                )));
+            }
+
+            if latest
+                .as_ref()
+                .is_none_or(|l| index.generation >= l.generation)
+            {
+                latest = Some(index);
+            }
+        }
+
+        let index = latest.ok_or(Error::NoIndex)?;
+
+        // The copy on the index partition points back to the one on the data
+        // partition.
+        let data_index = match index.location.partition == label.data_partition {
+            true => Some(index.location),
+            false => index
+                .previous
+                .filter(|previous| previous.partition == label.data_partition),
+        };
+
+        let mut volume = Self {
+            mt: device,
+            vol1,
+            label,
+            index,
+            data_index,
+            appended: false,
+            modified: false,
+            changes: Vec::new(),
+        };
+        volume.replay(journal);
+
+        Ok(volume)
+    }
+
+    /// Re-apply the changes of `journal` made to the index read from tape,
+    /// which the process making them ended before writing the index.
+    /// Changes made to an earlier generation are part of the index already.
+    fn replay(&mut self, journal: &[JournalEntry]) {
+        for entry in journal {
+            if entry.volume_uuid != self.label.volume_uuid
+                || entry.generation != self.index.generation
+            {
+                continue;
+            }
+
+            match &entry.change {
+                Change::Write { path, file } => {
+                    if let Ok((directories, _)) = self.file_path(path) {
+                        self.insert(&directories, file.clone());
+                        self.appended = true;
+                    }
+                }
+                Change::RemoveFile { path } => {
+                    self.remove_file(path);
+                }
+                Change::RemoveDirectory { path } => {
+                    self.remove_directory(path);
+                }
+                Change::Rename { from, to } => {
+                    let _ = self.rename_file(from, to);
+                }
+            }
+        }
+
+        // The journal holds them already.
+        self.changes.clear();
+    }
+
+    /// Partition the loaded cartridge and write the labels and an empty
+    /// index to both partitions.
+    ///
+    /// This overwrites the loaded cartridge!
+    pub fn format(device: Rc<dyn TapeDevice>, vol1: Vol1, label: Label) -> Result<Self, Error> {
+        let mt = device.as_ref();
+        mt.set_block_length(0)?;
+        mt.make_partition(-INDEX_PARTITION_SIZE)?;
+
+        for partition in [label.index_partition, label.data_partition] {
+            let label = Label {
+                location: partition,
+                ..label.clone()
+            };
+
+            locate(mt, partition, 0)?;
+            mt.write_block(&vol1.encode())?;
+            mt.weof(1)?;
+            mt.write_block(label.to_xml().as_bytes())?;
+            mt.weof(1)?;
+        }
+
+        let index = Index {
+            creator: label.creator.clone(),
+            volume_uuid: label.volume_uuid.clone(),
+            generation: 0,
+            update_time: label.format_time,
+            location: Position {
+                partition: label.index_partition,
+                block: FIRST_BLOCK,
+            },
+            previous: None,
+            highest_uid: 1,
+            root: Directory::new(1, &vol1.volume, label.format_time),
+        };
+
+        let time = label.format_time;
+        let mut volume = Self {
+            mt: device,
+            vol1,
+            label,
+            index,
+            data_index: None,
+            appended: false,
+            modified: true,
+            changes: Vec::new(),
+        };
+        volume.sync(time)?;
+
+        Ok(volume)
+    }
+
+    pub fn vol1(&self) -> &Vol1 {
+        &self.vol1
+    }
+
+    pub fn label(&self) -> &Label {
+        &self.label
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// The file at `path`, with directories separated by slashes.
+    pub fn find(&self, path: &str) -> Option<&File> {
+        self.index.root.find(&path.split('/').collect::<Vec<_>>())
+    }
+
+    /// Append `length` bytes of `data` to the data partition as the file at
+    /// `path`, replacing any file there, together with its SHA-256.
+    ///
+    /// The file only becomes part of the volume once the index has been
+    /// written by [`Volume::sync`], or its change has been kept in a journal
+    /// after [`Volume::checkpoint`].
+    pub fn write_file(
+        &mut self,
+        path: &str,
+        length: u64,
+        data: &mut dyn Read,
+        modify_time: u64,
+        mut attributes: Vec<(String, String)>,
+    ) -> Result<(), Error> {
+        let (directories, name) = self.file_path(path)?;
+        let partition = self.label.data_partition;
+
+        let mt = self.mt.as_ref();
+        mt.set_partition(partition_number(partition))?;
+        mt.eom()?;
+        let start_block = mt.get_position()? as u64;
+        self.appended = true;
+
+        let mut hasher = Algorithm::Sha256.hasher();
+        let mut record = vec![0u8; self.label.block_size];
+        let mut remaining = length;
+        while remaining > 0 {
+            let block = &mut record[..remaining.min(self.label.block_size as u64) as usize];
+            data.read_exact(block)?;
+            hasher.update(block);
+            mt.write_block(block)?;
+            remaining -= block.len() as u64;
+        }
+
+        let extents = match length {
+            0 => Vec::new(),
+            _ => vec![Extent {
+                file_offset: 0,
+                partition,
+                start_block,
+                byte_offset: 0,
+                byte_count: length,
+            }],
+        };
+
+        attributes.retain(|(key, _)| key != SHA256_ATTRIBUTE);
+        attributes.push((
+            SHA256_ATTRIBUTE.to_string(),
+            checksum::to_hex(&hasher.finish().digest),
+        ));
+
+        let file = File {
+            uid: 0,
+            name: name.to_string(),
+            length,
+            modify_time,
+            readonly: false,
+            symlink: None,
+            extents,
+            attributes,
+        };
+
+        self.changes.push(Change::Write {
+            path: path.to_string(),
+            file: file.clone(),
+        });
+        self.insert(&directories, file);
+
+        Ok(())
+    }
+
+    /// Split a path at which a file is to be added, which must not lead
+    /// through a file nor name a directory.
+    fn file_path<'p>(&self, path: &'p str) -> Result<(Vec<&'p str>, &'p str), Error> {
+        let (directories, name) = split_path(path)?;
+        if let Some(conflict) = self.index.root.conflict(&directories, name) {
+            return Err(Error::Invalid(conflict));
+        }
+
+        Ok((directories, name))
+    }
+
+    /// Add a file to the index, creating its directories. A new file gets a
+    /// new unique identifier, unless it replaces another one.
+    fn insert(&mut self, path: &[&str], mut file: File) {
+        let mut highest_uid = self.index.highest_uid;
+        let mut directory = &mut self.index.root;
+
+        for name in path {
+            let position = match directory.directories.iter().position(|d| d.name == *name) {
+                Some(position) => position,
+                None => {
+                    highest_uid += 1;
+                    let new = Directory::new(highest_uid, name, file.modify_time);
+                    directory.directories.push(new);
+                    directory.directories.len() - 1
+                }
+            };
+            directory = &mut directory.directories[position];
+        }
+
+        match directory.files.iter_mut().find(|f| f.name == file.name) {
+            Some(existing) => {
+                if file.uid == 0 {
+                    file.uid = existing.uid;
+                }
+                *existing = file;
+            }
+            None => {
+                if file.uid == 0 {
+                    highest_uid += 1;
+                    file.uid = highest_uid;
+                }
+                directory.files.push(file);
+            }
+        }
+
+        self.index.highest_uid = highest_uid;
+        self.modified = true;
+    }
+
+    /// Remove the file at `path` from the index, returning whether there was
+    /// one. Its contents stay on tape.
+    pub fn remove_file(&mut self, path: &str) -> bool {
+        let removed = self
+            .index
+            .root
+            .remove(&path.split('/').collect::<Vec<_>>(), false);
+        if removed {
+            self.modified = true;
+            self.changes.push(Change::RemoveFile {
+                path: path.to_string(),
+            });
+        }
+
+        removed
+    }
+
+    /// Remove the directory at `path` with everything in it from the index.
+    pub fn remove_directory(&mut self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        let removed = self
+            .index
+            .root
+            .remove(&path.split('/').collect::<Vec<_>>(), true);
+        if removed {
+            self.modified = true;
+            self.changes.push(Change::RemoveDirectory {
+                path: path.to_string(),
+            });
+        }
+
+        removed
+    }
+
+    /// Move the file at `from` to `to`, replacing any file there. Returns
+    /// whether there was a file to move.
+    pub fn rename_file(&mut self, from: &str, to: &str) -> Result<bool, Error> {
+        let (directories, name) = self.file_path(to)?;
+        let Some(mut file) = self.find(from).cloned() else {
+            return Ok(false);
+        };
+
+        self.index
+            .root
+            .remove(&from.split('/').collect::<Vec<_>>(), false);
+        file.name = name.to_string();
+        self.insert(&directories, file);
+        self.changes.push(Change::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+
+        Ok(true)
+    }
+
+    /// Write the files appended so far out of the buffer of the drive and
+    /// return the changes made since the last checkpoint, to be kept in a
+    /// journal until the next [`Volume::sync`].
+    pub fn checkpoint(&mut self) -> Result<Vec<JournalEntry>, Error> {
+        if self
+            .changes
+            .iter()
+            .any(|change| matches!(change, Change::Write { .. }))
+        {
+            self.mt.flush_drive_buffer()?;
+        }
+
+        let (volume_uuid, generation) = (&self.label.volume_uuid, self.index.generation);
+        let entries = self
+            .changes
+            .drain(..)
+            .map(|change| JournalEntry {
+                volume_uuid: volume_uuid.clone(),
+                generation,
+                change,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Write a new generation of the index to both partitions, unless it is
+    /// unchanged.
+    pub fn sync(&mut self, time: u64) -> Result<(), Error> {
+        if !self.modified {
+            return Ok(());
+        }
+
+        let mt = self.mt.as_ref();
+        let (data, index) = (self.label.data_partition, self.label.index_partition);
+
+        // The files appended since the last index end with a filemark.
+        mt.set_partition(partition_number(data))?;
+        mt.eom()?;
+        if self.appended {
+            mt.weof(1)?;
+        }
+
+        self.index.generation += 1;
+        self.index.update_time = time;
+        self.index.location = Position {
+            partition: data,
+            block: mt.get_position()? as u64,
+        };
+        self.index.previous = self.data_index;
+        write_index(mt, &self.index, self.label.block_size)?;
+        self.data_index = Some(self.index.location);
+        self.appended = false;
+
+        locate(mt, index, FIRST_BLOCK)?;
+        self.index.location = Position {
+            partition: index,
+            block: FIRST_BLOCK,
+        };
+        self.index.previous = self.data_index;
+        write_index(mt, &self.index, self.label.block_size)?;
+
+        mt.flush_drive_buffer()?;
+        self.modified = false;
+        // The index holds them now.
+        self.changes.clear();
+
+        Ok(())
+    }
+
+    /// Copy the contents of a file to `out`, returning its length.
+    ///
+    /// Parts of the file which no extent covers read as zeros. The contents
+    /// are verified against the SHA-256 attribute if the file has one.
+    pub fn read_file(&self, file: &File, out: &mut dyn Write) -> Result<u64, Error> {
+        let mut extents = file.extents.clone();
+        extents.sort_by_key(|extent| extent.file_offset);
+
+        let mut hasher = file
+            .attribute(SHA256_ATTRIBUTE)
+            .map(|hash| (checksum::from_hex(hash.trim()), Algorithm::Sha256.hasher()));
+        let mut emit = |data: &[u8]| -> io::Result<()> {
+            if let Some((_, hasher)) = hasher.as_mut() {
+                hasher.update(data);
+            }
+            out.write_all(data)
+        };
+
+        let mut record = vec![0u8; self.label.block_size];
+        let mut written = 0;
+        for extent in &extents {
+            if extent.file_offset < written || extent.file_offset + extent.byte_count > file.length
+            {
+                return Err(Error::Invalid(format!("extents of {}", file.name)));
+            }
+            write_zeros(&mut emit, extent.file_offset - written)?;
+
+            locate(self.mt.as_ref(), extent.partition, extent.start_block)?;
+            let mut skip = extent.byte_offset;
+            let mut remaining = extent.byte_count;
+            while remaining > 0 {
+                let length = self.mt.read_block(&mut record)?;
+                if length == 0 {
+                    return Err(Error::ShortFile(file.name.clone()));
+                }
+
+                let start = skip.min(length as u64);
+                skip -= start;
+                let data = &record[start as usize..length];
+                let data = &data[..data.len().min(remaining as usize)];
+                emit(data)?;
+                remaining -= data.len() as u64;
+            }
+
+            written = extent.file_offset + extent.byte_count;
+        }
+        write_zeros(&mut emit, file.length - written)?;
+
+        if let Some((expected, hasher)) = hasher {
+            if expected != Some(hasher.finish().digest) {
+                return Err(Error::ChecksumMismatch(file.name.clone()));
+            }
+        }
+
+        Ok(file.length)
+    }
+}
+
+fn write_zeros(emit: &mut dyn FnMut(&[u8]) -> io::Result<()>, mut count: u64) -> io::Result<()> {
+    let zeros = [0u8; 4096];
+    while count > 0 {
+        let length = count.min(zeros.len() as u64) as usize;
+        emit(&zeros[..length])?;
+        count -= length as u64;
+    }
+
+    Ok(())
+}
+
+/// Write an index at the current position, followed by a filemark.
+fn write_index(mt: &dyn TapeDevice, index: &Index, block_size: usize) -> Result<(), Error> {
+    for block in index.to_xml().as_bytes().chunks(block_size) {
+        mt.write_block(block)?;
+    }
+    mt.weof(1)?;
+
+    Ok(())
+}
+
+fn locate(mt: &dyn TapeDevice, partition: char, block: u64) -> Result<(), Error> {
+    mt.set_partition(partition_number(partition))?;
+    locate_block(mt, block)
+}
+
+fn is_filemark(mt: &dyn TapeDevice, block: u64, record: &mut [u8]) -> Result<bool, Error> {
+    locate_block(mt, block)?;
+    Ok(mt.read_block(record)? == 0)
+}
+
+fn locate_block(mt: &dyn TapeDevice, block: u64) -> Result<(), Error> {
+    let block = block
+        .try_into()
+        .map_err(|_| Error::Invalid(format!("block {block}")))?;
+    mt.seek(block)?;
+
+    Ok(())
+}
+
+/// Read the index at the end of a partition, `None` if it does not end with
+/// one.
+fn last_index(
+    mt: &dyn TapeDevice,
+    partition: char,
+    block_size: usize,
+) -> Result<Option<Index>, Error> {
+    let partition_number = partition_number(partition);
+    if let Err(e) = mt.set_partition(partition_number) {
+        return Err(match partition_number {
+            0 => e.into(),
+            _ => Error::SinglePartition,
+        });
+    }
+
+    mt.seek(0)?;
+    mt.eom()?;
+    let end = mt.get_position()? as u64;
+
+    // The index is followed by a filemark and preceded by another one, which
+    // may be the one after the label.
+    let mut record = vec![0u8; block_size];
+    if end < FIRST_BLOCK + 2 || !is_filemark(mt, end - 1, &mut record)? {
+        return Ok(None);
+    }
+
+    let mut start = end - 1;
+    while start > FIRST_BLOCK && !is_filemark(mt, start - 1, &mut record)? {
+        start -= 1;
+        if end - start > MAX_INDEX_RECORDS {
+            return Ok(None);
+        }
+    }
+    if start == end - 1 {
+        return Ok(None);
+    }
+
+    locate_block(mt, start)?;
+    let mut xml = Vec::new();
+    for _ in start..end - 1 {
+        let length = mt.read_block(&mut record)?;
+        xml.extend_from_slice(&record[..length]);
+    }
+
+    // Files may end with a filemark as well, which is not an index.
+    let Ok(xml) = String::from_utf8(xml) else {
+        return Ok(None);
+    };
+    match Index::parse(&xml) {
+        Ok(index) => Ok(Some(index)),
+        Err(Error::Invalid(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
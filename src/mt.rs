@@ -1,7 +1,9 @@
 use nix::fcntl;
 use nix::fcntl::OFlag;
 
-use crate::mtio;
+use crate::logpage::LogPage;
+use crate::{mtio, scsi};
+use std::fmt;
 use std::fs::read_to_string;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -34,8 +36,26 @@ pub enum Error {
     Errno(nix::Error),
     IO(io::Error),
     ParseIntError(ParseIntError),
+    Scsi(scsi::Error),
+    InvalidLogPage(u8),
+    Timeout,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Errno(e) => write!(f, "{e}"),
+            Self::IO(e) => write!(f, "{e}"),
+            Self::ParseIntError(e) => write!(f, "{e}"),
+            Self::Scsi(e) => write!(f, "{e}"),
+            Self::InvalidLogPage(page) => write!(f, "invalid log page 0x{page:02x}"),
+            Self::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
         Self::IO(value)
@@ -54,6 +74,12 @@ impl From<ParseIntError> for Error {
     }
 }
 
+impl From<scsi::Error> for Error {
+    fn from(value: scsi::Error) -> Self {
+        Self::Scsi(value)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 fn tape_nr(minor: i32) -> i32 {
@@ -76,9 +102,10 @@ pub struct MagneticTape {
 impl MagneticTape {
     pub fn new(path: &Path) -> Result<Self> {
         let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .custom_flags(libc::O_NONBLOCK).open(path)?;
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
 
         make_file_blocking(&file)?;
 
@@ -101,6 +128,106 @@ impl MagneticTape {
         }
     }
 
+    /// Send a SCSI command to the drive.
+    pub fn scsi_command(
+        &self,
+        cdb: &[u8],
+        direction: scsi::Direction,
+        data: &mut [u8],
+    ) -> Result<usize> {
+        Ok(scsi::execute(
+            self.file.as_raw_fd(),
+            cdb,
+            direction,
+            data,
+            scsi::DEFAULT_TIMEOUT,
+        )?)
+    }
+
+    /// Read the cumulative values of a log page.
+    pub fn log_sense(&self, page: u8) -> Result<LogPage> {
+        let mut buf = vec![0u8; 0xfffc];
+        let len = buf.len() as u16;
+
+        #[rustfmt::skip]
+        let cdb = [
+            0x4d, // LOG SENSE
+            0x00,
+            0x40 | (page & 0x3f), // PC = cumulative values
+            0x00,
+            0x00, 0x00, 0x00,
+            (len >> 8) as u8, len as u8,
+            0x00,
+        ];
+
+        let n = self.scsi_command(&cdb, scsi::Direction::FromDevice, &mut buf)?;
+
+        LogPage::parse(&buf[..n])
+            .filter(|p| p.code == page)
+            .ok_or(Error::InvalidLogPage(page))
+    }
+
+    /// Run the default self-test of the drive via SEND DIAGNOSTIC.
+    pub fn send_diagnostic(&self) -> Result<()> {
+        let cdb = [0x1d, 0x04, 0x00, 0x00, 0x00, 0x00]; // SEND DIAGNOSTIC, SELFTEST=1
+
+        scsi::execute(
+            self.file.as_raw_fd(),
+            &cdb,
+            scsi::Direction::None,
+            &mut [],
+            scsi::DIAGNOSTIC_TIMEOUT,
+        )?;
+
+        Ok(())
+    }
+
+    /// Check whether the drive is ready to accept media access commands.
+    ///
+    /// Returns the sense data if the drive is not ready.
+    pub fn test_unit_ready(&self) -> Result<Option<scsi::Sense>> {
+        let cdb = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00]; // TEST UNIT READY
+
+        match self.scsi_command(&cdb, scsi::Direction::None, &mut []) {
+            Ok(_) => Ok(None),
+            Err(Error::Scsi(scsi::Error::CheckCondition(sense))) => Ok(Some(sense)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the sense data of the last command.
+    pub fn request_sense(&self) -> Result<scsi::Sense> {
+        let mut buf = [0u8; 96];
+        let cdb = [0x03, 0x00, 0x00, 0x00, buf.len() as u8, 0x00]; // REQUEST SENSE
+
+        let n = self.scsi_command(&cdb, scsi::Direction::FromDevice, &mut buf)?;
+
+        Ok(scsi::Sense::parse(&buf[..n]))
+    }
+
+    /// Read the standard INQUIRY data.
+    pub fn inquiry(&self) -> Result<scsi::Inquiry> {
+        let mut buf = [0u8; 96];
+        let cdb = [0x12, 0x00, 0x00, 0x00, buf.len() as u8, 0x00]; // INQUIRY
+
+        let n = self.scsi_command(&cdb, scsi::Direction::FromDevice, &mut buf)?;
+
+        Ok(scsi::Inquiry::parse(&buf[..n]))
+    }
+
+    /// Read the unit serial number from the vital product data.
+    pub fn serial_number(&self) -> Result<String> {
+        let mut buf = [0u8; 255];
+        let cdb = [0x12, 0x01, 0x80, 0x00, buf.len() as u8, 0x00]; // INQUIRY, VPD page 0x80
+
+        let n = self.scsi_command(&cdb, scsi::Direction::FromDevice, &mut buf)?;
+        let len = usize::min(n, 4 + buf[3] as usize);
+
+        Ok(String::from_utf8_lossy(&buf[usize::min(4, len)..len])
+            .trim()
+            .to_string())
+    }
+
     /// Write a block of data to the tape.
     pub fn write_block(&self, block: &[u8]) -> Result<usize> {
         let bytes_written = unsafe {
@@ -227,6 +354,11 @@ impl MagneticTape {
         self.op(mtio::MTCmd::MTERASE, if fast { 1 } else { 0 })
     }
 
+    /// Run self test 1 (nondestructive).
+    pub fn self_test(&self) -> Result<i32> {
+        self.op(mtio::MTCmd::MTRAS1, 0)
+    }
+
     /// Set block length.
     pub fn set_block_length(&self, length: i32) -> Result<i32> {
         self.op(mtio::MTCmd::MTSETBLK, length)
@@ -288,6 +420,20 @@ impl MagneticTape {
         self.set_drive_buffer(cmd)
     }
 
+    /// Set how the driver detects cleaning requests in the extended sense data.
+    ///
+    /// The generic status bit `GMT_CLN` is set if the sense byte at `byte`
+    /// masked with `mask` matches `pattern`, or if any masked bit is set
+    /// and `pattern` is zero.
+    pub fn set_cleaning_request(&self, byte: u8, mask: u8, pattern: u8) -> Result<i32> {
+        let bits = (byte as i32) | ((mask as i32) << 8) | ((pattern as i32) << 16);
+        let cmd = mtio::SetDrvBufferOptions::from_bits_retain(
+            mtio::SetDrvBufferOptions::MT_ST_SET_CLN.bits() | bits,
+        );
+
+        self.set_drive_buffer(cmd)
+    }
+
     /// Space forward over setmarks.
     pub fn fss(&self, count: i32) -> Result<i32> {
         self.op(mtio::MTCmd::MTFSS, count)
@@ -343,3 +489,180 @@ impl MagneticTape {
         self.op(mtio::MTCmd::MTWEOFI, count)
     }
 }
+
+/// The operations of a tape drive which the higher layers rely on.
+///
+/// Implemented by `MagneticTape` for the Linux st driver and by
+/// `vtl::VirtualTape` for cartridge images on disk.
+pub trait TapeDevice {
+    /// Check whether the drive is ready, returning the sense data if not.
+    fn test_unit_ready(&self) -> Result<Option<scsi::Sense>>;
+
+    /// Read the sense data of the last command.
+    fn request_sense(&self) -> Result<scsi::Sense>;
+
+    /// Read the cumulative values of a log page.
+    fn log_sense(&self, page: u8) -> Result<LogPage>;
+
+    /// Read the standard INQUIRY data.
+    fn inquiry(&self) -> Result<scsi::Inquiry>;
+
+    /// Read the unit serial number.
+    fn serial_number(&self) -> Result<String>;
+
+    /// Run the default self-test of the drive.
+    fn send_diagnostic(&self) -> Result<()>;
+
+    /// Run self test 1 (nondestructive).
+    fn self_test(&self) -> Result<i32>;
+
+    /// Get drive status.
+    fn get_status(&self) -> Result<mtio::mtget>;
+
+    /// Get current tape position.
+    fn get_position(&self) -> Result<i64>;
+
+    /// Read a block of data from the tape.
+    fn read_block(&self, block: &mut [u8]) -> Result<usize>;
+
+    /// Write a block of data to the tape.
+    fn write_block(&self, block: &[u8]) -> Result<usize>;
+
+    /// Write an end-of-file record (mark).
+    fn weof(&self, count: i32) -> Result<i32>;
+
+    /// Rewind.
+    fn rewind(&self) -> Result<i32>;
+
+    /// Rewind and put the drive offline.
+    fn offline(&self) -> Result<i32>;
+
+    /// Goto end of recorded media.
+    fn eom(&self) -> Result<i32>;
+
+    /// Forward space over `count` filemarks.
+    fn fsf(&self, count: i32) -> Result<i32>;
+
+    /// Forward space over `count` records.
+    fn fsr(&self, count: i32) -> Result<i32>;
+
+    /// Erase tape.
+    fn erase(&self, fast: bool) -> Result<i32>;
+
+    /// Seek to block.
+    fn seek(&self, block: i32) -> Result<i32>;
+
+    /// Change the active partition. Blocks are numbered within a partition.
+    fn set_partition(&self, partition: i32) -> Result<i32>;
+
+    /// Format the cartridge with a single partition if `size` is zero,
+    /// otherwise with two, the second of `size` MB or the first of `-size` MB.
+    fn make_partition(&self, size: i32) -> Result<i32>;
+
+    /// Flush the drive buffer.
+    fn flush_drive_buffer(&self) -> Result<i32>;
+
+    /// Set block length.
+    fn set_block_length(&self, length: i32) -> Result<i32>;
+
+    /// Set how the driver detects cleaning requests.
+    fn set_cleaning_request(&self, byte: u8, mask: u8, pattern: u8) -> Result<i32>;
+}
+
+impl TapeDevice for MagneticTape {
+    fn test_unit_ready(&self) -> Result<Option<scsi::Sense>> {
+        MagneticTape::test_unit_ready(self)
+    }
+
+    fn request_sense(&self) -> Result<scsi::Sense> {
+        MagneticTape::request_sense(self)
+    }
+
+    fn log_sense(&self, page: u8) -> Result<LogPage> {
+        MagneticTape::log_sense(self, page)
+    }
+
+    fn inquiry(&self) -> Result<scsi::Inquiry> {
+        MagneticTape::inquiry(self)
+    }
+
+    fn serial_number(&self) -> Result<String> {
+        MagneticTape::serial_number(self)
+    }
+
+    fn send_diagnostic(&self) -> Result<()> {
+        MagneticTape::send_diagnostic(self)
+    }
+
+    fn self_test(&self) -> Result<i32> {
+        MagneticTape::self_test(self)
+    }
+
+    fn get_status(&self) -> Result<mtio::mtget> {
+        MagneticTape::get_status(self)
+    }
+
+    fn get_position(&self) -> Result<i64> {
+        MagneticTape::get_position(self)
+    }
+
+    fn read_block(&self, block: &mut [u8]) -> Result<usize> {
+        MagneticTape::read_block(self, block)
+    }
+
+    fn write_block(&self, block: &[u8]) -> Result<usize> {
+        MagneticTape::write_block(self, block)
+    }
+
+    fn weof(&self, count: i32) -> Result<i32> {
+        MagneticTape::weof(self, count)
+    }
+
+    fn rewind(&self) -> Result<i32> {
+        MagneticTape::rewind(self)
+    }
+
+    fn offline(&self) -> Result<i32> {
+        MagneticTape::offline(self)
+    }
+
+    fn eom(&self) -> Result<i32> {
+        MagneticTape::eom(self)
+    }
+
+    fn fsf(&self, count: i32) -> Result<i32> {
+        MagneticTape::fsf(self, count)
+    }
+
+    fn fsr(&self, count: i32) -> Result<i32> {
+        MagneticTape::fsr(self, count)
+    }
+
+    fn erase(&self, fast: bool) -> Result<i32> {
+        MagneticTape::erase(self, fast)
+    }
+
+    fn seek(&self, block: i32) -> Result<i32> {
+        MagneticTape::seek(self, block)
+    }
+
+    fn set_partition(&self, partition: i32) -> Result<i32> {
+        MagneticTape::set_partition(self, partition)
+    }
+
+    fn make_partition(&self, size: i32) -> Result<i32> {
+        MagneticTape::make_partition(self, size)
+    }
+
+    fn flush_drive_buffer(&self) -> Result<i32> {
+        MagneticTape::flush_drive_buffer(self)
+    }
+
+    fn set_block_length(&self, length: i32) -> Result<i32> {
+        MagneticTape::set_block_length(self, length)
+    }
+
+    fn set_cleaning_request(&self, byte: u8, mask: u8, pattern: u8) -> Result<i32> {
+        MagneticTape::set_cleaning_request(self, byte, mask, pattern)
+    }
+}
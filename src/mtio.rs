@@ -137,6 +137,7 @@ bitflags::bitflags! {
         const D_800 = 0x00200000;
         const DRIVE_OPEN = 0x00040000;  // Door open (no tape).
         const IM_REP_EN =  0x00010000;  // Immediate report mode.
+        const CLN = 0x00008000;  // Cleaning requested.
         const END_OF_STREAM = 0b00000001;
     }
 }
@@ -0,0 +1,94 @@
+//! Reed-Solomon parity records protecting the contents of objects
+//!
+//! The data records of an object are grouped into stripes of `data` records,
+//! each followed by `parity` records computed over the stripe. Up to `parity`
+//! records of a stripe may be unreadable, the missing data records are
+//! reconstructed from the others. The last stripe of an object may hold fewer
+//! data records.
+//!
+//! Parity records are as long as the first, i.e. longest, data record of their
+//! stripe. Shorter data records are padded with zeros for the computation.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::fmt;
+use std::str::FromStr;
+
+/// Maximum number of data and parity records in a stripe.
+const MAX_RECORDS: usize = 256;
+
+/// Ratio of data to parity records, written as `<data>+<parity>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parity {
+    pub data: u8,
+    pub parity: u8,
+}
+
+impl Parity {
+    pub fn new(data: u8, parity: u8) -> Option<Self> {
+        if data == 0 || parity == 0 || data as usize + parity as usize > MAX_RECORDS {
+            return None;
+        }
+
+        Some(Self { data, parity })
+    }
+
+    /// Compute the parity records of a stripe of data records.
+    pub fn encode(&self, stripe: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let size = stripe.iter().map(Vec::len).max().unwrap_or(0);
+
+        let mut shards: Vec<Vec<u8>> = stripe.iter().map(|record| padded(record, size)).collect();
+        shards.resize(stripe.len() + self.parity as usize, vec![0u8; size]);
+
+        // Stripes are never empty and the ratio has been validated.
+        let codec = ReedSolomon::new(stripe.len(), self.parity as usize).unwrap();
+        codec.encode(&mut shards).unwrap();
+
+        shards.split_off(stripe.len())
+    }
+
+    /// Reconstruct the missing data records of a stripe.
+    ///
+    /// `records` holds the data records followed by the parity records, with
+    /// `None` for records which could not be read. All records must have the
+    /// length of the parity records. Returns `false` if too many records are
+    /// missing.
+    pub fn reconstruct(&self, records: &mut [Option<Vec<u8>>]) -> bool {
+        let parity = self.parity as usize;
+        if records.len() <= parity {
+            return false;
+        }
+
+        match ReedSolomon::new(records.len() - parity, parity) {
+            Ok(codec) => codec.reconstruct_data(records).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Parity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.data, self.parity)
+    }
+}
+
+impl FromStr for Parity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (data, parity) = s.split_once('+').ok_or(())?;
+
+        Self::new(
+            data.parse().map_err(|_| ())?,
+            parity.parse().map_err(|_| ())?,
+        )
+        .ok_or(())
+    }
+}
+
+/// Copy a record, padded with zeros to `size`.
+pub fn padded(record: &[u8], size: usize) -> Vec<u8> {
+    let mut padded = record.to_vec();
+    padded.resize(size, 0);
+
+    padded
+}
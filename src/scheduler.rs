@@ -0,0 +1,219 @@
+//! Assignment of the drives of a library to concurrent transfers
+//!
+//! git-annex runs one remote process per concurrent transfer. The processes
+//! coordinate through lock files next to the catalog: a process holds the
+//! lock of the drive it uses and of the cartridge it needs, so that the same
+//! cartridge is never requested in two drives at once.
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::changer::{self, Address, Changer};
+
+/// Interval in which busy drives and cartridges are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum time to wait for a drive to become available.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+pub enum Error {
+    IO(io::Error),
+    Changer(changer::Error),
+    Busy,
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+impl From<changer::Error> for Error {
+    fn from(value: changer::Error) -> Self {
+        Self::Changer(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "{e}"),
+            Self::Changer(e) => write!(f, "{e}"),
+            Self::Busy => write!(f, "all drives are busy"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A drive of the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriveSlot {
+    /// Number of the drive within the changer.
+    pub unit: u16,
+    pub path: PathBuf,
+}
+
+/// State of a drive as reported by the changer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub unit: u16,
+    pub full: bool,
+    pub barcode: Option<String>,
+}
+
+/// Order the drives by preference.
+///
+/// A drive which already holds the wanted cartridge is the only choice.
+/// Otherwise empty drives come first and drives holding the cartridge to
+/// `avoid` (e.g. the one currently being filled) come last.
+pub fn preference(candidates: &[Candidate], wanted: Option<&str>, avoid: Option<&str>) -> Vec<u16> {
+    let holds = |c: &Candidate, barcode: Option<&str>| {
+        c.full && barcode.is_some() && c.barcode.as_deref() == barcode
+    };
+
+    if let Some(c) = candidates.iter().find(|c| holds(c, wanted)) {
+        return vec![c.unit];
+    }
+
+    let mut units: Vec<&Candidate> = candidates.iter().collect();
+    units.sort_by_key(|c| (holds(c, avoid), c.full));
+
+    units.into_iter().map(|c| c.unit).collect()
+}
+
+/// An exclusive lock which is released when dropped.
+pub struct Lock {
+    _file: Flock<File>,
+}
+
+impl Lock {
+    /// Try to take the lock without waiting.
+    pub(crate) fn try_lock(path: &Path) -> io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => Ok(Some(Self { _file: file })),
+            Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+            Err((_, errno)) => Err(errno.into()),
+        }
+    }
+}
+
+/// The right to use a drive, and the wanted cartridge, until dropped.
+pub struct Lease {
+    pub drive: DriveSlot,
+
+    /// Barcode of the cartridge which has been reserved.
+    pub media: Option<String>,
+
+    _drive_lock: Lock,
+    _media_lock: Option<Lock>,
+}
+
+pub struct Scheduler {
+    dir: PathBuf,
+    drives: Vec<DriveSlot>,
+}
+
+impl Scheduler {
+    /// Create a scheduler which keeps its lock files in `dir`.
+    pub fn new(dir: &Path, drives: Vec<DriveSlot>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            drives,
+        })
+    }
+
+    pub fn drives(&self) -> &[DriveSlot] {
+        &self.drives
+    }
+
+    fn candidates(&self, changer: Option<&dyn Changer>) -> Result<Vec<Candidate>, Error> {
+        self.drives
+            .iter()
+            .map(|d| match changer {
+                Some(changer) => {
+                    let element = changer.element(Address::drive(d.unit))?;
+                    Ok(Candidate {
+                        unit: d.unit,
+                        full: element.full,
+                        barcode: element.barcode,
+                    })
+                }
+                None => Ok(Candidate {
+                    unit: d.unit,
+                    full: false,
+                    barcode: None,
+                }),
+            })
+            .collect()
+    }
+
+    /// Reserve the cartridge with `barcode` without a drive, e.g. to move it
+    /// between slots, failing with `Busy` if another process holds it.
+    pub fn reserve(&self, barcode: &str) -> Result<Lock, Error> {
+        Lock::try_lock(&self.dir.join(format!("media-{barcode}.lock")))?.ok_or(Error::Busy)
+    }
+
+    /// Reserve a drive and the `wanted` cartridge, waiting up to `timeout`
+    /// for other processes to release them.
+    ///
+    /// Drives holding the `avoid` cartridge are only used if no other drive is free.
+    pub fn acquire(
+        &self,
+        changer: Option<&dyn Changer>,
+        wanted: Option<&str>,
+        avoid: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Lease, Error> {
+        let start = Instant::now();
+        let mut media_lock = None;
+
+        loop {
+            // Always lock the cartridge before the drive so that processes
+            // waiting for each other can not deadlock.
+            if let (Some(barcode), None) = (wanted, &media_lock) {
+                media_lock = Lock::try_lock(&self.dir.join(format!("media-{barcode}.lock")))?;
+            }
+
+            if wanted.is_none() || media_lock.is_some() {
+                let candidates = self.candidates(changer)?;
+
+                for unit in preference(&candidates, wanted, avoid) {
+                    let path = self.dir.join(format!("drive-{unit}.lock"));
+
+                    if let Some(drive_lock) = Lock::try_lock(&path)? {
+                        let drive = self.drives.iter().find(|d| d.unit == unit).unwrap();
+
+                        return Ok(Lease {
+                            drive: drive.clone(),
+                            media: wanted.map(str::to_string),
+                            _drive_lock: drive_lock,
+                            _media_lock: media_lock,
+                        });
+                    }
+                }
+            }
+
+            if start.elapsed() > timeout {
+                return Err(Error::Busy);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
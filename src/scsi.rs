@@ -0,0 +1,335 @@
+//! SCSI command passthrough and sense data decoding
+//!
+//! also see: SPC-4 (SCSI Primary Commands) and SSC-4 (SCSI Stream Commands)
+
+use std::fmt;
+use std::os::unix::io::RawFd;
+
+use crate::sgio;
+
+/// Default timeout for SCSI commands in milliseconds.
+pub const DEFAULT_TIMEOUT: u32 = 60_000;
+
+/// Timeout for self-tests in milliseconds.
+pub const DIAGNOSTIC_TIMEOUT: u32 = 30 * 60_000;
+
+const SENSE_BUFFER_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    None,
+    ToDevice,
+    FromDevice,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Errno(nix::Error),
+    CheckCondition(Sense),
+    Status(u8),
+    Host(u16),
+    Driver(u16),
+}
+
+impl From<nix::Error> for Error {
+    fn from(value: nix::Error) -> Self {
+        Self::Errno(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Errno(e) => write!(f, "{e}"),
+            Self::CheckCondition(sense) => write!(f, "check condition: {sense}"),
+            Self::Status(status) => write!(f, "unexpected SCSI status 0x{status:02x}"),
+            Self::Host(status) => write!(f, "host adapter error 0x{status:04x}"),
+            Self::Driver(status) => write!(f, "driver error 0x{status:04x}"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Execute a SCSI command via the SG_IO ioctl.
+///
+/// Returns the number of bytes actually transferred.
+pub fn execute(
+    fd: RawFd,
+    cdb: &[u8],
+    direction: Direction,
+    data: &mut [u8],
+    timeout: u32,
+) -> Result<usize> {
+    let mut sense = [0u8; SENSE_BUFFER_LENGTH];
+
+    let mut hdr = sgio::sg_io_hdr {
+        interface_id: sgio::SG_INTERFACE_ID_ORIG,
+        dxfer_direction: match direction {
+            Direction::None => sgio::SG_DXFER_NONE,
+            Direction::ToDevice => sgio::SG_DXFER_TO_DEV,
+            Direction::FromDevice => sgio::SG_DXFER_FROM_DEV,
+        },
+        cmd_len: cdb.len() as libc::c_uchar,
+        mx_sb_len: sense.len() as libc::c_uchar,
+        iovec_count: 0,
+        dxfer_len: if direction == Direction::None {
+            0
+        } else {
+            data.len() as libc::c_uint
+        },
+        dxferp: data.as_mut_ptr() as *mut libc::c_void,
+        cmdp: cdb.as_ptr(),
+        sbp: sense.as_mut_ptr(),
+        timeout,
+        flags: 0,
+        pack_id: 0,
+        usr_ptr: std::ptr::null_mut(),
+        status: 0,
+        masked_status: 0,
+        msg_status: 0,
+        sb_len_wr: 0,
+        host_status: 0,
+        driver_status: 0,
+        resid: 0,
+        duration: 0,
+        info: 0,
+    };
+
+    unsafe {
+        sgio::sgio(fd, &mut hdr)?;
+    }
+
+    if hdr.info & sgio::SG_INFO_OK_MASK != sgio::SG_INFO_OK {
+        if hdr.status == sgio::SAM_STAT_CHECK_CONDITION
+            || hdr.driver_status & sgio::DRIVER_SENSE != 0
+        {
+            let sense = Sense::parse(&sense[..hdr.sb_len_wr as usize]);
+
+            // Recovered errors and informational sense data are not failures.
+            if !sense.is_error() {
+                return Ok(data.len().saturating_sub(hdr.resid as usize));
+            }
+
+            return Err(Error::CheckCondition(sense));
+        }
+
+        if hdr.status != sgio::SAM_STAT_GOOD {
+            return Err(Error::Status(hdr.status));
+        }
+
+        if hdr.host_status != 0 {
+            return Err(Error::Host(hdr.host_status));
+        }
+
+        return Err(Error::Driver(hdr.driver_status));
+    }
+
+    Ok(data.len().saturating_sub(hdr.resid as usize))
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenseKey {
+    NoSense = 0x0,
+    RecoveredError = 0x1,
+    NotReady = 0x2,
+    MediumError = 0x3,
+    HardwareError = 0x4,
+    IllegalRequest = 0x5,
+    UnitAttention = 0x6,
+    DataProtect = 0x7,
+    BlankCheck = 0x8,
+    VendorSpecific = 0x9,
+    CopyAborted = 0xa,
+    AbortedCommand = 0xb,
+    Reserved = 0xc,
+    VolumeOverflow = 0xd,
+    Miscompare = 0xe,
+    Completed = 0xf,
+}
+
+impl From<u8> for SenseKey {
+    fn from(value: u8) -> Self {
+        match value & 0x0f {
+            0x0 => Self::NoSense,
+            0x1 => Self::RecoveredError,
+            0x2 => Self::NotReady,
+            0x3 => Self::MediumError,
+            0x4 => Self::HardwareError,
+            0x5 => Self::IllegalRequest,
+            0x6 => Self::UnitAttention,
+            0x7 => Self::DataProtect,
+            0x8 => Self::BlankCheck,
+            0x9 => Self::VendorSpecific,
+            0xa => Self::CopyAborted,
+            0xb => Self::AbortedCommand,
+            0xc => Self::Reserved,
+            0xd => Self::VolumeOverflow,
+            0xe => Self::Miscompare,
+            _ => Self::Completed,
+        }
+    }
+}
+
+/// Decoded SCSI sense data (fixed or descriptor format).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sense {
+    pub response_code: u8,
+    pub key: SenseKey,
+    pub asc: u8,
+    pub ascq: u8,
+    pub filemark: bool,
+    pub eom: bool,
+    pub ili: bool,
+    pub information: Option<u32>,
+}
+
+impl Sense {
+    /// Parse raw sense data as returned by the device.
+    pub fn parse(buf: &[u8]) -> Self {
+        let byte = |i: usize| buf.get(i).copied().unwrap_or(0);
+        let response_code = byte(0) & 0x7f;
+
+        match response_code {
+            // Descriptor format
+            0x72 | 0x73 => Self {
+                response_code,
+                key: SenseKey::from(byte(1)),
+                asc: byte(2),
+                ascq: byte(3),
+                filemark: false,
+                eom: false,
+                ili: false,
+                information: None,
+            },
+
+            // Fixed format
+            _ => Self {
+                response_code,
+                key: SenseKey::from(byte(2)),
+                asc: byte(12),
+                ascq: byte(13),
+                filemark: byte(2) & 0x80 != 0,
+                eom: byte(2) & 0x40 != 0,
+                ili: byte(2) & 0x20 != 0,
+                information: if byte(0) & 0x80 != 0 {
+                    Some(u32::from_be_bytes([byte(3), byte(4), byte(5), byte(6)]))
+                } else {
+                    None
+                },
+            },
+        }
+    }
+
+    /// Whether this sense data indicates a failed command.
+    pub fn is_error(&self) -> bool {
+        !matches!(self.key, SenseKey::NoSense | SenseKey::RecoveredError)
+    }
+
+    /// Human readable description of the additional sense code.
+    pub fn description(&self) -> Option<&'static str> {
+        ASC_DESCRIPTIONS
+            .iter()
+            .find(|(asc, ascq, _)| *asc == self.asc && *ascq == self.ascq)
+            .map(|(_, _, desc)| *desc)
+    }
+}
+
+impl fmt::Display for Sense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} (ASC 0x{:02x}, ASCQ 0x{:02x})",
+            self.key, self.asc, self.ascq
+        )?;
+
+        if let Some(desc) = self.description() {
+            write!(f, ": {desc}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Standard INQUIRY data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inquiry {
+    pub device_type: u8,
+    pub vendor: String,
+    pub product: String,
+    pub revision: String,
+}
+
+impl Inquiry {
+    pub fn parse(buf: &[u8]) -> Self {
+        let field = |start: usize, end: usize| {
+            let end = usize::min(end, buf.len());
+            let start = usize::min(start, end);
+
+            String::from_utf8_lossy(&buf[start..end]).trim().to_string()
+        };
+
+        Self {
+            device_type: buf.first().copied().unwrap_or(0x1f) & 0x1f,
+            vendor: field(8, 16),
+            product: field(16, 32),
+            revision: field(32, 36),
+        }
+    }
+}
+
+// Additional sense codes which are relevant for sequential-access devices.
+// from: SPC-4, Annex D
+const ASC_DESCRIPTIONS: &[(u8, u8, &str)] = &[
+    (0x00, 0x00, "No additional sense information"),
+    (0x00, 0x01, "Filemark detected"),
+    (0x00, 0x02, "End-of-partition/medium detected"),
+    (0x00, 0x04, "Beginning-of-partition/medium detected"),
+    (0x00, 0x05, "End-of-data detected"),
+    (0x00, 0x16, "Operation in progress"),
+    (0x00, 0x17, "Cleaning requested"),
+    (0x00, 0x18, "Erase operation in progress"),
+    (0x00, 0x19, "Locate operation in progress"),
+    (0x00, 0x1a, "Rewind operation in progress"),
+    (0x03, 0x02, "Excessive write errors"),
+    (0x04, 0x00, "Logical unit not ready, cause not reportable"),
+    (0x04, 0x01, "Logical unit is in process of becoming ready"),
+    (0x04, 0x02, "Logical unit not ready, initializing command required"),
+    (0x04, 0x03, "Logical unit not ready, manual intervention required"),
+    (0x04, 0x12, "Logical unit not ready, offline"),
+    (0x0c, 0x00, "Write error"),
+    (0x11, 0x00, "Unrecovered read error"),
+    (0x11, 0x01, "Read retries exhausted"),
+    (0x14, 0x00, "Recorded entity not found"),
+    (0x14, 0x03, "End-of-data not found"),
+    (0x15, 0x01, "Mechanical positioning error"),
+    (0x1a, 0x00, "Parameter list length error"),
+    (0x20, 0x00, "Invalid command operation code"),
+    (0x24, 0x00, "Invalid field in CDB"),
+    (0x26, 0x00, "Invalid field in parameter list"),
+    (0x27, 0x00, "Write protected"),
+    (0x28, 0x00, "Not ready to ready change, medium may have changed"),
+    (0x29, 0x00, "Power on, reset, or bus device reset occurred"),
+    (0x2a, 0x01, "Mode parameters changed"),
+    (0x30, 0x00, "Incompatible medium installed"),
+    (0x30, 0x01, "Cannot read medium, unknown format"),
+    (0x30, 0x02, "Cannot read medium, incompatible format"),
+    (0x30, 0x03, "Cleaning cartridge installed"),
+    (0x30, 0x07, "Cleaning failure"),
+    (0x31, 0x00, "Medium format corrupted"),
+    (0x33, 0x00, "Tape length error"),
+    (0x3a, 0x00, "Medium not present"),
+    (0x3b, 0x00, "Sequential positioning error"),
+    (0x3b, 0x08, "Reposition error"),
+    (0x3e, 0x01, "Logical unit failure"),
+    (0x3e, 0x03, "Logical unit failed self-test"),
+    (0x40, 0x00, "Diagnostic failure"),
+    (0x44, 0x00, "Internal target failure"),
+    (0x50, 0x00, "Write append error"),
+    (0x51, 0x00, "Erase failure"),
+    (0x52, 0x00, "Cartridge fault"),
+    (0x53, 0x00, "Media load or eject failed"),
+    (0x53, 0x02, "Medium removal prevented"),
+    (0x5d, 0x00, "Failure prediction threshold exceeded"),
+];
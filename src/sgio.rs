@@ -0,0 +1,53 @@
+//! Linux SCSI Generic ioctl definitions
+//!
+//! from: /usr/include/scsi/sg.h
+//!
+//! The st driver accepts the SG_IO ioctl on its character devices, which
+//! allows us to send arbitrary SCSI commands to the drive.
+
+use nix;
+
+pub const SG_INTERFACE_ID_ORIG: libc::c_int = b'S' as libc::c_int;
+
+pub const SG_DXFER_NONE: libc::c_int = -1; // e.g. a SCSI Test Unit Ready command.
+pub const SG_DXFER_TO_DEV: libc::c_int = -2; // e.g. a SCSI WRITE command.
+pub const SG_DXFER_FROM_DEV: libc::c_int = -3; // e.g. a SCSI READ command.
+
+pub const SG_INFO_OK_MASK: libc::c_uint = 0x1;
+pub const SG_INFO_OK: libc::c_uint = 0x0; // No sense or driver noise.
+pub const SG_INFO_CHECK: libc::c_uint = 0x1; // Something abnormal happened.
+
+pub const SAM_STAT_GOOD: u8 = 0x00;
+pub const SAM_STAT_CHECK_CONDITION: u8 = 0x02;
+
+pub const DRIVER_SENSE: libc::c_ushort = 0x08;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct sg_io_hdr {
+    pub interface_id: libc::c_int,    // [i] 'S' for SCSI generic (required).
+    pub dxfer_direction: libc::c_int, // [i] Data transfer direction.
+    pub cmd_len: libc::c_uchar,       // [i] SCSI command length ( <= 16 bytes).
+    pub mx_sb_len: libc::c_uchar,     // [i] Max length to write to sbp.
+    pub iovec_count: libc::c_ushort,  // [i] 0 implies no scatter gather.
+    pub dxfer_len: libc::c_uint,      // [i] Byte count of data transfer.
+    pub dxferp: *mut libc::c_void,    // [i], [*io] Points to data transfer memory or scatter gather list.
+    pub cmdp: *const libc::c_uchar,   // [i], [*i] Points to command to perform.
+    pub sbp: *mut libc::c_uchar,      // [i], [*o] Points to sense_buffer memory.
+    pub timeout: libc::c_uint,        // [i] MAX_UINT->no timeout (unit: millisec).
+    pub flags: libc::c_uint,          // [i] 0 -> default, see SG_FLAG...
+    pub pack_id: libc::c_int,         // [i->o] Unused internally (normally).
+    pub usr_ptr: *mut libc::c_void,   // [i->o] Unused internally.
+    pub status: libc::c_uchar,        // [o] SCSI status.
+    pub masked_status: libc::c_uchar, // [o] Shifted, masked SCSI status.
+    pub msg_status: libc::c_uchar,    // [o] Messaging level data (optional).
+    pub sb_len_wr: libc::c_uchar,     // [o] Byte count actually written to sbp.
+    pub host_status: libc::c_ushort,  // [o] Errors from host adapter.
+    pub driver_status: libc::c_ushort, // [o] Errors from software driver.
+    pub resid: libc::c_int,           // [o] dxfer_len - actual_transferred.
+    pub duration: libc::c_uint,       // [o] Time taken by cmd (unit: millisec).
+    pub info: libc::c_uint,           // [o] Auxiliary information.
+}
+
+//#define SG_IO 0x2285	// Similar effect as write() followed by read().
+nix::ioctl_readwrite_bad!(sgio, 0x2285, sg_io_hdr);
@@ -0,0 +1,159 @@
+//! Copies of small objects which are waiting in the pack of an archive
+//!
+//! git-annex may drop its copy of an object as soon as its store has been
+//! acknowledged, while a small object only reaches the tape once its pack is
+//! written. The remote keeps a copy of such objects in a spool next to the
+//! catalog until then:
+//!
+//! ```text
+//! .git/annex/spool/<remote>/<session>/<key>
+//! .git/annex/spool/<remote>/<session>.lock
+//! ```
+//!
+//! Every remote process spools into a session of its own, which it holds
+//! locked. Sessions which are not locked have been left behind by a process
+//! which ended before writing its pack, their objects are stored again by
+//! the next process.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::scheduler::Lock;
+
+pub struct Spool {
+    dir: PathBuf,
+    lock_path: PathBuf,
+    _lock: Lock,
+
+    /// Keys of the spooled objects in the order they have been added.
+    keys: Vec<String>,
+}
+
+impl Spool {
+    /// Start a new session in the spool `dir`.
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let name = uuid::Uuid::new_v4().to_string();
+        let lock_path = dir.join(format!("{name}.lock"));
+        let lock = Lock::try_lock(&lock_path)?
+            .ok_or_else(|| io::Error::other(format!("spool session {name} is in use")))?;
+
+        let dir = dir.join(name);
+        fs::create_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            lock_path,
+            _lock: lock,
+            keys: Vec::new(),
+        })
+    }
+
+    /// The sessions in the spool `dir` of processes which have ended.
+    pub fn abandoned(dir: &Path) -> io::Result<Vec<Self>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().to_string();
+            let lock_path = dir.join(format!("{name}.lock"));
+            let Some(lock) = Lock::try_lock(&lock_path)? else {
+                continue;
+            };
+
+            let keys = match spooled_keys(&entry.path()) {
+                Ok(keys) => keys,
+                // Removed by the process which held the lock before.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            sessions.push(Self {
+                dir: entry.path(),
+                lock_path,
+                _lock: lock,
+                keys,
+            });
+        }
+
+        Ok(sessions)
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Path of the spooled contents of `key`.
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Copy the contents of `key` from `data` into the spool. The copy is on
+    /// disk when this returns, and keeps the modification time of `data`.
+    pub fn add(&mut self, key: &str, data: &mut File) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{key}.tmp"));
+        let mut file = File::create(&tmp)?;
+        io::copy(data, &mut file)?;
+        if let Ok(modified) = data.metadata()?.modified() {
+            file.set_modified(modified)?;
+        }
+        file.sync_all()?;
+
+        fs::rename(&tmp, self.path(key))?;
+        File::open(&self.dir)?.sync_all()?;
+        self.keys.push(key.to_string());
+
+        Ok(())
+    }
+
+    /// Remove the spooled objects which are on tape, all but the last `waiting`.
+    pub fn settle(&mut self, waiting: usize) -> io::Result<()> {
+        let written = self.keys.len().saturating_sub(waiting);
+
+        for key in self.keys.drain(..written) {
+            match fs::remove_file(self.dir.join(&key)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// End the session. Unless all its objects are on tape, it is left for
+    /// the next process to store them again.
+    pub fn finish(self) -> io::Result<()> {
+        if self.keys.is_empty() {
+            fs::remove_dir_all(&self.dir)?;
+            fs::remove_file(&self.lock_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keys of the objects spooled in a session, leaving out partial copies.
+fn spooled_keys(dir: &Path) -> io::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".tmp") {
+            keys.push(name);
+        }
+    }
+
+    keys.sort();
+
+    Ok(keys)
+}
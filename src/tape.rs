@@ -1,72 +1,2958 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter;
 use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::{format, mt};
+use crate::checksum::{self, Checksum, KeyHash};
+use crate::compression::{self, Compressed, Compression, Decoder};
+use crate::crypto::{self, Cipher, KeyId};
+use crate::format::{
+    ArchiveFormat, ArchiveHeader, ArchiveToc, MediaHeader, MediaIdentity, Metadata, ObjectHeader,
+    ObjectTrailer, Origin, PackMember, TocEntry, Tombstone,
+};
+use crate::health::{DriveHealth, MediaHealth};
+use crate::mt::TapeDevice;
+use crate::parity::{self, Parity};
+use crate::{cleaning, diagnostic, format, logpage, ltfs, mt, mtio, scsi, tapealert, tar, vtl};
+use serde::{Deserialize, Serialize};
+
+/// Interval in which the drive is polled while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Maximum time the drive may take to load a cartridge.
+pub const LOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Size of the records in which headers and object data are written to tape.
+pub const RECORD_SIZE: usize = 256 * 1024;
+
+/// Objects up to this size are packed together with other small objects.
+pub const PACK_OBJECT_SIZE: u64 = 64 * 1024;
+
+/// Maximum length of the contents of a pack.
+pub const PACK_SIZE: usize = 16 * RECORD_SIZE;
+
+/// Key of the git bundle of the repository stored in archives. Keys of
+/// git-annex never start with a dot.
+pub const BUNDLE_KEY: &str = ".git-bundle";
+
+/// Contents of encrypted objects per record, leaving room for the tag.
+const SEALED_RECORD_SIZE: usize = RECORD_SIZE - crypto::TAG_LENGTH;
+
+/// Algorithm of the content checksums of newly written objects.
+const CHECKSUM_ALGORITHM: checksum::Algorithm = checksum::Algorithm::Sha256;
+
+#[derive(Debug)]
+pub enum Error {
+    Tape(mt::Error),
+    IO(io::Error),
+    Blank,
+    InvalidHeader(format::Error),
+    UnexpectedKey(String),
+    ShortObject {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch {
+        expected: Checksum,
+        actual: Checksum,
+    },
+    KeyMismatch(String),
+    Unrecoverable(String),
+    /// Encrypted data cannot be opened, the key is missing or wrong.
+    Decryption,
+    UnknownKey(KeyId),
+    /// The cartridge belongs to the remote with the given UUID.
+    ForeignMedia(String),
+    /// The feature cannot be used on cartridges in tar format.
+    TarUnsupported(&'static str),
+}
+
+impl From<mt::Error> for Error {
+    fn from(value: mt::Error) -> Self {
+        Self::Tape(value)
+    }
+}
+
+impl From<format::Error> for Error {
+    fn from(value: format::Error) -> Self {
+        Self::InvalidHeader(value)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tape(e) => write!(f, "{e}"),
+            Self::IO(e) => write!(f, "{e}"),
+            Self::Blank => write!(f, "cartridge is blank"),
+            Self::InvalidHeader(e) => write!(f, "invalid header: {e}"),
+            Self::UnexpectedKey(key) => write!(f, "found unexpected key {key}"),
+            Self::ShortObject { expected, actual } => {
+                write!(f, "object is {actual} bytes long instead of {expected}")
+            }
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch (expected {expected}, found {actual})")
+            }
+            Self::KeyMismatch(key) => write!(f, "content does not match key {key}"),
+            Self::Decryption => write!(f, "cannot decrypt, the key is missing or wrong"),
+            Self::UnknownKey(id) => write!(f, "archive is encrypted with unknown key {id}"),
+            Self::ForeignMedia(remote) => write!(f, "cartridge belongs to remote {remote}"),
+            Self::TarUnsupported(feature) => {
+                write!(f, "{feature} is not supported on cartridges in tar format")
+            }
+            Self::Unrecoverable(key) => {
+                write!(f, "unreadable records of {key} cannot be reconstructed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Name of this host as recorded in media and archive headers.
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// An object found by scanning a cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectEntry {
+    pub key: String,
+
+    /// Creation time of the archive containing the object.
+    pub archive: u64,
+
+    /// Logical block number of the object header.
+    pub block: u64,
+
+    pub length: u64,
+
+    /// Content checksum from the object trailer, if the object has one.
+    pub checksum: Option<Checksum>,
+}
+
+/// The tombstone of a removed object, found by scanning a cartridge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TombstoneEntry {
+    pub key: String,
+
+    /// Creation time of the archive containing the tombstone.
+    pub archive: u64,
+
+    /// Logical block number of the tombstone.
+    pub block: u64,
+}
+
+impl TombstoneEntry {
+    /// Check whether the tombstone removes `object`, i.e. whether the object
+    /// has been written before it.
+    pub fn removes(&self, object: &ObjectEntry) -> bool {
+        self.key == object.key && (self.archive, self.block) > (object.archive, object.block)
+    }
+}
+
+/// An archive found by scanning a cartridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub creation_time: u64,
+    pub host: String,
+
+    /// Logical block number of the archive header.
+    pub block: u64,
+
+    /// The archive has been listed from its table of contents.
+    pub indexed: bool,
+
+    pub origin: Origin,
+
+    /// Name and version of the program which wrote the archive.
+    pub writer: String,
+
+    /// Id of the key the archive is encrypted with, if it is not the key of
+    /// the cartridge. Its objects are counted but not listed.
+    pub unknown_key: Option<KeyId>,
+
+    /// Number and total length of the objects in an archive with an unknown key.
+    pub sealed_objects: u64,
+    pub sealed_length: u64,
+}
+
+/// Result of scanning all archives on a cartridge.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scan {
+    pub archives: Vec<ArchiveEntry>,
+
+    /// Completely written objects.
+    pub objects: Vec<ObjectEntry>,
+
+    /// Objects whose writing has been interrupted. Their keys need to be
+    /// stored again.
+    pub torn: Vec<ObjectEntry>,
+
+    /// Complete git bundles of the repository.
+    pub bundles: Vec<ObjectEntry>,
+
+    /// Tombstones of removed objects. Objects written before them are left
+    /// out of `objects` and `torn`.
+    pub tombstones: Vec<TombstoneEntry>,
+}
+
+impl Scan {
+    /// The bundle in the most recent archive.
+    pub fn newest_bundle(&self) -> Option<&ObjectEntry> {
+        self.bundles.iter().max_by_key(|b| (b.archive, b.block))
+    }
+}
+
+/// A record read while scanning.
+enum Record {
+    Data(usize),
+    Filemark,
+    EndOfData,
+}
+
+/// Outcome of scanning the data and trailer of an object.
+enum Scanned {
+    /// The object is complete, with the checksum from its trailer.
+    Complete(Option<Checksum>),
+    Torn,
+    /// The object is torn, and the given record needs to be scanned again.
+    Interrupted(u64, Record),
+}
+
+/// Reason why an object could not be salvaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loss {
+    /// A record of the object could not be read.
+    Unreadable,
+
+    /// Writing the object has been interrupted.
+    Torn,
+
+    /// The contents do not match the checksum in the trailer or the key.
+    Corrupt,
+
+    /// The object is listed in a table of contents, but its header could not
+    /// be read.
+    Missing,
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable => write!(f, "unreadable"),
+            Self::Torn => write!(f, "torn"),
+            Self::Corrupt => write!(f, "corrupt"),
+            Self::Missing => write!(f, "header unreadable"),
+        }
+    }
+}
+
+/// An object which could not be salvaged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LostObject {
+    pub key: String,
+
+    /// Creation time of the archive containing the object.
+    pub archive: u64,
+
+    /// Logical block number of the object header.
+    pub block: u64,
+
+    pub loss: Loss,
+}
+
+/// Result of salvaging a damaged cartridge.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Salvage {
+    /// Objects whose contents have been recovered and verified.
+    pub recovered: Vec<ObjectEntry>,
+
+    /// Objects which could not be recovered and have not been recovered from
+    /// another copy on the cartridge either.
+    pub lost: Vec<LostObject>,
+
+    /// Number of records which could not be read and have been skipped.
+    pub unreadable: u64,
+
+    /// Archives encrypted with an unknown key, whose objects have been skipped.
+    pub sealed: Vec<ArchiveEntry>,
+}
+
+/// Receives the contents of the objects read by [`Media::salvage`].
+pub trait SalvageSink {
+    /// Start receiving the contents of an object.
+    fn begin(&mut self, key: &str) -> io::Result<()>;
+
+    /// Receive the metadata of the object, if it has any.
+    fn describe(&mut self, _metadata: &Metadata) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// The contents received since `begin` are complete and verified.
+    fn commit(&mut self) -> io::Result<()>;
+
+    /// The contents received since `begin` have to be thrown away.
+    fn discard(&mut self) -> io::Result<()>;
+}
+
+/// Outcome of salvaging the data and trailer of an object.
+enum Salvaged {
+    /// The object has been verified, with the checksum of its contents.
+    Recovered(Option<Checksum>),
+    Lost(Loss),
+    /// The object is torn, and the given record needs to be salvaged again.
+    Interrupted(u64, Record),
+}
+
+/// A stripe of data records of an object, followed by its parity records.
+///
+/// Objects without parity consist of stripes of a single data record.
+struct Stripe {
+    /// Lengths of the data records.
+    lengths: Vec<usize>,
+    parity: usize,
+}
+
+impl Stripe {
+    /// Split the contents of an object into stripes.
+    fn split(header: &ObjectHeader) -> impl Iterator<Item = Stripe> {
+        let (data, parity) = header
+            .parity
+            .map_or((1, 0), |p| (p.data as usize, p.parity as usize));
+
+        let length = header.object_length;
+        let records = length.div_ceil(RECORD_SIZE as u64);
+
+        (0..records).step_by(data).map(move |first| {
+            let last = u64::min(first + data as u64, records);
+            let lengths = (first..last)
+                .map(|i| u64::min(length - i * RECORD_SIZE as u64, RECORD_SIZE as u64) as usize)
+                .collect();
+
+            Stripe { lengths, parity }
+        })
+    }
+
+    /// Length of the contents of the data records, without the tags of
+    /// encrypted objects.
+    fn contents_length(&self, header: &ObjectHeader) -> usize {
+        let tags = match header.nonce {
+            Some(_) => self.lengths.len() * crypto::TAG_LENGTH,
+            None => 0,
+        };
+
+        self.lengths.iter().sum::<usize>() - tags
+    }
+
+    /// Length of the parity records, which is that of the first data record.
+    fn parity_length(&self) -> usize {
+        self.lengths[0]
+    }
+
+    /// Lengths of all records of the stripe.
+    fn record_lengths(&self) -> impl Iterator<Item = usize> + '_ {
+        let parity = iter::repeat_n(self.parity_length(), self.parity);
+
+        self.lengths.iter().copied().chain(parity)
+    }
+}
+
+/// Outcome of reading a stripe of an object.
+enum StripeRead {
+    /// The data records, reconstructed from parity where necessary.
+    Complete(Vec<Vec<u8>>),
+    /// More records are unreadable than parity records are available.
+    Unrecoverable,
+    /// The object ends early, and the given record needs to be scanned again.
+    Interrupted(u64, Record),
+}
+
+/// Position of a stored object, as recorded in the git-annex state of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// Id of the cartridge, see [`Media::id`].
+    pub media: String,
+
+    /// Logical block number of the object header.
+    pub block: u64,
+
+    /// Barcode of the cartridge, if known.
+    pub barcode: Option<String>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "media={} block={}", self.media, self.block)?;
+
+        if let Some(barcode) = &self.barcode {
+            write!(f, " barcode={barcode}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Location {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut media = None;
+        let mut block = None;
+        let mut barcode = None;
+
+        for part in s.split_ascii_whitespace() {
+            match part.split_once('=') {
+                Some(("media", v)) => media = Some(v.to_string()),
+                Some(("block", v)) => block = v.parse().ok(),
+                Some(("barcode", v)) => barcode = Some(v.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            media: media.ok_or(())?,
+            block: block.ok_or(())?,
+            barcode,
+        })
+    }
+}
 
 pub struct Drive {
-    mt: mt::MagneticTape,
+    mt: Rc<dyn TapeDevice>,
+}
+
+impl Drive {
+    /// Open a tape drive, or a drive of a virtual library.
+    pub fn new(path: &Path) -> Result<Self, mt::Error> {
+        if let Some(tape) = vtl::VirtualTape::open(path)? {
+            return Ok(Self::with_device(Box::new(tape)));
+        }
+
+        Ok(Self::with_device(Box::new(mt::MagneticTape::new(path)?)))
+    }
+
+    pub fn with_device(mt: Box<dyn TapeDevice>) -> Self {
+        Self { mt: Rc::from(mt) }
+    }
+
+    /// Check whether the drive is ready, returning the sense data if not.
+    pub fn test_unit_ready(&self) -> Result<Option<scsi::Sense>, mt::Error> {
+        self.mt.test_unit_ready()
+    }
+
+    /// Wait until a cartridge has been loaded and the drive is ready.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<(), mt::Error> {
+        self.wait_until(timeout, |sense| sense.is_none())
+    }
+
+    /// Wait until the drive has ejected its cartridge.
+    pub fn wait_empty(&self, timeout: Duration) -> Result<(), mt::Error> {
+        // ASC/ASCQ 0x3a/0x00 "Medium not present"
+        self.wait_until(timeout, |sense| matches!(sense, Some(s) if s.asc == 0x3a))
+    }
+
+    fn wait_until(
+        &self,
+        timeout: Duration,
+        done: impl Fn(&Option<scsi::Sense>) -> bool,
+    ) -> Result<(), mt::Error> {
+        let start = Instant::now();
+
+        loop {
+            if done(&self.mt.test_unit_ready()?) {
+                return Ok(());
+            }
+
+            if start.elapsed() > timeout {
+                return Err(mt::Error::Timeout);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Rewind and eject the cartridge so that a changer can remove it.
+    pub fn eject(&self) -> Result<(), mt::Error> {
+        self.mt.rewind()?;
+        self.mt.offline()?;
+
+        Ok(())
+    }
+
+    /// Get the drive status.
+    pub fn status(&self) -> Result<mtio::mtget, mt::Error> {
+        self.mt.get_status()
+    }
+
+    /// Get the health of the drive from its error counters.
+    pub fn health(&self) -> Result<DriveHealth, mt::Error> {
+        let write = self.mt.log_sense(logpage::WRITE_ERROR_COUNTER)?;
+        let read = self.mt.log_sense(logpage::READ_ERROR_COUNTER)?;
+        let sequential = self.mt.log_sense(logpage::SEQUENTIAL_ACCESS_DEVICE).ok();
+
+        Ok(DriveHealth::from_pages(&write, &read, sequential.as_ref()))
+    }
+
+    /// Get the health of the loaded cartridge from its volume statistics.
+    pub fn media_health(&self) -> Result<MediaHealth, mt::Error> {
+        let page = self.mt.log_sense(logpage::VOLUME_STATISTICS)?;
+
+        Ok(MediaHealth::from_page(&page))
+    }
+
+    /// Get vendor and product of the drive.
+    pub fn inquiry(&self) -> Result<scsi::Inquiry, mt::Error> {
+        self.mt.inquiry()
+    }
+
+    /// Get the serial number which identifies the drive in the catalog.
+    pub fn serial_number(&self) -> Result<String, mt::Error> {
+        self.mt.serial_number()
+    }
+
+    /// Get the active TapeAlert flags.
+    pub fn tape_alerts(&self) -> Result<Vec<tapealert::Flag>, mt::Error> {
+        let page = self.mt.log_sense(logpage::TAPE_ALERT)?;
+
+        Ok(tapealert::from_page(&page))
+    }
+
+    /// Configure which bits of the sense data signal a cleaning request.
+    ///
+    /// See `mt::TapeDevice::set_cleaning_request`.
+    pub fn set_cleaning_request(&self, byte: u8, mask: u8, pattern: u8) -> Result<(), mt::Error> {
+        self.mt.set_cleaning_request(byte, mask, pattern)?;
+
+        Ok(())
+    }
+
+    /// Check whether the drive requests cleaning.
+    pub fn cleaning_required(&self) -> Result<Option<cleaning::Reason>, mt::Error> {
+        let status = self.mt.get_status()?;
+        if status.mt_gstat.contains(mtio::GMTStatusFlags::CLN) {
+            return Ok(Some(cleaning::Reason::StatusFlag));
+        }
+
+        if let Ok(page) = self.mt.log_sense(logpage::SEQUENTIAL_ACCESS_DEVICE) {
+            let required = page.counter(logpage::sequential_access::CLEANING_REQUIRED);
+            if required.unwrap_or(0) != 0 {
+                return Ok(Some(cleaning::Reason::LogPage));
+            }
+        }
+
+        if let Ok(flags) = self.tape_alerts() {
+            if let Some(reason) = cleaning::from_tape_alerts(&flags) {
+                return Ok(Some(reason));
+            }
+        }
+
+        if let Ok(sense) = self.mt.request_sense() {
+            if let Some(reason) = cleaning::from_sense(sense.asc, sense.ascq) {
+                return Ok(Some(reason));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Run the non-destructive self-test of the drive.
+    pub fn self_test(&self) -> diagnostic::SelfTest {
+        diagnostic::self_test(self.mt.as_ref())
+    }
+
+    /// Run a write/read/compare test with the given block size.
+    ///
+    /// This overwrites the loaded cartridge!
+    pub fn write_read_test(&self, block_size: usize, blocks: usize) -> diagnostic::BlockSizeResult {
+        diagnostic::write_read_compare(self.mt.as_ref(), block_size, blocks)
+    }
+
+    /// Open the loaded cartridge as an LTFS volume and read its latest index,
+    /// re-applying the changes of `journal`, see [`ltfs::Volume::open`].
+    pub fn ltfs(&self, journal: &[ltfs::JournalEntry]) -> Result<ltfs::Volume, ltfs::Error> {
+        ltfs::Volume::open(Rc::clone(&self.mt), journal)
+    }
+
+    /// Format the loaded cartridge as an empty LTFS volume.
+    ///
+    /// This overwrites the loaded cartridge!
+    pub fn format_ltfs(
+        &self,
+        vol1: ltfs::Vol1,
+        label: ltfs::Label,
+    ) -> Result<ltfs::Volume, ltfs::Error> {
+        ltfs::Volume::format(Rc::clone(&self.mt), vol1, label)
+    }
+
+    /// Erase the cartridge from the beginning of the tape.
+    ///
+    /// A fast erase only writes an end of data mark, a secure erase
+    /// overwrites the whole tape and may take hours.
+    pub fn erase(&self, secure: bool) -> Result<(), mt::Error> {
+        self.mt.rewind()?;
+        self.mt.erase(!secure)?;
+
+        Ok(())
+    }
+
+    /// Write a new media header with a new UUID to the beginning of the
+    /// cartridge.
+    ///
+    /// This makes all data on the cartridge inaccessible!
+    pub fn init_media(&self, creation_time: u64, host: &str) -> Result<(), Error> {
+        let identity = MediaIdentity {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            ..MediaIdentity::default()
+        };
+
+        self.write_media_header(&MediaHeader::new(creation_time, host).with_identity(identity))
+    }
+
+    /// Write `header` to the beginning of the cartridge.
+    ///
+    /// This makes all data on the cartridge inaccessible!
+    pub fn write_media_header(&self, header: &MediaHeader) -> Result<(), Error> {
+        self.mt.set_block_length(0)?;
+        self.mt.rewind()?;
+        self.mt.write_block(&format::encode(header))?;
+        self.mt.weof(1)?;
+
+        Ok(())
+    }
+
+    /// Rewind the loaded cartridge and read its media header.
+    pub fn load_media(self: &Rc<Self>) -> Result<Media, Error> {
+        self.mt.set_block_length(0)?;
+        self.mt.rewind()?;
+
+        let mut record = vec![0u8; RECORD_SIZE];
+        let n = match self.mt.read_block(&mut record) {
+            Ok(n) => n,
+            Err(e) => {
+                // Reading beyond the end of data fails on blank cartridges.
+                let status = self.mt.get_status()?;
+                if status.mt_gstat.contains(mtio::GMTStatusFlags::EOD) {
+                    return Err(Error::Blank);
+                }
+
+                return Err(e.into());
+            }
+        };
+
+        if n == 0 {
+            return Err(Error::Blank);
+        }
+
+        let header: MediaHeader = format::decode(&record[..n])?;
+
+        Ok(Media {
+            drive: Rc::clone(self),
+            creation_time: header.creation_time,
+            host: header.host,
+            identity: header.identity,
+            format: header.format,
+            cipher: None,
+            origin: Origin::default(),
+        })
+    }
+}
+
+/// A cartridge with a valid media header.
+pub struct Media {
+    drive: Rc<Drive>,
+    creation_time: u64,
+    host: String,
+    identity: MediaIdentity,
+    format: ArchiveFormat,
+    cipher: Option<Cipher>,
+    origin: Origin,
+}
+
+impl Media {
+    /// Encrypt the objects of new archives with `cipher`, and decrypt
+    /// objects when reading.
+    pub fn with_cipher(self, cipher: Option<Cipher>) -> Self {
+        Self { cipher, ..self }
+    }
+
+    /// Record `origin` in the headers of new archives.
+    pub fn with_origin(self, origin: Origin) -> Self {
+        Self { origin, ..self }
+    }
+
+    pub fn creation_time(&self) -> u64 {
+        self.creation_time
+    }
+
+    /// Identifier of the cartridge in the catalog and in object locations:
+    /// the UUID from its media header, or its creation time for older media
+    /// headers without one.
+    pub fn id(&self) -> String {
+        match self.identity.uuid.as_str() {
+            "" => self.creation_time.to_string(),
+            uuid => uuid.to_string(),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn identity(&self) -> &MediaIdentity {
+        &self.identity
+    }
+
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Check whether an object location refers to this cartridge.
+    ///
+    /// Locations recorded before cartridges were identified by their UUID
+    /// refer to their creation time.
+    pub fn contains(&self, location: &Location) -> bool {
+        location.media == self.id() || location.media == self.creation_time.to_string()
+    }
+
+    /// Start a new archive after the last archive on the cartridge.
+    ///
+    /// With `parity`, the contents of every object are followed by parity
+    /// records from which unreadable records are reconstructed.
+    ///
+    /// Cartridges owned by a remote other than that of the origin are refused.
+    /// Archives on cartridges in tar format can neither be encrypted nor have
+    /// parity records.
+    pub fn append_archive(
+        &self,
+        creation_time: u64,
+        host: &str,
+        parity: Option<Parity>,
+    ) -> Result<Archive, Error> {
+        if !self.identity.accepts(&self.origin.remote) {
+            return Err(Error::ForeignMedia(self.identity.remote.clone()));
+        }
+
+        if self.format == ArchiveFormat::Tar {
+            if self.cipher.is_some() {
+                return Err(Error::TarUnsupported("encryption"));
+            }
+            if parity.is_some() {
+                return Err(Error::TarUnsupported("parity"));
+            }
+        }
+
+        let mt = &self.drive.mt;
+
+        mt.eom()?;
+        let start = mt.get_position()? as u64;
+
+        let header = ArchiveHeader::new(creation_time, host)
+            .with_parity(parity)
+            .with_key_id(self.cipher.as_ref().map(Cipher::id))
+            .with_origin(self.origin.clone());
+        match self.format {
+            ArchiveFormat::Native => mt.write_block(&format::encode(&header))?,
+            ArchiveFormat::Tar => {
+                let member = tar::Member::Archive(header);
+                mt.write_block(&tar::encode_member(&member, 0, creation_time))?
+            }
+        };
+
+        Ok(Archive {
+            drive: Rc::clone(&self.drive),
+            creation_time,
+            format: self.format,
+            parity,
+            cipher: self.cipher.clone(),
+            compression: None,
+            pack: None,
+            start,
+            objects: 0,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Check whether the object header at `block` belongs to `key`.
+    pub fn check_object(&self, block: u64, key: &str) -> Result<bool, Error> {
+        if self.format == ArchiveFormat::Tar {
+            return self.check_tar_object(block, key);
+        }
+
+        let mut record = vec![0u8; RECORD_SIZE];
+
+        match self.read_object_header(block, &mut record) {
+            Ok(header) => Ok(header.key == key || header.members.iter().any(|m| m.key == key)),
+            Err(Error::InvalidHeader(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_object_header(&self, block: u64, record: &mut [u8]) -> Result<ObjectHeader, Error> {
+        let mt = &self.drive.mt;
+
+        mt.seek(block as i32)?;
+        let n = mt.read_block(record)?;
+
+        self.open_header(format::decode(&record[..n])?)
+    }
+
+    /// Decrypt the keys, member checksums and metadata of an encrypted object
+    /// header.
+    fn open_header(&self, mut header: ObjectHeader) -> Result<ObjectHeader, Error> {
+        if header.nonce.is_none() {
+            return Ok(header);
+        }
+
+        let cipher = self.cipher.as_ref().ok_or(Error::Decryption)?;
+
+        // Packs have no key of their own.
+        if !header.key.is_empty() {
+            header.key = cipher.open_key(&header.key).ok_or(Error::Decryption)?;
+        }
+        header.metadata = open_metadata(cipher, &header.metadata)?;
+        for member in &mut header.members {
+            member.key = cipher.open_key(&member.key).ok_or(Error::Decryption)?;
+            member.checksum = cipher
+                .open_checksum(&member.checksum)
+                .ok_or(Error::Decryption)?;
+            member.metadata = open_metadata(cipher, &member.metadata)?;
+        }
+
+        Ok(header)
+    }
+
+    /// Decrypt the key of the trailer of an encrypted object, so that it can
+    /// be compared to its opened header.
+    fn open_trailer(&self, header: &ObjectHeader, mut trailer: ObjectTrailer) -> ObjectTrailer {
+        if let (Some(_), Some(cipher), Some(object)) =
+            (header.nonce, &self.cipher, &mut trailer.object)
+        {
+            object.key = cipher.open_key(&object.key).unwrap_or_default();
+        }
+
+        trailer
+    }
+
+    /// Decrypt the data records of an object, starting with record `first`.
+    fn open_records(
+        &self,
+        header: &ObjectHeader,
+        first: usize,
+        records: Vec<Vec<u8>>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let Some(nonce) = &header.nonce else {
+            return Ok(records);
+        };
+
+        let cipher = self.cipher.as_ref().ok_or(Error::Decryption)?;
+        let count = header.object_length.div_ceil(RECORD_SIZE as u64) as usize;
+
+        records
+            .iter()
+            .zip(first..)
+            .map(|(record, index)| {
+                cipher
+                    .open_record(nonce, index as u32, index + 1 == count, record)
+                    .ok_or(Error::Decryption)
+            })
+            .collect()
+    }
+
+    /// The id of the key of an archive whose objects cannot be decrypted.
+    fn unknown_key(&self, header: &ArchiveHeader) -> Option<KeyId> {
+        match (header.key_id, &self.cipher) {
+            (Some(id), Some(cipher)) if cipher.id() != id => Some(id),
+            (Some(id), None) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Decrypt an entry of the table of contents of an encrypted archive.
+    fn open_entry(&self, header: &ArchiveHeader, mut entry: TocEntry) -> Result<TocEntry, Error> {
+        if let (Some(_), Some(cipher)) = (header.key_id, &self.cipher) {
+            entry.key = cipher.open_key(&entry.key).ok_or(Error::Decryption)?;
+            entry.checksum = match entry.checksum {
+                Some(checksum) => Some(cipher.open_checksum(&checksum).ok_or(Error::Decryption)?),
+                None => None,
+            };
+        }
+
+        Ok(entry)
+    }
+
+    /// Decrypt the key of a tombstone in an encrypted archive.
+    fn open_tombstone(&self, sealed: bool, mut tombstone: Tombstone) -> Result<Tombstone, Error> {
+        if let (true, Some(cipher)) = (sealed, &self.cipher) {
+            tombstone.key = cipher.open_key(&tombstone.key).ok_or(Error::Decryption)?;
+        }
+
+        Ok(tombstone)
+    }
+
+    /// Read the object stored at `block` and write its contents to `out`.
+    ///
+    /// The contents are verified against the checksum in the object trailer
+    /// and against the hash in the key. On a mismatch the data has already
+    /// been written to `out` and must be discarded.
+    ///
+    /// Returns the length of the object.
+    pub fn read_object(&self, block: u64, key: &str, out: &mut dyn Write) -> Result<u64, Error> {
+        if self.format == ArchiveFormat::Tar {
+            return self.read_tar_object(block, key, out);
+        }
+
+        let mt = &self.drive.mt;
+
+        let mut header_record = vec![0u8; RECORD_SIZE];
+        let header = self.read_object_header(block, &mut header_record)?;
+        if header.key != key {
+            return match header.members.iter().find(|m| m.key == key) {
+                Some(member) => self.read_member(block, &header, member, out),
+                None => Err(Error::UnexpectedKey(header.key)),
+            };
+        }
+
+        let mut hasher = header.checksum.map(checksum::Algorithm::hasher);
+        let mut contents = Decoder::new(header.compressed, Verify::new(key, out))?;
+
+        let mut record = vec![0u8; RECORD_SIZE];
+        let length = header.object_length;
+        let mut read = 0;
+        let mut index = 0;
+
+        for stripe in Stripe::split(&header) {
+            let records = match self.read_stripe(&stripe, header.parity, &mut record, &mut 0)? {
+                StripeRead::Complete(records) => records,
+                StripeRead::Unrecoverable => return Err(Error::Unrecoverable(key.to_string())),
+                StripeRead::Interrupted(..) => {
+                    return Err(Error::ShortObject {
+                        expected: length,
+                        actual: read,
+                    })
+                }
+            };
+
+            // The trailer checksum covers the contents as stored.
+            for data in &records {
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(data);
+                }
+
+                read += data.len() as u64;
+            }
+
+            let first = index;
+            index += records.len();
+
+            for data in self.open_records(&header, first, records)? {
+                contents.write_all(&data)?;
+            }
+        }
+
+        let contents = contents.finish()?;
+        if contents.length != header.uncompressed_length {
+            return Err(Error::ShortObject {
+                expected: header.uncompressed_length,
+                actual: contents.length,
+            });
+        }
+
+        if let Some(hasher) = hasher {
+            let n = mt.read_block(&mut record)?;
+            let trailer: ObjectTrailer = format::decode(&record[..n])?;
+
+            let actual = hasher.finish();
+            if actual != trailer.checksum {
+                return Err(Error::ChecksumMismatch {
+                    expected: trailer.checksum,
+                    actual,
+                });
+            }
+        }
+
+        if contents.verify() == Some(false) {
+            return Err(Error::KeyMismatch(key.to_string()));
+        }
+
+        Ok(header.uncompressed_length)
+    }
+
+    /// Read an object from the pack at `block`.
+    ///
+    /// Only the stripes holding the contents of the object are read.
+    fn read_member(
+        &self,
+        block: u64,
+        header: &ObjectHeader,
+        member: &PackMember,
+        out: &mut dyn Write,
+    ) -> Result<u64, Error> {
+        let mt = &self.drive.mt;
+
+        let mut stored = Vec::new();
+        let mut record = vec![0u8; RECORD_SIZE];
+        let end = member.offset + member.length;
+        let mut position = block + 1;
+        let mut offset = 0;
+        let mut index = 0;
+
+        for stripe in Stripe::split(header) {
+            if offset >= end {
+                break;
+            }
+
+            let size = stripe.contents_length(header) as u64;
+            if offset + size > member.offset {
+                mt.seek(position as i32)?;
+
+                let records = match self.read_stripe(&stripe, header.parity, &mut record, &mut 0)? {
+                    StripeRead::Complete(records) => self.open_records(header, index, records)?,
+                    StripeRead::Unrecoverable => {
+                        return Err(Error::Unrecoverable(member.key.clone()))
+                    }
+                    StripeRead::Interrupted(..) => {
+                        return Err(Error::ShortObject {
+                            expected: header.object_length,
+                            actual: offset,
+                        })
+                    }
+                };
+
+                let mut record_offset = offset;
+                for data in &records {
+                    let from = member
+                        .offset
+                        .saturating_sub(record_offset)
+                        .min(data.len() as u64);
+                    let to = end.saturating_sub(record_offset).min(data.len() as u64);
+                    stored.extend_from_slice(&data[from as usize..to as usize]);
+
+                    record_offset += data.len() as u64;
+                }
+            }
+
+            offset += size;
+            index += stripe.lengths.len();
+            position += (stripe.lengths.len() + stripe.parity) as u64;
+        }
+
+        let contents = unpack_member(member, stored)?;
+        out.write_all(&contents)?;
+
+        let mut hasher = member.checksum.algorithm.hasher();
+        hasher.update(&contents);
+        let actual = hasher.finish();
+        if actual != member.checksum {
+            return Err(Error::ChecksumMismatch {
+                expected: member.checksum.clone(),
+                actual,
+            });
+        }
+
+        if let Some(mut key_hash) = KeyHash::from_key(&member.key) {
+            key_hash.update(&contents);
+            if !key_hash.verify() {
+                return Err(Error::KeyMismatch(member.key.clone()));
+            }
+        }
+
+        Ok(member.uncompressed_length)
+    }
+
+    /// Read all archives on the cartridge and list the objects they contain.
+    ///
+    /// Closed archives are listed from their table of contents, which is
+    /// read by spacing to their end. Other archives are read record by
+    /// record: an object is complete if all of its data is followed by a
+    /// trailer which belongs to it. Objects cut short by a crash or a write
+    /// error are reported as torn. Bundles are listed apart from objects,
+    /// torn bundles are left out. Objects followed by a tombstone for their
+    /// key have been removed and are left out as well.
+    pub fn scan(&self) -> Result<Scan, Error> {
+        let mut scan = Scan::default();
+
+        match self.format {
+            ArchiveFormat::Native => self.scan_native(&mut scan)?,
+            ArchiveFormat::Tar => self.scan_tar(&mut scan)?,
+        }
+
+        let (bundles, objects) = scan.objects.into_iter().partition(|o| o.key == BUNDLE_KEY);
+        scan.bundles = bundles;
+        scan.objects = objects;
+        scan.torn.retain(|o| o.key != BUNDLE_KEY);
+
+        let tombstones = &scan.tombstones;
+        let removed = |o: &ObjectEntry| tombstones.iter().any(|t| t.removes(o));
+        scan.objects.retain(|o| !removed(o));
+        scan.torn.retain(|o| !removed(o));
+
+        Ok(scan)
+    }
+
+    fn scan_native(&self, scan: &mut Scan) -> Result<(), Error> {
+        let mt = &self.drive.mt;
+        let mut record = vec![0u8; RECORD_SIZE];
+
+        // Skip the media header.
+        mt.rewind()?;
+        mt.fsf(1)?;
+
+        loop {
+            let start = mt.get_position()? as u64;
+
+            match self.read_record(&mut record)? {
+                (_, Record::EndOfData) => break,
+                (_, Record::Filemark) => continue,
+                (_, Record::Data(n)) => {
+                    if let Ok(header) = format::decode::<ArchiveHeader>(&record[..n]) {
+                        if self.scan_toc(&header, start, scan, &mut record)? {
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            mt.seek(start as i32)?;
+            if !self.scan_records(scan, &mut record)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List an archive from its table of contents.
+    ///
+    /// Returns `false` if the archive has none, e.g. because it has not been
+    /// closed. The tape is left after the filemark of the archive otherwise.
+    fn scan_toc(
+        &self,
+        header: &ArchiveHeader,
+        start: u64,
+        scan: &mut Scan,
+        record: &mut [u8],
+    ) -> Result<bool, Error> {
+        let mt = &self.drive.mt;
+
+        // Archives which have not been closed end at the end of data.
+        if mt.fsf(1).is_err() {
+            return Ok(false);
+        }
+
+        // The table of contents is in front of the filemark.
+        let end = mt.get_position()? as u64;
+        if end < start + 3 {
+            return Ok(false);
+        }
+
+        let last = match self.toc_part(end - 2, header, record)? {
+            Some(toc) if toc.part + 1 == toc.parts && (toc.parts as u64) < end - start - 1 => toc,
+            _ => return Ok(false),
+        };
+
+        let mut entries = Vec::new();
+        let first = end - 1 - last.parts as u64;
+        for part in 0..last.parts - 1 {
+            match self.toc_part(first + part as u64, header, record)? {
+                Some(toc) if toc.part == part => entries.extend(toc.entries),
+                _ => return Ok(false),
+            }
+        }
+        entries.extend(last.entries);
+
+        let mut archive = ArchiveEntry {
+            creation_time: header.creation_time,
+            host: header.host.clone(),
+            block: start,
+            indexed: true,
+            origin: header.origin.clone(),
+            writer: header.writer.clone(),
+            unknown_key: self.unknown_key(header),
+            sealed_objects: 0,
+            sealed_length: 0,
+        };
+
+        // Without the key, the complete objects can still be counted.
+        if archive.unknown_key.is_some() {
+            for entry in entries
+                .iter()
+                .filter(|e| !e.tombstone && e.checksum.is_some())
+            {
+                archive.sealed_objects += 1;
+                archive.sealed_length += entry.length;
+            }
+
+            scan.archives.push(archive);
+            mt.seek(end as i32)?;
+
+            return Ok(true);
+        }
+
+        scan.archives.push(archive);
+        for entry in entries {
+            let entry = self.open_entry(header, entry)?;
+            if entry.tombstone {
+                scan.tombstones.push(TombstoneEntry {
+                    key: entry.key,
+                    archive: header.creation_time,
+                    block: start + entry.offset,
+                });
+                continue;
+            }
+
+            let object = ObjectEntry {
+                key: entry.key,
+                archive: header.creation_time,
+                block: start + entry.offset,
+                length: entry.length,
+                checksum: entry.checksum,
+            };
+
+            // Only complete objects have a checksum.
+            match object.checksum {
+                Some(_) => scan.objects.push(object),
+                None => scan.torn.push(object),
+            }
+        }
+
+        mt.seek(end as i32)?;
+
+        Ok(true)
+    }
+
+    /// Read a part of the table of contents of an archive.
+    fn toc_part(
+        &self,
+        block: u64,
+        header: &ArchiveHeader,
+        record: &mut [u8],
+    ) -> Result<Option<ArchiveToc>, Error> {
+        self.drive.mt.seek(block as i32)?;
+
+        match self.read_record(record)? {
+            (_, Record::Data(n)) => Ok(format::decode::<ArchiveToc>(&record[..n])
+                .ok()
+                .filter(|toc| toc.archive == header.creation_time)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Read the archives up to the next filemark record by record.
+    ///
+    /// Returns `false` once the end of data has been reached.
+    fn scan_records(&self, scan: &mut Scan, record: &mut [u8]) -> Result<bool, Error> {
+        let mut archive = None;
+        let mut sealed = false;
+        let mut locked = false;
+        let mut sequence = 0;
+        let mut next = self.read_record(record)?;
+
+        loop {
+            let (block, n) = match next {
+                (_, Record::EndOfData) => return Ok(false),
+                (_, Record::Filemark) => return Ok(true),
+                (block, Record::Data(n)) => (block, n),
+            };
+
+            if let Ok(header) = format::decode::<ArchiveHeader>(&record[..n]) {
+                let unknown_key = self.unknown_key(&header);
+                scan.archives.push(ArchiveEntry {
+                    creation_time: header.creation_time,
+                    host: header.host,
+                    block,
+                    indexed: false,
+                    origin: header.origin,
+                    writer: header.writer,
+                    unknown_key,
+                    sealed_objects: 0,
+                    sealed_length: 0,
+                });
+
+                archive = Some(header.creation_time);
+                sealed = header.key_id.is_some();
+                locked = unknown_key.is_some();
+                sequence = 0;
+                next = self.read_record(record)?;
+                continue;
+            }
+
+            // Objects of an archive with an unknown key are only counted.
+            if locked {
+                if let (Ok(header), Some(entry)) = (
+                    format::decode::<ObjectHeader>(&record[..n]),
+                    scan.archives.last_mut(),
+                ) {
+                    count_sealed(entry, &header);
+                }
+
+                next = self.read_record(record)?;
+                continue;
+            }
+
+            if let (Some(archive), Ok(tombstone)) =
+                (archive, format::decode::<Tombstone>(&record[..n]))
+            {
+                scan.tombstones.push(TombstoneEntry {
+                    key: self.open_tombstone(sealed, tombstone)?.key,
+                    archive,
+                    block,
+                });
+                next = self.read_record(record)?;
+                continue;
+            }
+
+            let (archive, header) = match (archive, format::decode::<ObjectHeader>(&record[..n])) {
+                (Some(archive), Ok(header)) => (archive, self.open_header(header)?),
+                // Skip anything else, e.g. the remains of a torn object.
+                _ => {
+                    next = self.read_record(record)?;
+                    continue;
+                }
+            };
+
+            next = match self.scan_object(&header, sequence, record)? {
+                Scanned::Complete(checksum) => {
+                    let entries = object_entries(&header, archive, block, checksum);
+                    scan.objects.extend(entries);
+                    sequence += 1;
+                    self.read_record(record)?
+                }
+                Scanned::Torn => {
+                    scan.torn
+                        .extend(object_entries(&header, archive, block, None));
+                    self.read_record(record)?
+                }
+                Scanned::Interrupted(at, following) => {
+                    scan.torn
+                        .extend(object_entries(&header, archive, block, None));
+                    (at, following)
+                }
+            };
+        }
+    }
+
+    /// Read the data and trailer of an object.
+    fn scan_object(
+        &self,
+        header: &ObjectHeader,
+        sequence: u64,
+        record: &mut [u8],
+    ) -> Result<Scanned, Error> {
+        // All data records but the last are full.
+        for stripe in Stripe::split(header) {
+            for expected in stripe.record_lengths() {
+                match self.read_record(record)? {
+                    (_, Record::Data(n)) if n == expected => {}
+                    (block, other) => return Ok(Scanned::Interrupted(block, other)),
+                }
+            }
+        }
+
+        if header.checksum.is_none() {
+            return Ok(Scanned::Complete(None));
+        }
+
+        match self.read_record(record)? {
+            (block, Record::Data(n)) => match format::decode::<ObjectTrailer>(&record[..n])
+                .map(|trailer| self.open_trailer(header, trailer))
+            {
+                Ok(trailer) if trailer.matches(header, sequence) => {
+                    Ok(Scanned::Complete(Some(trailer.checksum)))
+                }
+                Ok(_) => Ok(Scanned::Torn),
+                Err(_) => Ok(Scanned::Interrupted(block, Record::Data(n))),
+            },
+            (block, other) => Ok(Scanned::Interrupted(block, other)),
+        }
+    }
+
+    /// Check whether the tar member at `block` holds the object `key`.
+    fn check_tar_object(&self, block: u64, key: &str) -> Result<bool, Error> {
+        self.drive.mt.seek(block as i32)?;
+
+        let mut input = TarInput::new(self);
+        input.next_record()?;
+
+        match read_tar_member(&mut input) {
+            Ok(Some((_, tar::Member::Object { key: found, .. }))) => Ok(found == key),
+            Ok(_) | Err(Error::InvalidHeader(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the object stored in the tar member at `block`, which is
+    /// verified against the checksum of the member following it.
+    fn read_tar_object(&self, block: u64, key: &str, out: &mut dyn Write) -> Result<u64, Error> {
+        self.drive.mt.seek(block as i32)?;
+
+        let mut input = TarInput::new(self);
+        input.next_record()?;
+
+        let (entry, sequence, algorithm) = match read_tar_member(&mut input)? {
+            Some((
+                entry,
+                tar::Member::Object {
+                    key: found,
+                    sequence,
+                    algorithm,
+                    ..
+                },
+            )) if found == key => (entry, sequence, algorithm),
+            Some((_, member)) => return Err(Error::UnexpectedKey(member.name())),
+            None => return Err(Error::InvalidHeader(format::Error::Truncated)),
+        };
+
+        let mut hasher = algorithm.hasher();
+        let mut contents = Verify::new(key, out);
+        let read = input.read_data(entry.size, &mut |data| {
+            hasher.update(data);
+            contents.write_all(data)
+        })?;
+
+        if read != entry.size {
+            return Err(Error::ShortObject {
+                expected: entry.size,
+                actual: read,
+            });
+        }
+
+        match read_tar_member(&mut input)? {
+            Some((
+                _,
+                tar::Member::Trailer {
+                    key: trailer_key,
+                    sequence: trailer_sequence,
+                    checksum,
+                },
+            )) if trailer_key == key && trailer_sequence == sequence => {
+                let actual = hasher.finish();
+                if actual != checksum {
+                    return Err(Error::ChecksumMismatch {
+                        expected: checksum,
+                        actual,
+                    });
+                }
+            }
+            Some((_, member)) => return Err(Error::UnexpectedKey(member.name())),
+            None => return Err(Error::InvalidHeader(format::Error::Truncated)),
+        }
+
+        if contents.verify() == Some(false) {
+            return Err(Error::KeyMismatch(key.to_string()));
+        }
+
+        Ok(entry.size)
+    }
+
+    /// Read all archives on a cartridge in tar format member by member.
+    ///
+    /// Every member starts a new record. An object is complete if its data
+    /// is followed by the trailer member with its checksum. Where a member
+    /// has been cut short, scanning continues with the record it ended in.
+    fn scan_tar(&self, scan: &mut Scan) -> Result<(), Error> {
+        let mt = &self.drive.mt;
+        let mut input = TarInput::new(self);
+        let mut archive = None;
+
+        // Skip the media header.
+        mt.rewind()?;
+        mt.fsf(1)?;
+
+        loop {
+            let block = match input.next_record()? {
+                (_, Record::EndOfData) => return Ok(()),
+                (_, Record::Filemark) => {
+                    archive = None;
+                    continue;
+                }
+                (block, Record::Data(_)) => block,
+            };
+
+            let (entry, member) = match read_tar_member(&mut input) {
+                Ok(Some(member)) => member,
+                // The end of an archive, or the remains of a torn member.
+                Ok(None) | Err(Error::InvalidHeader(_)) => {
+                    input.resume(block)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let complete = match (archive, member) {
+                (_, tar::Member::Archive(header)) => {
+                    scan.archives.push(ArchiveEntry {
+                        creation_time: header.creation_time,
+                        host: header.host,
+                        block,
+                        indexed: false,
+                        origin: header.origin,
+                        writer: header.writer,
+                        unknown_key: None,
+                        sealed_objects: 0,
+                        sealed_length: 0,
+                    });
+
+                    archive = Some(header.creation_time);
+                    true
+                }
+                (Some(archive), tar::Member::Tombstone { key }) => {
+                    scan.tombstones.push(TombstoneEntry {
+                        key,
+                        archive,
+                        block,
+                    });
+                    input.skip_data(entry.size)?
+                }
+                (Some(archive), tar::Member::Object { key, sequence, .. }) => {
+                    let mut object = ObjectEntry {
+                        key,
+                        archive,
+                        block,
+                        length: entry.size,
+                        checksum: None,
+                    };
+
+                    object.checksum = self.scan_tar_object(&mut input, &object, sequence)?;
+                    match object.checksum {
+                        Some(_) => scan.objects.push(object),
+                        None => scan.torn.push(object),
+                    }
+                    true
+                }
+                // Skip anything else, e.g. members added by other programs.
+                _ => input.skip_data(entry.size)?,
+            };
+
+            if !complete {
+                input.resume(block)?;
+            }
+        }
+    }
+
+    /// Read the data and trailer of an object in tar format, returning the
+    /// checksum from its trailer if the object is complete.
+    ///
+    /// The reader is left where scanning continues.
+    fn scan_tar_object(
+        &self,
+        input: &mut TarInput,
+        object: &ObjectEntry,
+        sequence: u64,
+    ) -> Result<Option<Checksum>, Error> {
+        if !input.skip_data(object.length)? {
+            input.resume(object.block)?;
+            return Ok(None);
+        }
+
+        match read_tar_member(input) {
+            Ok(Some((
+                entry,
+                tar::Member::Trailer {
+                    key,
+                    sequence: trailer_sequence,
+                    checksum,
+                },
+            ))) if key == object.key && trailer_sequence == sequence => {
+                input.skip_data(entry.size)?;
+                Ok(Some(checksum))
+            }
+            Ok(_) | Err(Error::InvalidHeader(_)) => {
+                input.resume(object.block)?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the next record and the logical block number it started at.
+    fn read_record(&self, record: &mut [u8]) -> Result<(u64, Record), Error> {
+        let mt = &self.drive.mt;
+        let block = mt.get_position()? as u64;
+
+        match mt.read_block(record) {
+            Ok(0) => Ok((block, Record::Filemark)),
+            Ok(n) => Ok((block, Record::Data(n))),
+            Err(e) => {
+                let status = mt.get_status()?;
+                if status.mt_gstat.contains(mtio::GMTStatusFlags::EOD) {
+                    Ok((block, Record::EndOfData))
+                } else {
+                    Err(e.into())
+                }
+            }
+        }
+    }
+
+    /// Recover as many objects as possible from a damaged cartridge.
+    ///
+    /// Unreadable records are spaced over, and reading continues with the
+    /// next record which decodes as a valid header. The contents of every
+    /// object are passed to `sink` and verified against the checksum in its
+    /// trailer and the hash in its key. Objects listed in a table of contents
+    /// whose header could not be read are reported as lost as well.
+    pub fn salvage(&self, sink: &mut dyn SalvageSink) -> Result<Salvage, Error> {
+        if self.format == ArchiveFormat::Tar {
+            return Err(Error::TarUnsupported("salvage"));
+        }
+
+        let mt = &self.drive.mt;
+        let mut salvage = Salvage::default();
+        let mut listed = Vec::new();
+        let mut record = vec![0u8; RECORD_SIZE];
+        let mut archive = None;
+
+        // Skip the media header.
+        mt.rewind()?;
+        mt.fsf(1)?;
+
+        let mut next = self.read_resync(&mut record, &mut salvage.unreadable)?;
+
+        loop {
+            let (block, n) = match next {
+                Some((_, Record::EndOfData)) => break,
+                Some((block, Record::Data(n))) => (block, n),
+                // Filemarks and unreadable records.
+                _ => {
+                    next = self.read_resync(&mut record, &mut salvage.unreadable)?;
+                    continue;
+                }
+            };
+
+            if let Ok(header) = format::decode::<ArchiveHeader>(&record[..n]) {
+                archive = match self.unknown_key(&header) {
+                    Some(id) => {
+                        salvage.sealed.push(ArchiveEntry {
+                            creation_time: header.creation_time,
+                            host: header.host,
+                            block,
+                            indexed: false,
+                            origin: header.origin,
+                            writer: header.writer,
+                            unknown_key: Some(id),
+                            sealed_objects: 0,
+                            sealed_length: 0,
+                        });
+                        None
+                    }
+                    None => Some((header, block)),
+                };
+            } else if let (None, Some(entry), Ok(header)) = (
+                &archive,
+                salvage.sealed.last_mut(),
+                format::decode::<ObjectHeader>(&record[..n]),
+            ) {
+                count_sealed(entry, &header);
+            } else if let Ok(toc) = format::decode::<ArchiveToc>(&record[..n]) {
+                if let Some((header, start)) = archive
+                    .as_ref()
+                    .filter(|a| a.0.creation_time == toc.archive)
+                {
+                    // Torn objects have no checksum and are not expected.
+                    for entry in toc.entries {
+                        let entry = self.open_entry(header, entry)?;
+                        if entry.checksum.is_some() {
+                            listed.push(LostObject {
+                                key: entry.key,
+                                archive: header.creation_time,
+                                block: start + entry.offset,
+                                loss: Loss::Missing,
+                            });
+                        }
+                    }
+                }
+            } else if let (Some((archive, _)), Ok(header)) =
+                (&archive, format::decode::<ObjectHeader>(&record[..n]))
+            {
+                let header = self.open_header(header)?;
+                let location = (archive.creation_time, block);
+                let following = if header.is_pack() {
+                    self.salvage_pack(&header, location, &mut record, sink, &mut salvage)?
+                } else {
+                    self.salvage_single(&header, location, &mut record, sink, &mut salvage)?
+                };
+
+                if following.is_some() {
+                    next = following;
+                    continue;
+                }
+            }
+
+            next = self.read_resync(&mut record, &mut salvage.unreadable)?;
+        }
+
+        for object in listed {
+            let found = |key: &str, block| key == object.key && block == object.block;
+            let seen = salvage.recovered.iter().any(|o| found(&o.key, o.block))
+                || salvage.lost.iter().any(|o| found(&o.key, o.block));
+
+            if !seen {
+                salvage.lost.push(object);
+            }
+        }
+
+        // Keys stored again after a failure have been recovered after all.
+        let recovered = &salvage.recovered;
+        salvage
+            .lost
+            .retain(|lost| !recovered.iter().any(|o| o.key == lost.key));
+
+        Ok(salvage)
+    }
+
+    /// Salvage the object of `header` at `(archive, block)`.
+    ///
+    /// Returns the record following a torn object.
+    fn salvage_single(
+        &self,
+        header: &ObjectHeader,
+        (archive, block): (u64, u64),
+        record: &mut [u8],
+        sink: &mut dyn SalvageSink,
+        salvage: &mut Salvage,
+    ) -> Result<Option<(u64, Record)>, Error> {
+        sink.begin(&header.key)?;
+        describe(sink, &header.metadata)?;
+
+        let (loss, following) = match self.salvage_object(header, record, sink, salvage)? {
+            Salvaged::Recovered(checksum) => {
+                sink.commit()?;
+                salvage.recovered.push(ObjectEntry {
+                    key: header.key.clone(),
+                    archive,
+                    block,
+                    length: header.uncompressed_length,
+                    checksum,
+                });
+
+                (None, None)
+            }
+            Salvaged::Lost(loss) => (Some(loss), None),
+            Salvaged::Interrupted(at, following) => (Some(Loss::Torn), Some((at, following))),
+        };
+
+        if let Some(loss) = loss {
+            sink.discard()?;
+            salvage.lost.push(LostObject {
+                key: header.key.clone(),
+                archive,
+                block,
+                loss,
+            });
+        }
+
+        Ok(following)
+    }
+
+    /// Salvage the members of the pack of `header` at `(archive, block)`.
+    ///
+    /// Members are verified on their own, so that those outside of damaged
+    /// stripes are recovered. Returns the record following a torn pack.
+    fn salvage_pack(
+        &self,
+        header: &ObjectHeader,
+        (archive, block): (u64, u64),
+        record: &mut [u8],
+        sink: &mut dyn SalvageSink,
+        salvage: &mut Salvage,
+    ) -> Result<Option<(u64, Record)>, Error> {
+        let mut contents = Vec::new();
+        let mut damaged = Vec::new();
+        let mut following = None;
+
+        let mut index = 0;
+
+        for stripe in Stripe::split(header) {
+            let from = contents.len();
+            let size = stripe.contents_length(header);
+
+            let first = index;
+            index += stripe.lengths.len();
+
+            match self.read_stripe(&stripe, header.parity, record, &mut salvage.unreadable)? {
+                StripeRead::Complete(records) => match self.open_records(header, first, records) {
+                    Ok(records) => records
+                        .iter()
+                        .for_each(|data| contents.extend_from_slice(data)),
+                    Err(_) => {
+                        damaged.push((from..from + size, Loss::Corrupt));
+                        contents.resize(from + size, 0);
+                    }
+                },
+                StripeRead::Unrecoverable => {
+                    damaged.push((from..from + size, Loss::Unreadable));
+                    contents.resize(from + size, 0);
+                }
+                StripeRead::Interrupted(at, other) => {
+                    damaged.push((from..header.object_length as usize, Loss::Torn));
+                    following = Some((at, other));
+                    break;
+                }
+            }
+        }
+
+        // The trailer is skipped, as members carry their own checksums.
+        if following.is_none() && header.checksum.is_some() {
+            match self.read_resync(record, &mut salvage.unreadable)? {
+                Some((_, Record::Data(n)))
+                    if format::decode::<ObjectTrailer>(&record[..n]).is_ok() => {}
+                None => {}
+                other => following = other,
+            }
+        }
+
+        for member in &header.members {
+            let range = member.offset as usize..(member.offset + member.length) as usize;
+            let damage = damaged
+                .iter()
+                .find(|(r, _)| r.start < range.end && range.start < r.end);
+
+            let result = match (damage, contents.get(range)) {
+                (Some((_, loss)), _) => Err(*loss),
+                (None, None) => Err(Loss::Torn),
+                (None, Some(data)) => verify_member(member, data).ok_or(Loss::Corrupt),
+            };
+
+            match result {
+                Ok(data) => {
+                    sink.begin(&member.key)?;
+                    describe(sink, &member.metadata)?;
+                    sink.write(&data)?;
+                    sink.commit()?;
+
+                    salvage.recovered.push(ObjectEntry {
+                        key: member.key.clone(),
+                        archive,
+                        block,
+                        length: member.uncompressed_length,
+                        checksum: Some(member.checksum.clone()),
+                    });
+                }
+                Err(loss) => salvage.lost.push(LostObject {
+                    key: member.key.clone(),
+                    archive,
+                    block,
+                    loss,
+                }),
+            }
+        }
+
+        Ok(following)
+    }
+
+    /// Read the data and trailer of an object, passing the data to `sink`.
+    fn salvage_object(
+        &self,
+        header: &ObjectHeader,
+        record: &mut [u8],
+        sink: &mut dyn SalvageSink,
+        salvage: &mut Salvage,
+    ) -> Result<Salvaged, Error> {
+        let mut hasher = header.checksum.map(checksum::Algorithm::hasher);
+        let output = SinkWriter {
+            sink,
+            failed: false,
+        };
+        let mut contents = Decoder::new(header.compressed, Verify::new(&header.key, output))?;
+
+        let mut index = 0;
+
+        for stripe in Stripe::split(header) {
+            let records =
+                match self.read_stripe(&stripe, header.parity, record, &mut salvage.unreadable)? {
+                    StripeRead::Complete(records) => records,
+                    StripeRead::Unrecoverable => return Ok(Salvaged::Lost(Loss::Unreadable)),
+                    StripeRead::Interrupted(block, other) => {
+                        return Ok(Salvaged::Interrupted(block, other))
+                    }
+                };
+
+            if let Some(hasher) = &mut hasher {
+                records.iter().for_each(|data| hasher.update(data));
+            }
+
+            let first = index;
+            index += records.len();
+
+            let Ok(records) = self.open_records(header, first, records) else {
+                return Ok(Salvaged::Lost(Loss::Corrupt));
+            };
+
+            for data in &records {
+                if let Err(e) = contents.write_all(data) {
+                    // Contents which fail to decompress are corrupt.
+                    if contents.get_ref().out.failed {
+                        return Err(e.into());
+                    }
+                    return Ok(Salvaged::Lost(Loss::Corrupt));
+                }
+            }
+        }
+
+        let contents = match contents.finish() {
+            Ok(contents) if contents.length == header.uncompressed_length => contents,
+            _ => return Ok(Salvaged::Lost(Loss::Corrupt)),
+        };
+
+        let verified = contents.verify();
+        if verified == Some(false) {
+            return Ok(Salvaged::Lost(Loss::Corrupt));
+        }
+
+        let actual = match hasher {
+            Some(hasher) => hasher.finish(),
+            None => return Ok(Salvaged::Recovered(None)),
+        };
+
+        match self.read_resync(record, &mut salvage.unreadable)? {
+            Some((block, Record::Data(n))) => match format::decode::<ObjectTrailer>(&record[..n])
+                .map(|trailer| self.open_trailer(header, trailer))
+            {
+                // Objects whose header could not be read throw off the
+                // sequence numbers, which are therefore not compared.
+                Ok(trailer) if trailer.belongs_to(header) => {
+                    if trailer.checksum == actual {
+                        Ok(Salvaged::Recovered(Some(actual)))
+                    } else {
+                        Ok(Salvaged::Lost(Loss::Corrupt))
+                    }
+                }
+                Ok(_) => Ok(Salvaged::Lost(Loss::Torn)),
+                Err(_) => Ok(Salvaged::Interrupted(block, Record::Data(n))),
+            },
+            Some((block, other)) => Ok(Salvaged::Interrupted(block, other)),
+            // Without its trailer, the contents can only be verified by the key.
+            None if verified == Some(true) => Ok(Salvaged::Recovered(Some(actual))),
+            None => Ok(Salvaged::Lost(Loss::Unreadable)),
+        }
+    }
+
+    /// Read the data and parity records of a stripe of an object, spacing
+    /// over unreadable records.
+    fn read_stripe(
+        &self,
+        stripe: &Stripe,
+        parity: Option<Parity>,
+        record: &mut [u8],
+        unreadable: &mut u64,
+    ) -> Result<StripeRead, Error> {
+        let size = stripe.parity_length();
+        let mut records = Vec::new();
+
+        for expected in stripe.record_lengths() {
+            match self.read_resync(record, unreadable)? {
+                Some((_, Record::Data(n))) if n == expected => {
+                    records.push(Some(parity::padded(&record[..n], size)))
+                }
+                Some((block, other)) => return Ok(StripeRead::Interrupted(block, other)),
+                None => records.push(None),
+            }
+        }
+
+        let data = stripe.lengths.len();
+        if records[..data].iter().any(Option::is_none) {
+            match parity {
+                Some(parity) if parity.reconstruct(&mut records) => {}
+                _ => return Ok(StripeRead::Unrecoverable),
+            }
+        }
+
+        let records = records
+            .into_iter()
+            .zip(&stripe.lengths)
+            .map(|(record, &length)| {
+                let mut record = record.unwrap_or_default();
+                record.truncate(length);
+                record
+            })
+            .collect();
+
+        Ok(StripeRead::Complete(records))
+    }
+
+    /// Read the next record, spacing over it if it cannot be read.
+    ///
+    /// Returns `None` for an unreadable record, which is counted in
+    /// `unreadable`.
+    fn read_resync(
+        &self,
+        record: &mut [u8],
+        unreadable: &mut u64,
+    ) -> Result<Option<(u64, Record)>, Error> {
+        let mt = &self.drive.mt;
+        let block = mt.get_position()? as u64;
+
+        match self.read_record(record) {
+            Ok(next) => Ok(Some(next)),
+            Err(Error::Tape(_)) => {
+                *unreadable += 1;
+
+                // The position after a failed read depends on the drive.
+                mt.seek(block as i32)?;
+                mt.fsr(1)?;
+
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
 }
 
-impl Drive {
-    pub fn new(path: &Path) -> Result<Self, mt::Error> {
-        Ok(Self {
-            mt: mt::MagneticTape::new(path)?,
+/// An archive which is currently being written.
+///
+/// Archives are terminated by their table of contents and a filemark when
+/// they are closed. Archives in tar format end with two zero blocks in place
+/// of a table of contents.
+pub struct Archive {
+    drive: Rc<Drive>,
+    creation_time: u64,
+    format: ArchiveFormat,
+    parity: Option<Parity>,
+    compression: Option<Compression>,
+    cipher: Option<Cipher>,
+
+    /// Small objects which have not been written yet.
+    pack: Option<Pack>,
+
+    /// Logical block number of the archive header.
+    start: u64,
+
+    objects: u64,
+    entries: Vec<TocEntry>,
+}
+
+/// Small objects collected to be written back to back.
+struct Pack {
+    /// Logical block number at which the pack is going to be written.
+    block: u64,
+
+    contents: Vec<u8>,
+    members: Vec<PackMember>,
+
+    /// Encoded length of the pack header.
+    header_length: usize,
+}
+
+impl Pack {
+    fn fits(&self, member: &PackMember) -> bool {
+        self.contents.len() as u64 + member.length <= PACK_SIZE as u64
+            && self.header_length + member.encoded_len() <= RECORD_SIZE
+    }
+}
+
+impl Archive {
+    /// Write an object to the archive.
+    ///
+    /// Objects up to [`PACK_OBJECT_SIZE`] are collected into a pack, which is
+    /// written once it is full, before the next large object and when the
+    /// archive is flushed or closed. Objects in tar format are never packed.
+    /// Packed objects are compressed each on its own.
+    ///
+    /// Returns the logical block number at which the object or its pack starts.
+    pub fn write_object(
+        &mut self,
+        key: &str,
+        length: u64,
+        data: &mut dyn Read,
+    ) -> Result<u64, Error> {
+        self.write_object_with_metadata(key, length, data, &Metadata::default())
+    }
+
+    /// Write an object to the archive, describing it with `metadata`.
+    pub fn write_object_with_metadata(
+        &mut self,
+        key: &str,
+        length: u64,
+        data: &mut dyn Read,
+        metadata: &Metadata,
+    ) -> Result<u64, Error> {
+        if self.format == ArchiveFormat::Tar {
+            return self.write_tar_object(key, length, data, metadata);
+        }
+
+        if length <= PACK_OBJECT_SIZE {
+            return self.pack_object(key, length, data, metadata);
+        }
+
+        self.flush()?;
+
+        let stored_key = self.stored_key(key);
+        let mut header = ObjectHeader::new(length, &stored_key, CHECKSUM_ALGORITHM)
+            .with_metadata(self.stored_metadata(metadata));
+
+        let mut skipped;
+        let mut spooled;
+        let data: &mut dyn Read = match self.compression {
+            None => data,
+            Some(compression) => match compression.compress(data)? {
+                Compressed::Skipped(data) => {
+                    skipped = data;
+                    &mut skipped
+                }
+                Compressed::Spooled {
+                    file,
+                    length: compressed,
+                    uncompressed,
+                } => {
+                    if uncompressed != length {
+                        return Err(Error::ShortObject {
+                            expected: length,
+                            actual: uncompressed,
+                        });
+                    }
+
+                    header = header.with_compression(compressed);
+                    spooled = file;
+                    &mut spooled
+                }
+            },
+        };
+
+        let contents_length = header.object_length;
+        let header = self.complete_header(header);
+
+        let mt = &self.drive.mt;
+        let block = mt.get_position()? as u64;
+        mt.write_block(&format::encode(&header))?;
+
+        let result = self.write_contents(&header, contents_length, data);
+
+        let checksum = result.as_ref().ok().map(|c| self.stored_checksum(c));
+        self.entries.push(TocEntry {
+            key: stored_key,
+            offset: block - self.start,
+            length,
+            checksum,
+            tombstone: false,
+        });
+
+        result?;
+        self.objects += 1;
+
+        Ok(block)
+    }
+
+    /// Add a small object to the pack.
+    ///
+    /// The pack is not compressed as a whole, as members are read by their
+    /// offset within the pack and salvaged record by record. Each member is
+    /// compressed on its own instead.
+    fn pack_object(
+        &mut self,
+        key: &str,
+        length: u64,
+        data: &mut dyn Read,
+        metadata: &Metadata,
+    ) -> Result<u64, Error> {
+        let mut contents = Vec::new();
+        data.take(length + 1).read_to_end(&mut contents)?;
+
+        if contents.len() as u64 != length {
+            return Err(Error::ShortObject {
+                expected: length,
+                actual: contents.len() as u64,
+            });
+        }
+
+        let mut hasher = CHECKSUM_ALGORITHM.hasher();
+        hasher.update(&contents);
+
+        let compressed = match self.compression {
+            Some(compression) => compression.compress_bytes(&contents)?,
+            None => None,
+        };
+        let is_compressed = compressed.is_some();
+        let contents = compressed.unwrap_or(contents);
+
+        let mut member = PackMember {
+            key: self.stored_key(key),
+            offset: 0,
+            length: contents.len() as u64,
+            checksum: self.stored_checksum(&hasher.finish()),
+            metadata: self.stored_metadata(metadata),
+            compressed: is_compressed,
+            uncompressed_length: length,
+        };
+
+        if !self.pack.as_ref().is_none_or(|pack| pack.fits(&member)) {
+            self.flush()?;
+        }
+
+        let pack = match &mut self.pack {
+            Some(pack) => pack,
+            None => {
+                let header = ObjectHeader::pack(0, CHECKSUM_ALGORITHM, Vec::new());
+                let header_length = format::encode(&self.complete_header(header)).len();
+
+                self.pack.insert(Pack {
+                    block: self.drive.mt.get_position()? as u64,
+                    contents: Vec::new(),
+                    members: Vec::new(),
+                    header_length,
+                })
+            }
+        };
+
+        member.offset = pack.contents.len() as u64;
+        pack.contents.extend_from_slice(&contents);
+        pack.header_length += member.encoded_len();
+        pack.members.push(member);
+
+        Ok(pack.block)
+    }
+
+    /// Write the pack of small objects, if there is one.
+    ///
+    /// Packed objects are only on tape once their pack has been written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let Some(pack) = self.pack.take() else {
+            return Ok(());
+        };
+
+        let length = pack.contents.len() as u64;
+        let header = ObjectHeader::pack(length, CHECKSUM_ALGORITHM, pack.members.clone());
+        let header = self.complete_header(header);
+        self.drive.mt.write_block(&format::encode(&header))?;
+
+        let result = self.write_contents(&header, length, &mut &pack.contents[..]);
+
+        for member in pack.members {
+            let checksum = if result.is_ok() {
+                Some(member.checksum)
+            } else {
+                None
+            };
+            self.entries.push(TocEntry {
+                key: member.key,
+                offset: pack.block - self.start,
+                length: member.uncompressed_length,
+                checksum,
+                tombstone: false,
+            });
+        }
+
+        result?;
+        self.objects += 1;
+
+        Ok(())
+    }
+
+    /// Write the pending pack and the buffer of the drive to tape, after
+    /// which every object written so far survives a crash.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        self.flush_buffer()
+    }
+
+    /// Write the buffer of the drive to tape, after which every object
+    /// written so far survives a crash, but those waiting in the pack.
+    pub fn flush_buffer(&mut self) -> Result<(), Error> {
+        self.drive.mt.flush_drive_buffer()?;
+
+        Ok(())
+    }
+
+    /// Number of small objects waiting in the pack.
+    pub fn pending(&self) -> usize {
+        self.pack.as_ref().map_or(0, |pack| pack.members.len())
+    }
+
+    /// Write a tombstone which removes the copies of `key` written before.
+    ///
+    /// The pending pack is written first, so that objects packed before the
+    /// removal precede their tombstone.
+    ///
+    /// Returns the logical block number of the tombstone.
+    pub fn write_tombstone(&mut self, key: &str) -> Result<u64, Error> {
+        if self.format == ArchiveFormat::Tar {
+            return self.write_tar_tombstone(key);
+        }
+
+        self.flush()?;
+
+        let stored_key = self.stored_key(key);
+
+        let mt = &self.drive.mt;
+        let block = mt.get_position()? as u64;
+        mt.write_block(&format::encode(&Tombstone::new(&stored_key)))?;
+        mt.flush_drive_buffer()?;
+
+        self.entries.push(TocEntry {
+            key: stored_key,
+            offset: block - self.start,
+            length: 0,
+            checksum: None,
+            tombstone: true,
+        });
+
+        Ok(block)
+    }
+
+    /// Write an object as a tar member followed by the member with its
+    /// checksum. Members start a new record, so that they can be found by
+    /// their logical block number.
+    ///
+    /// If the contents are cut short, the member is padded to its announced
+    /// length and left without a checksum, so that the tar stream stays
+    /// readable.
+    fn write_tar_object(
+        &mut self,
+        key: &str,
+        length: u64,
+        data: &mut dyn Read,
+        metadata: &Metadata,
+    ) -> Result<u64, Error> {
+        let mt = &*self.drive.mt;
+        let block = mt.get_position()? as u64;
+        let mtime = metadata.mtime.unwrap_or(self.creation_time);
+
+        let member = tar::Member::Object {
+            key: key.to_string(),
+            sequence: self.objects,
+            algorithm: CHECKSUM_ALGORITHM,
+            metadata: metadata.clone(),
+        };
+        let mut out = TarOutput::new(mt);
+        out.write(&tar::encode_member(&member, length, mtime))?;
+
+        let mut hasher = CHECKSUM_ALGORITHM.hasher();
+        let mut record = vec![0u8; RECORD_SIZE];
+        let mut read = 0u64;
+
+        let mut contents = data.take(length);
+        let result = loop {
+            match read_full(&mut contents, &mut record) {
+                Ok(0) => break Ok(()),
+                Ok(n) => {
+                    hasher.update(&record[..n]);
+                    out.write(&record[..n])?;
+                    read += n as u64;
+                }
+                Err(e) => break Err(Error::from(e)),
+            }
+        };
+
+        let result = result.and_then(|()| {
+            let actual = read + io::copy(data, &mut io::sink())?;
+            match actual == length {
+                true => Ok(()),
+                false => Err(Error::ShortObject {
+                    expected: length,
+                    actual,
+                }),
+            }
+        });
+
+        out.zeros(length - read)?;
+        out.zeros(tar::padding(length) as u64)?;
+
+        if result.is_ok() {
+            let checksum = hasher.finish();
+            let contents = tar::trailer_contents(key, &checksum);
+            let trailer = tar::Member::Trailer {
+                key: key.to_string(),
+                sequence: self.objects,
+                checksum,
+            };
+
+            out.write(&tar::encode_member(&trailer, contents.len() as u64, mtime))?;
+            out.write(&contents)?;
+            out.zeros(tar::padding(contents.len() as u64) as u64)?;
+        }
+
+        out.finish()?;
+
+        // Make sure the object has reached the tape before reporting success.
+        mt.flush_drive_buffer()?;
+
+        result?;
+        self.objects += 1;
+
+        Ok(block)
+    }
+
+    fn write_tar_tombstone(&mut self, key: &str) -> Result<u64, Error> {
+        let mt = &self.drive.mt;
+        let block = mt.get_position()? as u64;
+
+        let tombstone = tar::Member::Tombstone {
+            key: key.to_string(),
+        };
+        mt.write_block(&tar::encode_member(&tombstone, 0, self.creation_time))?;
+        mt.flush_drive_buffer()?;
+
+        Ok(block)
+    }
+
+    /// Key of an object as stored on tape, sealed in encrypted archives.
+    fn stored_key(&self, key: &str) -> String {
+        match &self.cipher {
+            Some(cipher) => cipher.seal_key(key),
+            None => key.to_string(),
+        }
+    }
+
+    fn stored_checksum(&self, checksum: &Checksum) -> Checksum {
+        match &self.cipher {
+            Some(cipher) => cipher.seal_checksum(checksum),
+            None => checksum.clone(),
+        }
+    }
+
+    fn stored_metadata(&self, metadata: &Metadata) -> Vec<u8> {
+        let encoded = metadata.encode();
+
+        match &self.cipher {
+            Some(cipher) if !encoded.is_empty() => cipher.seal(&encoded),
+            _ => encoded,
+        }
+    }
+
+    /// Add the parity ratio of the archive and, in encrypted archives, a new
+    /// nonce to the header of an object.
+    fn complete_header(&self, header: ObjectHeader) -> ObjectHeader {
+        let header = header.with_parity(self.parity);
+
+        match self.cipher {
+            Some(_) => {
+                let length = crypto::sealed_length(header.object_length, SEALED_RECORD_SIZE);
+                header.with_encryption(Cipher::nonce(), length)
+            }
+            None => header,
+        }
+    }
+
+    /// Write the data and trailer of an object, whose `length` bytes of
+    /// contents are sealed if the header has a nonce.
+    fn write_contents(
+        &self,
+        header: &ObjectHeader,
+        length: u64,
+        data: &mut dyn Read,
+    ) -> Result<Checksum, Error> {
+        let mt = &self.drive.mt;
+
+        let size = match header.nonce {
+            Some(_) => SEALED_RECORD_SIZE,
+            None => RECORD_SIZE,
+        };
+        let records = length.div_ceil(size as u64).max(1);
+
+        let mut hasher = CHECKSUM_ALGORITHM.hasher();
+        let mut record = vec![0u8; size];
+        let mut stripe = Vec::new();
+        let mut read = 0u64;
+        let mut index = 0;
+
+        loop {
+            let n = read_full(data, &mut record)?;
+            read += n as u64;
+
+            // Encrypted objects have at least one record, even when empty.
+            let sealed;
+            let stored = match (&header.nonce, &self.cipher) {
+                (Some(nonce), Some(cipher)) if n > 0 || index == 0 => {
+                    let last = index + 1 == records;
+                    sealed = cipher.seal_record(nonce, index as u32, last, &record[..n]);
+                    &sealed[..]
+                }
+                _ => &record[..n],
+            };
+            index += 1;
+
+            if !stored.is_empty() {
+                mt.write_block(stored)?;
+                hasher.update(stored);
+            }
+
+            if let Some(parity) = self.parity {
+                if !stored.is_empty() {
+                    stripe.push(stored.to_vec());
+                }
+
+                // Stripes end after their data records and with the object.
+                if stripe.len() == parity.data as usize || (n == 0 && !stripe.is_empty()) {
+                    for parity_record in parity.encode(&stripe) {
+                        mt.write_block(&parity_record)?;
+                    }
+                    stripe.clear();
+                }
+            }
+
+            if n == 0 {
+                break;
+            }
+        }
+
+        if read != length {
+            return Err(Error::ShortObject {
+                expected: length,
+                actual: read,
+            });
+        }
+
+        let checksum = hasher.finish();
+        let trailer = ObjectTrailer::new(
+            &header.key,
+            header.object_length,
+            self.objects,
+            checksum.clone(),
+        );
+        mt.write_block(&format::encode(&trailer))?;
+
+        // Make sure the object has reached the tape before reporting success.
+        mt.flush_drive_buffer()?;
+
+        Ok(checksum)
+    }
+
+    /// Compress the contents of objects which get their own records.
+    pub fn with_compression(self, compression: Option<Compression>) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn creation_time(&self) -> u64 {
+        self.creation_time
+    }
+
+    /// Number of objects and packs written to the archive.
+    pub fn objects(&self) -> u64 {
+        self.objects
+    }
+
+    /// Write the pending pack, the table of contents and terminate the
+    /// archive with a filemark.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.flush()?;
+
+        let mt = &self.drive.mt;
+
+        if self.format == ArchiveFormat::Tar {
+            mt.write_block(&[0u8; 2 * tar::BLOCK_SIZE])?;
+            mt.weof(1)?;
+
+            return Ok(());
+        }
+
+        // Split the table of contents into parts which fit into a record.
+        let mut parts = vec![Vec::new()];
+        let mut size = format::ARCHIVE_TOC_OVERHEAD;
+        for entry in self.entries {
+            if size + entry.encoded_len() > RECORD_SIZE {
+                parts.push(Vec::new());
+                size = format::ARCHIVE_TOC_OVERHEAD;
+            }
+
+            size += entry.encoded_len();
+            parts.last_mut().unwrap().push(entry);
+        }
+
+        let count = parts.len() as u32;
+        for (part, entries) in parts.into_iter().enumerate() {
+            let toc = ArchiveToc {
+                archive: self.creation_time,
+                part: part as u32,
+                parts: count,
+                entries,
+            };
+
+            mt.write_block(&format::encode(&toc))?;
+        }
+
+        mt.weof(1)?;
+
+        Ok(())
+    }
+}
+
+/// Decrypt the encoded metadata of an object, if it has any.
+fn open_metadata(cipher: &Cipher, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    if sealed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    cipher.open(sealed).ok_or(Error::Decryption)
+}
+
+/// Pass the metadata of an object being salvaged on to `sink`.
+///
+/// Metadata which cannot be decoded is left out, the object is still
+/// recovered.
+fn describe(sink: &mut dyn SalvageSink, encoded: &[u8]) -> io::Result<()> {
+    match Metadata::decode(encoded) {
+        Ok(metadata) if !metadata.is_empty() => sink.describe(&metadata),
+        _ => Ok(()),
+    }
+}
+
+/// Entries of the object at `block`, or of the members of the pack at `block`.
+///
+/// `checksum` is that of a complete object, members of a complete pack get
+/// their own.
+/// Count the objects of `header` in an archive with an unknown key.
+fn count_sealed(archive: &mut ArchiveEntry, header: &ObjectHeader) {
+    if header.is_pack() {
+        archive.sealed_objects += header.members.len() as u64;
+        archive.sealed_length += header
+            .members
+            .iter()
+            .map(|m| m.uncompressed_length)
+            .sum::<u64>();
+    } else {
+        archive.sealed_objects += 1;
+        archive.sealed_length += header.uncompressed_length;
+    }
+}
+
+fn object_entries(
+    header: &ObjectHeader,
+    archive: u64,
+    block: u64,
+    checksum: Option<Checksum>,
+) -> Vec<ObjectEntry> {
+    if !header.is_pack() {
+        return vec![ObjectEntry {
+            key: header.key.clone(),
+            archive,
+            block,
+            length: header.uncompressed_length,
+            checksum,
+        }];
+    }
+
+    header
+        .members
+        .iter()
+        .map(|member| ObjectEntry {
+            key: member.key.clone(),
+            archive,
+            block,
+            length: member.uncompressed_length,
+            checksum: checksum.as_ref().map(|_| member.checksum.clone()),
         })
+        .collect()
+}
+
+/// Counts the contents of an object on their way to `out` and checks them
+/// against the hash in the key.
+struct Verify<W: Write> {
+    out: W,
+    key_hash: Option<KeyHash>,
+    length: u64,
+}
+
+impl<W: Write> Verify<W> {
+    fn new(key: &str, out: W) -> Self {
+        Self {
+            out,
+            key_hash: KeyHash::from_key(key),
+            length: 0,
+        }
     }
 
-    pub fn load_media(&self) -> Result<Media, mt::Error> {
-        self.mt.rewind()?;
+    /// Check the contents against the key, `None` if the key has no hash.
+    fn verify(self) -> Option<bool> {
+        self.key_hash.map(KeyHash::verify)
+    }
+}
+
+impl<W: Write> Write for Verify<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
 
-        Ok(Media { drive: self })
+        if let Some(key_hash) = &mut self.key_hash {
+            key_hash.update(&buf[..n]);
+        }
+        self.length += n as u64;
+
+        Ok(n)
     }
 
-    pub fn init_media(&self) -> Result<(), mt::Error> {
-        todo!("initialize media");
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
     }
 }
 
-pub struct Media<'a> {
-    drive: &'a Drive,
+/// Reads the tar stream of an archive from consecutive records and hands out
+/// its blocks.
+///
+/// Members start with a new record and continue into the next record only
+/// if the current one is full, as written by [`TarOutput`].
+struct TarInput<'a> {
+    media: &'a Media,
+    record: Vec<u8>,
+
+    /// Logical block number and length of the current record.
+    block: u64,
+    length: usize,
+
+    /// Offset of the next tar block in the current record.
+    offset: usize,
 }
 
-impl Media<'_> {
-    pub fn init() {}
+impl<'a> TarInput<'a> {
+    fn new(media: &'a Media) -> Self {
+        Self {
+            media,
+            record: vec![0u8; RECORD_SIZE],
+            block: 0,
+            length: 0,
+            offset: 0,
+        }
+    }
+
+    /// Read the next record, at which the next member starts.
+    fn next_record(&mut self) -> Result<(u64, Record), Error> {
+        let (block, record) = self.media.read_record(&mut self.record)?;
+
+        self.block = block;
+        self.length = match record {
+            Record::Data(n) => n,
+            _ => 0,
+        };
+        self.offset = 0;
+
+        Ok((block, record))
+    }
+
+    /// The next block of the current member, `None` if the member has been
+    /// cut short.
+    fn block(&mut self) -> Result<Option<&[u8]>, Error> {
+        if self.offset >= self.length {
+            if self.length < RECORD_SIZE {
+                return Ok(None);
+            }
+
+            if let (_, Record::Filemark | Record::EndOfData) = self.next_record()? {
+                return Ok(None);
+            }
+        }
+
+        if self.length - self.offset < tar::BLOCK_SIZE {
+            return Ok(None);
+        }
+
+        let block = &self.record[self.offset..self.offset + tar::BLOCK_SIZE];
+        self.offset += tar::BLOCK_SIZE;
+
+        Ok(Some(block))
+    }
+
+    /// Read `size` bytes of contents and their padding, passing the contents
+    /// on to `f`. Returns the number of bytes read, less than `size` if the
+    /// member has been cut short.
+    fn read_data(
+        &mut self,
+        size: u64,
+        f: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> Result<u64, Error> {
+        let mut read = 0;
+
+        while read < size {
+            let Some(block) = self.block()? else {
+                break;
+            };
+
+            let n = (size - read).min(tar::BLOCK_SIZE as u64) as usize;
+            f(&block[..n])?;
+            read += n as u64;
+        }
+
+        Ok(read)
+    }
+
+    /// Skip `size` bytes of contents, `false` if the member has been cut
+    /// short.
+    fn skip_data(&mut self, size: u64) -> Result<bool, Error> {
+        Ok(self.read_data(size, &mut |_| Ok(()))? == size)
+    }
+
+    /// Continue after a member cut short which started at `start`.
+    ///
+    /// If reading went on beyond its first record, the record it stopped in
+    /// may start the next member, e.g. an archive written after a crash.
+    fn resume(&mut self, start: u64) -> Result<(), Error> {
+        if self.block != start {
+            self.media.drive.mt.seek(self.block as i32)?;
+        }
 
-    pub fn append_archive(&self) -> Result<Archive, mt::Error> {
-        Ok(Archive::new(self.drive.load_media()?))
+        Ok(())
     }
 }
 
-impl<'a> Iterator for Media<'a> {
-    type Item = Archive<'a>;
+/// Read the headers of the next tar member, including its pax header.
+///
+/// Returns `None` at the end of an archive or if the headers have been cut
+/// short. The reader is left at the contents of the member.
+fn read_tar_member(input: &mut TarInput) -> Result<Option<(tar::Entry, tar::Member)>, Error> {
+    let mut attributes = None;
+
+    loop {
+        let Some(block) = input.block()? else {
+            return Ok(None);
+        };
+        let Some(mut entry) = tar::decode_header(block)? else {
+            return Ok(None);
+        };
+
+        if let tar::EntryType::Extended | tar::EntryType::Global = entry.kind {
+            let mut records = Vec::new();
+            let read = input.read_data(entry.size, &mut |data| {
+                records.extend_from_slice(data);
+                Ok(())
+            })?;
+            if read != entry.size {
+                return Ok(None);
+            }
+
+            let decoded = tar::Attributes::decode(&records)?;
+            if entry.kind == tar::EntryType::Global {
+                return Ok(Some((entry, tar::Member::from_attributes(&decoded))));
+            }
+
+            attributes = Some(decoded);
+            continue;
+        }
+
+        let Some(attributes) = attributes else {
+            return Ok(Some((entry, tar::Member::Other)));
+        };
+
+        if let Some(path) = attributes.get("path") {
+            entry.name = path.to_string();
+        }
+        if let Some(size) = attributes.get("size").and_then(|s| s.parse().ok()) {
+            entry.size = size;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        return Ok(Some((entry, tar::Member::from_attributes(&attributes))));
     }
 }
 
-pub struct Archive<'a> {
-    media: Media<'a>,
+/// Writes the tar stream of a member in records of [`RECORD_SIZE`], the last
+/// of which may be short.
+struct TarOutput<'a> {
+    mt: &'a dyn TapeDevice,
+    buffer: Vec<u8>,
 }
 
-impl<'a> Archive<'a> {
-    pub fn new(cartridge: Media<'a>) -> Self {
-        Self { media: cartridge }
+impl<'a> TarOutput<'a> {
+    fn new(mt: &'a dyn TapeDevice) -> Self {
+        Self {
+            mt,
+            buffer: Vec::with_capacity(RECORD_SIZE),
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let n = data.len().min(RECORD_SIZE - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+
+            if self.buffer.len() == RECORD_SIZE {
+                self.mt.write_block(&self.buffer)?;
+                self.buffer.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn zeros(&mut self, mut count: u64) -> Result<(), Error> {
+        let zeros = [0u8; tar::BLOCK_SIZE];
+
+        while count > 0 {
+            let n = count.min(zeros.len() as u64) as usize;
+            self.write(&zeros[..n])?;
+            count -= n as u64;
+        }
+
+        Ok(())
     }
 
-    pub fn write_object(&self, _object: &[u8]) -> Result<(), mt::Error> {
-        todo!("write object to tape");
+    /// Write the rest of the member as a short record.
+    fn finish(self) -> Result<(), Error> {
+        if !self.buffer.is_empty() {
+            self.mt.write_block(&self.buffer)?;
+        }
+
+        Ok(())
     }
 }
 
-impl<'a> Iterator for Archive<'a> {
-    type Item = &'a [u8];
+/// Passes salvaged contents to a sink, remembering whether the sink failed.
+struct SinkWriter<'a> {
+    sink: &'a mut dyn SalvageSink,
+    failed: bool,
+}
+
+impl Write for SinkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink.write(buf).inspect_err(|_| self.failed = true)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+/// The contents of a pack member from the bytes stored in the pack.
+fn unpack_member(member: &PackMember, stored: Vec<u8>) -> io::Result<Vec<u8>> {
+    if member.compressed {
+        compression::decompress_bytes(&stored, member.uncompressed_length)
+    } else {
+        Ok(stored)
     }
 }
 
-pub struct Object<'a> {
-    header: &'a format::ObjectHeader<'a>,
-    data: &'a [u8],
+/// The contents of a pack member, if they decompress and match its checksum
+/// and key.
+fn verify_member(member: &PackMember, stored: &[u8]) -> Option<Vec<u8>> {
+    let contents = unpack_member(member, stored.to_vec()).ok()?;
+
+    let mut hasher = member.checksum.algorithm.hasher();
+    hasher.update(&contents);
+
+    let key_hash = KeyHash::from_key(&member.key).map(|mut key_hash| {
+        key_hash.update(&contents);
+        key_hash.verify()
+    });
+
+    (hasher.finish() == member.checksum && key_hash != Some(false)).then_some(contents)
+}
+
+/// Fill the buffer as far as possible so that all but the last record are full.
+fn read_full(data: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match data.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
 }
@@ -0,0 +1,127 @@
+//! TapeAlert flags
+//!
+//! also see: SSC-4, Annex A and the TapeAlert specification v3
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::logpage::LogPage;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Information,
+    Warning,
+    Critical,
+}
+
+pub const READ_WARNING: u8 = 0x01;
+pub const WRITE_WARNING: u8 = 0x02;
+pub const HARD_ERROR: u8 = 0x03;
+pub const MEDIA: u8 = 0x04;
+pub const READ_FAILURE: u8 = 0x05;
+pub const WRITE_FAILURE: u8 = 0x06;
+pub const MEDIA_LIFE: u8 = 0x07;
+pub const CLEANING_MEDIA: u8 = 0x0b;
+pub const NEARING_MEDIA_LIFE: u8 = 0x13;
+pub const CLEAN_NOW: u8 = 0x14;
+pub const CLEAN_PERIODIC: u8 = 0x15;
+pub const EXPIRED_CLEANING_MEDIA: u8 = 0x16;
+pub const INVALID_CLEANING_TAPE: u8 = 0x17;
+
+const FLAGS: &[(u8, Severity, &str)] = &[
+    (0x01, Severity::Warning, "Read warning"),
+    (0x02, Severity::Warning, "Write warning"),
+    (0x03, Severity::Warning, "Hard error"),
+    (0x04, Severity::Critical, "Media"),
+    (0x05, Severity::Critical, "Read failure"),
+    (0x06, Severity::Critical, "Write failure"),
+    (0x07, Severity::Warning, "Media life"),
+    (0x08, Severity::Warning, "Not data grade"),
+    (0x09, Severity::Critical, "Write protect"),
+    (0x0a, Severity::Information, "No removal"),
+    (0x0b, Severity::Information, "Cleaning media"),
+    (0x0c, Severity::Information, "Unsupported format"),
+    (0x0d, Severity::Critical, "Recoverable mechanical cartridge failure"),
+    (0x0e, Severity::Critical, "Unrecoverable mechanical cartridge failure"),
+    (0x0f, Severity::Warning, "Memory chip in cartridge failure"),
+    (0x10, Severity::Critical, "Forced eject"),
+    (0x11, Severity::Warning, "Read only format"),
+    (0x12, Severity::Warning, "Tape directory corrupted on load"),
+    (0x13, Severity::Information, "Nearing media life"),
+    (0x14, Severity::Critical, "Clean now"),
+    (0x15, Severity::Warning, "Clean periodic"),
+    (0x16, Severity::Critical, "Expired cleaning media"),
+    (0x17, Severity::Critical, "Invalid cleaning tape"),
+    (0x18, Severity::Warning, "Retension requested"),
+    (0x19, Severity::Warning, "Dual-port interface error"),
+    (0x1a, Severity::Warning, "Cooling fan failure"),
+    (0x1b, Severity::Warning, "Power supply failure"),
+    (0x1c, Severity::Warning, "Power consumption"),
+    (0x1d, Severity::Warning, "Drive maintenance"),
+    (0x1e, Severity::Critical, "Hardware A"),
+    (0x1f, Severity::Critical, "Hardware B"),
+    (0x20, Severity::Warning, "Interface"),
+    (0x21, Severity::Critical, "Eject media"),
+    (0x22, Severity::Warning, "Microcode update fail"),
+    (0x23, Severity::Warning, "Drive humidity"),
+    (0x24, Severity::Critical, "Drive temperature"),
+    (0x25, Severity::Critical, "Drive voltage"),
+    (0x26, Severity::Critical, "Predictive failure"),
+    (0x27, Severity::Warning, "Diagnostics required"),
+    (0x32, Severity::Warning, "Lost statistics"),
+    (0x33, Severity::Warning, "Tape directory invalid at unload"),
+    (0x34, Severity::Critical, "Tape system area write failure"),
+    (0x35, Severity::Critical, "Tape system area read failure"),
+    (0x36, Severity::Critical, "No start of data"),
+    (0x37, Severity::Critical, "Loading failure"),
+    (0x38, Severity::Critical, "Unrecoverable unload failure"),
+    (0x39, Severity::Critical, "Automation interface failure"),
+    (0x3a, Severity::Warning, "Microcode failure"),
+    (0x3b, Severity::Warning, "WORM medium - integrity check failed"),
+    (0x3c, Severity::Warning, "WORM medium - overwrite attempted"),
+];
+
+/// A single active TapeAlert flag.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flag(pub u8);
+
+impl Flag {
+    pub fn severity(&self) -> Severity {
+        FLAGS
+            .iter()
+            .find(|(code, _, _)| *code == self.0)
+            .map(|(_, severity, _)| *severity)
+            .unwrap_or(Severity::Information)
+    }
+
+    pub fn description(&self) -> &'static str {
+        FLAGS
+            .iter()
+            .find(|(code, _, _)| *code == self.0)
+            .map(|(_, _, desc)| *desc)
+            .unwrap_or("Unknown")
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}h {} ({:?})",
+            self.0,
+            self.description(),
+            self.severity()
+        )
+    }
+}
+
+/// Decode the active flags from the TapeAlert log page.
+///
+/// Note that drives clear the flags once the page has been read.
+pub fn from_page(page: &LogPage) -> Vec<Flag> {
+    page.parameters
+        .iter()
+        .filter(|p| (1..=64).contains(&p.code) && p.as_u64() & 0x1 != 0)
+        .map(|p| Flag(p.code as u8))
+        .collect()
+}
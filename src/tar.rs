@@ -0,0 +1,442 @@
+//! POSIX pax tar encoding of archives on cartridges in tar format
+//!
+//! Each archive on such a cartridge is a tar stream which `tar` can extract on
+//! its own, from tape records which are multiples of 512 byte tar blocks:
+//!
+//! ```text
+//! global header      fields of the archive header
+//! <key>              contents of an object, preceded by an extended
+//!                    header with its key, sequence number, checksum
+//!                    algorithm and metadata
+//! <key>.sha256       checksum of the contents, in place of the trailer
+//! <key>.removed      empty file, the tombstone of a removed object
+//! ...
+//! two zero blocks    end of the archive
+//! ```
+//!
+//! Our own fields are pax keywords prefixed with `GIT-ANNEX-TAPE.`, tar
+//! ignores them. GNU tar warns about them unless given
+//! `--warning=no-unknown-keyword`.
+
+use std::convert::TryInto;
+
+use crate::checksum::{self, Checksum};
+use crate::format::{ArchiveHeader, Error, Metadata, Origin};
+
+/// Size of a tar block. Headers and contents are padded to whole blocks.
+pub const BLOCK_SIZE: usize = 512;
+
+const PREFIX: &str = "GIT-ANNEX-TAPE.";
+
+/// Largest size which fits the octal size field of a header.
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// Type of a tar entry, from its type flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    /// pax extended header for the following entry.
+    Extended,
+    /// pax global header for all following entries.
+    Global,
+    Other(u8),
+}
+
+impl EntryType {
+    fn flag(self) -> u8 {
+        match self {
+            Self::File => b'0',
+            Self::Extended => b'x',
+            Self::Global => b'g',
+            Self::Other(flag) => flag,
+        }
+    }
+
+    fn from_flag(flag: u8) -> Self {
+        match flag {
+            b'0' | 0 => Self::File,
+            b'x' => Self::Extended,
+            b'g' => Self::Global,
+            flag => Self::Other(flag),
+        }
+    }
+}
+
+/// The fields of a ustar header we care about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub mtime: u64,
+    pub kind: EntryType,
+}
+
+/// Keywords and values of a pax extended or global header, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes(pub Vec<(String, String)>);
+
+impl Attributes {
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        // Later records override earlier ones.
+        self.0
+            .iter()
+            .rev()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn ours(&self, name: &str) -> Option<&str> {
+        self.get(&format!("{PREFIX}{name}"))
+    }
+
+    fn set(&mut self, keyword: &str, value: impl ToString) {
+        self.0.push((keyword.to_string(), value.to_string()));
+    }
+
+    fn set_ours(&mut self, name: &str, value: impl ToString) {
+        self.set(&format!("{PREFIX}{name}"), value);
+    }
+
+    /// Encode the attributes as pax records, `<length> <keyword>=<value>\n`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut records = Vec::new();
+
+        for (keyword, value) in &self.0 {
+            // The length includes its own digits.
+            let base = keyword.len() + value.len() + 3;
+            let mut length = base + 1;
+            while base + length.to_string().len() != length {
+                length = base + length.to_string().len();
+            }
+
+            records.extend_from_slice(format!("{length} {keyword}={value}\n").as_bytes());
+        }
+
+        records
+    }
+
+    pub fn decode(mut records: &[u8]) -> Result<Self, Error> {
+        let mut attributes = Vec::new();
+
+        while !records.is_empty() {
+            let space = records
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or(Error::Truncated)?;
+            let length: usize = std::str::from_utf8(&records[..space])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or(Error::Truncated)?;
+            if length <= space + 1 || length > records.len() || records[length - 1] != b'\n' {
+                return Err(Error::Truncated);
+            }
+
+            let record = std::str::from_utf8(&records[space + 1..length - 1])
+                .map_err(|_| Error::Encoding)?;
+            let (keyword, value) = record.split_once('=').ok_or(Error::Truncated)?;
+            attributes.push((keyword.to_string(), value.to_string()));
+
+            records = &records[length..];
+        }
+
+        Ok(Self(attributes))
+    }
+}
+
+/// What a tar entry holds, from our fields in its pax header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Member {
+    /// Global header at the start of an archive.
+    Archive(ArchiveHeader),
+    Object {
+        key: String,
+        sequence: u64,
+        /// Algorithm of the checksum in the trailer.
+        algorithm: checksum::Algorithm,
+        metadata: Metadata,
+    },
+    /// Checksum of the object written before.
+    Trailer {
+        key: String,
+        sequence: u64,
+        checksum: Checksum,
+    },
+    Tombstone {
+        key: String,
+    },
+    /// An entry without our fields.
+    Other,
+}
+
+impl Member {
+    /// Name of the file the member is extracted to.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Archive(header) => format!("archive-{}", header.creation_time),
+            Self::Object { key, .. } => key.clone(),
+            Self::Trailer { key, checksum, .. } => format!("{key}.{}", checksum.algorithm),
+            Self::Tombstone { key } => format!("{key}.removed"),
+            Self::Other => String::new(),
+        }
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        let mut attributes = Attributes::default();
+
+        match self {
+            Self::Archive(header) => {
+                attributes.set_ours("archive", header.creation_time);
+                attributes.set_ours("host", &header.host);
+                attributes.set_ours("writer", &header.writer);
+                attributes.set_ours("repository", &header.origin.repository);
+                attributes.set_ours("remote", &header.origin.remote);
+                attributes.set_ours("remote-name", &header.origin.remote_name);
+            }
+            Self::Object {
+                key,
+                sequence,
+                algorithm,
+                metadata,
+            } => {
+                attributes.set("path", key);
+                attributes.set_ours("key", key);
+                attributes.set_ours("sequence", sequence);
+                attributes.set_ours("algorithm", algorithm);
+                if !metadata.is_empty() {
+                    // Metadata only fails to serialize for non-string map keys.
+                    attributes.set_ours("metadata", serde_json::to_string(metadata).unwrap());
+                }
+            }
+            Self::Trailer {
+                key,
+                sequence,
+                checksum,
+            } => {
+                attributes.set("path", self.name());
+                attributes.set_ours("trailer", key);
+                attributes.set_ours("sequence", sequence);
+                attributes.set_ours("checksum", checksum);
+            }
+            Self::Tombstone { key } => {
+                attributes.set("path", self.name());
+                attributes.set_ours("tombstone", key);
+            }
+            Self::Other => {}
+        }
+
+        attributes
+    }
+
+    /// Recognize a member from the attributes of its pax header.
+    pub fn from_attributes(attributes: &Attributes) -> Self {
+        let sequence = || attributes.ours("sequence").and_then(|s| s.parse().ok());
+
+        if let Some(creation_time) = attributes.ours("archive").and_then(|s| s.parse().ok()) {
+            let field = |name| attributes.ours(name).unwrap_or_default().to_string();
+
+            let mut header =
+                ArchiveHeader::new(creation_time, &field("host")).with_origin(Origin {
+                    repository: field("repository"),
+                    remote: field("remote"),
+                    remote_name: field("remote-name"),
+                });
+            header.writer = field("writer");
+
+            return Self::Archive(header);
+        }
+
+        // Objects written before the algorithm was recorded are checksummed
+        // with SHA256.
+        let algorithm = attributes
+            .ours("algorithm")
+            .map_or(Some(checksum::Algorithm::Sha256), |a| a.parse().ok());
+
+        if let (Some(key), Some(sequence), Some(algorithm)) =
+            (attributes.ours("key"), sequence(), algorithm)
+        {
+            let metadata = attributes
+                .ours("metadata")
+                .and_then(|m| serde_json::from_str(m).ok())
+                .unwrap_or_default();
+
+            return Self::Object {
+                key: key.to_string(),
+                sequence,
+                algorithm,
+                metadata,
+            };
+        }
+
+        if let (Some(key), Some(sequence), Some(checksum)) = (
+            attributes.ours("trailer"),
+            sequence(),
+            attributes.ours("checksum").and_then(|c| c.parse().ok()),
+        ) {
+            return Self::Trailer {
+                key: key.to_string(),
+                sequence,
+                checksum,
+            };
+        }
+
+        if let Some(key) = attributes.ours("tombstone") {
+            return Self::Tombstone {
+                key: key.to_string(),
+            };
+        }
+
+        Self::Other
+    }
+}
+
+/// Encode a member: its pax header followed by the header of the file, or
+/// only the global header of an archive. The contents of the file follow.
+pub fn encode_member(member: &Member, size: u64, mtime: u64) -> Vec<u8> {
+    let name = member.name();
+    let mut attributes = member.attributes();
+    if size > MAX_OCTAL_SIZE {
+        attributes.set("size", size);
+    }
+
+    let records = attributes.encode();
+    let kind = match member {
+        Member::Archive(_) => EntryType::Global,
+        _ => EntryType::Extended,
+    };
+
+    let mut encoded = encode_header(&Entry {
+        name: format!("PaxHeaders/{name}"),
+        size: records.len() as u64,
+        mtime,
+        kind,
+    })
+    .to_vec();
+    encoded.extend_from_slice(&records);
+    encoded.resize(encoded.len() + padding(records.len() as u64), 0);
+
+    if kind == EntryType::Extended {
+        encoded.extend_from_slice(&encode_header(&Entry {
+            name,
+            size,
+            mtime,
+            kind: EntryType::File,
+        }));
+    }
+
+    encoded
+}
+
+/// Contents of the trailer of an object, in the format of `sha256sum`.
+pub fn trailer_contents(key: &str, checksum: &Checksum) -> Vec<u8> {
+    format!("{}  {key}\n", checksum::to_hex(&checksum.digest)).into_bytes()
+}
+
+/// Number of zero bytes which pad `length` bytes to whole blocks.
+pub fn padding(length: u64) -> usize {
+    (length.next_multiple_of(BLOCK_SIZE as u64) - length) as usize
+}
+
+/// Encode a ustar header. Names longer than the header allows are cut short,
+/// the full name is in the pax header.
+pub fn encode_header(entry: &Entry) -> [u8; BLOCK_SIZE] {
+    let mut block = [0u8; BLOCK_SIZE];
+
+    let mut end = entry.name.len().min(100);
+    while !entry.name.is_char_boundary(end) {
+        end -= 1;
+    }
+    block[..end].copy_from_slice(&entry.name.as_bytes()[..end]);
+
+    octal(&mut block[100..108], 0o644);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], entry.size.min(MAX_OCTAL_SIZE));
+    octal(&mut block[136..148], entry.mtime.min(MAX_OCTAL_SIZE));
+    block[156] = entry.kind.flag();
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    let sum = header_sum(&block);
+    block[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+
+    block
+}
+
+/// Decode a ustar header, `None` for a zero block at the end of an archive.
+pub fn decode_header(block: &[u8]) -> Result<Option<Entry>, Error> {
+    let block: &[u8; BLOCK_SIZE] = block
+        .get(..BLOCK_SIZE)
+        .and_then(|b| b.try_into().ok())
+        .ok_or(Error::Truncated)?;
+
+    if block.iter().all(|&b| b == 0) {
+        return Ok(None);
+    }
+
+    if &block[257..262] != b"ustar" {
+        return Err(Error::Magic(block[257..265].try_into().unwrap()));
+    }
+
+    let expected = parse_octal(&block[148..156])? as u32;
+    let actual = header_sum(block);
+    if expected != actual {
+        return Err(Error::Checksum { expected, actual });
+    }
+
+    let mut name = field_str(&block[..100])?;
+    let prefix = field_str(&block[345..500])?;
+    if !prefix.is_empty() {
+        name = format!("{prefix}/{name}");
+    }
+
+    Ok(Some(Entry {
+        name,
+        size: parse_number(&block[124..136])?,
+        mtime: parse_number(&block[136..148])?,
+        kind: EntryType::from_flag(block[156]),
+    }))
+}
+
+/// Sum of the bytes of a header, with its checksum field taken as spaces.
+fn header_sum(block: &[u8; BLOCK_SIZE]) -> u32 {
+    block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u32)
+        .sum()
+}
+
+/// Write a number as octal digits followed by a NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field[..digits].copy_from_slice(format!("{value:0digits$o}").as_bytes());
+    field[digits] = 0;
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, Error> {
+    let digits = std::str::from_utf8(field).map_err(|_| Error::Encoding)?;
+    let digits = digits.trim_matches(|c| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(digits, 8).map_err(|_| Error::Encoding)
+}
+
+/// Parse a numeric field, in octal or, as written by GNU tar for large
+/// values, in base-256.
+fn parse_number(field: &[u8]) -> Result<u64, Error> {
+    if field[0] & 0x80 == 0 {
+        return parse_octal(field);
+    }
+
+    Ok(field[1..]
+        .iter()
+        .fold((field[0] & 0x7f) as u64, |n, &b| (n << 8) | b as u64))
+}
+
+fn field_str(field: &[u8]) -> Result<String, Error> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+
+    String::from_utf8(field[..end].to_vec()).map_err(|_| Error::Encoding)
+}
@@ -0,0 +1,857 @@
+//! Virtual tape library backed by a directory of cartridge images
+//!
+//! The library can be used instead of real hardware for testing and for
+//! disk-staging setups:
+//!
+//! ```text
+//! <dir>/library.json           elements of the library and their cartridges
+//! <dir>/library.lock
+//! <dir>/cartridges/<barcode>   cartridge images
+//! <dir>/cartridges/<barcode>.pN partition N of partitioned cartridges
+//! ```
+//!
+//! The changer is opened with the path of the directory, drive `N` with the
+//! path `<dir>/driveN`. Cartridges with a barcode starting with `CLN` are
+//! treated as cleaning cartridges and ejected by the drive right after loading.
+//!
+//! A cartridge image is a sequence of records, each prefixed with its length
+//! as a little endian `u32`. Filemarks are stored as a length of `u32::MAX`.
+//! Records whose length has the [`UNREADABLE`] bit set fail to read, which
+//! simulates media errors.
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::changer::{self, Address, Changer, Element, ElementType, Params};
+use crate::logpage::LogPage;
+use crate::mt::{self, TapeDevice};
+use crate::{mtio, scsi};
+
+const STATE_FILE: &str = "library.json";
+const LOCK_FILE: &str = "library.lock";
+const CARTRIDGE_DIR: &str = "cartridges";
+
+const FILEMARK: u32 = u32::MAX;
+
+/// Length bit of records which have been damaged.
+const UNREADABLE: u32 = 1 << 31;
+
+/// Barcode prefix of cleaning cartridges.
+pub const CLEANING_PREFIX: &str = "CLN";
+
+/// Contents of a single element of the library.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Slot {
+    address: Address,
+    barcode: Option<String>,
+    source: Option<Address>,
+
+    /// Cartridge has been placed into the mail slot by an operator.
+    #[serde(default)]
+    imported: bool,
+
+    /// Cartridge is loaded and not ejected (drives only).
+    #[serde(default)]
+    loaded: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct State {
+    elements: Vec<Slot>,
+}
+
+impl State {
+    fn get(&self, address: Address) -> Option<&Slot> {
+        self.elements.iter().find(|e| e.address == address)
+    }
+
+    fn get_mut(&mut self, address: Address) -> Option<&mut Slot> {
+        self.elements.iter_mut().find(|e| e.address == address)
+    }
+}
+
+/// The directory of a virtual library.
+#[derive(Debug, Clone)]
+pub struct Library {
+    dir: PathBuf,
+}
+
+impl Library {
+    /// Create a new library with blank cartridges in the first slots.
+    pub fn create(
+        dir: &Path,
+        slots: u16,
+        drives: u16,
+        mailslots: u16,
+        barcodes: &[String],
+    ) -> io::Result<Self> {
+        if barcodes.len() > slots as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more cartridges than slots",
+            ));
+        }
+
+        fs::create_dir_all(dir.join(CARTRIDGE_DIR))?;
+
+        let mut state = State::default();
+        let kinds = [
+            (ElementType::DataTransfer, drives),
+            (ElementType::Storage, slots),
+            (ElementType::ImportExport, mailslots),
+        ];
+
+        for (kind, count) in kinds {
+            for unit in 0..count {
+                state.elements.push(Slot {
+                    address: Address { kind, unit },
+                    barcode: None,
+                    source: None,
+                    imported: false,
+                    loaded: false,
+                });
+            }
+        }
+
+        for (unit, barcode) in barcodes.iter().enumerate() {
+            File::create(dir.join(CARTRIDGE_DIR).join(barcode))?;
+            state.get_mut(Address::slot(unit as u16)).unwrap().barcode = Some(barcode.clone());
+        }
+
+        let library = Self {
+            dir: dir.to_path_buf(),
+        };
+
+        library.write(&state)?;
+
+        Ok(library)
+    }
+
+    /// Open the library in `dir`, if there is one.
+    pub fn open(dir: &Path) -> Option<Self> {
+        if dir.join(STATE_FILE).is_file() {
+            Some(Self {
+                dir: dir.to_path_buf(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Path under which drive `unit` is opened.
+    pub fn drive_path(&self, unit: u16) -> PathBuf {
+        self.dir.join(format!("drive{unit}"))
+    }
+
+    fn image_path(&self, barcode: &str) -> PathBuf {
+        self.dir.join(CARTRIDGE_DIR).join(barcode)
+    }
+
+    /// Path of the image of a partition, the first one is the cartridge image.
+    pub fn partition_path(&self, barcode: &str, partition: u32) -> PathBuf {
+        match partition {
+            0 => self.image_path(barcode),
+            n => self.dir.join(CARTRIDGE_DIR).join(format!("{barcode}.p{n}")),
+        }
+    }
+
+    fn read(&self) -> io::Result<State> {
+        let buf = fs::read(self.dir.join(STATE_FILE))?;
+
+        serde_json::from_slice(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn write(&self, state: &State) -> io::Result<()> {
+        let buf = serde_json::to_vec_pretty(state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let tmp = self.dir.join(format!("{STATE_FILE}.tmp"));
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, self.dir.join(STATE_FILE))
+    }
+
+    /// Modify the state while holding the library lock.
+    fn update<T, E: From<io::Error>>(
+        &self,
+        f: impl FnOnce(&mut State) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(LOCK_FILE))?;
+
+        let _lock = Flock::lock(file, FlockArg::LockExclusive)
+            .map_err(|(_, errno)| io::Error::from(errno))?;
+
+        let mut state = self.read()?;
+        let result = f(&mut state)?;
+        self.write(&state)?;
+
+        Ok(result)
+    }
+
+    /// Put a new blank cartridge into a mail slot, as an operator would.
+    pub fn insert(&self, mailslot: u16, barcode: &str) -> io::Result<()> {
+        let image = self.image_path(barcode);
+        if !image.exists() {
+            File::create(image)?;
+        }
+
+        self.update(|state| {
+            let slot = state
+                .get_mut(Address::mailslot(mailslot))
+                .filter(|s| s.barcode.is_none())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "mail slot not empty")
+                })?;
+
+            slot.barcode = Some(barcode.to_string());
+            slot.imported = true;
+            slot.source = None;
+
+            Ok(())
+        })
+    }
+
+    /// Take a cartridge out of a mail slot, as an operator would.
+    ///
+    /// The image is kept so that the cartridge can be inserted again.
+    pub fn remove(&self, mailslot: u16) -> io::Result<Option<String>> {
+        self.update(|state| {
+            let slot = state
+                .get_mut(Address::mailslot(mailslot))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no such mail slot"))?;
+
+            slot.imported = false;
+            slot.source = None;
+
+            Ok(slot.barcode.take())
+        })
+    }
+
+    /// Make the record at logical block `block` of a cartridge unreadable.
+    ///
+    /// The drive fails to read the record afterwards, but can still space
+    /// over it.
+    pub fn damage(&self, barcode: &str, block: u64) -> io::Result<()> {
+        let mut image = Image::open(&self.image_path(barcode))?;
+        if block >= image.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "block beyond the end of data",
+            ));
+        }
+
+        image.damage(block as usize)
+    }
+}
+
+/// Changer of a virtual library.
+pub struct VirtualChanger {
+    library: Library,
+}
+
+impl VirtualChanger {
+    /// Open the changer of the library in `dir`, if there is one.
+    pub fn open(dir: &Path) -> io::Result<Option<Self>> {
+        Ok(Library::open(dir).map(|library| Self { library }))
+    }
+}
+
+impl Changer for VirtualChanger {
+    fn params(&self) -> changer::Result<Params> {
+        let state = self.library.read()?;
+        let count = |kind| {
+            state
+                .elements
+                .iter()
+                .filter(|e| e.address.kind == kind)
+                .count() as u16
+        };
+
+        Ok(Params {
+            pickers: 1,
+            slots: count(ElementType::Storage),
+            mailslots: count(ElementType::ImportExport),
+            drives: count(ElementType::DataTransfer),
+        })
+    }
+
+    fn element(&self, address: Address) -> changer::Result<Element> {
+        let state = self.library.read()?;
+        let slot = state
+            .get(address)
+            .ok_or(changer::Error::NoSuchElement(address))?;
+
+        Ok(Element {
+            address,
+            full: slot.barcode.is_some(),
+            exception: false,
+            accessible: true,
+            imported: slot.imported,
+            barcode: slot.barcode.clone(),
+            source: slot.source,
+        })
+    }
+
+    fn init_elements(&self) -> changer::Result<()> {
+        Ok(())
+    }
+
+    fn move_medium(&self, from: Address, to: Address) -> changer::Result<()> {
+        self.library.update(|state| {
+            let source = state
+                .get(from)
+                .ok_or(changer::Error::NoSuchElement(from))?
+                .clone();
+
+            if source.barcode.is_none() {
+                return Err(changer::Error::Empty(from));
+            }
+
+            // Like a real drive, a loaded cartridge has to be ejected first.
+            if source.loaded {
+                return Err(changer::Error::Errno(Errno::EBUSY));
+            }
+
+            let target = state.get_mut(to).ok_or(changer::Error::NoSuchElement(to))?;
+            if target.barcode.is_some() {
+                return Err(changer::Error::Full(to));
+            }
+
+            target.barcode = source.barcode;
+            target.source = Some(from);
+            target.imported = false;
+            target.loaded = to.kind == ElementType::DataTransfer;
+
+            let source = state.get_mut(from).unwrap();
+            source.barcode = None;
+            source.source = None;
+            source.imported = false;
+
+            Ok(())
+        })
+    }
+}
+
+/// An open cartridge image.
+struct Image {
+    file: File,
+
+    /// Offset of each record and filemark, followed by the end of data.
+    offsets: Vec<u64>,
+}
+
+impl Image {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+
+        while offset < len {
+            offsets.push(offset);
+
+            let mut header = [0u8; 4];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut header)?;
+
+            offset += 4;
+            match u32::from_le_bytes(header) {
+                FILEMARK => {}
+                length => offset += (length & !UNREADABLE) as u64,
+            }
+        }
+
+        offsets.push(offset);
+
+        Ok(Self { file, offsets })
+    }
+
+    /// Number of records and filemarks on the cartridge.
+    fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Read the entry at `position`, returning `None` for a filemark.
+    fn read(&mut self, position: usize) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 4];
+        self.file.seek(SeekFrom::Start(self.offsets[position]))?;
+        self.file.read_exact(&mut header)?;
+
+        match u32::from_le_bytes(header) {
+            FILEMARK => Ok(None),
+            length => {
+                let mut data = vec![0u8; length as usize];
+                self.file.read_exact(&mut data)?;
+
+                Ok(Some(data))
+            }
+        }
+    }
+
+    fn length(&mut self, position: usize) -> io::Result<u32> {
+        let mut header = [0u8; 4];
+        self.file.seek(SeekFrom::Start(self.offsets[position]))?;
+        self.file.read_exact(&mut header)?;
+
+        Ok(u32::from_le_bytes(header))
+    }
+
+    fn is_filemark(&mut self, position: usize) -> io::Result<bool> {
+        Ok(self.length(position)? == FILEMARK)
+    }
+
+    fn is_unreadable(&mut self, position: usize) -> io::Result<bool> {
+        let length = self.length(position)?;
+
+        Ok(length != FILEMARK && length & UNREADABLE != 0)
+    }
+
+    /// Make the record at `position` unreadable.
+    fn damage(&mut self, position: usize) -> io::Result<()> {
+        let length = self.length(position)?;
+        if length == FILEMARK {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot damage a filemark",
+            ));
+        }
+
+        self.file.seek(SeekFrom::Start(self.offsets[position]))?;
+        self.file.write_all(&(length | UNREADABLE).to_le_bytes())
+    }
+
+    /// Discard everything from `position` on.
+    fn truncate(&mut self, position: usize) -> io::Result<()> {
+        let offset = self.offsets[position];
+
+        self.file.set_len(offset)?;
+        self.offsets.truncate(position + 1);
+
+        Ok(())
+    }
+
+    /// Write an entry at `position`, discarding everything after it.
+    fn write(&mut self, position: usize, data: Option<&[u8]>) -> io::Result<()> {
+        self.truncate(position)?;
+
+        let offset = self.offsets[position];
+        self.file.seek(SeekFrom::Start(offset))?;
+
+        let length = match data {
+            Some(data) => {
+                self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+                self.file.write_all(data)?;
+                4 + data.len() as u64
+            }
+            None => {
+                self.file.write_all(&FILEMARK.to_le_bytes())?;
+                4
+            }
+        };
+
+        self.offsets.push(offset + length);
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Loaded {
+    barcode: Option<String>,
+    image: Option<Image>,
+    partition: u32,
+    position: usize,
+}
+
+/// Drive of a virtual library.
+pub struct VirtualTape {
+    library: Library,
+    unit: u16,
+    loaded: RefCell<Loaded>,
+}
+
+fn sense(key: u8, asc: u8, ascq: u8) -> scsi::Sense {
+    let mut buf = [0u8; 18];
+    buf[0] = 0x70;
+    buf[2] = key;
+    buf[7] = 10;
+    buf[12] = asc;
+    buf[13] = ascq;
+
+    scsi::Sense::parse(&buf)
+}
+
+impl VirtualTape {
+    /// Open a drive by its path `<dir>/driveN`, if it belongs to a virtual library.
+    pub fn open(path: &Path) -> io::Result<Option<Self>> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(None);
+        };
+
+        let Some(unit) = name
+            .to_str()
+            .and_then(|n| n.strip_prefix("drive"))
+            .and_then(|n| n.parse().ok())
+        else {
+            return Ok(None);
+        };
+
+        let Some(library) = Library::open(dir) else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            library,
+            unit,
+            loaded: RefCell::default(),
+        }))
+    }
+
+    fn address(&self) -> Address {
+        Address::drive(self.unit)
+    }
+
+    /// Barcode of the cartridge which is loaded and ready.
+    fn ready_barcode(&self) -> mt::Result<Option<String>> {
+        let state = self.library.read()?;
+
+        Ok(state
+            .get(self.address())
+            .filter(|s| s.loaded)
+            .and_then(|s| s.barcode.clone()))
+    }
+
+    /// Run `f` with the image of the loaded cartridge and the position on it.
+    ///
+    /// The image is (re)opened whenever the changer has loaded another cartridge.
+    fn with_image<T>(
+        &self,
+        f: impl FnOnce(&mut Image, &mut usize) -> mt::Result<T>,
+    ) -> mt::Result<T> {
+        let barcode = self.ready_barcode()?;
+        let mut loaded = self.loaded.borrow_mut();
+
+        if loaded.barcode != barcode {
+            *loaded = Loaded::default();
+
+            if let Some(barcode) = &barcode {
+                loaded.image = Some(Image::open(&self.library.image_path(barcode))?);
+                loaded.barcode = Some(barcode.clone());
+            }
+        }
+
+        let Loaded {
+            image, position, ..
+        } = &mut *loaded;
+
+        match image {
+            Some(image) => f(image, position),
+            None => Err(Errno::ENOMEDIUM.into()),
+        }
+    }
+
+    fn set_loaded(&self, value: bool) -> io::Result<()> {
+        let address = self.address();
+
+        self.library.update(|state| {
+            if let Some(slot) = state.get_mut(address) {
+                slot.loaded = value && slot.barcode.is_some();
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl TapeDevice for VirtualTape {
+    fn test_unit_ready(&self) -> mt::Result<Option<scsi::Sense>> {
+        match self.ready_barcode()? {
+            None => Ok(Some(sense(0x02, 0x3a, 0x00))), // Medium not present
+            Some(barcode) if barcode.starts_with(CLEANING_PREFIX) => {
+                // Cleaning finishes instantly and ejects the cartridge.
+                self.set_loaded(false)?;
+
+                Ok(Some(sense(0x02, 0x3a, 0x00)))
+            }
+            Some(_) => Ok(None),
+        }
+    }
+
+    fn request_sense(&self) -> mt::Result<scsi::Sense> {
+        Ok(sense(0x00, 0x00, 0x00))
+    }
+
+    fn log_sense(&self, page: u8) -> mt::Result<LogPage> {
+        Err(mt::Error::InvalidLogPage(page))
+    }
+
+    fn inquiry(&self) -> mt::Result<scsi::Inquiry> {
+        Ok(scsi::Inquiry {
+            device_type: 0x01,
+            vendor: "VIRTUAL".to_string(),
+            product: "VTL".to_string(),
+            revision: "0001".to_string(),
+        })
+    }
+
+    fn serial_number(&self) -> mt::Result<String> {
+        Ok(format!("VTL{:04}", self.unit))
+    }
+
+    fn send_diagnostic(&self) -> mt::Result<()> {
+        Ok(())
+    }
+
+    fn self_test(&self) -> mt::Result<i32> {
+        Ok(0)
+    }
+
+    fn get_status(&self) -> mt::Result<mtio::mtget> {
+        let mut status = mtio::mtget::default();
+
+        let result = self.with_image(|image, position| {
+            let mut fileno = 0;
+            let mut blkno = 0;
+
+            for i in 0..*position {
+                if image.is_filemark(i)? {
+                    fileno += 1;
+                    blkno = 0;
+                } else {
+                    blkno += 1;
+                }
+            }
+
+            let mut gstat = mtio::GMTStatusFlags::ONLINE;
+            if *position == 0 {
+                gstat |= mtio::GMTStatusFlags::BOT;
+            }
+            if *position == image.len() {
+                gstat |= mtio::GMTStatusFlags::EOD;
+            }
+            if *position > 0 && image.is_filemark(*position - 1)? {
+                gstat |= mtio::GMTStatusFlags::EOF;
+            }
+
+            Ok((fileno, blkno, gstat))
+        });
+
+        match result {
+            Ok((fileno, blkno, gstat)) => {
+                status.mt_fileno = fileno;
+                status.mt_blkno = blkno;
+                status.mt_gstat = gstat;
+
+                // Like the st driver, report the active partition here.
+                status.mt_resid = self.loaded.borrow().partition as libc::c_long;
+            }
+            Err(mt::Error::Errno(Errno::ENOMEDIUM)) => {
+                status.mt_fileno = -1;
+                status.mt_blkno = -1;
+                status.mt_gstat = mtio::GMTStatusFlags::DRIVE_OPEN;
+            }
+            Err(e) => return Err(e),
+        }
+
+        Ok(status)
+    }
+
+    fn get_position(&self) -> mt::Result<i64> {
+        self.with_image(|_, position| Ok(*position as i64))
+    }
+
+    fn read_block(&self, block: &mut [u8]) -> mt::Result<usize> {
+        self.with_image(|image, position| {
+            if *position >= image.len() {
+                return Err(Errno::EIO.into());
+            }
+
+            // Like a real drive, stay in front of a record which failed to read.
+            if image.is_unreadable(*position)? {
+                return Err(Errno::EIO.into());
+            }
+
+            let data = image.read(*position)?;
+            *position += 1;
+
+            match data {
+                None => Ok(0),
+                Some(data) if data.len() > block.len() => Err(Errno::ENOMEM.into()),
+                Some(data) => {
+                    block[..data.len()].copy_from_slice(&data);
+
+                    Ok(data.len())
+                }
+            }
+        })
+    }
+
+    fn write_block(&self, block: &[u8]) -> mt::Result<usize> {
+        self.with_image(|image, position| {
+            image.write(*position, Some(block))?;
+            *position += 1;
+
+            Ok(block.len())
+        })
+    }
+
+    fn weof(&self, count: i32) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            for _ in 0..count {
+                image.write(*position, None)?;
+                *position += 1;
+            }
+
+            Ok(0)
+        })
+    }
+
+    fn rewind(&self) -> mt::Result<i32> {
+        // Rewinding returns to the first partition, which is reopened.
+        if self.loaded.borrow().partition != 0 {
+            *self.loaded.borrow_mut() = Loaded::default();
+        }
+
+        self.with_image(|_, position| {
+            *position = 0;
+
+            Ok(0)
+        })
+    }
+
+    fn offline(&self) -> mt::Result<i32> {
+        self.rewind()?;
+        self.set_loaded(false)?;
+
+        Ok(0)
+    }
+
+    fn eom(&self) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            *position = image.len();
+
+            Ok(0)
+        })
+    }
+
+    fn fsf(&self, count: i32) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            for _ in 0..count {
+                loop {
+                    if *position >= image.len() {
+                        return Err(Errno::EIO.into());
+                    }
+
+                    *position += 1;
+                    if image.is_filemark(*position - 1)? {
+                        break;
+                    }
+                }
+            }
+
+            Ok(0)
+        })
+    }
+
+    fn fsr(&self, count: i32) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            for _ in 0..count {
+                if *position >= image.len() {
+                    return Err(Errno::EIO.into());
+                }
+
+                // Spacing stops after a filemark.
+                *position += 1;
+                if image.is_filemark(*position - 1)? {
+                    return Err(Errno::EIO.into());
+                }
+            }
+
+            Ok(0)
+        })
+    }
+
+    fn erase(&self, _fast: bool) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            image.truncate(*position)?;
+
+            Ok(0)
+        })
+    }
+
+    fn seek(&self, block: i32) -> mt::Result<i32> {
+        self.with_image(|image, position| {
+            if block < 0 || block as usize > image.len() {
+                return Err(Errno::EIO.into());
+            }
+
+            *position = block as usize;
+
+            Ok(0)
+        })
+    }
+
+    fn set_partition(&self, partition: i32) -> mt::Result<i32> {
+        let barcode = self.ready_barcode()?.ok_or(Errno::ENOMEDIUM)?;
+
+        // Cartridges have as many partitions as they have images.
+        let path = self.library.partition_path(&barcode, partition as u32);
+        if partition < 0 || !path.is_file() {
+            return Err(Errno::EIO.into());
+        }
+
+        *self.loaded.borrow_mut() = Loaded {
+            barcode: Some(barcode),
+            image: Some(Image::open(&path)?),
+            partition: partition as u32,
+            position: 0,
+        };
+
+        Ok(0)
+    }
+
+    fn make_partition(&self, size: i32) -> mt::Result<i32> {
+        let barcode = self.ready_barcode()?.ok_or(Errno::ENOMEDIUM)?;
+
+        // Formatting discards all data, the sizes of the partitions are not
+        // emulated.
+        File::create(self.library.image_path(&barcode))?;
+        let path = self.library.partition_path(&barcode, 1);
+        match size {
+            0 => match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+            _ => drop(File::create(path)?),
+        }
+
+        *self.loaded.borrow_mut() = Loaded::default();
+
+        Ok(0)
+    }
+
+    fn flush_drive_buffer(&self) -> mt::Result<i32> {
+        self.with_image(|image, _| {
+            image.file.sync_data()?;
+
+            Ok(0)
+        })
+    }
+
+    fn set_block_length(&self, _length: i32) -> mt::Result<i32> {
+        Ok(0)
+    }
+
+    fn set_cleaning_request(&self, _byte: u8, _mask: u8, _pattern: u8) -> mt::Result<i32> {
+        Ok(0)
+    }
+}
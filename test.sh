@@ -21,6 +21,12 @@ cd test-repo
 header "Build code"
 cargo build --bin git-annex-remote-tape
 
+header "Create virtual tape library"
+VTL=$(pwd)/vtl
+git-annex-remote-tape vtl "$VTL" create
+git-annex-remote-tape changer -c "$VTL" load 0 0
+git-annex-remote-tape tape -f "$VTL/drive0" init
+
 header "Initializing git repo"
 git init
 
@@ -28,10 +34,10 @@ header "Initializing git-annex"
 git annex -d init
 
 header "Init git-annex remote"
-git annex -d initremote tape1 type=external externaltype=tape drive=/dev/st0 encryption=none
+git annex -d initremote tape1 type=external externaltype=tape drive="$VTL/drive0" changer="$VTL" encryption=none
 
 header "Enable git-annex remote with settings"
-git annex -d enableremote tape1 type=external externaltype=tape drive=/dev/st0 encryption=none
+git annex -d enableremote tape1 type=external externaltype=tape drive="$VTL/drive0" changer="$VTL" encryption=none
 
 header "Enable git-annex remote without settings"
 git annex -d enableremote tape1
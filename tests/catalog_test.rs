@@ -0,0 +1,107 @@
+use git_annex_remote_tape::catalog::{Catalog, MediaRecord, Placement};
+use git_annex_remote_tape::changer::Address;
+use git_annex_remote_tape::format::MediaIdentity;
+use std::path::PathBuf;
+
+const REMOTE: &str = "6d8e5e2c-4a2b-4b0e-9a57-2f0d3c1e8a41";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("catalog-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+fn record(id: u64, slot: u16, pool: &str, last_written: Option<u64>) -> MediaRecord {
+    MediaRecord {
+        id: format!("uuid-{id}"),
+        created: id,
+        barcode: Some(format!("VTL{id:03}L8")),
+        placement: Placement::Library(Address::slot(slot)),
+        last_written,
+        identity: MediaIdentity {
+            uuid: format!("uuid-{id}"),
+            pool: pool.to_string(),
+            remote: REMOTE.to_string(),
+            ..MediaIdentity::default()
+        },
+        ..MediaRecord::default()
+    }
+}
+
+#[test]
+fn test_degraded_media_is_not_filled() {
+    let dir = temp_dir("degraded");
+    let catalog = Catalog::open(&dir).unwrap();
+
+    let mut filling = record(1, 0, "daily", Some(200));
+    catalog.save_media(&filling).unwrap();
+    catalog
+        .save_media(&record(2, 1, "daily", Some(100)))
+        .unwrap();
+    catalog.save_media(&record(3, 2, "daily", None)).unwrap();
+    catalog
+        .save_media(&record(4, 3, "weekly", Some(300)))
+        .unwrap();
+
+    let fill = catalog.fill_media(REMOTE).unwrap().unwrap();
+    assert_eq!(fill.id, "uuid-4");
+
+    filling.degraded = Some("Cartridge health is poor".to_string());
+    catalog.save_media(&filling).unwrap();
+
+    // The spare is the cartridge of the pool written to most recently.
+    let spare = catalog.spare_media(REMOTE, "daily").unwrap().unwrap();
+    assert_eq!(spare.id, "uuid-2");
+    assert!(catalog.spare_media(REMOTE, "monthly").unwrap().is_none());
+    assert_eq!(catalog.media("uuid-1").unwrap().degraded, filling.degraded);
+
+    let mut weekly = catalog.media("uuid-4").unwrap();
+    weekly.degraded = Some("Cartridge health is poor".to_string());
+    catalog.save_media(&weekly).unwrap();
+    assert_eq!(catalog.fill_media(REMOTE).unwrap().unwrap().id, "uuid-2");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_records_are_moved_to_their_uuid() {
+    let dir = temp_dir("migrate");
+    let tapes = dir.join("annex/tapes");
+
+    // Records used to be named after the creation time of the cartridge.
+    for (created, uuid) in [(1000, "uuid-1000"), (1001, "")] {
+        std::fs::create_dir_all(tapes.join(created.to_string())).unwrap();
+        let details = format!(
+            r#"{{"id": {created}, "host": "host", "barcode": null, "last_seen": null,
+                "identity": {{"uuid": "{uuid}", "label": "", "pool": "", "remote": ""}}}}"#
+        );
+        std::fs::write(tapes.join(format!("{created}/details.json")), details).unwrap();
+        std::fs::write(tapes.join(format!("{created}/objects.json")), "[]").unwrap();
+    }
+
+    let catalog = Catalog::open(&dir).unwrap();
+    assert!(!tapes.join("1000").exists());
+    assert!(tapes.join("uuid-1000/objects.json").exists());
+
+    let record = catalog.media("uuid-1000").unwrap();
+    assert_eq!(record.id, "uuid-1000");
+    assert_eq!(record.created, 1000);
+
+    // Cartridges without a UUID keep their creation time.
+    assert_eq!(catalog.media("1001").unwrap().created, 1001);
+
+    let found = catalog.media_by_creation_time(1000).unwrap().unwrap();
+    assert_eq!(found.id, "uuid-1000");
+    assert!(catalog.media_by_creation_time(1001).unwrap().is_none());
+
+    let ids: Vec<_> = catalog
+        .all_media()
+        .unwrap()
+        .into_iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(ids, ["uuid-1000", "1001"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+use git_annex_remote_tape::checksum::{Algorithm, KeyHash};
+
+const CONTENT: &[u8] = b"Hello World\n";
+
+fn verify(key: &str, content: &[u8]) -> Option<bool> {
+    let mut hash = KeyHash::from_key(key)?;
+    hash.update(content);
+
+    Some(hash.verify())
+}
+
+#[test]
+fn test_sha256_keys() {
+    let hash = "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26";
+
+    assert_eq!(
+        verify(&format!("SHA256E-s12--{hash}.txt"), CONTENT),
+        Some(true)
+    );
+    assert_eq!(verify(&format!("SHA256-s12--{hash}"), CONTENT), Some(true));
+    assert_eq!(
+        verify(&format!("SHA256E-s12--{hash}.txt"), b"Hello World!"),
+        Some(false)
+    );
+}
+
+#[test]
+fn test_other_hash_keys() {
+    let blake2b = "0990a82fddb28de6073328865cef23a4d52acc6cd417d8ab396669d63c3ba8bd";
+    let sha3 = "265a271f568a62eb8c64e5cbedbdfd41d996303de25868af9b1892bda0bbcdfa";
+
+    assert_eq!(
+        verify(&format!("BLAKE2B256E-s12--{blake2b}.txt"), CONTENT),
+        Some(true)
+    );
+    assert_eq!(
+        verify(&format!("SHA3_256-s12--{sha3}"), CONTENT),
+        Some(true)
+    );
+    assert_eq!(
+        verify(&format!("SHA3_256-s12--{blake2b}"), CONTENT),
+        Some(false)
+    );
+}
+
+#[test]
+fn test_unverifiable_keys() {
+    assert!(KeyHash::from_key("WORM-s12-m1700000000--test.txt").is_none());
+    assert!(KeyHash::from_key("URL--https&c%%example.com%test.txt").is_none());
+    assert!(KeyHash::from_key("SHA256E-s12--tooshort.txt").is_none());
+
+    // The hash of chunked keys covers the whole file.
+    let hash = "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26";
+    assert!(KeyHash::from_key(&format!("SHA256E-s24-S12-C1--{hash}.txt")).is_none());
+}
+
+#[test]
+fn test_content_checksum() {
+    let mut hasher = Algorithm::Sha256.hasher();
+    hasher.update(&CONTENT[..5]);
+    hasher.update(&CONTENT[5..]);
+
+    assert_eq!(
+        hasher.finish().to_string(),
+        "sha256:d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26"
+    );
+}
@@ -0,0 +1,64 @@
+use git_annex_remote_tape::cleaning::{self, Reason, SenseBits};
+use git_annex_remote_tape::tapealert::{self, Flag};
+use std::str::FromStr;
+
+#[test]
+fn test_sense_bits() {
+    assert_eq!(
+        SenseBits::from_str("70:0x08"),
+        Ok(SenseBits {
+            byte: 70,
+            mask: 0x08,
+            pattern: 0,
+        })
+    );
+    assert_eq!(
+        SenseBits::from_str("0x46:8:0x08"),
+        Ok(SenseBits {
+            byte: 70,
+            mask: 8,
+            pattern: 8,
+        })
+    );
+
+    for invalid in ["", "70", "70:0x08:0:1", "256:1", "70:0xzz", "-1:1"] {
+        assert_eq!(SenseBits::from_str(invalid), Err(()), "{invalid}");
+    }
+}
+
+#[test]
+fn test_cleaning_from_sense() {
+    assert_eq!(
+        cleaning::from_sense(0x00, 0x17),
+        Some(Reason::Sense {
+            asc: 0x00,
+            ascq: 0x17,
+        })
+    );
+    assert_eq!(cleaning::from_sense(0x00, 0x00), None);
+    assert_eq!(cleaning::from_sense(0x30, 0x03), None);
+}
+
+#[test]
+fn test_cleaning_from_tape_alerts() {
+    assert_eq!(cleaning::from_tape_alerts(&[]), None);
+    assert_eq!(
+        cleaning::from_tape_alerts(&[Flag(tapealert::READ_WARNING), Flag(tapealert::MEDIA)]),
+        None
+    );
+
+    // The cleaning media flag is about the cartridge, not a request.
+    assert_eq!(
+        cleaning::from_tape_alerts(&[Flag(tapealert::CLEANING_MEDIA)]),
+        None
+    );
+
+    assert_eq!(
+        cleaning::from_tape_alerts(&[Flag(tapealert::HARD_ERROR), Flag(tapealert::CLEAN_NOW)]),
+        Some(Reason::TapeAlert(Flag(tapealert::CLEAN_NOW)))
+    );
+    assert_eq!(
+        cleaning::from_tape_alerts(&[Flag(tapealert::CLEAN_PERIODIC), Flag(tapealert::CLEAN_NOW)]),
+        Some(Reason::TapeAlert(Flag(tapealert::CLEAN_PERIODIC)))
+    );
+}
@@ -0,0 +1,399 @@
+use git_annex_remote_tape::checksum::{Algorithm, Checksum};
+use git_annex_remote_tape::compression::{self, Compression};
+use git_annex_remote_tape::crypto::{self, Cipher, KeyId};
+use git_annex_remote_tape::format::{
+    self, ArchiveFormat, ArchiveHeader, ArchiveToc, Decoder, Encoder, Error, Header, MediaHeader,
+    MediaIdentity, Metadata, ObjectHeader, ObjectTrailer, Origin, PackMember, TocEntry, Tombstone,
+};
+use git_annex_remote_tape::parity::Parity;
+
+#[test]
+fn test_media_header_layout() {
+    let record = format::encode(&MediaHeader::new(1, "h"));
+
+    #[rustfmt::skip]
+    let expected = [
+        b'M', b'E', b'D', b'I', b'A', b'T', b'H', b'D',
+        3, 0, 0, 0,
+        0, 0, 0, 40,
+        0, 0, 0, 0, 0, 0, 0, 1,
+        0, 1, b'h',
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0,
+        0x5e, 0x7f, 0x40, 0xa7,
+    ];
+
+    assert_eq!(record, expected);
+}
+
+#[test]
+fn test_media_identity() {
+    #[rustfmt::skip]
+    let version1 = [
+        b'M', b'E', b'D', b'I', b'A', b'T', b'H', b'D',
+        1, 0, 0, 0,
+        0, 0, 0, 31,
+        0, 0, 0, 0, 0, 0, 0, 1,
+        0, 1, b'h',
+        0x5a, 0xb4, 0x67, 0xc2,
+    ];
+    assert_eq!(
+        format::decode::<MediaHeader>(&version1),
+        Ok(MediaHeader::new(1, "h"))
+    );
+
+    let identity = MediaIdentity {
+        uuid: "0f4c2a8e-0000-4000-8000-000000000001".to_string(),
+        label: "VTL000L8".to_string(),
+        pool: "weekly".to_string(),
+        remote: "0f4c2a8e-0000-4000-8000-000000000002".to_string(),
+    };
+    let media = MediaHeader::new(1, "host").with_identity(identity.clone());
+    assert_eq!(
+        format::decode::<MediaHeader>(&format::encode(&media)),
+        Ok(media)
+    );
+
+    // Only the owner may write to owned cartridges, anyone to others.
+    assert!(identity.accepts(&identity.remote));
+    assert!(!identity.accepts("0f4c2a8e-0000-4000-8000-000000000003"));
+    assert!(MediaIdentity::default().accepts(&identity.remote));
+}
+
+#[test]
+fn test_archive_format() {
+    let media = MediaHeader::new(1, "host").with_format(ArchiveFormat::Tar);
+    assert_eq!(
+        format::decode::<MediaHeader>(&format::encode(&media)),
+        Ok(media)
+    );
+
+    // Cartridges initialized before the format was recorded are native.
+    #[rustfmt::skip]
+    let version2 = [
+        b'M', b'E', b'D', b'I', b'A', b'T', b'H', b'D',
+        2, 0, 0, 0,
+        0, 0, 0, 39,
+        0, 0, 0, 0, 0, 0, 0, 1,
+        0, 1, b'h',
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0x5f, 0xc7, 0x05, 0xc6,
+    ];
+    assert_eq!(
+        format::decode::<MediaHeader>(&version2),
+        Ok(MediaHeader::new(1, "h"))
+    );
+
+    assert_eq!("tar".parse(), Ok(ArchiveFormat::Tar));
+    assert_eq!("native".parse(), Ok(ArchiveFormat::Native));
+    assert_eq!("cpio".parse::<ArchiveFormat>(), Err(()));
+    assert_eq!(ArchiveFormat::Tar.to_string(), "tar");
+}
+
+#[test]
+fn test_round_trip() {
+    let media = MediaHeader::new(1700000000, "tapehost");
+    let archive = ArchiveHeader::new(1700000001, "otherhost").with_parity(Parity::new(10, 2));
+    let object = ObjectHeader::new(42, "SHA256E-s42--0123456789abcdef.txt", Algorithm::Sha256)
+        .with_parity(Parity::new(10, 2));
+
+    assert_eq!(
+        format::decode::<MediaHeader>(&format::encode(&media)),
+        Ok(media)
+    );
+    assert_eq!(
+        format::decode::<ArchiveHeader>(&format::encode(&archive)),
+        Ok(archive)
+    );
+    assert_eq!(
+        format::decode::<ObjectHeader>(&format::encode(&object)),
+        Ok(object)
+    );
+
+    let checksum = Checksum {
+        algorithm: Algorithm::Sha256,
+        digest: vec![0xab; 32],
+    };
+    let trailer = ObjectTrailer::new("KEY", 42, 7, checksum);
+    assert_eq!(
+        format::decode::<ObjectTrailer>(&format::encode(&trailer)),
+        Ok(trailer)
+    );
+}
+
+#[test]
+fn test_pack_round_trip() {
+    let members = (0..3)
+        .map(|i| PackMember {
+            key: format!("KEY{i}"),
+            offset: i * 100,
+            length: 100,
+            checksum: Checksum {
+                algorithm: Algorithm::Sha256,
+                digest: vec![i as u8; 32],
+            },
+            metadata: Metadata {
+                paths: vec![format!("dir/file{i}")],
+                ..Metadata::default()
+            }
+            .encode(),
+            compressed: i == 1,
+            uncompressed_length: if i == 1 { 4000 } else { 100 },
+        })
+        .collect();
+
+    let pack = ObjectHeader::pack(300, Algorithm::Sha256, members);
+    assert!(pack.is_pack());
+    assert!(!ObjectHeader::new(300, "KEY", Algorithm::Sha256).is_pack());
+
+    assert_eq!(
+        format::decode::<ObjectHeader>(&format::encode(&pack)),
+        Ok(pack)
+    );
+}
+
+#[test]
+fn test_tombstone_round_trip() {
+    let tombstone = Tombstone::new("SHA256E-s42--0123456789abcdef.txt");
+    assert_eq!(
+        format::decode::<Tombstone>(&format::encode(&tombstone)),
+        Ok(tombstone)
+    );
+
+    let entries = vec![
+        TocEntry {
+            key: "KEY".to_string(),
+            offset: 1,
+            length: 42,
+            checksum: Some(Checksum {
+                algorithm: Algorithm::Sha256,
+                digest: vec![0xab; 32],
+            }),
+            tombstone: false,
+        },
+        TocEntry {
+            key: "KEY".to_string(),
+            offset: 5,
+            length: 0,
+            checksum: None,
+            tombstone: true,
+        },
+    ];
+    let toc = ArchiveToc {
+        archive: 1,
+        part: 0,
+        parts: 1,
+        entries,
+    };
+
+    let record = format::encode(&toc);
+    let length = toc.entries.iter().map(TocEntry::encoded_len).sum::<usize>();
+    assert_eq!(record.len(), format::ARCHIVE_TOC_OVERHEAD + length);
+    assert_eq!(format::decode::<ArchiveToc>(&record), Ok(toc));
+}
+
+#[test]
+fn test_compressed_round_trip() {
+    let object = ObjectHeader::new(4096, "KEY", Algorithm::Sha256).with_compression(1000);
+    assert_eq!(object.object_length, 1000);
+    assert_eq!(object.uncompressed_length, 4096);
+    assert!(object.compressed);
+
+    assert_eq!(
+        format::decode::<ObjectHeader>(&format::encode(&object)),
+        Ok(object)
+    );
+}
+
+#[test]
+fn test_compression_level() {
+    assert_eq!(
+        "zstd".parse(),
+        Ok(Compression::new(compression::DEFAULT_LEVEL).unwrap())
+    );
+    assert_eq!("zstd:19".parse(), Ok(Compression { level: 19 }));
+    assert_eq!(Compression { level: 19 }.to_string(), "zstd:19");
+
+    for invalid in ["", "gzip", "zstd:", "zstd:x", "zstd:1000"] {
+        assert_eq!(invalid.parse::<Compression>(), Err(()));
+    }
+}
+
+#[test]
+fn test_encrypted_round_trip() {
+    let archive = ArchiveHeader::new(1, "host").with_key_id(Some(KeyId([7; 8])));
+    assert_eq!(
+        format::decode::<ArchiveHeader>(&format::encode(&archive)),
+        Ok(archive)
+    );
+
+    let object = ObjectHeader::new(42, "KEY", Algorithm::Sha256)
+        .with_encryption([9; crypto::NONCE_PREFIX_LENGTH], 58);
+    assert_eq!(object.object_length, 58);
+    assert_eq!(
+        format::decode::<ObjectHeader>(&format::encode(&object)),
+        Ok(object)
+    );
+}
+
+#[test]
+fn test_cipher() {
+    let cipher = Cipher::generate();
+    let parsed: Cipher = cipher.to_string().parse().unwrap();
+    assert_eq!(parsed.id(), cipher.id());
+    assert_ne!(Cipher::generate().id(), cipher.id());
+    assert!("0123".parse::<Cipher>().is_err());
+
+    let nonce = Cipher::nonce();
+    let record = cipher.seal_record(&nonce, 3, false, b"data");
+    assert_eq!(record.len(), 4 + crypto::TAG_LENGTH);
+    assert_eq!(
+        cipher.open_record(&nonce, 3, false, &record),
+        Some(b"data".to_vec())
+    );
+
+    // Records cannot be moved, truncated or opened with another key.
+    assert_eq!(cipher.open_record(&nonce, 4, false, &record), None);
+    assert_eq!(cipher.open_record(&nonce, 3, true, &record), None);
+    assert_eq!(
+        Cipher::generate().open_record(&nonce, 3, false, &record),
+        None
+    );
+
+    let sealed = cipher.seal_key("KEY");
+    assert_ne!(sealed, cipher.seal_key("KEY"));
+    assert_eq!(cipher.open_key(&sealed), Some("KEY".to_string()));
+
+    assert_eq!(crypto::sealed_length(0, 100), 16);
+    assert_eq!(crypto::sealed_length(250, 100), 250 + 3 * 16);
+}
+
+#[test]
+fn test_origin_round_trip() {
+    let archive = ArchiveHeader::new(1, "host").with_origin(Origin {
+        repository: "d4c3b2a1-0000-4000-8000-000000000001".to_string(),
+        remote: "d4c3b2a1-0000-4000-8000-000000000002".to_string(),
+        remote_name: "tape".to_string(),
+    });
+    assert_eq!(archive.writer, format::WRITER);
+
+    assert_eq!(
+        format::decode::<ArchiveHeader>(&format::encode(&archive)),
+        Ok(archive)
+    );
+}
+
+#[test]
+fn test_metadata_round_trip() {
+    assert!(Metadata::default().encode().is_empty());
+    assert_eq!(Metadata::decode(&[]), Ok(Metadata::default()));
+
+    let metadata = Metadata {
+        paths: vec!["photos/a.jpg".to_string(), "backup/a.jpg".to_string()],
+        mtime: Some(1700000000),
+        commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+    };
+    assert_eq!(Metadata::decode(&metadata.encode()), Ok(metadata.clone()));
+
+    let object = ObjectHeader::new(42, "KEY", Algorithm::Sha256).with_metadata(metadata.encode());
+    assert_eq!(
+        format::decode::<ObjectHeader>(&format::encode(&object)),
+        Ok(object)
+    );
+
+    // Paths beyond the limit are left out.
+    let many = Metadata {
+        paths: vec!["x".repeat(1000); 100],
+        ..Metadata::default()
+    };
+    let decoded = Metadata::decode(&many.encode()).unwrap();
+    assert_eq!(decoded.paths.len(), 16);
+}
+
+/// Object header as written before checksums were added.
+struct ObjectHeaderV1 {
+    object_length: u64,
+    key: &'static str,
+}
+
+impl Header for ObjectHeaderV1 {
+    const MAGIC: [u8; 8] = format::OBJECT_HEADER_MAGIC;
+    const VERSION: u8 = 1;
+
+    fn encode_fields(&self, fields: &mut Encoder) {
+        fields.u64(self.object_length);
+        fields.str(self.key);
+    }
+
+    fn decode_fields(_version: u8, _fields: &mut Decoder) -> Result<Self, Error> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_older_versions_are_readable() {
+    let record = format::encode(&ObjectHeaderV1 {
+        object_length: 42,
+        key: "KEY",
+    });
+
+    let header: ObjectHeader = format::decode(&record).unwrap();
+    assert_eq!(header.object_length, 42);
+    assert_eq!(header.key, "KEY");
+    assert_eq!(header.checksum, None);
+}
+
+#[test]
+fn test_trailing_data_is_ignored() {
+    let object = ObjectHeader::new(42, "KEY", Algorithm::Sha256);
+    let mut record = format::encode(&object);
+    record.extend_from_slice(&[0xff; 100]);
+
+    assert_eq!(format::decode::<ObjectHeader>(&record), Ok(object));
+}
+
+#[test]
+fn test_wrong_header_type() {
+    let record = format::encode(&ArchiveHeader::new(1, "host"));
+
+    assert_eq!(
+        format::decode::<MediaHeader>(&record),
+        Err(Error::Magic(format::ARCHIVE_HEADER_MAGIC))
+    );
+}
+
+#[test]
+fn test_corruption_is_detected() {
+    let mut record = format::encode(&ObjectHeader::new(42, "KEY", Algorithm::Sha256));
+    let n = record.len();
+    record[n - 6] ^= 0x01;
+
+    assert!(matches!(
+        format::decode::<ObjectHeader>(&record),
+        Err(Error::Checksum { .. })
+    ));
+}
+
+#[test]
+fn test_truncation_is_detected() {
+    let record = format::encode(&ObjectHeader::new(42, "KEY", Algorithm::Sha256));
+
+    for n in [0, 10, record.len() - 1] {
+        assert_eq!(
+            format::decode::<ObjectHeader>(&record[..n]),
+            Err(Error::Truncated)
+        );
+    }
+}
+
+#[test]
+fn test_parity_ratio() {
+    let parity = Parity::new(10, 2).unwrap();
+    assert_eq!((parity.data, parity.parity), (10, 2));
+    assert_eq!("10+2".parse(), Ok(parity));
+    assert_eq!(parity.to_string(), "10+2");
+
+    for invalid in ["", "10", "0+2", "10+0", "200+100", "a+b"] {
+        assert_eq!(invalid.parse::<Parity>(), Err(()));
+    }
+}
@@ -0,0 +1,52 @@
+use git_annex_remote_tape::health::{MediaHealth, Status};
+use git_annex_remote_tape::logpage::{self, volume_statistics, LogPage};
+
+fn page(code: u8, params: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (param, value) in params {
+        body.extend_from_slice(&param.to_be_bytes());
+        body.push(0x00);
+        body.push(value.len() as u8);
+        body.extend_from_slice(value);
+    }
+
+    let mut buf = vec![code, 0x00];
+    buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&body);
+    buf
+}
+
+#[test]
+fn test_parse_volume_statistics() {
+    let buf = page(
+        logpage::VOLUME_STATISTICS,
+        &[
+            (volume_statistics::THREAD_COUNT, &[0x00, 0x0a]),
+            (volume_statistics::LIFETIME_MB_WRITTEN, &[0x00, 0x01, 0x86, 0xa0]),
+            (volume_statistics::VOLUME_BARCODE, b"ABC123L8  "),
+        ],
+    );
+
+    let page = LogPage::parse(&buf).unwrap();
+    assert_eq!(page.code, logpage::VOLUME_STATISTICS);
+    assert_eq!(page.counter(volume_statistics::THREAD_COUNT), Some(10));
+
+    let health = MediaHealth::from_page(&page);
+    assert_eq!(health.lifetime_mb_written, 100_000);
+    assert_eq!(health.barcode.as_deref(), Some("ABC123L8"));
+    assert_eq!(health.status(), Status::Good);
+}
+
+#[test]
+fn test_uncorrected_errors_degrade_media() {
+    let buf = page(
+        logpage::VOLUME_STATISTICS,
+        &[
+            (volume_statistics::LIFETIME_MB_READ, &[0x00, 0x00, 0x27, 0x10]),
+            (volume_statistics::TOTAL_UNRECOVERED_READ_ERRORS, &[0x00, 0x02]),
+        ],
+    );
+
+    let health = MediaHealth::from_page(&LogPage::parse(&buf).unwrap());
+    assert!(health.status() < Status::Good);
+}
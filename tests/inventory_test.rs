@@ -0,0 +1,147 @@
+use git_annex_remote_tape::catalog::{MediaRecord, Placement};
+use git_annex_remote_tape::changer::Address;
+use git_annex_remote_tape::inventory::{self, Cartridge, Finding, Identity};
+
+fn record(id: &str, barcode: &str) -> MediaRecord {
+    MediaRecord {
+        id: id.to_string(),
+        barcode: Some(barcode.to_string()),
+        ..Default::default()
+    }
+}
+
+fn cartridge(slot: u16, barcode: Option<&str>, identity: Identity) -> Cartridge {
+    Cartridge {
+        address: Address::slot(slot),
+        barcode: barcode.map(str::to_string),
+        identity,
+    }
+}
+
+#[test]
+fn test_reconcile() {
+    let mut known = vec![
+        record("1", "AAA001L8"),
+        record("2", "AAA002L8"),
+        record("3", "AAA003L8"),
+    ];
+
+    let cartridges = vec![
+        cartridge(0, Some("AAA001L8"), Identity::Unscanned),
+        cartridge(
+            1,
+            None,
+            Identity::Media {
+                id: "2".to_string(),
+                host: "host".to_string(),
+            },
+        ),
+        cartridge(2, Some("BBB001L8"), Identity::Unscanned),
+        cartridge(3, None, Identity::Blank),
+        cartridge(4, None, Identity::Foreign),
+        cartridge(
+            5,
+            Some("CCC001L8"),
+            Identity::Media {
+                id: "3".to_string(),
+                host: "host".to_string(),
+            },
+        ),
+    ];
+
+    let findings = inventory::reconcile(&known, &cartridges);
+
+    assert_eq!(
+        findings,
+        vec![
+            Finding::Present {
+                id: "1".to_string(),
+                address: Address::slot(0)
+            },
+            Finding::Present {
+                id: "2".to_string(),
+                address: Address::slot(1)
+            },
+            Finding::Unknown {
+                address: Address::slot(2),
+                barcode: Some("BBB001L8".to_string())
+            },
+            Finding::Blank {
+                address: Address::slot(3),
+                barcode: None
+            },
+            Finding::Foreign {
+                address: Address::slot(4),
+                barcode: None
+            },
+            Finding::Relabelled {
+                id: "3".to_string(),
+                address: Address::slot(5),
+                old: Some("AAA003L8".to_string()),
+                new: Some("CCC001L8".to_string()),
+            },
+        ]
+    );
+
+    let changed = inventory::apply(&mut known, &findings, 100);
+    assert_eq!(changed.len(), 3);
+    assert_eq!(known[2].barcode.as_deref(), Some("CCC001L8"));
+    assert_eq!(known[0].placement, Placement::Library(Address::slot(0)));
+}
+
+#[test]
+fn test_missing_media_is_offsite() {
+    let mut known = vec![record("1", "AAA001L8")];
+
+    let findings = inventory::reconcile(&known, &[]);
+    assert_eq!(
+        findings,
+        vec![Finding::Missing {
+            id: "1".to_string(),
+            barcode: Some("AAA001L8".to_string())
+        }]
+    );
+
+    inventory::apply(&mut known, &findings, 100);
+    assert_eq!(known[0].placement, Placement::Offsite);
+    assert_eq!(known[0].last_seen, None);
+}
+
+#[test]
+fn test_unscanned_media_may_be_known() {
+    let mut known = vec![record("1", "AAA001L8"), record("2", "AAA002L8")];
+    known[1].barcode = None;
+    known[1].placement = Placement::Library(Address::slot(1));
+
+    // A cartridge with an unknown barcode may be the one without a barcode.
+    let cartridges = vec![cartridge(1, Some("BBB001L8"), Identity::Unscanned)];
+    let findings = inventory::reconcile(&known, &cartridges);
+    assert_eq!(
+        findings,
+        vec![
+            Finding::Unknown {
+                address: Address::slot(1),
+                barcode: Some("BBB001L8".to_string())
+            },
+            Finding::Missing {
+                id: "1".to_string(),
+                barcode: Some("AAA001L8".to_string())
+            },
+        ]
+    );
+
+    let changed = inventory::apply(&mut known, &findings, 100);
+    assert_eq!(changed.len(), 1);
+    assert_eq!(known[1].placement, Placement::Library(Address::slot(1)));
+
+    // A cartridge without a barcode may be any of them.
+    let cartridges = vec![cartridge(1, None, Identity::Unscanned)];
+    let findings = inventory::reconcile(&known, &cartridges);
+    assert_eq!(
+        findings,
+        vec![Finding::Unknown {
+            address: Address::slot(1),
+            barcode: None
+        }]
+    );
+}
@@ -0,0 +1,586 @@
+use git_annex_remote_tape::changer::{self, Address};
+use git_annex_remote_tape::ltfs::{self, Error, Index, Label, Vol1, Volume};
+use git_annex_remote_tape::mt::TapeDevice;
+use git_annex_remote_tape::tape::Drive;
+use git_annex_remote_tape::vtl;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const UUID: &str = "f6c1a4e2-1d0b-4f57-9a4e-3a7a9e0b2c11";
+
+const BLOCK_SIZE: usize = 1024;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ltfs-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+/// Create a library with a cartridge of `partitions` partitions loaded into
+/// its drive.
+fn setup(dir: &Path, partitions: u32) -> Rc<dyn TapeDevice> {
+    let barcode = "LTF000L8".to_string();
+    let library = vtl::Library::create(dir, 2, 1, 1, std::slice::from_ref(&barcode)).unwrap();
+    for partition in 1..partitions {
+        std::fs::File::create(library.partition_path(&barcode, partition)).unwrap();
+    }
+
+    changer::open(dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    Rc::new(
+        vtl::VirtualTape::open(&library.drive_path(0))
+            .unwrap()
+            .unwrap(),
+    )
+}
+
+fn vol1() -> Vec<u8> {
+    let mut record = format!("VOL1LTF000L{:13}{:13}{:14}", "", "LTFS", "");
+    record.push_str(&" ".repeat(28));
+    record.push('4');
+
+    record.into_bytes()
+}
+
+fn label(location: char) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ltfslabel version="2.4.0">
+  <creator>test</creator>
+  <formattime>2024-01-31T12:00:00.000000000Z</formattime>
+  <volumeuuid>{UUID}</volumeuuid>
+  <location><partition>{location}</partition></location>
+  <partitions><index>a</index><data>b</data></partitions>
+  <blocksize>{BLOCK_SIZE}</blocksize>
+  <compression>true</compression>
+</ltfslabel>"#
+    )
+}
+
+fn index(generation: u64, partition: char, block: u64, contents: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ltfsindex version="2.4.0">
+  <creator>test</creator>
+  <volumeuuid>{UUID}</volumeuuid>
+  <generationnumber>{generation}</generationnumber>
+  <updatetime>2024-01-31T12:00:00.000000000Z</updatetime>
+  <location><partition>{partition}</partition><startblock>{block}</startblock></location>
+  <directory>
+    <name>LTF000</name>
+    <modifytime>2024-01-31T12:00:00.000000000Z</modifytime>
+    <contents>{contents}</contents>
+  </directory>
+</ltfsindex>"#
+    )
+}
+
+fn file(name: &str, length: u64, extents: &[(u64, u64, u64, u64)], sha256: Option<&str>) -> String {
+    let extents: String = extents
+        .iter()
+        .map(|(offset, block, byte_offset, count)| {
+            format!(
+                "<extent><fileoffset>{offset}</fileoffset><partition>b</partition>\
+                 <startblock>{block}</startblock><byteoffset>{byte_offset}</byteoffset>\
+                 <bytecount>{count}</bytecount></extent>"
+            )
+        })
+        .collect();
+    let attributes = sha256.map_or(String::new(), |hash| {
+        format!(
+            "<extendedattributes><xattr><key>ltfs.hash.sha256sum</key>\
+             <value>{hash}</value></xattr></extendedattributes>"
+        )
+    });
+
+    // Names which are not valid XML are percent-encoded.
+    let encoded = match name.contains('%') {
+        true => " percentencoded=\"true\"",
+        false => "",
+    };
+
+    format!(
+        "<file><name{encoded}>{name}</name><length>{length}</length><readonly>false</readonly>\
+         <modifytime>2024-01-31T12:00:00.000000000Z</modifytime>\
+         <extentinfo>{extents}</extentinfo>{attributes}</file>"
+    )
+}
+
+/// Write the VOL1 and LTFS labels at the beginning of a partition.
+fn write_labels(tape: &dyn TapeDevice, partition: i32, location: char) {
+    tape.set_partition(partition).unwrap();
+    tape.write_block(&vol1()).unwrap();
+    tape.weof(1).unwrap();
+    tape.write_block(label(location).as_bytes()).unwrap();
+    tape.weof(1).unwrap();
+}
+
+/// Write an index split into blocks, followed by a filemark.
+fn write_index(tape: &dyn TapeDevice, xml: &str) {
+    for chunk in xml.as_bytes().chunks(BLOCK_SIZE) {
+        tape.write_block(chunk).unwrap();
+    }
+    tape.weof(1).unwrap();
+}
+
+const HELLO_SHA256: &str = "A591A6D40BF420404A011733CFB7B190D62C65BF0BCDA32B57B277D9AD9F146E";
+
+/// Write a volume with two files. The data partition holds the first
+/// generation of the index, the index partition the second.
+fn format_volume(tape: &dyn TapeDevice) {
+    write_labels(tape, 0, 'a');
+    write_labels(tape, 1, 'b');
+
+    // Blocks 4 to 6 of the data partition.
+    tape.write_block(b"Hello World").unwrap();
+    tape.write_block(&[b'a'; BLOCK_SIZE]).unwrap();
+    let mut block = vec![b'b'; 476];
+    block.extend_from_slice(&[b'c'; 300]);
+    tape.write_block(&block).unwrap();
+    tape.weof(1).unwrap();
+
+    let hello = file("hello.txt", 11, &[(0, 4, 0, 11)], Some(HELLO_SHA256));
+    write_index(tape, &index(1, 'b', 8, &hello));
+
+    let big = file(
+        "big%2Ebin",
+        2500,
+        &[(2000, 6, 476, 300), (0, 5, 0, 1500)],
+        None,
+    );
+    let contents = format!(
+        "{hello}<directory><name>dir</name>\
+         <modifytime>2024-01-31T12:00:00.000000000Z</modifytime>\
+         <contents>{big}</contents></directory>"
+    );
+
+    tape.set_partition(0).unwrap();
+    tape.seek(4).unwrap();
+    write_index(tape, &index(2, 'a', 4, &contents));
+}
+
+#[test]
+fn test_read_volume() {
+    let dir = temp_dir("read");
+    let tape = setup(&dir, 2);
+    format_volume(tape.as_ref());
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(volume.vol1().volume, "LTF000");
+    assert_eq!(volume.label().volume_uuid, UUID);
+    assert_eq!(volume.label().block_size, BLOCK_SIZE);
+    assert!(volume.label().compression);
+
+    // The index partition holds the latest generation.
+    let index = volume.index();
+    assert_eq!(index.generation, 2);
+    assert_eq!(index.location.partition, 'a');
+    assert_eq!(index.root.name, "LTF000");
+
+    let files = index.files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["hello.txt", "dir/big.bin"]);
+
+    let mut out = Vec::new();
+    assert_eq!(volume.read_file(files[0].1, &mut out).unwrap(), 11);
+    assert_eq!(out, b"Hello World");
+
+    // Gaps between the extents and after the last one read as zeros.
+    let mut out = Vec::new();
+    volume.read_file(files[1].1, &mut out).unwrap();
+    let mut expected = vec![b'a'; 1024];
+    expected.extend_from_slice(&[b'b'; 476]);
+    expected.extend_from_slice(&[0; 500]);
+    expected.extend_from_slice(&[b'c'; 300]);
+    expected.extend_from_slice(&[0; 200]);
+    assert_eq!(out, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_checksum_mismatch() {
+    let dir = temp_dir("checksum");
+    let tape = setup(&dir, 2);
+    format_volume(tape.as_ref());
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    let mut file = volume.index().root.files[0].clone();
+    file.attributes[0].1 = "00".repeat(32);
+
+    assert!(matches!(
+        volume.read_file(&file, &mut Vec::new()),
+        Err(Error::ChecksumMismatch(name)) if name == "hello.txt"
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_index_without_index_partition() {
+    let dir = temp_dir("unclean");
+    let tape = setup(&dir, 2);
+    format_volume(tape.as_ref());
+
+    // The index partition was lost after the first generation was written.
+    tape.set_partition(0).unwrap();
+    tape.seek(4).unwrap();
+    tape.erase(true).unwrap();
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(volume.index().generation, 1);
+    assert_eq!(volume.index().files().len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_not_ltfs() {
+    let dir = temp_dir("not-ltfs");
+    let library = vtl::Library::create(&dir, 2, 1, 1, &["LTF000L8".to_string()]).unwrap();
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+    let drive = Drive::new(&library.drive_path(0)).unwrap();
+
+    assert!(matches!(drive.ltfs(&[]), Err(Error::Blank)));
+
+    drive.init_media(1000, "host").unwrap();
+    assert!(matches!(drive.ltfs(&[]), Err(Error::NotLtfs)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_single_partition() {
+    let dir = temp_dir("single");
+    let tape = setup(&dir, 1);
+    write_labels(tape.as_ref(), 0, 'a');
+
+    assert!(matches!(
+        Volume::open(Rc::clone(&tape), &[]),
+        Err(Error::SinglePartition)
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_vol1() {
+    let vol1 = Vol1::decode(&vol1()).unwrap();
+    assert_eq!(vol1.volume, "LTF000");
+    assert_eq!(vol1.owner, "");
+
+    let mut record = self::vol1();
+    record[24..28].copy_from_slice(b"XXXX");
+    assert_eq!(Vol1::decode(&record), None);
+    assert_eq!(Vol1::decode(&record[..79]), None);
+}
+
+#[test]
+fn test_label() {
+    let label = Label::parse(&label('b')).unwrap();
+    assert_eq!(label.format_time, 1706702400);
+    assert_eq!(label.location, 'b');
+    assert_eq!(label.index_partition, 'a');
+    assert_eq!(label.data_partition, 'b');
+
+    assert!(matches!(
+        Label::parse(&index(1, 'a', 4, "")),
+        Err(Error::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_names_must_not_escape() {
+    for name in ["..", ".", "a/b", "%2E%2E", "a%2Fb"] {
+        let xml = index(1, 'a', 4, &file(name, 0, &[], None));
+
+        assert!(
+            matches!(Index::parse(&xml), Err(Error::Invalid(_))),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_parse_time() {
+    assert_eq!(ltfs::parse_time("1970-01-01T00:00:00.000000000Z"), Some(0));
+    assert_eq!(
+        ltfs::parse_time("2024-01-31T12:00:00.123456789Z"),
+        Some(1706702400)
+    );
+    assert_eq!(ltfs::parse_time("2000-02-29T23:59:59Z"), Some(951868799));
+    assert_eq!(ltfs::parse_time("2024-13-01T00:00:00Z"), None);
+    assert_eq!(ltfs::parse_time("2024-01-31 12:00:00Z"), None);
+}
+
+/// Format the cartridge of a new library as an empty volume.
+fn format_new(dir: &Path) -> (Rc<dyn TapeDevice>, Volume) {
+    let tape = setup(dir, 1);
+    let volume = Volume::format(
+        Rc::clone(&tape),
+        Vol1::new("LTF000L8"),
+        Label::new(UUID, 1706702400),
+    )
+    .unwrap();
+
+    (tape, volume)
+}
+
+fn contents(volume: &Volume, path: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    volume
+        .read_file(volume.find(path).unwrap(), &mut out)
+        .unwrap();
+
+    out
+}
+
+#[test]
+fn test_write_volume() {
+    let dir = temp_dir("write");
+    let (tape, mut volume) = format_new(&dir);
+    assert_eq!(volume.index().generation, 1);
+    assert!(volume.index().files().is_empty());
+
+    let big: Vec<u8> = (0..ltfs::DEFAULT_BLOCK_SIZE * 2 + 100)
+        .map(|i| i as u8)
+        .collect();
+    volume
+        .write_file(
+            "hello.txt",
+            11,
+            &mut &b"Hello World"[..],
+            1706702500,
+            Vec::new(),
+        )
+        .unwrap();
+    volume
+        .write_file(
+            "dir/sub/big.bin",
+            big.len() as u64,
+            &mut &big[..],
+            1706702600,
+            Vec::new(),
+        )
+        .unwrap();
+    volume
+        .write_file("dir/empty", 0, &mut &b""[..], 1706702700, Vec::new())
+        .unwrap();
+    volume.sync(1706702800).unwrap();
+    drop(volume);
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(volume.vol1().volume, "LTF000");
+    assert_eq!(volume.label().volume_uuid, UUID);
+
+    let index = volume.index();
+    assert_eq!(index.generation, 2);
+    assert_eq!(index.location.partition, 'a');
+    assert_eq!(index.previous.map(|p| p.partition), Some('b'));
+    assert_eq!(index.update_time, 1706702800);
+    assert_eq!(index.root.name, "LTF000");
+
+    let paths: Vec<String> = index.files().into_iter().map(|(path, _)| path).collect();
+    assert_eq!(paths, ["hello.txt", "dir/empty", "dir/sub/big.bin"]);
+
+    let hello = volume.find("hello.txt").unwrap();
+    assert_eq!(hello.modify_time, 1706702500);
+    assert_eq!(
+        hello.attribute(ltfs::SHA256_ATTRIBUTE),
+        Some(HELLO_SHA256.to_lowercase().as_str())
+    );
+    assert_eq!(contents(&volume, "hello.txt"), b"Hello World");
+    assert_eq!(contents(&volume, "dir/sub/big.bin"), big);
+    assert!(contents(&volume, "dir/empty").is_empty());
+
+    // Every file and directory has its own identifier.
+    let mut uids = vec![index.root.uid];
+    uids.extend(index.root.directories.iter().map(|d| d.uid));
+    uids.extend(index.files().iter().map(|(_, file)| file.uid));
+    uids.sort_unstable();
+    uids.dedup();
+    assert_eq!(uids.len(), 5);
+    assert_eq!(index.highest_uid, 6);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rename_and_remove() {
+    let dir = temp_dir("rename");
+    let (tape, mut volume) = format_new(&dir);
+
+    volume
+        .write_file("a/one", 3, &mut &b"one"[..], 0, Vec::new())
+        .unwrap();
+    volume
+        .write_file("a/two", 3, &mut &b"two"[..], 0, Vec::new())
+        .unwrap();
+    let uid = volume.find("a/one").unwrap().uid;
+
+    assert!(volume.rename_file("a/one", "b/c/three").unwrap());
+    assert!(!volume.rename_file("a/one", "b/four").unwrap());
+    assert_eq!(volume.find("b/c/three").unwrap().uid, uid);
+
+    // A file cannot replace a directory, nor be put below a file.
+    assert!(volume.rename_file("a/two", "b").is_err());
+    assert!(volume.rename_file("a/two", "b/c/three/x").is_err());
+    assert!(volume.find("a/two").is_some());
+
+    // Removing the last file of a directory removes the directory.
+    assert!(volume.remove_file("a/two"));
+    assert!(!volume.remove_file("a/two"));
+    assert!(volume
+        .index()
+        .root
+        .directories
+        .iter()
+        .all(|d| d.name != "a"));
+
+    volume.sync(1).unwrap();
+    let mut volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(contents(&volume, "b/c/three"), b"one");
+
+    assert!(volume.remove_directory("b/"));
+    assert!(!volume.remove_directory("b"));
+    volume.sync(2).unwrap();
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(volume.index().generation, 3);
+    assert!(volume.index().files().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_append_sessions() {
+    let dir = temp_dir("append");
+    let (tape, mut volume) = format_new(&dir);
+
+    volume
+        .write_file("first", 5, &mut &b"first"[..], 0, Vec::new())
+        .unwrap();
+    volume.sync(1).unwrap();
+    let first = volume.index().previous;
+
+    // An unchanged index is not written again.
+    volume.sync(2).unwrap();
+    assert_eq!(volume.index().generation, 2);
+
+    let mut volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    volume
+        .write_file("first", 6, &mut &b"second"[..], 0, Vec::new())
+        .unwrap();
+    volume.sync(3).unwrap();
+
+    // The copy on the data partition points back to the previous one.
+    tape.set_partition(0).unwrap();
+    tape.seek(4).unwrap();
+    tape.erase(true).unwrap();
+
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert_eq!(volume.index().generation, 3);
+    assert_eq!(volume.index().location.partition, 'b');
+    assert_eq!(volume.index().previous, first);
+    assert_eq!(contents(&volume, "first"), b"second");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_journal() {
+    let dir = temp_dir("journal");
+    let (tape, mut volume) = format_new(&dir);
+
+    volume
+        .write_file("a/one", 3, &mut &b"one"[..], 0, Vec::new())
+        .unwrap();
+    volume
+        .write_file("two", 3, &mut &b"two"[..], 0, Vec::new())
+        .unwrap();
+    let mut journal = volume.checkpoint().unwrap();
+    assert_eq!(journal.len(), 2);
+    assert!(volume.checkpoint().unwrap().is_empty());
+
+    assert!(volume.rename_file("two", "a/three").unwrap());
+    assert!(volume.remove_file("a/one"));
+    journal.extend(volume.checkpoint().unwrap());
+    assert_eq!(journal.len(), 4);
+    drop(volume);
+
+    // The index on tape holds none of the changes, the journal all of them.
+    let volume = Volume::open(Rc::clone(&tape), &[]).unwrap();
+    assert!(volume.index().files().is_empty());
+
+    let mut volume = Volume::open(Rc::clone(&tape), &journal).unwrap();
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["a/three"]);
+    assert_eq!(contents(&volume, "a/three"), b"two");
+    assert!(volume.checkpoint().unwrap().is_empty());
+
+    volume
+        .write_file("four", 4, &mut &b"four"[..], 0, Vec::new())
+        .unwrap();
+    volume.sync(2).unwrap();
+    assert!(volume.checkpoint().unwrap().is_empty());
+
+    // Changes made to an earlier index are part of the current one.
+    let volume = Volume::open(Rc::clone(&tape), &journal).unwrap();
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["four", "a/three"]);
+    assert_eq!(contents(&volume, "a/three"), b"two");
+    assert_eq!(contents(&volume, "four"), b"four");
+
+    // Changes to other volumes are left out.
+    let mut volume = volume;
+    volume.remove_file("four");
+    let mut journal = volume.checkpoint().unwrap();
+    drop(volume);
+    journal[0].volume_uuid = "other".to_string();
+    let volume = Volume::open(Rc::clone(&tape), &journal).unwrap();
+    assert!(volume.find("four").is_some());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_index_round_trip() {
+    let contents = format!(
+        "{}{}",
+        file("hello.txt", 11, &[(0, 4, 0, 11)], Some(HELLO_SHA256)),
+        file("big%2Ebin", 2500, &[(0, 5, 0, 1500)], None)
+    );
+    let mut index = Index::parse(&index(3, 'b', 8, &contents)).unwrap();
+    index.root.files[1].name = "tab\there & <there>".to_string();
+
+    assert_eq!(Index::parse(&index.to_xml()).unwrap(), index);
+}
+
+#[test]
+fn test_format_time() {
+    assert_eq!(ltfs::format_time(0), "1970-01-01T00:00:00.000000000Z");
+    assert_eq!(
+        ltfs::format_time(951868799),
+        "2000-02-29T23:59:59.000000000Z"
+    );
+    for time in [0, 951868799, 1706702400, 4102444800] {
+        assert_eq!(ltfs::parse_time(&ltfs::format_time(time)), Some(time));
+    }
+}
+
+#[test]
+fn test_vol1_encode() {
+    let vol1 = Vol1::new("LTF000L8");
+    assert_eq!(vol1.encode(), self::vol1());
+    assert_eq!(Vol1::decode(&vol1.encode()), Some(vol1));
+}
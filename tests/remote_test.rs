@@ -0,0 +1,920 @@
+//! Runs the remote against a virtual tape library through the external
+//! special remote protocol.
+
+use git_annex_remote_tape::catalog::Catalog;
+use git_annex_remote_tape::changer::{self, Address};
+use git_annex_remote_tape::format::{ArchiveFormat, MediaHeader, MediaIdentity};
+use git_annex_remote_tape::tape::{self, Drive};
+use git_annex_remote_tape::vtl;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+
+const UUID: &str = "6d8e5e2c-4a2b-4b0e-9a57-2f0d3c1e8a41";
+
+/// Plays the part of git-annex.
+struct Annex {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    config: HashMap<String, String>,
+    git_dir: PathBuf,
+}
+
+impl Annex {
+    /// Start the remote and negotiate extensions, as before `INITREMOTE`.
+    fn spawn(
+        config: &[(&str, String)],
+        git_dir: &Path,
+        state: &mut HashMap<String, String>,
+    ) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-annex-remote-tape"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut annex = Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            config: config
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+            git_dir: git_dir.to_path_buf(),
+        };
+
+        assert_eq!(annex.read_line(), "VERSION 2");
+        assert_eq!(annex.request("EXTENSIONS INFO", state), "EXTENSIONS INFO");
+
+        annex
+    }
+
+    fn start(
+        config: &[(&str, String)],
+        git_dir: &Path,
+        state: &mut HashMap<String, String>,
+    ) -> Self {
+        let mut annex = Self::spawn(config, git_dir, state);
+        assert_eq!(annex.request("PREPARE", state), "PREPARE-SUCCESS");
+
+        annex
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.stdout.read_line(&mut line).unwrap();
+
+        line.trim_end().to_string()
+    }
+
+    /// Send a request and answer the remote's queries until it replies.
+    fn request(&mut self, line: &str, state: &mut HashMap<String, String>) -> String {
+        writeln!(self.stdin, "{line}").unwrap();
+
+        loop {
+            let line = self.read_line();
+            let (cmd, args) = line.split_once(' ').unwrap_or((&line, ""));
+
+            let value = match cmd {
+                "DEBUG" | "INFO" => continue,
+                "GETCONFIG" => self.config.get(args).cloned().unwrap_or_default(),
+                "GETUUID" => UUID.to_string(),
+                "GETGITDIR" => self.git_dir.display().to_string(),
+                "GETGITREMOTENAME" => "tape".to_string(),
+                "GETSTATE" => state.get(args).cloned().unwrap_or_default(),
+                "SETSTATE" => {
+                    // Cleared state loses its separator to `read_line`.
+                    let (key, value) = args.split_once(' ').unwrap_or((args, ""));
+                    state.insert(key.to_string(), value.to_string());
+                    continue;
+                }
+                // Creds are kept alongside the state, under their setting.
+                "GETCREDS" => {
+                    let creds = state.get(&format!("creds {args}"));
+                    writeln!(self.stdin, "CREDS {}", creds.map_or(" ", |c| c)).unwrap();
+                    continue;
+                }
+                "SETCREDS" => {
+                    let (setting, creds) = args.split_once(' ').unwrap();
+                    state.insert(format!("creds {setting}"), creds.to_string());
+                    continue;
+                }
+                _ => return line,
+            };
+
+            writeln!(self.stdin, "VALUE {value}").unwrap();
+        }
+    }
+
+    fn finish(mut self) {
+        drop(self.stdin);
+        assert!(self.child.wait().unwrap().success());
+    }
+
+    /// End the remote without letting it close its archive, as a crash would.
+    fn kill(mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+    }
+}
+
+/// Create a library with an initialized cartridge in its drive.
+fn setup(name: &str) -> (PathBuf, vtl::Library, [(&'static str, String); 3]) {
+    let dir = std::env::temp_dir().join(format!("remote-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let library_dir = dir.join("library");
+    let barcodes = vec!["VTL000L8".to_string(), "VTL001L8".to_string()];
+    let library = vtl::Library::create(&library_dir, 4, 1, 0, &barcodes).unwrap();
+    std::fs::create_dir_all(dir.join("repo/.git")).unwrap();
+
+    changer::open(&library_dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+    Drive::new(&library.drive_path(0))
+        .unwrap()
+        .init_media(1000, "host")
+        .unwrap();
+
+    let config = [
+        ("drive", library.drive_path(0).display().to_string()),
+        ("changer", library_dir.display().to_string()),
+        ("changerdrive", "0".to_string()),
+    ];
+
+    (dir, library, config)
+}
+
+#[test]
+fn test_store_and_retrieve() {
+    let (dir, library, config) = setup("store");
+    let library_dir = dir.join("library");
+    let git_dir = dir.join("repo/.git");
+    let changer = changer::open(&library_dir).unwrap();
+
+    let contents = [("KEY1", "Hello World\n"), ("KEY2", "Goodbye World\n")];
+    let mut state = HashMap::new();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    for (key, data) in contents {
+        let file = dir.join(key);
+        std::fs::write(&file, data).unwrap();
+
+        let reply = annex.request(
+            &format!("TRANSFER STORE {key} {}", file.display()),
+            &mut state,
+        );
+        assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+    }
+
+    // Reading while the archive is still open.
+    let file = dir.join("out1");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE KEY1 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY1");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), contents[0].1);
+    annex.finish();
+
+    assert!(state.values().all(|location| location.contains("VTL000L8")));
+
+    // The archive records the remote it has been written for.
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let scan = media.scan().unwrap();
+    assert_eq!(scan.archives[0].origin.remote, UUID);
+    drop(media);
+
+    // Move the cartridge back to its slot and let the remote fetch it again.
+    Drive::new(&library.drive_path(0)).unwrap().eject().unwrap();
+    changer.unload(0, None).unwrap();
+    changer.load(Address::slot(1), 0).unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request("CHECKPRESENT KEY2", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY2");
+
+    let file = dir.join("out2");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE KEY2 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY2");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), contents[1].1);
+
+    let reply = annex.request("CHECKPRESENT KEY3", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-FAILURE KEY3");
+    annex.finish();
+
+    let status = changer.status().unwrap();
+    assert_eq!(status.drives[0].barcode, Some("VTL000L8".to_string()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stored_objects_survive_a_crash() {
+    let (dir, library, config) = setup("crash");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    for key in ["KEY1", "KEY2"] {
+        let file = dir.join(key);
+        std::fs::write(&file, format!("Hello {key}\n")).unwrap();
+
+        let reply = annex.request(
+            &format!("TRANSFER STORE {key} {}", file.display()),
+            &mut state,
+        );
+        assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+
+        // git-annex may drop its copy right away.
+        std::fs::remove_file(&file).unwrap();
+    }
+    annex.kill();
+
+    // The small objects are still waiting in their pack, they are only in
+    // the spool.
+    let spool = git_dir.join("annex/spool").join(UUID);
+    let sessions: Vec<_> = std::fs::read_dir(&spool)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect();
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].join("KEY1").exists());
+
+    // The next process stores them again and updates their locations.
+    let annex = Annex::start(&config, &git_dir, &mut state);
+    annex.finish();
+    assert_eq!(std::fs::read_dir(&spool).unwrap().count(), 0);
+
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["KEY1", "KEY2"]);
+
+    // Both objects share a pack.
+    let locations: Vec<tape::Location> = ["KEY1", "KEY2"]
+        .iter()
+        .map(|key| state[*key].parse().unwrap())
+        .collect();
+    assert_eq!(locations[0].block, locations[1].block);
+
+    for (key, location) in ["KEY1", "KEY2"].iter().zip(&locations) {
+        let mut out = Vec::new();
+        media.read_object(location.block, key, &mut out).unwrap();
+        assert_eq!(out, format!("Hello {key}\n").as_bytes());
+    }
+    drop(media);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_remove() {
+    let (dir, library, config) = setup("remove");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    for key in ["KEY1", "KEY2"] {
+        let file = dir.join(key);
+        std::fs::write(&file, key).unwrap();
+
+        let reply = annex.request(
+            &format!("TRANSFER STORE {key} {}", file.display()),
+            &mut state,
+        );
+        assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+    }
+
+    assert_eq!(
+        annex.request("REMOVE KEY1", &mut state),
+        "REMOVE-SUCCESS KEY1"
+    );
+    assert_eq!(state["KEY1"], "");
+    let reply = annex.request("CHECKPRESENT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-FAILURE KEY1");
+
+    // Keys which have never been stored are removed already.
+    assert_eq!(
+        annex.request("REMOVE KEY3", &mut state),
+        "REMOVE-SUCCESS KEY3"
+    );
+    annex.finish();
+
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["KEY2"]);
+    assert_eq!(scan.tombstones.len(), 1);
+    let id = media.id();
+    drop(media);
+
+    // Keys found among the scanned objects are removed by a new archive,
+    // and stay removed without their state.
+    state.clear();
+    let catalog = Catalog::open(&git_dir).unwrap();
+    catalog.save_objects(&id, &scan.objects).unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request("CHECKPRESENT KEY2", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY2");
+    assert_eq!(
+        annex.request("REMOVE KEY2", &mut state),
+        "REMOVE-SUCCESS KEY2"
+    );
+    let reply = annex.request("CHECKPRESENT KEY2", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-FAILURE KEY2");
+    annex.finish();
+
+    assert_eq!(catalog.tombstones(&id).unwrap().len(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_remove_from_other_cartridge() {
+    let (dir, library, config) = setup("remove-other");
+    let library_dir = dir.join("library");
+    let git_dir = dir.join("repo/.git");
+    let changer = changer::open(&library_dir).unwrap();
+    let mut state = HashMap::new();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    for key in ["KEY1", "KEY2"] {
+        let file = dir.join(key);
+        std::fs::write(&file, key).unwrap();
+
+        if key == "KEY2" {
+            // Fill the blank cartridge once the first one is degrading.
+            annex.finish();
+
+            let drive = Drive::new(&library.drive_path(0)).unwrap();
+            let id = Rc::new(drive).load_media().unwrap().id();
+            let catalog = Catalog::open(&git_dir).unwrap();
+            let mut record = catalog.media(&id).unwrap();
+            record.degraded = Some("Cartridge health is poor".to_string());
+            catalog.save_media(&record).unwrap();
+
+            Drive::new(&library.drive_path(0)).unwrap().eject().unwrap();
+            changer.unload(0, None).unwrap();
+            changer.load(Address::slot(1), 0).unwrap();
+            annex = Annex::start(&config, &git_dir, &mut state);
+        }
+
+        let reply = annex.request(
+            &format!("TRANSFER STORE {key} {}", file.display()),
+            &mut state,
+        );
+        assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+    }
+    assert!(state["KEY2"].contains("VTL001L8"));
+
+    // The tombstone goes to the cartridge holding the copy.
+    assert_eq!(
+        annex.request("REMOVE KEY1", &mut state),
+        "REMOVE-SUCCESS KEY1"
+    );
+    annex.finish();
+
+    let status = changer.status().unwrap();
+    assert_eq!(status.drives[0].barcode, Some("VTL000L8".to_string()));
+
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let catalog = Catalog::open(&git_dir).unwrap();
+    let tombstones = catalog.tombstones(&media.id()).unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].key, "KEY1");
+
+    let scan = media.scan().unwrap();
+    assert!(scan.objects.is_empty());
+    assert_eq!(scan.tombstones.len(), 1);
+    drop(media);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_locations_by_creation_time() {
+    let (dir, library, config) = setup("legacy-location");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello World\n").unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(
+        &format!("TRANSFER STORE KEY1 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+    annex.finish();
+
+    // Locations used to refer to the creation time of the cartridge.
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let location = state["KEY1"].clone();
+    let legacy = location.replace(&media.id(), "1000");
+    assert_ne!(legacy, location);
+    drop(media);
+    state.insert("KEY1".to_string(), legacy);
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request("CHECKPRESENT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY1");
+    annex.finish();
+
+    assert_eq!(state["KEY1"], location);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_media_identity() {
+    let (dir, library, config) = setup("identity");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let mut config = config.to_vec();
+    config.push(("pool", "weekly".to_string()));
+
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello World\n").unwrap();
+    let store = format!("TRANSFER STORE KEY1 {}", file.display());
+
+    // Cartridges owned by another remote are not written to.
+    let other = "0f4c2a8e-0000-4000-8000-000000000002";
+    Drive::new(&library.drive_path(0))
+        .unwrap()
+        .write_media_header(
+            &MediaHeader::new(1000, "host").with_identity(MediaIdentity {
+                remote: other.to_string(),
+                ..MediaIdentity::default()
+            }),
+        )
+        .unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(&store, &mut state);
+    assert!(reply.starts_with("TRANSFER-FAILURE STORE KEY1"));
+    assert!(reply.ends_with(&format!("cartridge belongs to remote {other}")));
+    annex.finish();
+
+    // Blank cartridges are initialized for the remote.
+    Drive::new(&library.drive_path(0))
+        .unwrap()
+        .erase(false)
+        .unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(&store, &mut state);
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+    annex.finish();
+
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let identity = media.identity().clone();
+    assert_eq!(identity.label, "VTL000L8");
+    assert_eq!(identity.pool, "weekly");
+    assert_eq!(identity.remote, UUID);
+
+    let record = Catalog::open(&git_dir)
+        .unwrap()
+        .media(&identity.uuid)
+        .unwrap();
+    assert_eq!(record.created, media.creation_time());
+    assert_eq!(record.identity, identity);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tape_format() {
+    let (dir, library, config) = setup("tar");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let mut config = config.to_vec();
+    config.push(("tapeformat", "tar".to_string()));
+
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello World\n").unwrap();
+
+    // Blank cartridges are initialized in the configured format.
+    Drive::new(&library.drive_path(0))
+        .unwrap()
+        .erase(false)
+        .unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(
+        &format!("TRANSFER STORE KEY1 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+
+    let out = dir.join("out");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE KEY1 {}", out.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY1");
+    assert_eq!(std::fs::read(&out).unwrap(), b"Hello World\n");
+
+    let reply = annex.request("CHECKPRESENT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY1");
+    annex.finish();
+
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    assert_eq!(media.format(), ArchiveFormat::Tar);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_retrieve_verifies_key() {
+    let (dir, _library, config) = setup("verify");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    // The key claims the hash of different content.
+    let key = "SHA256E-s12--d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26.txt";
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello Wor1d\n").unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(
+        &format!("TRANSFER STORE {key} {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+
+    let out = dir.join("out");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE {key} {}", out.display()),
+        &mut state,
+    );
+    assert!(reply.starts_with(&format!("TRANSFER-FAILURE RETRIEVE {key}")));
+    assert!(!out.exists());
+    annex.finish();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_encrypted_store_and_retrieve() {
+    let (dir, _library, config) = setup("encrypted");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let mut config = config.to_vec();
+    config.push(("tapeencryption", "yes".to_string()));
+
+    // Initializing creates a key and keeps it on enabling the remote again.
+    let mut annex = Annex::spawn(&config, &git_dir, &mut state);
+    assert_eq!(
+        annex.request("INITREMOTE", &mut state),
+        "INITREMOTE-SUCCESS"
+    );
+    annex.finish();
+    let creds = state["creds tapekey"].clone();
+
+    let mut annex = Annex::spawn(&config, &git_dir, &mut state);
+    assert_eq!(
+        annex.request("INITREMOTE", &mut state),
+        "INITREMOTE-SUCCESS"
+    );
+    annex.finish();
+    assert_eq!(state["creds tapekey"], creds);
+
+    let key = "SHA256E-s12--secret.txt";
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello World\n").unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(
+        &format!("TRANSFER STORE {key} {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, format!("TRANSFER-SUCCESS STORE {key}"));
+    annex.finish();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let out = dir.join("out");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE {key} {}", out.display()),
+        &mut state,
+    );
+    assert_eq!(reply, format!("TRANSFER-SUCCESS RETRIEVE {key}"));
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "Hello World\n");
+    annex.finish();
+
+    let image = std::fs::read(dir.join("library/cartridges/VTL000L8")).unwrap();
+    assert!(!image.windows(6).any(|w| w == b"secret"));
+
+    // Without the key, the remote cannot be prepared.
+    state.remove("creds tapekey");
+    let mut annex = Annex::spawn(&config, &git_dir, &mut state);
+    assert!(annex.request("PREPARE", &mut state).starts_with("ERROR"));
+    annex.finish();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bundle_and_catalog_locations() {
+    let (dir, library, config) = setup("bundle");
+    let repo = dir.join("repo");
+    let git_dir = repo.join(".git");
+    let mut state = HashMap::new();
+
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "--quiet"]);
+    git(&["commit", "--quiet", "--allow-empty", "-m", "Initial commit"]);
+    git(&["branch", "git-annex"]);
+
+    let mut config = config.to_vec();
+    config.push(("gitbundle", "yes".to_string()));
+
+    let file = dir.join("in");
+    std::fs::write(&file, "Hello World\n").unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request(
+        &format!("TRANSFER STORE KEY1 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+    annex.finish();
+
+    // Closing the archive has written the bundle after the object.
+    let media = Rc::new(Drive::new(&library.drive_path(0)).unwrap())
+        .load_media()
+        .unwrap();
+    let scan = media.scan().unwrap();
+    assert_eq!(scan.objects.len(), 1);
+    assert_eq!(scan.bundles.len(), 1);
+
+    let bundle = dir.join("repo.bundle");
+    let block = scan.newest_bundle().unwrap().block;
+    media
+        .read_object(
+            block,
+            tape::BUNDLE_KEY,
+            &mut std::fs::File::create(&bundle).unwrap(),
+        )
+        .unwrap();
+    let id = media.id();
+    drop(media);
+
+    let heads = Command::new("git")
+        .args(["bundle", "list-heads"])
+        .arg(&bundle)
+        .output()
+        .unwrap();
+    let heads = String::from_utf8(heads.stdout).unwrap();
+    assert!(heads.contains("refs/heads/git-annex"));
+
+    // Without its state, the key is found among the scanned objects.
+    state.clear();
+    Catalog::open(&git_dir)
+        .unwrap()
+        .save_objects(&id, &scan.objects)
+        .unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let reply = annex.request("CHECKPRESENT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY1");
+
+    let out = dir.join("out");
+    let reply = annex.request(
+        &format!("TRANSFER RETRIEVE KEY1 {}", out.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY1");
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "Hello World\n");
+    annex.finish();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_export() {
+    let (dir, library, config) = setup("export");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let file = dir.join("KEY1");
+    std::fs::write(&file, "Hello World\n").unwrap();
+    let store = format!("TRANSFEREXPORT STORE KEY1 {}", file.display());
+
+    // Cartridges holding archives are not overwritten.
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    assert_eq!(
+        annex.request("EXPORTSUPPORTED", &mut state),
+        "EXPORTSUPPORTED-SUCCESS"
+    );
+    writeln!(annex.stdin, "EXPORT dir/hello.txt").unwrap();
+    let reply = annex.request(&store, &mut state);
+    assert!(
+        reply.starts_with("TRANSFER-FAILURE STORE KEY1"),
+        "{}",
+        reply
+    );
+    annex.finish();
+
+    Drive::new(&library.drive_path(0))
+        .unwrap()
+        .erase(false)
+        .unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    writeln!(annex.stdin, "EXPORT dir/hello.txt").unwrap();
+    let reply = annex.request(&store, &mut state);
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+    writeln!(annex.stdin, "EXPORT other.txt").unwrap();
+    let reply = annex.request(&store, &mut state);
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+
+    writeln!(annex.stdin, "EXPORT dir/hello.txt").unwrap();
+    let reply = annex.request("CHECKPRESENTEXPORT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-SUCCESS KEY1");
+    writeln!(annex.stdin, "EXPORT dir/hello.txt").unwrap();
+    let reply = annex.request("CHECKPRESENTEXPORT KEY2", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-FAILURE KEY2");
+
+    writeln!(annex.stdin, "EXPORT dir/hello.txt").unwrap();
+    let reply = annex.request("RENAMEEXPORT KEY1 new dir/hello world.txt", &mut state);
+    assert_eq!(reply, "RENAMEEXPORT-SUCCESS KEY1");
+    writeln!(annex.stdin, "EXPORT other.txt").unwrap();
+    let reply = annex.request("REMOVEEXPORT KEY1", &mut state);
+    assert_eq!(reply, "REMOVE-SUCCESS KEY1");
+    annex.finish();
+
+    // The tree is readable as an LTFS volume once the remote is done.
+    let drive = Drive::new(&library.drive_path(0)).unwrap();
+    let volume = drive.ltfs(&[]).unwrap();
+    assert_eq!(volume.vol1().volume, "VTL000");
+
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["new dir/hello world.txt"]);
+    assert_eq!(files[0].1.attribute("git-annex.key"), Some("KEY1"));
+    drop(volume);
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    let out = dir.join("out");
+    writeln!(annex.stdin, "EXPORT new dir/hello world.txt").unwrap();
+    let reply = annex.request(
+        &format!("TRANSFEREXPORT RETRIEVE KEY1 {}", out.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY1");
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "Hello World\n");
+
+    // Files hold the key they have been exported for.
+    writeln!(annex.stdin, "EXPORT new dir/hello world.txt").unwrap();
+    let reply = annex.request(
+        &format!("TRANSFEREXPORT RETRIEVE KEY2 {}", out.display()),
+        &mut state,
+    );
+    assert!(
+        reply.starts_with("TRANSFER-FAILURE RETRIEVE KEY2"),
+        "{}",
+        reply
+    );
+
+    let reply = annex.request("REMOVEEXPORTDIRECTORY new dir", &mut state);
+    assert_eq!(reply, "REMOVEEXPORTDIRECTORY-SUCCESS");
+    annex.finish();
+
+    let volume = drive.ltfs(&[]).unwrap();
+    assert!(volume.index().files().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_exported_files_survive_a_crash() {
+    let (dir, library, config) = setup("export-crash");
+    let git_dir = dir.join("repo/.git");
+    let mut state = HashMap::new();
+
+    let drive = Drive::new(&library.drive_path(0)).unwrap();
+    drive.erase(false).unwrap();
+
+    let file = dir.join("KEY1");
+    std::fs::write(&file, "Hello World\n").unwrap();
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    writeln!(annex.stdin, "EXPORT hello.txt").unwrap();
+    let reply = annex.request(
+        &format!("TRANSFEREXPORT STORE KEY1 {}", file.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS STORE KEY1");
+    annex.kill();
+
+    // The store was acknowledged once it was in the journal, the index on
+    // tape is written later.
+    let catalog = Catalog::open(&git_dir).unwrap();
+    let journal = catalog.journal(UUID).unwrap();
+    assert_eq!(journal.len(), 1);
+    assert!(drive.ltfs(&[]).unwrap().index().files().is_empty());
+
+    let volume = drive.ltfs(&journal).unwrap();
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["hello.txt"]);
+    drop(volume);
+
+    // The next process re-applies the journal, and writes the index when it
+    // is done.
+    let out = dir.join("out");
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    writeln!(annex.stdin, "EXPORT hello.txt").unwrap();
+    let reply = annex.request(
+        &format!("TRANSFEREXPORT RETRIEVE KEY1 {}", out.display()),
+        &mut state,
+    );
+    assert_eq!(reply, "TRANSFER-SUCCESS RETRIEVE KEY1");
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "Hello World\n");
+    std::fs::remove_file(&out).unwrap();
+    annex.finish();
+
+    assert!(catalog.journal(UUID).unwrap().is_empty());
+    let mut volume = drive.ltfs(&[]).unwrap();
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["hello.txt"]);
+
+    // Files written by other LTFS implementations hold no key.
+    volume
+        .write_file("other.txt", 5, &mut &b"Hello"[..], 0, Vec::new())
+        .unwrap();
+    volume.sync(1700000000).unwrap();
+    drop(volume);
+
+    let mut annex = Annex::start(&config, &git_dir, &mut state);
+    writeln!(annex.stdin, "EXPORT other.txt").unwrap();
+    let reply = annex.request("CHECKPRESENTEXPORT KEY1", &mut state);
+    assert_eq!(reply, "CHECKPRESENT-FAILURE KEY1");
+
+    writeln!(annex.stdin, "EXPORT other.txt").unwrap();
+    let reply = annex.request(
+        &format!("TRANSFEREXPORT RETRIEVE KEY1 {}", out.display()),
+        &mut state,
+    );
+    assert!(
+        reply.starts_with("TRANSFER-FAILURE RETRIEVE KEY1"),
+        "{}",
+        reply
+    );
+    assert!(!out.exists());
+
+    writeln!(annex.stdin, "EXPORT other.txt").unwrap();
+    let reply = annex.request("REMOVEEXPORT KEY1", &mut state);
+    assert_eq!(reply, "REMOVE-SUCCESS KEY1");
+    annex.kill();
+
+    let journal = catalog.journal(UUID).unwrap();
+    assert_eq!(drive.ltfs(&[]).unwrap().index().files().len(), 2);
+    let volume = drive.ltfs(&journal).unwrap();
+    let files = volume.index().files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["hello.txt"]);
+    drop(volume);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
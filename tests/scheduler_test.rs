@@ -0,0 +1,66 @@
+use git_annex_remote_tape::scheduler::{self, Candidate};
+
+fn candidate(unit: u16, barcode: Option<&str>) -> Candidate {
+    Candidate {
+        unit,
+        full: barcode.is_some(),
+        barcode: barcode.map(str::to_string),
+    }
+}
+
+#[test]
+fn test_prefer_drive_holding_wanted_cartridge() {
+    let drives = [candidate(0, Some("AAA001L8")), candidate(1, None)];
+
+    assert_eq!(
+        scheduler::preference(&drives, Some("AAA001L8"), None),
+        vec![0]
+    );
+}
+
+#[test]
+fn test_avoid_fill_cartridge() {
+    let drives = [
+        candidate(0, Some("FILL01L8")),
+        candidate(1, Some("AAA001L8")),
+        candidate(2, None),
+    ];
+
+    assert_eq!(
+        scheduler::preference(&drives, Some("BBB001L8"), Some("FILL01L8")),
+        vec![2, 1, 0]
+    );
+}
+
+#[test]
+fn test_drive_is_leased_once() {
+    let dir = std::env::temp_dir().join(format!("scheduler-test-{}", std::process::id()));
+    let drives = (0..2)
+        .map(|unit| scheduler::DriveSlot {
+            unit,
+            path: format!("/dev/nst{unit}").into(),
+        })
+        .collect();
+
+    let scheduler = scheduler::Scheduler::new(&dir, drives).unwrap();
+    let timeout = std::time::Duration::ZERO;
+
+    let first = scheduler
+        .acquire(None, Some("AAA001L8"), None, timeout)
+        .unwrap();
+    let second = scheduler.acquire(None, None, None, timeout).unwrap();
+    assert_ne!(first.drive.unit, second.drive.unit);
+
+    // The cartridge is reserved even though a drive would be free.
+    drop(second);
+    assert!(scheduler
+        .acquire(None, Some("AAA001L8"), None, timeout)
+        .is_err());
+
+    drop(first);
+    assert!(scheduler
+        .acquire(None, Some("AAA001L8"), None, timeout)
+        .is_ok());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use git_annex_remote_tape::tape::Location;
+use std::str::FromStr;
+
+#[test]
+fn test_location_roundtrip() {
+    let location = Location {
+        media: "9b2f3c1e-5d4a-4f6b-8e7c-0a1b2c3d4e5f".to_string(),
+        block: 42,
+        barcode: Some("ABC123L8".to_string()),
+    };
+
+    assert_eq!(
+        location.to_string(),
+        "media=9b2f3c1e-5d4a-4f6b-8e7c-0a1b2c3d4e5f block=42 barcode=ABC123L8"
+    );
+    assert_eq!(Location::from_str(&location.to_string()), Ok(location));
+}
+
+#[test]
+fn test_location_without_barcode() {
+    let location = Location::from_str("media=1 block=7").unwrap();
+
+    assert_eq!(location.media, "1");
+    assert_eq!(location.barcode, None);
+    assert_eq!(location.block, 7);
+    assert!(Location::from_str("block=7").is_err());
+}
@@ -0,0 +1,226 @@
+use git_annex_remote_tape::checksum::{Algorithm, Checksum};
+use git_annex_remote_tape::format::{ArchiveHeader, Error, Metadata, Origin};
+use git_annex_remote_tape::tar::{self, Attributes, Entry, EntryType, Member, BLOCK_SIZE};
+
+fn checksum() -> Checksum {
+    Checksum {
+        algorithm: Algorithm::Sha256,
+        digest: vec![0xab; 32],
+    }
+}
+
+#[test]
+fn test_header_round_trip() {
+    let entry = Entry {
+        name: "SHA256E-s42--0123456789abcdef.txt".to_string(),
+        size: 42,
+        mtime: 1700000000,
+        kind: EntryType::File,
+    };
+
+    let block = tar::encode_header(&entry);
+    assert_eq!(&block[257..265], b"ustar\x0000");
+    assert_eq!(&block[124..136], b"00000000052\0");
+    assert_eq!(tar::decode_header(&block), Ok(Some(entry)));
+
+    assert_eq!(tar::decode_header(&[0u8; BLOCK_SIZE]), Ok(None));
+    assert_eq!(tar::decode_header(&block[..100]), Err(Error::Truncated));
+}
+
+#[test]
+fn test_header_corruption_is_detected() {
+    let mut block = tar::encode_header(&Entry {
+        name: "KEY".to_string(),
+        size: 42,
+        mtime: 0,
+        kind: EntryType::File,
+    });
+    block[0] ^= 0x01;
+
+    assert!(matches!(
+        tar::decode_header(&block),
+        Err(Error::Checksum { .. })
+    ));
+}
+
+#[test]
+fn test_long_names_are_cut_short() {
+    let name = "x".repeat(200);
+    let block = tar::encode_header(&Entry {
+        name: name.clone(),
+        size: 0,
+        mtime: 0,
+        kind: EntryType::Extended,
+    });
+
+    let entry = tar::decode_header(&block).unwrap().unwrap();
+    assert_eq!(entry.name, name[..100]);
+    assert_eq!(entry.kind, EntryType::Extended);
+}
+
+#[test]
+fn test_base256_size() {
+    let mut block = tar::encode_header(&Entry {
+        name: "KEY".to_string(),
+        size: 0,
+        mtime: 0,
+        kind: EntryType::File,
+    });
+
+    // GNU tar writes sizes beyond the octal field in base-256.
+    block[124..136].copy_from_slice(&[0x80, 0, 0, 0, 0, 0, 0, 0x02, 0, 0, 0, 0]);
+    block[148..156].copy_from_slice(b"        ");
+    let sum: u32 = block.iter().map(|&b| b as u32).sum();
+    block[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+
+    let entry = tar::decode_header(&block).unwrap().unwrap();
+    assert_eq!(entry.size, 1 << 33);
+}
+
+#[test]
+fn test_attributes_round_trip() {
+    // The length of a record includes its own digits, which may add one.
+    let attributes = Attributes(vec![
+        ("path".to_string(), "KEY".to_string()),
+        ("comment".to_string(), "x".repeat(89)),
+        ("comment".to_string(), "y".repeat(1000)),
+    ]);
+
+    let records = attributes.encode();
+    assert!(records.starts_with(b"12 path=KEY\n102 comment=x"));
+    assert_eq!(Attributes::decode(&records), Ok(attributes.clone()));
+    assert_eq!(attributes.get("comment"), Some(&*"y".repeat(1000)));
+
+    assert_eq!(Attributes::decode(b"20 path=KEY\n"), Err(Error::Truncated));
+}
+
+#[test]
+fn test_member_round_trip() {
+    let mut header = ArchiveHeader::new(1700000000, "host").with_origin(Origin {
+        repository: "0f4c2a8e-0000-4000-8000-000000000001".to_string(),
+        remote: "0f4c2a8e-0000-4000-8000-000000000002".to_string(),
+        remote_name: "tape".to_string(),
+    });
+    header.writer = "git-annex-remote-tape 0.1.0".to_string();
+
+    let members = [
+        Member::Archive(header),
+        Member::Object {
+            key: "KEY".to_string(),
+            sequence: 3,
+            algorithm: Algorithm::Sha256,
+            metadata: Metadata {
+                paths: vec!["docs/a = b.txt".to_string()],
+                mtime: Some(1600000000),
+                commit: "0123456789abcdef".to_string(),
+            },
+        },
+        Member::Trailer {
+            key: "KEY".to_string(),
+            sequence: 3,
+            checksum: checksum(),
+        },
+        Member::Tombstone {
+            key: "KEY".to_string(),
+        },
+    ];
+
+    for member in members {
+        assert_eq!(Member::from_attributes(&member.attributes()), member);
+    }
+
+    assert_eq!(
+        Member::from_attributes(&Attributes::default()),
+        Member::Other
+    );
+}
+
+#[test]
+fn test_object_checksum_algorithm() {
+    let object = |algorithm: Option<&str>| {
+        let mut attributes = vec![
+            ("GIT-ANNEX-TAPE.key".to_string(), "KEY".to_string()),
+            ("GIT-ANNEX-TAPE.sequence".to_string(), "0".to_string()),
+        ];
+        if let Some(algorithm) = algorithm {
+            attributes.push((
+                "GIT-ANNEX-TAPE.algorithm".to_string(),
+                algorithm.to_string(),
+            ));
+        }
+
+        Member::from_attributes(&Attributes(attributes))
+    };
+
+    // Objects written before the algorithm was recorded use SHA256.
+    let expected = Member::Object {
+        key: "KEY".to_string(),
+        sequence: 0,
+        algorithm: Algorithm::Sha256,
+        metadata: Metadata::default(),
+    };
+    assert_eq!(object(None), expected);
+    assert_eq!(object(Some("sha256")), expected);
+    assert_eq!(object(Some("unknown")), Member::Other);
+}
+
+#[test]
+fn test_member_layout() {
+    let member = Member::Object {
+        key: "KEY".to_string(),
+        sequence: 0,
+        algorithm: Algorithm::Sha256,
+        metadata: Metadata::default(),
+    };
+    let encoded = tar::encode_member(&member, 42, 1700000000);
+
+    // The pax header, its records padded to a block and the file header.
+    assert_eq!(encoded.len(), 3 * BLOCK_SIZE);
+
+    let pax = tar::decode_header(&encoded).unwrap().unwrap();
+    assert_eq!(pax.name, "PaxHeaders/KEY");
+    assert_eq!(pax.kind, EntryType::Extended);
+
+    let records = &encoded[BLOCK_SIZE..BLOCK_SIZE + pax.size as usize];
+    let attributes = Attributes::decode(records).unwrap();
+    assert_eq!(attributes.get("path"), Some("KEY"));
+    assert_eq!(Member::from_attributes(&attributes), member);
+
+    let file = tar::decode_header(&encoded[2 * BLOCK_SIZE..])
+        .unwrap()
+        .unwrap();
+    assert_eq!(file.name, "KEY");
+    assert_eq!(file.size, 42);
+    assert_eq!(file.kind, EntryType::File);
+
+    // Archives have only a global header.
+    let archive = tar::encode_member(&Member::Archive(ArchiveHeader::new(1, "host")), 0, 1);
+    let global = tar::decode_header(&archive).unwrap().unwrap();
+    assert_eq!(global.kind, EntryType::Global);
+    assert_eq!(archive.len(), 2 * BLOCK_SIZE);
+}
+
+#[test]
+fn test_large_sizes_are_in_the_pax_header() {
+    let member = Member::Object {
+        key: "KEY".to_string(),
+        sequence: 0,
+        algorithm: Algorithm::Sha256,
+        metadata: Metadata::default(),
+    };
+    let encoded = tar::encode_member(&member, 1 << 40, 0);
+
+    let pax = tar::decode_header(&encoded).unwrap().unwrap();
+    let records = &encoded[BLOCK_SIZE..BLOCK_SIZE + pax.size as usize];
+    let attributes = Attributes::decode(records).unwrap();
+    assert_eq!(attributes.get("size"), Some("1099511627776"));
+}
+
+#[test]
+fn test_trailer_contents() {
+    let contents = tar::trailer_contents("KEY", &checksum());
+
+    assert_eq!(contents, format!("{}  KEY\n", "ab".repeat(32)).into_bytes());
+    assert_eq!(tar::padding(contents.len() as u64), BLOCK_SIZE - 70);
+    assert_eq!(tar::padding(BLOCK_SIZE as u64), 0);
+}
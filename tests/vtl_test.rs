@@ -0,0 +1,1584 @@
+use git_annex_remote_tape::catalog::Catalog;
+use git_annex_remote_tape::changer::{self, Address};
+use git_annex_remote_tape::compression::Compression;
+use git_annex_remote_tape::crypto::Cipher;
+use git_annex_remote_tape::format::{ArchiveFormat, MediaHeader, MediaIdentity, Metadata, Origin};
+use git_annex_remote_tape::inventory::{self, Finding};
+use git_annex_remote_tape::parity::Parity;
+use git_annex_remote_tape::tape::{self, Drive, Location, Loss, SalvageSink};
+use git_annex_remote_tape::{cleaning, diagnostic, format, scheduler, vtl};
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vtl-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+fn barcodes(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("VTL{i:03}L8")).collect()
+}
+
+#[test]
+fn test_write_and_read_objects() {
+    let dir = temp_dir("objects");
+    let library = vtl::Library::create(&dir, 4, 1, 1, &barcodes(2)).unwrap();
+
+    let changer = changer::open(&dir).unwrap();
+    changer.load(Address::slot(0), 0).unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    assert!(drive.test_unit_ready().unwrap().is_none());
+
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+    assert_eq!(media.creation_time(), 1000);
+
+    let objects: [(&str, &[u8]); 2] = [("KEY1", b"Hello World"), ("KEY2", &[0x42; 300_000])];
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let blocks: Vec<u64> = objects
+        .iter()
+        .map(|(key, data)| {
+            archive
+                .write_object(key, data.len() as u64, &mut &data[..])
+                .unwrap()
+        })
+        .collect();
+    archive.close().unwrap();
+
+    for ((key, data), block) in objects.iter().zip(&blocks) {
+        let mut out = Vec::new();
+        media.read_object(*block, key, &mut out).unwrap();
+        assert_eq!(&out[..], *data);
+    }
+
+    assert!(media
+        .read_object(blocks[1], "KEY1", &mut Vec::new())
+        .is_err());
+
+    // The data survives moving the cartridge to another slot and back.
+    drive.eject().unwrap();
+    let slot = changer.unload(0, Some(Address::slot(3))).unwrap();
+    assert!(drive.test_unit_ready().unwrap().is_some());
+
+    changer.load(slot, 0).unwrap();
+    let media = drive.load_media().unwrap();
+    assert!(media.contains(&Location {
+        media: media.identity().uuid.clone(),
+        block: blocks[0],
+        barcode: None,
+    }));
+
+    // Locations used to refer to the creation time of the cartridge.
+    assert!(media.contains(&Location {
+        media: "1000".to_string(),
+        block: blocks[0],
+        barcode: None,
+    }));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_read_compare() {
+    let dir = temp_dir("diagnostic");
+    let library = vtl::Library::create(&dir.join("library"), 4, 1, 0, &barcodes(1)).unwrap();
+    changer::open(&dir.join("library"))
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let repo = dir.join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    let status = Command::new("git")
+        .args(["init", "--quiet"])
+        .current_dir(&repo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let drive_path = library.drive_path(0);
+    let output = Command::new(env!("CARGO_BIN_EXE_git-annex-remote-tape"))
+        .arg("drives")
+        .arg("-f")
+        .arg(&drive_path)
+        .args(["test", "--scratch", "--block-sizes", "512,65536,262144"])
+        .args(["--blocks", "16"])
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Result: passed"), "{}", stdout);
+
+    let record = Catalog::open(&repo.join(".git"))
+        .unwrap()
+        .drive("VTL0000")
+        .unwrap();
+    assert_eq!(record.vendor.as_deref(), Some("VIRTUAL"));
+    assert_eq!(record.diagnostics.len(), 1);
+
+    let report = &record.diagnostics[0];
+    assert!(report.passed());
+    assert_eq!(report.self_test, diagnostic::SelfTest::Passed);
+
+    let block_sizes: Vec<_> = report.write_read.iter().map(|r| r.block_size).collect();
+    assert_eq!(block_sizes, [512, 65536, 262144]);
+    for result in &report.write_read {
+        assert_eq!((result.blocks, result.mismatches), (16, 0));
+        assert_eq!(result.error, None);
+    }
+
+    // The scratch cartridge has been overwritten with the test data.
+    let drive = Rc::new(Drive::new(&drive_path).unwrap());
+    assert!(matches!(
+        drive.load_media(),
+        Err(tape::Error::InvalidHeader(_))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_blank_cartridge() {
+    let dir = temp_dir("blank");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    assert!(matches!(drive.load_media(), Err(tape::Error::Blank)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_loaded_cartridge_must_be_ejected() {
+    let dir = temp_dir("eject");
+    vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    let changer = changer::open(&dir).unwrap();
+    changer.load(Address::slot(0), 0).unwrap();
+
+    assert!(changer.unload(0, None).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unload_without_empty_slot() {
+    let dir = temp_dir("no-empty-slot");
+    let library = vtl::Library::create(&dir, 1, 1, 1, &barcodes(1)).unwrap();
+    library.insert(0, "VTL001L8").unwrap();
+
+    let changer = changer::open(&dir).unwrap();
+    changer.load(Address::mailslot(0), 0).unwrap();
+    Drive::new(&library.drive_path(0)).unwrap().eject().unwrap();
+
+    // Cartridges loaded from a mailslot go to an empty slot, of which there is none.
+    assert!(matches!(
+        changer.unload(0, None),
+        Err(changer::Error::NoEmptyElement(
+            changer::ElementType::Storage
+        ))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cleaning() {
+    let dir = temp_dir("cleaning");
+    let mut cartridges = barcodes(1);
+    cartridges.push(format!("{}000L1", vtl::CLEANING_PREFIX));
+    let library = vtl::Library::create(&dir, 4, 1, 0, &cartridges).unwrap();
+
+    let changer = changer::open(&dir).unwrap();
+    changer.load(Address::slot(0), 0).unwrap();
+
+    let drive = Drive::new(&library.drive_path(0)).unwrap();
+    cleaning::clean(changer.as_ref(), &drive, 0, 1).unwrap();
+
+    // The data cartridge is back in the drive, the cleaning cartridge in its slot.
+    let status = changer.status().unwrap();
+    assert_eq!(status.drives[0].barcode, Some(cartridges[0].clone()));
+    assert_eq!(status.slots[1].barcode, Some(cartridges[1].clone()));
+    assert!(drive.test_unit_ready().unwrap().is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_inventory_and_mailslots() {
+    let dir = temp_dir("inventory");
+    let library = vtl::Library::create(&dir, 4, 1, 1, &barcodes(2)).unwrap();
+    let catalog = Catalog::open(&dir.join("git")).unwrap();
+
+    let changer = changer::open(&dir).unwrap();
+    changer.load(Address::slot(0), 0).unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let id = drive.load_media().unwrap().id();
+    drive.eject().unwrap();
+    changer.unload(0, None).unwrap();
+
+    // An unlabelled scan identifies the initialized and the blank cartridge.
+    let found = inventory::scan(changer.as_ref(), None).unwrap();
+    assert_eq!(found.len(), 2);
+
+    let mut record = catalog.media(&id).unwrap();
+    record.barcode = Some("VTL000L8".to_string());
+    catalog.save_media(&record).unwrap();
+
+    let result = inventory::run(&catalog, changer.as_ref(), Some((&drive, 0))).unwrap();
+    assert!(result.findings.contains(&Finding::Present {
+        id: id.clone(),
+        address: Address::slot(0),
+    }));
+
+    // Rotate the cartridge out of the library.
+    let mailslot = changer.export(Address::slot(0)).unwrap();
+    assert_eq!(
+        library.remove(mailslot.unit).unwrap(),
+        Some("VTL000L8".to_string())
+    );
+
+    let result = inventory::run(&catalog, changer.as_ref(), None).unwrap();
+    assert!(result.findings.contains(&Finding::Missing {
+        id,
+        barcode: Some("VTL000L8".to_string()),
+    }));
+
+    library.insert(0, "VTL000L8").unwrap();
+    let slot = changer.import(Address::mailslot(0)).unwrap();
+    assert!(changer.element(slot).unwrap().full);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_export_ejects_only_the_drive_holding_the_cartridge() {
+    let dir = temp_dir("export-drive");
+    let library = vtl::Library::create(&dir.join("library"), 4, 2, 1, &barcodes(1)).unwrap();
+    let changer = changer::open(&dir.join("library")).unwrap();
+    changer.load(Address::slot(0), 1).unwrap();
+
+    let repo = dir.join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    let status = Command::new("git")
+        .args(["init", "--quiet"])
+        .current_dir(&repo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let export = |drive: u16| {
+        Command::new(env!("CARGO_BIN_EXE_git-annex-remote-tape"))
+            .arg("changer")
+            .arg("-c")
+            .arg(dir.join("library"))
+            .arg("-f")
+            .arg(library.drive_path(drive))
+            .args(["export", "--drive", &drive.to_string(), "VTL000L8"])
+            .current_dir(&repo)
+            .output()
+            .unwrap()
+    };
+
+    // The cartridge is in drive 1, so the tape drive of drive 0 is not ejected.
+    let output = export(0);
+    assert!(!output.status.success());
+    assert!(changer.element(Address::drive(1)).unwrap().full);
+
+    let output = export(1);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!changer.element(Address::drive(1)).unwrap().full);
+    assert!(changer.element(Address::mailslot(0)).unwrap().full);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_changer_does_not_move_leased_cartridges() {
+    let dir = temp_dir("changer-lease");
+    let library = vtl::Library::create(&dir.join("library"), 4, 2, 0, &barcodes(2)).unwrap();
+    let changer = changer::open(&dir.join("library")).unwrap();
+    changer.load(Address::slot(0), 0).unwrap();
+    let drive_path = library.drive_path(0).display().to_string();
+
+    let repo = dir.join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    let status = Command::new("git")
+        .args(["init", "--quiet"])
+        .current_dir(&repo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_git-annex-remote-tape"))
+            .arg("changer")
+            .arg("-c")
+            .arg(dir.join("library"))
+            .args(args)
+            .current_dir(&repo)
+            .output()
+            .unwrap()
+    };
+
+    // A remote process uses drive 0 and has reserved the cartridge in slot 1.
+    let lock_dir = Catalog::open(&repo.join(".git")).unwrap().lock_dir();
+    let drives = vec![scheduler::DriveSlot {
+        unit: 0,
+        path: PathBuf::new(),
+    }];
+    let lease = scheduler::Scheduler::new(&lock_dir, drives)
+        .unwrap()
+        .acquire(None, Some("VTL001L8"), None, Duration::ZERO)
+        .unwrap();
+
+    assert!(!run(&["-f", &drive_path, "unload", "0"]).status.success());
+    assert!(changer.element(Address::drive(0)).unwrap().full);
+
+    assert!(!run(&["transfer", "VTL001L8", "slot:3"]).status.success());
+    assert!(!run(&["load", "VTL001L8", "1"]).status.success());
+    assert!(changer.element(Address::slot(1)).unwrap().full);
+
+    drop(lease);
+    let output = run(&["transfer", "VTL001L8", "slot:3"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(changer.element(Address::slot(3)).unwrap().full);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_corrupted_object_is_detected() {
+    let dir = temp_dir("corrupted");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let data = b"Hello World, this is going to be corrupted";
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let block = archive
+        .write_object("KEY", data.len() as u64, &mut &data[..])
+        .unwrap();
+    archive.close().unwrap();
+
+    // Flip a bit of the object data in the cartridge image.
+    let image = dir.join("cartridges").join("VTL000L8");
+    let mut bytes = std::fs::read(&image).unwrap();
+    let offset = bytes.windows(data.len()).position(|w| w == data).unwrap();
+    bytes[offset] ^= 0x01;
+    std::fs::write(&image, bytes).unwrap();
+
+    let media = drive.load_media().unwrap();
+    assert!(matches!(
+        media.read_object(block, "KEY", &mut Vec::new()),
+        Err(tape::Error::ChecksumMismatch { .. })
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Yields some data and then fails, like a crashing writer.
+struct Failing(usize);
+
+impl std::io::Read for Failing {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.0 == 0 {
+            return Err(std::io::Error::other("crash"));
+        }
+
+        let n = buf.len().min(self.0);
+        buf[..n].fill(0x55);
+        self.0 -= n;
+
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_scan_finds_torn_objects() {
+    let dir = temp_dir("scan");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let big = vec![0x42; tape::RECORD_SIZE * 2 + 10];
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("A", 5, &mut &b"Hello"[..]).unwrap();
+
+    // The data is shorter than announced.
+    let length = tape::RECORD_SIZE as u64 * 2;
+    assert!(archive
+        .write_object("B", length, &mut &b"short"[..])
+        .is_err());
+    archive
+        .write_object("C", big.len() as u64, &mut &big[..])
+        .unwrap();
+
+    // The writer crashes in the middle of an object, without closing the archive.
+    let length = tape::RECORD_SIZE as u64 * 3;
+    let mut failing = Failing(tape::RECORD_SIZE + 100);
+    assert!(archive.write_object("D", length, &mut failing).is_err());
+    drop(archive);
+
+    let media = drive.load_media().unwrap();
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    archive.write_object("E", 5, &mut &b"World"[..]).unwrap();
+    archive.close().unwrap();
+
+    let scan = media.scan().unwrap();
+
+    let keys = |entries: &[tape::ObjectEntry]| -> Vec<String> {
+        entries.iter().map(|e| e.key.clone()).collect()
+    };
+    assert_eq!(keys(&scan.objects), ["A", "C", "E"]);
+    assert_eq!(keys(&scan.torn), ["B", "D"]);
+
+    // The crashed archive has no table of contents and is read record by record.
+    assert_eq!(scan.archives.len(), 2);
+    assert!(scan.archives.iter().all(|a| !a.indexed));
+
+    assert_eq!(scan.objects[2].archive, 3000);
+    assert_eq!(scan.objects[1].length, big.len() as u64);
+    assert!(scan.objects.iter().all(|e| e.checksum.is_some()));
+
+    // Complete objects remain readable.
+    let mut out = Vec::new();
+    media
+        .read_object(scan.objects[1].block, "C", &mut out)
+        .unwrap();
+    assert_eq!(out, big);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_reads_table_of_contents() {
+    let dir = temp_dir("toc");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let a = archive.write_object("A", 5, &mut &b"Hello"[..]).unwrap();
+    let length = tape::RECORD_SIZE as u64 * 2;
+    assert!(archive
+        .write_object("B", length, &mut &b"short"[..])
+        .is_err());
+    archive.close().unwrap();
+
+    // Enough objects with long keys to split the table of contents.
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    let keys: Vec<String> = (0..1200).map(|i| format!("{i:0250}")).collect();
+    for key in &keys {
+        archive.write_object(key, 1, &mut &b"x"[..]).unwrap();
+    }
+    archive.close().unwrap();
+
+    let scan = media.scan().unwrap();
+
+    assert_eq!(scan.archives.len(), 2);
+    assert!(scan.archives.iter().all(|a| a.indexed));
+
+    assert_eq!(scan.objects.len(), 1 + keys.len());
+    assert_eq!(scan.objects[0].key, "A");
+    assert_eq!(scan.objects[0].block, a);
+    assert_eq!(scan.torn.len(), 1);
+    assert_eq!(scan.torn[0].key, "B");
+
+    let last = scan.objects.last().unwrap();
+    assert_eq!(&last.key, keys.last().unwrap());
+    assert!(media.check_object(last.block, &last.key).unwrap());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Collects the contents of salvaged objects.
+#[derive(Default)]
+struct Collect {
+    objects: Vec<(String, Vec<u8>)>,
+    current: Option<(String, Vec<u8>)>,
+    metadata: Vec<(String, Metadata)>,
+}
+
+impl SalvageSink for Collect {
+    fn begin(&mut self, key: &str) -> std::io::Result<()> {
+        self.current = Some((key.to_string(), Vec::new()));
+        Ok(())
+    }
+
+    fn describe(&mut self, metadata: &Metadata) -> std::io::Result<()> {
+        let key = self.current.as_ref().unwrap().0.clone();
+        self.metadata.push((key, metadata.clone()));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.current.as_mut().unwrap().1.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> std::io::Result<()> {
+        self.objects.push(self.current.take().unwrap());
+        Ok(())
+    }
+
+    fn discard(&mut self) -> std::io::Result<()> {
+        self.current = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_salvage_skips_unreadable_records() {
+    let dir = temp_dir("salvage");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let big = vec![0x42; tape::RECORD_SIZE * 2 + 10];
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("A", 5, &mut &b"Hello"[..]).unwrap();
+    let b = archive
+        .write_object("B", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.write_object("C", 3, &mut &b"foo"[..]).unwrap();
+    archive.flush().unwrap();
+    let d = archive.write_object("D", 3, &mut &b"bar"[..]).unwrap();
+    archive.close().unwrap();
+
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    archive
+        .write_object("E", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.close().unwrap();
+
+    // A data record of B and the header of D become unreadable.
+    library.damage("VTL000L8", b + 2).unwrap();
+    library.damage("VTL000L8", d).unwrap();
+
+    let mut out = Vec::new();
+    assert!(media.read_object(b, "B", &mut out).is_err());
+
+    let mut sink = Collect::default();
+    let salvage = media.salvage(&mut sink).unwrap();
+
+    assert_eq!(salvage.unreadable, 2);
+
+    let keys: Vec<_> = salvage.recovered.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["A", "C", "E"]);
+    assert_eq!(salvage.recovered[2].archive, 3000);
+
+    let lost: Vec<_> = salvage
+        .lost
+        .iter()
+        .map(|o| (o.key.as_str(), o.block, o.loss))
+        .collect();
+    assert_eq!(lost, [("B", b, Loss::Unreadable), ("D", d, Loss::Missing)]);
+
+    assert_eq!(
+        sink.objects,
+        [
+            ("A".to_string(), b"Hello".to_vec()),
+            ("C".to_string(), b"foo".to_vec()),
+            ("E".to_string(), big),
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parity_reconstructs_unreadable_records() {
+    let dir = temp_dir("parity");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    // Six data records in three stripes, each followed by a parity record.
+    let big: Vec<u8> = (0..tape::RECORD_SIZE * 5 + 10)
+        .map(|i| (i % 251) as u8)
+        .collect();
+
+    let parity = Parity::new(2, 1);
+    let mut archive = media.append_archive(2000, "host", parity).unwrap();
+    let a = archive
+        .write_object("A", big.len() as u64, &mut &big[..])
+        .unwrap();
+    let b = archive
+        .write_object("B", big.len() as u64, &mut &big[..])
+        .unwrap();
+    let c = archive.write_object("C", 5, &mut &b"Hello"[..]).unwrap();
+    archive.flush().unwrap();
+    drop(archive);
+
+    // One record of every stripe of A, including the short last record.
+    for block in [a + 1, a + 5, a + 8] {
+        library.damage("VTL000L8", block).unwrap();
+    }
+    // Both data records of a stripe of B.
+    library.damage("VTL000L8", b + 4).unwrap();
+    library.damage("VTL000L8", b + 5).unwrap();
+    // The only data record of C.
+    library.damage("VTL000L8", c + 1).unwrap();
+
+    let mut out = Vec::new();
+    media.read_object(a, "A", &mut out).unwrap();
+    assert_eq!(out, big);
+
+    let mut out = Vec::new();
+    assert!(matches!(
+        media.read_object(b, "B", &mut out),
+        Err(tape::Error::Unrecoverable(_))
+    ));
+
+    let mut out = Vec::new();
+    media.read_object(c, "C", &mut out).unwrap();
+    assert_eq!(out, b"Hello");
+
+    let mut sink = Collect::default();
+    let salvage = media.salvage(&mut sink).unwrap();
+    let keys: Vec<_> = salvage.recovered.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["A", "C"]);
+    assert_eq!(salvage.lost[0].key, "B");
+    assert_eq!(salvage.lost[0].loss, Loss::Unreadable);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_skips_parity_records() {
+    let dir = temp_dir("parity-scan");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let big = vec![0x42; tape::RECORD_SIZE * 3];
+
+    let mut archive = media
+        .append_archive(2000, "host", Parity::new(2, 2))
+        .unwrap();
+    archive
+        .write_object("A", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.write_object("B", 5, &mut &b"Hello"[..]).unwrap();
+    archive.flush().unwrap();
+    drop(archive);
+
+    // Without a table of contents, the archive is read record by record.
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["A", "B"]);
+    assert!(scan.torn.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_small_objects_share_a_pack() {
+    let dir = temp_dir("pack");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let small: Vec<Vec<u8>> = (0..100u32)
+        .map(|i| i.to_be_bytes().repeat(1000 + i as usize))
+        .collect();
+    let big = vec![0x42; tape::RECORD_SIZE * 2 + 10];
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let mut blocks = Vec::new();
+    for (i, data) in small.iter().enumerate() {
+        let block = archive
+            .write_object(&format!("S{i}"), data.len() as u64, &mut &data[..])
+            .unwrap();
+        blocks.push(block);
+    }
+    let b = archive
+        .write_object("B", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.close().unwrap();
+
+    // The small objects are written as a single pack, ahead of the large one.
+    assert!(blocks.iter().all(|&block| block == blocks[0]));
+    assert!(b > blocks[0]);
+
+    for i in [0, 42, 99] {
+        let mut out = Vec::new();
+        media
+            .read_object(blocks[i], &format!("S{i}"), &mut out)
+            .unwrap();
+        assert_eq!(out, small[i]);
+    }
+    assert!(media.check_object(blocks[7], "S7").unwrap());
+    assert!(!media.check_object(blocks[7], "B").unwrap());
+
+    let mut out = Vec::new();
+    media.read_object(b, "B", &mut out).unwrap();
+    assert_eq!(out, big);
+
+    let scan = media.scan().unwrap();
+    assert!(scan.archives[0].indexed);
+    assert_eq!(scan.objects.len(), small.len() + 1);
+    assert_eq!(scan.objects[42].key, "S42");
+    assert_eq!(scan.objects[42].block, blocks[42]);
+    assert_eq!(scan.objects[42].length, small[42].len() as u64);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_packed_objects_need_a_sync() {
+    let dir = temp_dir("pack-sync");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("A", 5, &mut &b"Hello"[..]).unwrap();
+    archive.sync().unwrap();
+    archive.write_object("B", 5, &mut &b"World"[..]).unwrap();
+
+    // A crash loses the objects which are still in the pack.
+    drop(archive);
+
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["A"]);
+    assert!(scan.torn.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_salvage_recovers_undamaged_pack_members() {
+    let dir = temp_dir("pack-salvage");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    // Eight members of the largest packed size fill two data records.
+    let small: Vec<Vec<u8>> = (0..8u8)
+        .map(|i| vec![i; tape::PACK_OBJECT_SIZE as usize])
+        .collect();
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let mut block = 0;
+    for (i, data) in small.iter().enumerate() {
+        block = archive
+            .write_object(&format!("S{i}"), data.len() as u64, &mut &data[..])
+            .unwrap();
+    }
+    archive.flush().unwrap();
+    drop(archive);
+
+    // Without a table of contents, the pack is read record by record.
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["S0", "S1", "S2", "S3", "S4", "S5", "S6", "S7"]);
+
+    // The second data record holds the last four members.
+    library.damage("VTL000L8", block + 2).unwrap();
+
+    let mut out = Vec::new();
+    media.read_object(block, "S1", &mut out).unwrap();
+    assert_eq!(out, small[1]);
+    assert!(media.read_object(block, "S5", &mut Vec::new()).is_err());
+
+    let mut sink = Collect::default();
+    let salvage = media.salvage(&mut sink).unwrap();
+
+    let keys: Vec<_> = sink.objects.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, ["S0", "S1", "S2", "S3"]);
+    assert_eq!(sink.objects[3].1, small[3]);
+
+    let lost: Vec<_> = salvage
+        .lost
+        .iter()
+        .map(|o| (o.key.as_str(), o.block, o.loss))
+        .collect();
+    let expected: Vec<_> = ["S4", "S5", "S6", "S7"]
+        .iter()
+        .map(|&key| (key, block, Loss::Unreadable))
+        .collect();
+    assert_eq!(lost, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_compressed_objects() {
+    let dir = temp_dir("compression");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let text: Vec<u8> = (0..tape::RECORD_SIZE * 3)
+        .map(|i| b"all work and no play "[i % 21])
+        .collect();
+
+    // Noise from a xorshift generator does not compress.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let noise: Vec<u8> = (0..tape::RECORD_SIZE * 2 + 10)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+
+    let mut archive = media
+        .append_archive(2000, "host", None)
+        .unwrap()
+        .with_compression(Compression::new(3));
+    let a = archive
+        .write_object("A", text.len() as u64, &mut &text[..])
+        .unwrap();
+    let b = archive
+        .write_object("B", noise.len() as u64, &mut &noise[..])
+        .unwrap();
+    let c = archive
+        .write_object("C", text.len() as u64, &mut &text[..])
+        .unwrap();
+    assert!(archive
+        .write_object("D", text.len() as u64 + 1, &mut &text[..])
+        .is_err());
+    archive.close().unwrap();
+
+    // The text fits into a single record, the noise is stored as it is.
+    assert_eq!(b - a, 3);
+    assert_eq!(c - b, 5);
+
+    let mut out = Vec::new();
+    assert_eq!(
+        media.read_object(a, "A", &mut out).unwrap(),
+        text.len() as u64
+    );
+    assert_eq!(out, text);
+
+    let mut out = Vec::new();
+    media.read_object(b, "B", &mut out).unwrap();
+    assert_eq!(out, noise);
+
+    let scan = media.scan().unwrap();
+    let lengths: Vec<_> = scan.objects.iter().map(|o| o.length).collect();
+    assert_eq!(
+        lengths,
+        [text.len() as u64, noise.len() as u64, text.len() as u64]
+    );
+
+    let mut sink = Collect::default();
+    media.salvage(&mut sink).unwrap();
+    assert_eq!(sink.objects.len(), 3);
+    assert_eq!(sink.objects[2], ("C".to_string(), text));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_packed_objects_are_compressed_on_their_own() {
+    let dir = temp_dir("pack-compression");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    let mut small: Vec<Vec<u8>> = (0..8u8)
+        .map(|i| {
+            (0..tape::PACK_OBJECT_SIZE as usize)
+                .map(|j| b"all work and no play "[(i as usize + j) % 21])
+                .collect()
+        })
+        .collect();
+
+    // Noise from a xorshift generator is stored as it is.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    small.push(
+        (0..10_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect(),
+    );
+
+    let mut archive = media
+        .append_archive(2000, "host", None)
+        .unwrap()
+        .with_compression(Compression::new(3));
+    let mut block = 0;
+    for (i, data) in small.iter().enumerate() {
+        block = archive
+            .write_object(&format!("S{i}"), data.len() as u64, &mut &data[..])
+            .unwrap();
+    }
+    let big = vec![0x42; tape::PACK_OBJECT_SIZE as usize + 1];
+    let b = archive
+        .write_object("B", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.sync().unwrap();
+    drop(archive);
+
+    // Uncompressed, the members would fill three data records.
+    assert_eq!(b - block, 3);
+
+    for i in [0, 5, 8] {
+        let mut out = Vec::new();
+        let length = media
+            .read_object(block, &format!("S{i}"), &mut out)
+            .unwrap();
+        assert_eq!(length, small[i].len() as u64);
+        assert_eq!(out, small[i]);
+    }
+
+    // Without a table of contents, the pack is read record by record.
+    let scan = media.scan().unwrap();
+    let lengths: Vec<_> = scan.objects.iter().map(|o| o.length).collect();
+    let expected: Vec<_> = small
+        .iter()
+        .chain([&big])
+        .map(|data| data.len() as u64)
+        .collect();
+    assert_eq!(lengths, expected);
+
+    let mut sink = Collect::default();
+    media.salvage(&mut sink).unwrap();
+    assert_eq!(sink.objects.len(), small.len() + 1);
+    for (i, data) in small.iter().enumerate() {
+        assert_eq!(sink.objects[i], (format!("S{i}"), data.clone()));
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_encrypted_objects() {
+    let dir = temp_dir("encryption");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+
+    let cipher = Cipher::generate();
+    let media = drive
+        .load_media()
+        .unwrap()
+        .with_cipher(Some(cipher.clone()));
+
+    let large = vec![0x42; tape::RECORD_SIZE * 2];
+    let small = b"Hello World".to_vec();
+
+    let mut archive = media
+        .append_archive(2000, "host", Parity::new(4, 1))
+        .unwrap();
+    let a = archive
+        .write_object("SECRET-A", large.len() as u64, &mut &large[..])
+        .unwrap();
+    let metadata = Metadata {
+        paths: vec!["SECRET-PATH".to_string()],
+        ..Metadata::default()
+    };
+    let b = archive
+        .write_object_with_metadata("SECRET-B", small.len() as u64, &mut &small[..], &metadata)
+        .unwrap();
+    let c = archive.write_object("SECRET-C", 0, &mut &[][..]).unwrap();
+    archive.close().unwrap();
+
+    // Sealed records lose room for their tag, so the contents need three
+    // records, followed by a parity record and the trailer.
+    assert_eq!(b - a, 6);
+
+    for (block, key, data) in [(a, "SECRET-A", &large[..]), (b, "SECRET-B", &small[..])] {
+        let mut out = Vec::new();
+        media.read_object(block, key, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+    media.read_object(c, "SECRET-C", &mut Vec::new()).unwrap();
+
+    let scan = media.scan().unwrap();
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["SECRET-A", "SECRET-B", "SECRET-C"]);
+
+    let mut sink = Collect::default();
+    media.salvage(&mut sink).unwrap();
+    assert_eq!(sink.objects.len(), 3);
+    assert_eq!(sink.objects[0], ("SECRET-A".to_string(), large.clone()));
+    assert_eq!(sink.metadata, [("SECRET-B".to_string(), metadata)]);
+
+    // Neither keys nor contents end up on tape in the clear.
+    let image = std::fs::read(dir.join("cartridges").join(&barcodes(1)[0])).unwrap();
+    assert!(!image.windows(6).any(|w| w == b"SECRET"));
+    assert!(!image.windows(11).any(|w| w == small));
+
+    // An archive which is not closed is scanned record by record.
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    archive
+        .write_object("SECRET-D", large.len() as u64, &mut &large[..])
+        .unwrap();
+    drop(archive);
+
+    // Without the right key, nothing can be read but the number and length
+    // of the objects.
+    for other in [None, Some(Cipher::generate())] {
+        let media = drive.load_media().unwrap().with_cipher(other);
+        assert!(matches!(
+            media.read_object(a, "SECRET-A", &mut Vec::new()),
+            Err(tape::Error::Decryption)
+        ));
+
+        let scan = media.scan().unwrap();
+        assert!(scan.objects.is_empty());
+        let sealed: Vec<_> = scan
+            .archives
+            .iter()
+            .map(|a| (a.indexed, a.unknown_key, a.sealed_objects, a.sealed_length))
+            .collect();
+        let length = (large.len() + small.len()) as u64;
+        assert_eq!(
+            sealed,
+            [
+                (true, Some(cipher.id()), 3, length),
+                (false, Some(cipher.id()), 1, large.len() as u64)
+            ]
+        );
+
+        let mut sink = Collect::default();
+        let salvage = media.salvage(&mut sink).unwrap();
+        assert!(sink.objects.is_empty());
+        assert!(salvage.lost.is_empty());
+        let sealed: Vec<_> = salvage
+            .sealed
+            .iter()
+            .map(|a| (a.creation_time, a.sealed_objects))
+            .collect();
+        assert_eq!(sealed, [(2000, 3), (3000, 1)]);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_archive_origin_and_object_metadata() {
+    let dir = temp_dir("metadata");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+
+    let origin = Origin {
+        repository: "repository-uuid".to_string(),
+        remote: "remote-uuid".to_string(),
+        remote_name: "tape".to_string(),
+    };
+    let media = drive.load_media().unwrap().with_origin(origin.clone());
+
+    let metadata = |path: &str| Metadata {
+        paths: vec![path.to_string()],
+        mtime: Some(1700000000),
+        commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+    };
+
+    let large = vec![0x42; tape::RECORD_SIZE + 10];
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive
+        .write_object_with_metadata("A", large.len() as u64, &mut &large[..], &metadata("large"))
+        .unwrap();
+    archive
+        .write_object_with_metadata("B", 5, &mut &b"small"[..], &metadata("small"))
+        .unwrap();
+    archive.write_object("C", 5, &mut &b"plain"[..]).unwrap();
+    archive.close().unwrap();
+
+    let scan = media.scan().unwrap();
+    assert_eq!(scan.archives[0].origin, origin);
+    assert_eq!(scan.archives[0].writer, format::WRITER);
+
+    // Objects without metadata are not described.
+    let mut sink = Collect::default();
+    media.salvage(&mut sink).unwrap();
+    assert_eq!(sink.objects.len(), 3);
+    assert_eq!(
+        sink.metadata,
+        [
+            ("A".to_string(), metadata("large")),
+            ("B".to_string(), metadata("small"))
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_lists_bundles_apart() {
+    let dir = temp_dir("bundles");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive.load_media().unwrap();
+
+    for (time, bundle) in [(2000, &b"first"[..]), (3000, &b"second"[..])] {
+        let mut archive = media.append_archive(time, "host", None).unwrap();
+        archive.write_object("KEY", 5, &mut &b"hello"[..]).unwrap();
+        archive
+            .write_object(tape::BUNDLE_KEY, bundle.len() as u64, &mut &bundle[..])
+            .unwrap();
+        archive.close().unwrap();
+    }
+
+    let scan = media.scan().unwrap();
+    assert_eq!(scan.objects.len(), 2);
+    assert_eq!(scan.bundles.len(), 2);
+
+    let newest = scan.newest_bundle().unwrap();
+    assert_eq!(newest.archive, 3000);
+
+    let mut out = Vec::new();
+    media
+        .read_object(newest.block, tape::BUNDLE_KEY, &mut out)
+        .unwrap();
+    assert_eq!(out, b"second");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tombstones_remove_earlier_copies() {
+    let dir = temp_dir("tombstones");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    drive.init_media(1000, "host").unwrap();
+    let media = drive
+        .load_media()
+        .unwrap()
+        .with_cipher(Some(Cipher::generate()));
+
+    let big = vec![0x42; tape::RECORD_SIZE + 10];
+
+    // The first archive is closed and listed from its table of contents.
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("A", 5, &mut &b"Hello"[..]).unwrap();
+    archive
+        .write_object("B", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.write_object("C", 5, &mut &b"World"[..]).unwrap();
+    let a_removed = archive.write_tombstone("A").unwrap();
+    archive.close().unwrap();
+
+    // The second one is not, and is read record by record.
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    archive.write_tombstone("B").unwrap();
+    let a = archive.write_object("A", 3, &mut &b"new"[..]).unwrap();
+    archive.write_tombstone("UNKNOWN").unwrap();
+    archive.flush().unwrap();
+    drop(archive);
+
+    let scan = media.scan().unwrap();
+
+    // Objects stored again after their removal are present.
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["C", "A"]);
+    assert_eq!(scan.objects[1].block, a);
+
+    let keys: Vec<_> = scan.tombstones.iter().map(|t| t.key.as_str()).collect();
+    assert_eq!(keys, ["A", "B", "UNKNOWN"]);
+    assert_eq!(scan.tombstones[0].block, a_removed);
+    assert!(scan.torn.is_empty());
+
+    let mut out = Vec::new();
+    media.read_object(a, "A", &mut out).unwrap();
+    assert_eq!(out, b"new");
+
+    // Tombstones only remove the copies on their own cartridge.
+    let catalog = Catalog::open(&dir.join("git")).unwrap();
+    catalog.save_objects("1000", &scan.objects[..1]).unwrap();
+    assert_eq!(
+        catalog.find_object("C").unwrap(),
+        Some(("1000".to_string(), scan.objects[0].clone()))
+    );
+
+    let removed = tape::TombstoneEntry {
+        key: "C".to_string(),
+        archive: 4000,
+        block: 1,
+    };
+    catalog.add_tombstone("1001", removed.clone()).unwrap();
+    assert_eq!(
+        catalog.find_object("C").unwrap(),
+        Some(("1000".to_string(), scan.objects[0].clone()))
+    );
+
+    catalog.add_tombstone("1000", removed).unwrap();
+    assert_eq!(catalog.find_object("C").unwrap(), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_media_owned_by_other_remote() {
+    let dir = temp_dir("owner");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    let identity = MediaIdentity {
+        uuid: "0f4c2a8e-0000-4000-8000-000000000001".to_string(),
+        label: barcodes(1)[0].clone(),
+        pool: "weekly".to_string(),
+        remote: "0f4c2a8e-0000-4000-8000-000000000002".to_string(),
+    };
+    drive
+        .write_media_header(&MediaHeader::new(1000, "host").with_identity(identity.clone()))
+        .unwrap();
+
+    let media = drive.load_media().unwrap();
+    assert_eq!(media.identity(), &identity);
+
+    let origin = |remote: &str| Origin {
+        remote: remote.to_string(),
+        ..Origin::default()
+    };
+
+    let media = media.with_origin(origin("0f4c2a8e-0000-4000-8000-000000000003"));
+    assert!(matches!(
+        media.append_archive(2000, "host", None),
+        Err(tape::Error::ForeignMedia(remote)) if remote == identity.remote
+    ));
+
+    let media = media.with_origin(origin(&identity.remote));
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("KEY", 5, &mut &b"Hello"[..]).unwrap();
+    archive.close().unwrap();
+
+    // Cartridges initialized without an owner accept every remote, and get a
+    // UUID of their own.
+    drive.init_media(3000, "host").unwrap();
+    let media = drive
+        .load_media()
+        .unwrap()
+        .with_origin(origin("0f4c2a8e-0000-4000-8000-000000000003"));
+    assert!(media.identity().remote.is_empty());
+    assert_ne!(media.identity().uuid, identity.uuid);
+    assert!(media.append_archive(4000, "host", None).is_ok());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The tar stream of archive `n` of a cartridge image, as read with `mt fsf`
+/// and `tar`.
+fn tar_stream(image: &std::path::Path, n: usize) -> Vec<u8> {
+    let image = std::fs::read(image).unwrap();
+    let mut stream = Vec::new();
+    let mut filemarks = 0;
+    let mut offset = 0;
+
+    while offset < image.len() {
+        let length = u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap());
+        offset += 4;
+
+        if length == u32::MAX {
+            filemarks += 1;
+            continue;
+        }
+
+        if filemarks == n {
+            stream.extend_from_slice(&image[offset..offset + length as usize]);
+        }
+        offset += length as usize;
+    }
+
+    stream
+}
+
+#[test]
+fn test_tar_archives() {
+    let dir = temp_dir("tar");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    let header = MediaHeader::new(1000, "host").with_format(ArchiveFormat::Tar);
+    drive.write_media_header(&header).unwrap();
+
+    let origin = Origin {
+        repository: "0f4c2a8e-0000-4000-8000-000000000001".to_string(),
+        ..Origin::default()
+    };
+    let media = drive.load_media().unwrap().with_origin(origin.clone());
+    assert_eq!(media.format(), ArchiveFormat::Tar);
+
+    // Neither encryption nor parity fit into a plain tar stream.
+    let encrypted = drive
+        .load_media()
+        .unwrap()
+        .with_cipher(Some(Cipher::generate()));
+    assert!(matches!(
+        encrypted.append_archive(2000, "host", None),
+        Err(tape::Error::TarUnsupported("encryption"))
+    ));
+    assert!(matches!(
+        media.append_archive(2000, "host", Parity::new(4, 1)),
+        Err(tape::Error::TarUnsupported("parity"))
+    ));
+
+    let big = vec![0x42; 3 * tape::RECORD_SIZE + 100];
+    let metadata = Metadata {
+        paths: vec!["docs/hello.txt".to_string()],
+        mtime: Some(1600000000),
+        commit: String::new(),
+    };
+
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    let hello = archive
+        .write_object_with_metadata("KEY1", 5, &mut &b"Hello"[..], &metadata)
+        .unwrap();
+    let removed = archive.write_object("KEY2", 5, &mut &b"World"[..]).unwrap();
+    let big_block = archive
+        .write_object("KEY3", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.write_tombstone("KEY2").unwrap();
+
+    // Objects cut short are padded and left without a trailer.
+    assert!(matches!(
+        archive.write_object("SHORT", 1000, &mut &b"short"[..]),
+        Err(tape::Error::ShortObject {
+            expected: 1000,
+            actual: 5
+        })
+    ));
+    archive.close().unwrap();
+
+    // Objects are not packed, each starts a new record.
+    assert_eq!(removed, hello + 1);
+    assert_eq!(big_block, removed + 1);
+
+    let mut out = Vec::new();
+    assert_eq!(media.read_object(hello, "KEY1", &mut out).unwrap(), 5);
+    assert_eq!(out, b"Hello");
+
+    let mut out = Vec::new();
+    media.read_object(big_block, "KEY3", &mut out).unwrap();
+    assert_eq!(out, big);
+
+    assert!(media.check_object(big_block, "KEY3").unwrap());
+    assert!(!media.check_object(big_block, "KEY1").unwrap());
+    assert!(matches!(
+        media.read_object(hello, "KEY3", &mut Vec::new()),
+        Err(tape::Error::UnexpectedKey(key)) if key == "KEY1"
+    ));
+
+    // An archive which has not been closed is followed by the next one.
+    let mut archive = media.append_archive(3000, "host", None).unwrap();
+    let again = archive.write_object("KEY2", 5, &mut &b"again"[..]).unwrap();
+    drop(archive);
+
+    let mut archive = media.append_archive(4000, "host", None).unwrap();
+    archive.write_object("KEY4", 4, &mut &b"last"[..]).unwrap();
+    archive.close().unwrap();
+
+    let scan = media.scan().unwrap();
+
+    let archives: Vec<_> = scan.archives.iter().map(|a| a.creation_time).collect();
+    assert_eq!(archives, [2000, 3000, 4000]);
+    assert_eq!(scan.archives[0].origin, origin);
+    assert!(!scan.archives[0].indexed);
+
+    let keys: Vec<_> = scan.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["KEY1", "KEY3", "KEY2", "KEY4"]);
+    assert_eq!(scan.objects[1].length, big.len() as u64);
+    assert_eq!(scan.objects[2].block, again);
+    assert!(scan.objects.iter().all(|o| o.checksum.is_some()));
+
+    let keys: Vec<_> = scan.torn.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, ["SHORT"]);
+    assert_eq!(scan.tombstones.len(), 1);
+
+    assert!(matches!(
+        media.salvage(&mut Collect::default()),
+        Err(tape::Error::TarUnsupported("salvage"))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tar_archives_extract_with_tar() {
+    let dir = temp_dir("tar-extract");
+    let library = vtl::Library::create(&dir, 2, 1, 0, &barcodes(1)).unwrap();
+
+    changer::open(&dir)
+        .unwrap()
+        .load(Address::slot(0), 0)
+        .unwrap();
+
+    let drive = Rc::new(Drive::new(&library.drive_path(0)).unwrap());
+    let header = MediaHeader::new(1000, "host").with_format(ArchiveFormat::Tar);
+    drive.write_media_header(&header).unwrap();
+    let media = drive.load_media().unwrap();
+
+    let big: Vec<u8> = (0..tape::RECORD_SIZE * 2 + 1000).map(|i| i as u8).collect();
+    let mut archive = media.append_archive(2000, "host", None).unwrap();
+    archive.write_object("KEY1", 5, &mut &b"Hello"[..]).unwrap();
+    archive
+        .write_object("KEY2", big.len() as u64, &mut &big[..])
+        .unwrap();
+    archive.write_tombstone("KEY1").unwrap();
+    archive.close().unwrap();
+    drop(media);
+    drop(drive);
+
+    // The first archive follows the filemark of the media header.
+    let out = dir.join("out");
+    std::fs::create_dir(&out).unwrap();
+    let mut tar = std::process::Command::new("tar")
+        .args(["-x", "--warning=no-unknown-keyword", "-f", "-", "-C"])
+        .arg(&out)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let image = dir.join("cartridges").join(&barcodes(1)[0]);
+    std::io::Write::write_all(tar.stdin.as_mut().unwrap(), &tar_stream(&image, 1)).unwrap();
+    drop(tar.stdin.take());
+    assert!(tar.wait().unwrap().success());
+
+    assert_eq!(std::fs::read(out.join("KEY1")).unwrap(), b"Hello");
+    assert_eq!(std::fs::read(out.join("KEY2")).unwrap(), big);
+    assert!(std::fs::read_to_string(out.join("KEY2.sha256"))
+        .unwrap()
+        .ends_with("  KEY2\n"));
+    assert!(out.join("KEY1.removed").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}